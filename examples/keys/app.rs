@@ -0,0 +1,337 @@
+use launchpad_pro_rs::hal;
+use launchpad_pro_rs::hal::midi::{MidiMessage, Port};
+use launchpad_pro_rs::hal::LaunchpadAppMut;
+use launchpad_pro_rs::layout::{self, Layout, Style};
+use launchpad_pro_rs::scale::Scale;
+use launchpad_pro_rs::settings;
+use wmidi::{Channel, Note, U7};
+
+/// Indices of the border buttons used by the keyboard.
+const UP: u8 = 91;
+const DOWN: u8 = 92;
+const LEFT: u8 = 93;
+const RIGHT: u8 = 94;
+const SESSION: u8 = 95;
+const NOTE: u8 = 96;
+const DEVICE: u8 = 97;
+const SHIFT: u8 = 80;
+
+/// The layouts that can be chosen with the bottom row of buttons, left to right.
+const STYLES: [Style; 6] = [
+    Style::FOURTHS,
+    Style::FIFTHS,
+    Style::WickiHayden,
+    Style::HarmonicTable,
+    Style::Piano,
+    Style::LINEAR,
+];
+
+/// The Launchpad Pro app, which owns its state.
+pub struct App {
+    /// Where the layout is saved in flash.
+    settings_offset: usize,
+    /// The mapping from pads to notes.
+    layout: Layout,
+    /// The note played by each held pad, so that it can be ended after the layout changes.
+    held: [Option<u8>; hal::Grid::size() as usize],
+    /// Whether the Shift button is held.
+    shift: bool,
+}
+
+impl App {
+    /// Create the app, saving its layout at an offset in the user area of flash.
+    pub const fn new(settings_offset: usize) -> Self {
+        Self {
+            settings_offset,
+            layout: Layout::new(Style::FOURTHS),
+            held: [None; hal::Grid::size() as usize],
+            shift: false,
+        }
+    }
+
+    /// Send a note message to USB and DIN.
+    fn send(message: MidiMessage<'static>) {
+        for port in [Port::USB, Port::DIN] {
+            hal::midi::send_message(port, &message);
+        }
+    }
+
+    /// Play the note under a pad.
+    fn press_pad(&mut self, point: hal::Point, velocity: u8) {
+        if let Some(note) = self.layout.note(point) {
+            self.held[point.to_index() as usize] = Some(note);
+            Self::send(MidiMessage::NoteOn(
+                Channel::Ch1,
+                Note::from_u8_lossy(note),
+                U7::from_u8_lossy(velocity),
+            ));
+            self.draw_note(note);
+        }
+    }
+
+    /// End the note started by a pad.
+    fn release_pad(&mut self, point: hal::Point) {
+        if let Some(note) = self.held[point.to_index() as usize].take() {
+            Self::send(MidiMessage::NoteOff(
+                Channel::Ch1,
+                Note::from_u8_lossy(note),
+                U7::MIN,
+            ));
+            self.draw_note(note);
+        }
+    }
+
+    /// Move through the scale catalogue, or back to its start from a user-defined scale.
+    fn change_scale(&mut self, delta: isize) {
+        let mut key = self.layout.key();
+        let count = Scale::ALL.len() as isize;
+        let index = match key.scale().index() {
+            Some(index) => (index as isize + delta).rem_euclid(count),
+            None => 0,
+        };
+        key.set_scale(Scale::ALL[index as usize]);
+        self.layout.set_key(key);
+    }
+
+    /// Move the root of the key by a number of semitones.
+    fn change_root(&mut self, delta: i8) {
+        let mut key = self.layout.key();
+        key.set_root((key.root() as i8 + delta).rem_euclid(12) as u8);
+        self.layout.set_key(key);
+    }
+
+    /// Returns true if any held pad is playing a note.
+    fn is_held(&self, note: u8) -> bool {
+        self.held.contains(&Some(note))
+    }
+
+    /// Redraw every pad playing a note, lighting them while the note is held.
+    fn draw_note(&self, note: u8) {
+        let is_held = self.is_held(note);
+        for point in self.layout.points(note) {
+            let colour = if is_held {
+                hal::Rgb::new(0, 255, 0)
+            } else {
+                self.layout.colour(point)
+            };
+            hal::surface::set_led(point, colour);
+        }
+    }
+
+    /// Draw the layout and the controls.
+    fn draw(&self) {
+        self.layout.draw();
+        for point in layout::pads() {
+            if let Some(note) = self.layout.note(point).filter(|&note| self.is_held(note)) {
+                self.draw_note(note);
+            }
+        }
+
+        for (index, style) in STYLES.iter().enumerate() {
+            let colour = if self.layout.style() == *style {
+                hal::Rgb::new(255, 127, 0)
+            } else {
+                hal::Rgb::new(32, 16, 0)
+            };
+            hal::surface::set_led(hal::Point::new(index as i8 + 1, 0), colour);
+        }
+
+        // the scale and root buttons get brighter further through the catalogue and the octave
+        let key = self.layout.key();
+        let scale = key.scale().index().map_or(0, |index| 64 + index as u8 * 12);
+        hal::surface::set_led(
+            hal::Point::from_index(SESSION),
+            hal::Rgb::new(scale, 0, scale),
+        );
+        let root = 64 + key.root() * 16;
+        hal::surface::set_led(hal::Point::from_index(NOTE), hal::Rgb::new(root, root, 0));
+        hal::surface::set_led(
+            hal::Point::from_index(DEVICE),
+            match self.layout.is_scale_locked() {
+                true => hal::Rgb::new(255, 0, 0),
+                false => hal::Rgb::new(32, 0, 0),
+            },
+        );
+
+        let octave = self.layout.octave();
+        let level = |shift: i8| (shift.max(0) as u8).min(4) * 63;
+        hal::surface::set_led(
+            hal::Point::from_index(UP),
+            hal::Rgb::new(0, 0, level(octave)),
+        );
+        hal::surface::set_led(
+            hal::Point::from_index(DOWN),
+            hal::Rgb::new(0, 0, level(-octave)),
+        );
+        let transpose = self.layout.transpose();
+        hal::surface::set_led(
+            hal::Point::from_index(LEFT),
+            hal::Rgb::new(0, level(-transpose), 0),
+        );
+        hal::surface::set_led(
+            hal::Point::from_index(RIGHT),
+            hal::Rgb::new(0, level(transpose), 0),
+        );
+    }
+}
+
+/// Implement the LaunchpadAppMut trait for our app in order to be notified of events that occur
+/// on the Launchpad Pro hardware.
+impl LaunchpadAppMut for App {
+    fn init_event(&mut self, _pads: hal::surface::Pads) {
+        if let Ok(layout) = settings::load(self.settings_offset) {
+            self.layout = layout;
+        }
+        self.draw();
+    }
+
+    fn button_event(&mut self, button_event: hal::surface::ButtonEvent) {
+        if let hal::surface::Button::Pad(point) = button_event.button {
+            let previous = self.layout;
+            match (point.to_index(), button_event.event) {
+                (SHIFT, hal::surface::Event::Press(_)) => {
+                    self.shift = true;
+                    return;
+                }
+                (SHIFT, hal::surface::Event::Release) => {
+                    self.shift = false;
+                    return;
+                }
+                (_, hal::surface::Event::Release) => {
+                    self.release_pad(point);
+                    return;
+                }
+                (UP, _) => self.layout.shift_octave(1),
+                (DOWN, _) => self.layout.shift_octave(-1),
+                (LEFT, _) => {
+                    let transpose = self.layout.transpose();
+                    self.layout.set_transpose(transpose - 1)
+                }
+                (RIGHT, _) => {
+                    let transpose = self.layout.transpose();
+                    self.layout.set_transpose(transpose + 1)
+                }
+                (SESSION, _) => {
+                    let delta = if self.shift { -1 } else { 1 };
+                    self.change_scale(delta)
+                }
+                (NOTE, _) => {
+                    let delta = if self.shift { -1 } else { 1 };
+                    self.change_root(delta)
+                }
+                (DEVICE, _) => {
+                    let scale_lock = self.layout.is_scale_locked();
+                    self.layout.set_scale_lock(!scale_lock)
+                }
+                (index @ 1..=6, _) => self.layout.set_style(STYLES[index as usize - 1]),
+                (_, hal::surface::Event::Press(velocity)) => {
+                    self.press_pad(point, velocity);
+                    return;
+                }
+            }
+            // remember the layout across power cycles, writing to flash only when it changed
+            if self.layout != previous {
+                let _ = settings::save(&self.layout, self.settings_offset);
+            }
+            self.draw();
+        }
+    }
+
+    fn suspend_event(&mut self) {
+        // the releases of held pads will go to another app, so end their notes now
+        for point in layout::pads() {
+            self.release_pad(point);
+        }
+        self.shift = false;
+    }
+
+    fn resume_event(&mut self) {
+        self.draw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use launchpad_pro_rs::scale::Key;
+
+    /// Where the layout is saved in flash.
+    const SETTINGS_OFFSET: usize = 0;
+
+    fn press(app: &mut App, index: u8) {
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Pad(hal::Point::from_index(index)),
+            event: hal::surface::Event::Press(100),
+        });
+    }
+
+    fn release(app: &mut App, index: u8) {
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Pad(hal::Point::from_index(index)),
+            event: hal::surface::Event::Release,
+        });
+    }
+
+    #[test]
+    fn held_notes_survive_layout_changes() {
+        let mut app = App::new(SETTINGS_OFFSET);
+
+        press(&mut app, 11);
+        assert!(app.is_held(36));
+
+        // changing the layout while the pad is held still ends the note it started
+        press(&mut app, UP);
+        press(&mut app, 5);
+        assert_eq!(app.layout.style(), Style::Piano);
+        release(&mut app, 11);
+        assert!(!app.is_held(36));
+
+        press(&mut app, 11);
+        assert!(app.is_held(48));
+    }
+
+    #[test]
+    fn border_buttons_choose_the_key() {
+        let mut app = App::new(SETTINGS_OFFSET);
+
+        press(&mut app, SESSION);
+        press(&mut app, NOTE);
+        press(&mut app, NOTE);
+        assert_eq!(app.layout.key(), Key::new(2, Scale::DORIAN));
+
+        // shift moves backwards through the catalogue and wraps around
+        press(&mut app, SHIFT);
+        press(&mut app, SESSION);
+        press(&mut app, SESSION);
+        press(&mut app, NOTE);
+        press(&mut app, NOTE);
+        press(&mut app, NOTE);
+        release(&mut app, SHIFT);
+        assert_eq!(app.layout.key(), Key::new(11, Scale::CHROMATIC));
+
+        // with scale lock on, pads outside B major play nothing
+        press(&mut app, SESSION);
+        press(&mut app, DEVICE);
+        press(&mut app, 13);
+        assert!(!app.held.iter().any(Option::is_some));
+        press(&mut app, 12);
+        assert!(app.is_held(37));
+    }
+
+    #[test]
+    fn layout_is_saved_when_it_changes() {
+        let mut app = App::new(SETTINGS_OFFSET);
+
+        // choosing the style already in use leaves the flash alone
+        press(&mut app, 1);
+        assert!(settings::load::<Layout>(SETTINGS_OFFSET).is_err());
+
+        press(&mut app, UP);
+        assert_eq!(settings::load(SETTINGS_OFFSET), Ok(app.layout));
+
+        let mut restored = App::new(SETTINGS_OFFSET);
+        let adc = [0u16; 64];
+        restored.init_event(hal::surface::Pads::new(adc.as_ptr()));
+        assert_eq!(restored.layout, app.layout);
+    }
+}
@@ -0,0 +1,240 @@
+#[path = "life.rs"]
+pub mod life;
+
+use launchpad_pro_rs::hal;
+use launchpad_pro_rs::hal::timer::Scheduler;
+use launchpad_pro_rs::hal::LaunchpadApp;
+
+use life::{AreaSwitch, Control, Life};
+
+/// The Launchpad Pro app state.
+struct State {
+    /// A flag to indicate whether the Game of Life simulation is running.
+    is_running: bool,
+    /// Our Game of Life state.
+    life: Life,
+    /// Switches the universe between the whole grid and the pads.
+    area_switch: AreaSwitch,
+    /// Draws the frames of the simulation.
+    timers: Scheduler<fn(&mut State)>,
+}
+
+impl State {
+    /// Create the app.
+    const fn new() -> Self {
+        Self {
+            is_running: false,
+            life: Life::new(),
+            area_switch: AreaSwitch::new(),
+            timers: Scheduler::new(),
+        }
+    }
+
+    /// Draw the Game of Life universe on the Launchpad Pro grid, and the controls around it when
+    /// it only covers the pads.
+    fn draw_universe(&self) {
+        life::draw(&self.life);
+    }
+
+    /// Respond to a button: pads toggle cells or choose the rules and edges around the pads, and
+    /// Setup pauses and resumes the simulation unless it switches the area.
+    fn button_event(&mut self, button_event: hal::surface::ButtonEvent) {
+        if self.area_switch.button_event(&mut self.life, button_event) {
+            self.draw_universe();
+            return;
+        }
+
+        if let hal::surface::Event::Release = button_event.event {
+            match button_event.button {
+                hal::surface::Button::Pad(point) => {
+                    match Control::at(point, self.life.area()) {
+                        Some(control) => control.choose(&mut self.life),
+                        _ => self.toggle_cell(point),
+                    }
+                    self.draw_universe();
+                }
+                hal::surface::Button::Setup => {
+                    self.toggle_is_running();
+                }
+            }
+        }
+    }
+
+    /// Move the simulation forward by one tick.
+    fn tick(&mut self) {
+        self.life.tick();
+    }
+
+    /// Draw the next frame of the simulation, if it is running.
+    fn frame(&mut self) {
+        if self.is_running() {
+            self.tick();
+            self.draw_universe();
+        }
+    }
+
+    /// Toggle the state of the cell at the point on the grid.
+    fn toggle_cell(&mut self, point: hal::Point) {
+        let toggled_state = !self.life.get(point);
+        self.life.set(point, toggled_state);
+    }
+
+    fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Toggle whether the simulation is running.
+    fn toggle_is_running(&mut self) {
+        self.is_running = ! self.is_running;
+    }
+}
+
+/// The Game of Life app.
+pub struct App {
+    state: hal::Mutex<State>
+}
+
+impl App {
+    const fn new() -> Self {
+        Self {
+            state: hal::Mutex::new(State::new())
+        }
+    }
+}
+
+/// The number of frames per second in our simulation.
+const FRAMES_PER_SECOND: u32 = 4;
+/// The time between frames in ms.
+const FRAME_PERIOD: u32 = 1000 / FRAMES_PER_SECOND;
+
+/// Implement the LaunchpadApp trait for our app in order to be notified of events that occur on
+/// the Launchpad Pro hardware.
+impl LaunchpadApp for App {
+    fn init_event(&self, _pads: hal::surface::Pads) {
+        let mut state = self.state.lock();
+        state.draw_universe();
+        state.timers.every(FRAME_PERIOD, State::frame).unwrap();
+    }
+
+    fn timer_event(&self) {
+        let mut state = self.state.lock();
+        for callback in state.timers.advance(1) {
+            callback(&mut state);
+        }
+    }
+
+    fn midi_event(&self, _port: hal::midi::Port, _midi_event: hal::midi::MidiMessage) {
+    }
+
+    fn sysex_event(&self, _port: hal::midi::Port, _data: &[u8]) {
+    }
+
+    fn cable_event(&self, _cable_event: hal::midi::CableEvent) {
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        self.state.lock().button_event(button_event);
+    }
+
+    fn aftertouch_event(&self, _aftertouch_event: hal::surface::AftertouchEvent) {
+    }
+
+    fn resume_event(&self) {
+        self.state.lock().draw_universe();
+    }
+}
+
+/// Create a static instance of our app.
+pub static APP: App = App::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use life::{Area, Rule, AREA, EDGES, FIRST_EDGES, FIRST_RULE, RULES};
+
+    #[test]
+    fn app_starts_paused_until_setup_button_is_pressed() {
+        // start our app
+        let app = &APP;
+        let adc = [0u16; 64];
+        app.init_event(hal::surface::Pads::new(adc.as_ptr()));
+
+        // we expect that our newly created app will start paused
+        assert_eq!(app.state.lock().is_running, false);
+
+        // create a single cell that will immediately die once the simulation starts
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Pad(hal::Point::new(5, 5)),
+            event: hal::surface::Event::Release,
+        });
+
+        // expect that the cell we created is now alive
+        assert_eq!(
+            app.state.lock().life.get(hal::Point::new(5, 5)),
+            life::Cell::Alive
+        );
+
+        // call the timer until the simulation is progressed by one tick (if it was running...)
+        for _ in 0..FRAME_PERIOD {
+            app.timer_event();
+        }
+
+        // check that our cell is still alive
+        assert_eq!(
+            app.state.lock().life.get(hal::Point::new(5, 5)),
+            life::Cell::Alive
+        );
+
+        // press the setup button to unpause the simulation
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Setup,
+            event: hal::surface::Event::Release,
+        });
+
+        // check that our button press was registered
+        assert_eq!(app.state.lock().is_running, true);
+
+        // call the timer until the simulation is progressed by one tick
+        for _ in 0..FRAME_PERIOD {
+            app.timer_event();
+        }
+
+        // now that the simulation as started we expect that our solitary cell has died
+        assert_eq!(app.state.lock().life.get(hal::Point::new(5, 5)), life::Cell::Dead);
+    }
+
+    #[test]
+    fn border_buttons_choose_the_rules() {
+        let mut state = State::new();
+        let send = |state: &mut State, index: u8, event| {
+            state.button_event(hal::surface::ButtonEvent {
+                button: hal::surface::Button::Pad(hal::Point::from_index(index)),
+                event,
+            });
+        };
+
+        // the universe covers the whole grid, so the buttons around the pads are cells
+        send(&mut state, FIRST_RULE + 1, hal::surface::Event::Release);
+        assert_eq!(state.life.rule(), Rule::LIFE);
+        assert_eq!(state.life.get(hal::Point::from_index(FIRST_RULE + 1)), life::Cell::Alive);
+
+        // hold the corner and tap Setup to limit it to the pads
+        send(&mut state, AREA, hal::surface::Event::Press(127));
+        state.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Setup,
+            event: hal::surface::Event::Release,
+        });
+        send(&mut state, AREA, hal::surface::Event::Release);
+        assert_eq!(state.life.area(), Area::Pads);
+        assert!(!state.is_running());
+
+        for (offset, &rule) in RULES.iter().enumerate() {
+            send(&mut state, FIRST_RULE + offset as u8, hal::surface::Event::Release);
+            assert_eq!(state.life.rule(), rule);
+        }
+        for (offset, &edges) in EDGES.iter().enumerate() {
+            send(&mut state, FIRST_EDGES + offset as u8, hal::surface::Event::Release);
+            assert_eq!(state.life.edges(), edges);
+        }
+    }
+}
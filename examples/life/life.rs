@@ -1,4 +1,6 @@
-use crate::hal::{Grid, Point};
+use crate::hal::surface::{self, Button, ButtonEvent, Event};
+use crate::hal::{Grid, Point, Rgb};
+use core::fmt;
 use core::ops::Not;
 
 /// A cell within the Game of Life.
@@ -20,10 +22,143 @@ impl Not for Cell {
     }
 }
 
+/// The rules of a life-like cellular automaton: the numbers of live neighbours that bring a dead
+/// cell to life, and that keep a live cell alive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// Bit n is set if a dead cell with n live neighbours is born.
+    birth: u16,
+    /// Bit n is set if a live cell with n live neighbours survives.
+    survival: u16,
+}
+
+impl Rule {
+    /// Conway's Game of Life.
+    pub const LIFE: Rule = Rule::expect("B3/S23");
+    /// Like Life, but six neighbours also bring a cell to life, which makes replicators.
+    pub const HIGH_LIFE: Rule = Rule::expect("B36/S23");
+    /// Every live cell dies, so patterns explode or vanish.
+    pub const SEEDS: Rule = Rule::expect("B2/S");
+    /// Live and dead cells behave the same, so patterns of each mirror the other.
+    pub const DAY_AND_NIGHT: Rule = Rule::expect("B3678/S34678");
+
+    /// Parse a rule in B/S notation, e.g. `B36/S23`: `B` and the neighbour counts that bring a cell
+    /// to life, then `/S` and the counts that keep it alive. Either list may be empty, and the
+    /// letters may be lower case. Returns `None` if the rule is malformed.
+    pub const fn parse(text: &str) -> Option<Rule> {
+        let bytes = text.as_bytes();
+        let mut rule = Rule {
+            birth: 0,
+            survival: 0,
+        };
+        let mut index = 0;
+        // 0 before the B, 1 in the birth counts and 2 in the survival counts
+        let mut part = 0;
+        while index < bytes.len() {
+            match (part, bytes[index]) {
+                (0, b'B' | b'b') => part = 1,
+                (1, b'/') if index + 1 < bytes.len() => {
+                    if !matches!(bytes[index + 1], b'S' | b's') {
+                        return None;
+                    }
+                    index += 1;
+                    part = 2;
+                }
+                (1 | 2, count @ b'0'..=b'8') => {
+                    let bit = 1 << (count - b'0');
+                    if part == 1 {
+                        rule.birth |= bit;
+                    } else {
+                        rule.survival |= bit;
+                    }
+                }
+                _ => return None,
+            }
+            index += 1;
+        }
+        match part {
+            2 => Some(rule),
+            _ => None,
+        }
+    }
+
+    /// Parse a rule that is known to be valid.
+    const fn expect(text: &str) -> Rule {
+        match Rule::parse(text) {
+            Some(rule) => rule,
+            None => panic!("invalid rule"),
+        }
+    }
+
+    /// Returns the next state of a cell with some live neighbours.
+    pub fn next(&self, cell: Cell, neighbours: u8) -> Cell {
+        let counts = match cell {
+            Cell::Alive => self.survival,
+            Cell::Dead => self.birth,
+        };
+        match counts & 1 << neighbours {
+            0 => Cell::Dead,
+            _ => Cell::Alive,
+        }
+    }
+}
+
+/// Writes the rule in B/S notation.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (letter, counts) in [("B", self.birth), ("/S", self.survival)] {
+            f.write_str(letter)?;
+            for count in (0..=8).filter(|count| counts & 1 << count != 0) {
+                write!(f, "{}", count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What lies beyond the edges of the universe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edges {
+    /// The opposite edge, so the universe is a torus.
+    Wrap,
+    /// Dead cells.
+    Dead,
+    /// A reflection of the universe, so a cell on an edge is its own neighbour across it.
+    Mirror,
+}
+
+/// The part of the grid the universe covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Area {
+    /// The whole 10x10 grid, including the buttons around the pads.
+    Grid,
+    /// The 8x8 pads.
+    Pads,
+}
+
+impl Area {
+    /// Returns the first and last coordinates inside the area, which are the same for x and y.
+    fn bounds(&self) -> (i8, i8) {
+        match self {
+            Area::Grid => (0, Grid::width() as i8 - 1),
+            Area::Pads => (1, Grid::width() as i8 - 2),
+        }
+    }
+
+    /// Returns true if the point is inside the area.
+    pub fn contains(&self, point: Point) -> bool {
+        let (first, last) = self.bounds();
+        (first..=last).contains(&point.x()) && (first..=last).contains(&point.y())
+    }
+}
+
 /// The Game of Life.
 pub struct Life {
     universe: [[Cell; Grid::size() as usize]; 2], // double buffered universe
     active: usize,
+    rule: Rule,
+    edges: Edges,
+    area: Area,
 }
 
 impl Life {
@@ -32,6 +167,42 @@ impl Life {
         Life {
             universe: [[Cell::Dead; Grid::size() as usize]; 2],
             active: 0,
+            rule: Rule::LIFE,
+            edges: Edges::Wrap,
+            area: Area::Grid,
+        }
+    }
+
+    /// Returns the rule the simulation follows.
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    /// Change the rule the simulation follows.
+    pub fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    /// Returns what lies beyond the edges of the universe.
+    pub fn edges(&self) -> Edges {
+        self.edges
+    }
+
+    /// Change what lies beyond the edges of the universe.
+    pub fn set_edges(&mut self, edges: Edges) {
+        self.edges = edges;
+    }
+
+    /// Returns the part of the grid the universe covers.
+    pub fn area(&self) -> Area {
+        self.area
+    }
+
+    /// Change the part of the grid the universe covers. Cells outside it die.
+    pub fn set_area(&mut self, area: Area) {
+        self.area = area;
+        for point in Grid::points().filter(|&point| !area.contains(point)) {
+            self.set_buffer(self.active, point, Cell::Dead);
         }
     }
 
@@ -40,8 +211,11 @@ impl Life {
         self.universe[self.active][point.to_index() as usize]
     }
 
-    /// Set the state of a cell at the given point.
+    /// Set the state of a cell at the given point. Cells outside the universe stay dead.
     pub fn set(&mut self, point: Point, cell: Cell) {
+        if !self.area.contains(point) {
+            return;
+        }
         self.set_buffer(self.active, point, cell);
     }
 
@@ -53,24 +227,34 @@ impl Life {
     pub fn tick(&mut self) {
         let next_universe: usize = if self.active == 0 { 1 } else { 0 };
         for point in Grid::points() {
-            self.set_buffer(next_universe, point, self.next_state(point));
+            let cell = match self.area.contains(point) {
+                true => self.next_state(point),
+                false => Cell::Dead,
+            };
+            self.set_buffer(next_universe, point, cell);
         }
         self.active = next_universe;
     }
 
     /// Returns the next state of a cell at a point in the universe.
     fn next_state(&self, point: Point) -> Cell {
-        match (self.get(point), self.live_neighbours(point)) {
-            // rule 1 - underpopulation
-            (Cell::Alive, n) if n < 2 => Cell::Dead,
-            // rule 2 - stable population
-            (Cell::Alive, 2) | (Cell::Alive, 3) => Cell::Alive,
-            // rule 3 - overpopulation
-            (Cell::Alive, n) if n > 3 => Cell::Dead,
-            // rule 4 - reproduction
-            (Cell::Dead, 3) => Cell::Alive,
-            // anything else is stable
-            (current, _) => current,
+        self.rule.next(self.get(point), self.live_neighbours(point))
+    }
+
+    /// Returns the cell some distance from a point, following the edges of the universe.
+    fn neighbour(&self, point: Point, x: i8, y: i8) -> Cell {
+        let (first, last) = self.area.bounds();
+        let size = last - first + 1;
+        let follow_edges = |coordinate: i8| match self.edges {
+            _ if (first..=last).contains(&coordinate) => Some(coordinate),
+            Edges::Wrap => Some(first + (coordinate - first).rem_euclid(size)),
+            Edges::Dead => None,
+            Edges::Mirror if coordinate < first => Some(2 * first - 1 - coordinate),
+            Edges::Mirror => Some(2 * last + 1 - coordinate),
+        };
+        match (follow_edges(point.x() + x), follow_edges(point.y() + y)) {
+            (Some(x), Some(y)) => self.get(Point::new(x, y)),
+            _ => Cell::Dead,
         }
     }
 
@@ -87,12 +271,130 @@ impl Life {
             (1, 1),
         ]
         .iter()
-        .map(|&(x, y)| point + Point::new(x, y))
-        .filter(|&p| self.get(p) == Cell::Alive)
+        .filter(|&&(x, y)| self.neighbour(point, x, y) == Cell::Alive)
         .count() as u8
     }
 }
 
+/// The rules chosen with the top row of buttons, from left to right.
+pub const RULES: [Rule; 4] = [
+    Rule::LIFE,
+    Rule::HIGH_LIFE,
+    Rule::SEEDS,
+    Rule::DAY_AND_NIGHT,
+];
+/// The first button of the top row.
+pub const FIRST_RULE: u8 = 91;
+/// What lies beyond the edges, chosen with the first buttons of the bottom row.
+pub const EDGES: [Edges; 3] = [Edges::Wrap, Edges::Dead, Edges::Mirror];
+/// The first button of the bottom row.
+pub const FIRST_EDGES: u8 = 1;
+/// The last button of the bottom row. Tapping Setup while it is held switches between the whole
+/// grid and the pads.
+pub const AREA: u8 = 8;
+
+/// A choice made with a button around the pads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Rule(Rule),
+    Edges(Edges),
+}
+
+impl Control {
+    /// Returns the control of the button at a point, if it has one. The buttons around the pads
+    /// are cells when the universe covers the whole grid, so they only have controls when it is
+    /// limited to the pads.
+    pub fn at(point: Point, area: Area) -> Option<Control> {
+        let index = point.to_index();
+        let nth = |first: u8| index.checked_sub(first).map(|n| n as usize);
+        match area {
+            Area::Grid => None,
+            Area::Pads if point.y() == 9 => {
+                RULES.get(nth(FIRST_RULE)?).map(|&rule| Control::Rule(rule))
+            }
+            Area::Pads if point.y() == 0 => EDGES
+                .get(nth(FIRST_EDGES)?)
+                .map(|&edges| Control::Edges(edges)),
+            Area::Pads => None,
+        }
+    }
+
+    /// Returns true if the choice is in use.
+    pub fn is_chosen(self, life: &Life) -> bool {
+        match self {
+            Control::Rule(rule) => life.rule() == rule,
+            Control::Edges(edges) => life.edges() == edges,
+        }
+    }
+
+    /// Make the choice.
+    pub fn choose(self, life: &mut Life) {
+        match self {
+            Control::Rule(rule) => life.set_rule(rule),
+            Control::Edges(edges) => life.set_edges(edges),
+        }
+    }
+}
+
+/// Draw the universe on the grid, and the controls around it when it only covers the pads. A
+/// control is bright if its choice is in use and dim if not.
+pub fn draw(life: &Life) {
+    for point in Grid::points() {
+        let colour = match Control::at(point, life.area()) {
+            Some(control) if control.is_chosen(life) => Rgb::new(255, 255, 255),
+            Some(_) => Rgb::new(32, 32, 32),
+            None => match life.get(point) {
+                Cell::Alive => Rgb::new(0, 255, 0),
+                Cell::Dead => Rgb::new(0, 0, 0),
+            },
+        };
+        surface::set_led(point, colour);
+    }
+}
+
+/// Switches the area of a universe when Setup is tapped while the last button of the bottom row
+/// is held. That button is a cell when the universe covers the whole grid, so it is left alone
+/// when it is let go after switching.
+pub struct AreaSwitch {
+    /// Whether the button is held.
+    held: bool,
+    /// Whether the area was switched while the button was held.
+    switched: bool,
+}
+
+impl AreaSwitch {
+    pub const fn new() -> Self {
+        AreaSwitch {
+            held: false,
+            switched: false,
+        }
+    }
+
+    /// Handle a button event. Returns true if it was part of switching the area, in which case
+    /// the app should do nothing else with it.
+    pub fn button_event(&mut self, life: &mut Life, button_event: ButtonEvent) -> bool {
+        match (button_event.button, button_event.event) {
+            (Button::Pad(point), Event::Press(_)) if point.to_index() == AREA => {
+                self.held = true;
+                false
+            }
+            (Button::Pad(point), Event::Release) if point.to_index() == AREA => {
+                self.held = false;
+                core::mem::take(&mut self.switched)
+            }
+            (Button::Setup, Event::Release) if self.held => {
+                life.set_area(match life.area() {
+                    Area::Grid => Area::Pads,
+                    Area::Pads => Area::Grid,
+                });
+                self.switched = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,4 +472,145 @@ mod tests {
         life.tick();
         assert_eq!(life.get(Point::new(2, 2)), Cell::Alive);
     }
+
+    #[test]
+    fn rules_are_parsed() {
+        assert_eq!(Rule::parse("B3/S23"), Some(Rule::LIFE));
+        assert_eq!(Rule::parse("b3/s23"), Some(Rule::LIFE));
+        assert_eq!(Rule::parse("B36/S23").unwrap().to_string(), "B36/S23");
+        assert_eq!(Rule::SEEDS.to_string(), "B2/S");
+        assert_eq!(Rule::DAY_AND_NIGHT.to_string(), "B3678/S34678");
+        for invalid in ["", "B3", "B3/", "B3/23", "S23/B3", "B9/S23", "B3/S23 "] {
+            assert_eq!(Rule::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn high_life_reproduction_rule() {
+        let mut life = Life::new();
+        life.set_rule(Rule::HIGH_LIFE);
+        for (x, y) in [(1, 1), (2, 1), (3, 1), (1, 3), (2, 3), (3, 3)] {
+            life.set(Point::new(x, y), Cell::Alive);
+        }
+        life.tick();
+        assert_eq!(life.get(Point::new(2, 2)), Cell::Alive);
+    }
+
+    #[test]
+    fn seeds_rules() {
+        let mut life = Life::new();
+        life.set_rule(Rule::SEEDS);
+        life.set(Point::new(1, 2), Cell::Alive);
+        life.set(Point::new(3, 2), Cell::Alive);
+        life.set(Point::new(2, 3), Cell::Alive);
+        life.set(Point::new(2, 2), Cell::Alive);
+        life.tick();
+
+        // no cell survives, and two neighbours bring a cell to life
+        assert_eq!(life.get(Point::new(2, 2)), Cell::Dead);
+        assert_eq!(life.get(Point::new(1, 3)), Cell::Dead);
+        assert_eq!(life.get(Point::new(1, 1)), Cell::Alive);
+    }
+
+    #[test]
+    fn day_and_night_rules() {
+        let mut life = Life::new();
+        life.set_rule(Rule::DAY_AND_NIGHT);
+        life.set(Point::new(2, 2), Cell::Alive);
+        life.set(Point::new(3, 2), Cell::Alive);
+        life.set(Point::new(1, 2), Cell::Alive);
+        life.set(Point::new(2, 3), Cell::Alive);
+        life.set(Point::new(2, 1), Cell::Alive);
+        life.tick();
+
+        // four neighbours keep a cell alive
+        assert_eq!(life.get(Point::new(2, 2)), Cell::Alive);
+        // three neighbours bring a cell to life
+        assert_eq!(life.get(Point::new(3, 3)), Cell::Alive);
+    }
+
+    #[test]
+    fn edges_rule() {
+        // a cell on the edge with two neighbours along it and one across it
+        let setup = |edges| {
+            let mut life = Life::new();
+            life.set_edges(edges);
+            life.set(Point::new(0, 4), Cell::Alive);
+            life.set(Point::new(0, 3), Cell::Alive);
+            life.set(Point::new(0, 5), Cell::Alive);
+            life.set(Point::new(9, 4), Cell::Alive);
+            life
+        };
+
+        let life = setup(Edges::Wrap);
+        assert_eq!(life.live_neighbours(Point::new(0, 4)), 3);
+        let life = setup(Edges::Dead);
+        assert_eq!(life.live_neighbours(Point::new(0, 4)), 2);
+        // the cell and its neighbours along the edge are reflected across it
+        let life = setup(Edges::Mirror);
+        assert_eq!(life.live_neighbours(Point::new(0, 4)), 5);
+    }
+
+    #[test]
+    fn pads_area_rule() {
+        let mut life = Life::new();
+        life.set(Point::new(0, 0), Cell::Alive);
+        life.set_area(Area::Pads);
+        assert_eq!(life.get(Point::new(0, 0)), Cell::Dead);
+        life.set(Point::new(9, 9), Cell::Alive);
+        assert_eq!(life.get(Point::new(9, 9)), Cell::Dead);
+
+        // the pads wrap onto each other, skipping the buttons around them
+        life.set(Point::new(1, 4), Cell::Alive);
+        life.set(Point::new(8, 3), Cell::Alive);
+        life.set(Point::new(8, 5), Cell::Alive);
+        life.tick();
+        assert_eq!(life.get(Point::new(8, 4)), Cell::Alive);
+        assert_eq!(life.get(Point::new(9, 4)), Cell::Dead);
+    }
+
+    #[test]
+    fn border_buttons_are_cells_until_the_universe_is_limited_to_the_pads() {
+        let rule = Point::from_index(FIRST_RULE + 1);
+        let edges = Point::from_index(FIRST_EDGES + 2);
+        assert_eq!(Control::at(rule, Area::Grid), None);
+        assert_eq!(Control::at(edges, Area::Grid), None);
+        assert_eq!(
+            Control::at(rule, Area::Pads),
+            Some(Control::Rule(Rule::HIGH_LIFE))
+        );
+        assert_eq!(
+            Control::at(edges, Area::Pads),
+            Some(Control::Edges(Edges::Mirror))
+        );
+
+        // buttons without a control and the pads never have one
+        assert_eq!(Control::at(Point::from_index(95), Area::Pads), None);
+        assert_eq!(Control::at(Point::from_index(AREA), Area::Pads), None);
+        assert_eq!(Control::at(Point::new(4, 4), Area::Pads), None);
+    }
+
+    #[test]
+    fn tapping_setup_while_holding_the_corner_switches_the_area() {
+        let mut life = Life::new();
+        let mut switch = AreaSwitch::new();
+        let mut send = |life: &mut Life, button, event| {
+            switch.button_event(life, ButtonEvent { button, event })
+        };
+        let corner = Button::Pad(Point::from_index(AREA));
+
+        // a tap on its own is left to the app
+        assert!(!send(&mut life, Button::Setup, Event::Release));
+        assert!(!send(&mut life, corner, Event::Press(127)));
+        assert!(!send(&mut life, corner, Event::Release));
+        assert_eq!(life.area(), Area::Grid);
+
+        for area in [Area::Pads, Area::Grid] {
+            assert!(!send(&mut life, corner, Event::Press(127)));
+            assert!(send(&mut life, Button::Setup, Event::Release));
+            assert_eq!(life.area(), area);
+            // letting go doesn't toggle the cell under the button
+            assert!(send(&mut life, corner, Event::Release));
+        }
+    }
 }
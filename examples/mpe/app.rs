@@ -0,0 +1,848 @@
+// The diamond and voice helpers expose more than the app currently uses.
+#![allow(dead_code)]
+
+#[path = "diamond.rs"]
+mod diamond;
+#[path = "mpe.rs"]
+mod mpe;
+#[path = "resources.rs"]
+mod resources;
+
+use crate::hal::surface::*;
+use crate::hal::*;
+use launchpad_pro_rs::arpeggiator::{ArpEvent, ArpNote, Arpeggiator};
+use launchpad_pro_rs::clock::{Clock, ClockEvent};
+use launchpad_pro_rs::hal;
+use launchpad_pro_rs::hal::timer::{self, Scheduler};
+use launchpad_pro_rs::hal::LaunchpadApp;
+use launchpad_pro_rs::looper::{self, Action, Looper, Mode};
+use launchpad_pro_rs::presets::{Preset, Presets};
+use launchpad_pro_rs::router::{Region, Router};
+use launchpad_pro_rs::rpc;
+use launchpad_pro_rs::settings::{Error, Reader, Settings, Writer};
+use wmidi::Note as MidiNote;
+
+use self::diamond::{Diamond, MAX_SEMITONES};
+use self::mpe::{MPEZone, VoiceManager, MAX_VOICES, TRANSPOSE};
+use self::resources::TONES;
+
+/// The Launchpad Pro app state.
+struct State {
+    /// JI diamond state
+    diamond: Diamond,
+    /// MPE voice manager
+    mpe: VoiceManager,
+    pads: Option<Pads>,
+    /// The pending MPE configuration message, sent once the synth has had time to start
+    init: Option<timer::Handle>,
+    /// Timers, fired as the app's timer events arrive
+    timers: Scheduler<fn(&mut State)>,
+    /// Arpeggiator for held pads, enabled with the setup button
+    arp: Arpeggiator,
+    /// Clock driving the arpeggiator
+    clock: Clock,
+    /// Presets saved and recalled with the scene buttons
+    presets: Presets<MpeSettings, PRESET_SLOTS>,
+    /// Whether the Shift button is held
+    shift: bool,
+    /// Loops what is played on the pads, for drones without a DAW
+    looper: Looper<LOOP_EVENTS>,
+}
+
+/// The settings saved in a preset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MpeSettings {
+    base_note: u8,
+    pitch_bend_range: u8,
+    zone: MPEZone,
+    voice_count: u8,
+}
+
+impl Settings for MpeSettings {
+    const VERSION: u8 = 1;
+
+    fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+        writer.put_u8(self.base_note)?;
+        writer.put_u8(self.pitch_bend_range)?;
+        writer.put_bool(self.zone == MPEZone::Upper)?;
+        writer.put_u8(self.voice_count)
+    }
+
+    /// Presets may be imported over SysEx, so values the diamond and the voice manager can't use
+    /// are rejected.
+    fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+        let settings = MpeSettings {
+            base_note: reader.get_u8()?,
+            pitch_bend_range: reader.get_u8()?,
+            zone: match reader.get_bool()? {
+                true => MPEZone::Upper,
+                false => MPEZone::Lower,
+            },
+            voice_count: reader.get_u8()?,
+        };
+        if settings.base_note > 127 - MAX_SEMITONES
+            || !(1..=96).contains(&settings.pitch_bend_range)
+            || !(1..=15).contains(&settings.voice_count)
+        {
+            return Err(Error::Invalid);
+        }
+        Ok(settings)
+    }
+}
+
+/// How long to wait before configuring MPE on a synth that has just been connected, in ms
+const INIT_DELAY: u32 = 100;
+
+/// Buttons shifting the octave of the played notes
+const UP: u8 = 91;
+const DOWN: u8 = 92;
+const MAX_OCTAVE_SHIFT: i8 = 4;
+
+/// Hold Shift and press a scene button to save a preset, or press a scene button to recall one
+const SHIFT: u8 = 80;
+const PRESET_SLOTS: usize = 8;
+const PRESET_SLOT_SIZE: usize = 32;
+/// The flash used by the presets, from the start of the user area.
+pub const PRESETS_SIZE: usize = PRESET_SLOTS * PRESET_SLOT_SIZE;
+const PRESET_NAMES: [&str; PRESET_SLOTS] = [
+    "MPE 1", "MPE 2", "MPE 3", "MPE 4", "MPE 5", "MPE 6", "MPE 7", "MPE 8",
+];
+
+/// Buttons recording, overdubbing and playing a loop, undoing the last layer, stopping or
+/// clearing the loop, and quantising its playback to sixteenth notes at the clock's tempo. Hold
+/// Shift and press the record button to sync the loop to the clock.
+const RECORD: u8 = 93;
+const UNDO: u8 = 94;
+const STOP: u8 = 95;
+const QUANTISE: u8 = 96;
+const LOOPER_BUTTONS: [u8; 4] = [RECORD, UNDO, STOP, QUANTISE];
+const LOOP_EVENTS: usize = 128;
+
+impl State {
+    /// Create the app.
+    const fn new() -> Self {
+        Self {
+            diamond: Diamond::new(),
+            mpe: VoiceManager::new(),
+            pads: None,
+            init: None,
+            timers: Scheduler::new(),
+            arp: Arpeggiator::new(),
+            clock: Clock::new(120),
+            presets: Presets::new(0, PRESET_SLOT_SIZE),
+            shift: false,
+            looper: Looper::new(),
+        }
+    }
+
+    /// The settings saved in a preset
+    fn settings(&self) -> MpeSettings {
+        MpeSettings {
+            base_note: self.diamond.base_note(),
+            pitch_bend_range: self.diamond.pitch_bend_range(),
+            zone: self.mpe.zone(),
+            voice_count: self.mpe.voice_count(),
+        }
+    }
+
+    /// Apply the settings from a preset, returning whether they were applied. Ignored while notes
+    /// are sounding.
+    fn apply_settings(&mut self, settings: MpeSettings) -> bool {
+        if !self.mpe.is_idle() || self.arp.is_playing() {
+            return false;
+        }
+        self.diamond.set_base_note(settings.base_note);
+        self.diamond.set_pitch_bend_range(settings.pitch_bend_range);
+        self.mpe.configure(settings.zone, settings.voice_count);
+        self.mpe.init_mpe(settings.pitch_bend_range);
+        true
+    }
+
+    /// Save the current settings to a slot, or recall the preset in it
+    fn preset_button(&mut self, slot: usize) {
+        if self.shift {
+            let preset = Preset::new(PRESET_NAMES[slot], self.settings());
+            let _ = self.presets.save(slot, preset);
+        } else if let Some(preset) = self.presets.get(slot) {
+            if self.apply_settings(preset.value) {
+                self.presets.select(slot);
+            }
+        }
+        self.draw_presets();
+    }
+
+    /// Release every held pad, ending its note.
+    fn release_pads(&mut self) {
+        for x in 1..=8 {
+            for y in 1..=8 {
+                self.pad_event(Point::new(x, y), surface::Event::Release);
+            }
+        }
+    }
+
+    /// Light the scene buttons of occupied preset slots, top to bottom
+    fn draw_presets(&self) {
+        self.presets
+            .draw(|slot| Point::new(9, PRESET_SLOTS as i8 - slot as i8));
+    }
+
+    /// Move the timers, the clock and the loop forward by one tick.
+    fn tick(&mut self) {
+        for callback in self.timers.advance(1) {
+            callback(self);
+        }
+        if let Some(event) = self.clock.tick() {
+            self.clock_event(event);
+        }
+        let mut due = heapless::Vec::<Action, LOOP_EVENTS>::new();
+        self.looper.tick(|action| {
+            let _ = due.push(*action);
+        });
+        self.play_actions(&due);
+    }
+
+    /// Play the pads, as the player does.
+    fn pad_event(&mut self, point: Point, event: surface::Event) {
+        let row = point.x() as u8 - 1;
+        let col = point.y() as u8 - 1;
+        match event {
+            surface::Event::Press(value) => {
+                let note = self.diamond.get_note(row as usize, col as usize);
+                let is_arpeggiating = self.is_arpeggiating();
+                if let Some(voice) = &mut self.mpe.take(row, col) {
+                    // Voice taken
+                    set_led(point, Rgb::new(0xff, 0xff, 0xff));
+                    voice.set_note(note);
+                    let arp_note = ArpNote {
+                        pitch: u8::from(note.midi_note()),
+                        velocity: value,
+                        voice: voice.channel(),
+                    };
+                    if is_arpeggiating {
+                        self.arp.note_on(arp_note);
+                    } else {
+                        voice.send_note_on(value);
+                    }
+                }
+            }
+            surface::Event::Release => {
+                if let Some(&mut voice) = self.mpe.release(row, col) {
+                    set_led(point, voice.rgb());
+                    let Self { arp, mpe, .. } = self;
+                    let pitch = u8::from(voice.note().midi_note());
+                    if arp.contains(pitch, voice.channel()) {
+                        arp.note_off(pitch, voice.channel(), &mut Self::arp_output(mpe));
+                    } else {
+                        voice.send_note_off(0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the pressure on a held pad to the voice it plays.
+    fn pressure_event(&mut self, point: Point, pressure: u8) {
+        let row = point.x() as u8 - 1;
+        let col = point.y() as u8 - 1;
+        if let Some(voice) = self.mpe.get_taken(row, col) {
+            voice.send_pressure(pressure);
+        }
+    }
+
+    /// Play back pad presses, releases and pressure from the loop.
+    fn play_actions(&mut self, actions: &[Action]) {
+        for action in actions {
+            match *action {
+                Action::Button(ButtonEvent {
+                    button: Button::Pad(point),
+                    event,
+                }) => self.pad_event(point, event),
+                Action::Aftertouch(AftertouchEvent { point, value }) => {
+                    self.pressure_event(point, value)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Handle the looper buttons.
+    fn looper_button(&mut self, button: u8) {
+        let mut released = heapless::Vec::<Action, LOOP_EVENTS>::new();
+        let release = |action: &Action| {
+            let _ = released.push(*action);
+        };
+        match button {
+            RECORD if self.shift => {
+                let sync = !self.looper.is_synced();
+                self.looper.set_sync(sync);
+            }
+            RECORD => self.looper.toggle(),
+            UNDO => self.looper.undo(release),
+            QUANTISE => {
+                let grid = match self.looper.quantise() {
+                    Some(_) => None,
+                    None => Some(60_000 / (4 * self.clock.bpm() as u32)),
+                };
+                self.looper.set_quantise(grid);
+            }
+            STOP if self.looper.mode() == Mode::Stopped => self.looper.clear(),
+            _ => self.looper.stop(release),
+        }
+        self.play_actions(&released);
+        self.draw_looper();
+    }
+
+    /// Stop the loop, ending the notes it is playing.
+    fn stop_looper(&mut self) {
+        let mut released = heapless::Vec::<Action, LOOP_EVENTS>::new();
+        self.looper.stop(|action| {
+            let _ = released.push(*action);
+        });
+        self.play_actions(&released);
+        self.draw_looper();
+    }
+
+    /// Light the record button red while recording, green while playing and orange while
+    /// overdubbing, the undo and stop buttons while there is something to undo or stop, and the
+    /// quantise button while playback is quantised.
+    fn draw_looper(&self) {
+        let record = match self.looper.mode() {
+            Mode::Recording => Rgb::new(0x3f, 0, 0),
+            Mode::Playing => Rgb::new(0, 0x3f, 0),
+            Mode::Overdubbing => Rgb::new(0x3f, 0x1f, 0),
+            Mode::Empty | Mode::Stopped => Rgb::new(0, 0, 0),
+        };
+        let level = if self.looper.layers() > 0 { 0x1f } else { 0 };
+        set_led(Point::from_index(RECORD), record);
+        set_led(Point::from_index(UNDO), Rgb::new(level, level, level));
+        set_led(Point::from_index(STOP), Rgb::new(level, level, level));
+        let quantise = match self.looper.quantise() {
+            Some(_) => Rgb::new(0, 0x3f, 0x3f),
+            None => Rgb::new(0, 0, 0),
+        };
+        set_led(Point::from_index(QUANTISE), quantise);
+    }
+
+    /// Configure MPE after a delay, replacing any configuration still waiting to be sent.
+    fn schedule_init(&mut self) {
+        if let Some(init) = self.init.take() {
+            self.timers.cancel(init);
+        }
+        self.init = self.timers.after(INIT_DELAY, Self::init_mpe).ok();
+    }
+
+    /// Send the MPE configuration message scheduled by `schedule_init`.
+    fn init_mpe(&mut self) {
+        self.init = None;
+        self.mpe.init_mpe(self.diamond.pitch_bend_range());
+    }
+
+    fn is_arpeggiating(&self) -> bool {
+        self.clock.is_running()
+    }
+
+    /// Start or stop arpeggiating held pads
+    fn toggle_arp(&mut self) {
+        let Self {
+            arp, mpe, clock, ..
+        } = self;
+        if clock.is_running() {
+            // pads held from now on play normally, so forget the arpeggiated ones
+            clock.stop();
+            arp.clear(&mut Self::arp_output(mpe));
+            arp.clock_event(ClockEvent::Stop, &mut Self::arp_output(mpe));
+        } else {
+            clock.start();
+            arp.clock_event(ClockEvent::Start, &mut Self::arp_output(mpe));
+        }
+    }
+
+    /// Shift the played notes by octaves. Ignored while notes are sounding, so that every note off
+    /// matches its note on.
+    fn shift_octave(&mut self, octaves: i8) {
+        if !self.mpe.is_idle() || self.arp.is_playing() {
+            return;
+        }
+        let mut transpose = TRANSPOSE.lock();
+        let octave =
+            (transpose.semitones() / 12 + octaves).clamp(-MAX_OCTAVE_SHIFT, MAX_OCTAVE_SHIFT);
+        transpose.set_semitones(octave * 12);
+        draw_octave(octave);
+    }
+
+    fn clock_event(&mut self, event: ClockEvent) {
+        let Self { arp, mpe, .. } = self;
+        arp.clock_event(event, &mut Self::arp_output(mpe));
+        let mut due = heapless::Vec::<Action, LOOP_EVENTS>::new();
+        self.looper.clock_event(event, |action| {
+            let _ = due.push(*action);
+        });
+        self.play_actions(&due);
+    }
+
+    /// Play arpeggiated notes on the channel of the voice holding them, keeping the voice's JI
+    /// pitch bend
+    fn arp_output(mpe: &VoiceManager) -> impl FnMut(ArpEvent) + '_ {
+        move |event| match event {
+            ArpEvent::NoteOn(note) => {
+                if let Some(voice) = mpe.get_voice_by_channel(note.voice) {
+                    voice.send_note_on_at(MidiNote::from_u8_lossy(note.pitch), note.velocity);
+                }
+            }
+            ArpEvent::NoteOff(note) => {
+                if let Some(voice) = mpe.get_voice_by_channel(note.voice) {
+                    voice.send_note_off_at(MidiNote::from_u8_lossy(note.pitch), 0);
+                }
+            }
+        }
+    }
+}
+
+/// Light the pads with the tones of the diamond.
+fn draw_pads() {
+    for (i, tones) in TONES.iter().enumerate() {
+        for (j, tone) in tones.iter().enumerate() {
+            set_led(Point::new(1 + i as i8, 1 + j as i8), tone.rgb())
+        }
+    }
+}
+
+/// Light the up or down button according to the octave shift.
+fn draw_octave(octave: i8) {
+    let level = octave.unsigned_abs() * 0x3f;
+    let (up, down) = if octave > 0 { (level, 0) } else { (0, level) };
+    set_led(Point::from_index(UP), Rgb::new(0, up, 0));
+    set_led(Point::from_index(DOWN), Rgb::new(0, down, 0));
+}
+
+#[derive(Clone, Copy)]
+pub enum Colour {
+    Black = 0x0f0f0f,
+    Red = 0xff0000,
+    Orange = 0xffa500,
+    Yellow = 0xffff00,
+    Green = 0x008000,
+    Blue = 0x0000ff,
+    Purple = 0x4b0082,
+    Magenta = 0xee82ee,
+}
+
+use self::Colour::*;
+
+pub const COLOURS: [Colour; 16] = [
+    Black, Black, Black, Red, Black, Orange, Black, Yellow, Black, Green, Black, Blue, Black,
+    Purple, Black, Magenta,
+];
+
+/// The app state, shared by the components.
+static STATE: hal::Mutex<State> = hal::Mutex::new(State::new());
+
+/// The RPC command that recalls the preset in a slot, given as a `u8`.
+const RECALL_PRESET: u8 = rpc::FIRST_APP_COMMAND;
+
+/// Answers commands sent from the host.
+static RPC: rpc::Server<State, 1> = rpc::Server::new(
+    0,
+    env!("CARGO_PKG_VERSION"),
+    [(RECALL_PRESET, |state, request, _reply| {
+        let slot = request.get_u8()? as usize;
+        let preset = state.presets.get(slot).ok_or(rpc::Error::BadArguments)?;
+        let applied = state.apply_settings(preset.value);
+        if applied {
+            state.presets.select(slot);
+        }
+        state.draw_presets();
+        // presets can't be recalled while notes are sounding
+        match applied {
+            true => Ok(()),
+            false => Err(rpc::Error::Failed),
+        }
+    })],
+);
+
+/// Plays the JI diamond on the pads.
+struct Keys;
+
+impl LaunchpadApp for Keys {
+    fn init_event(&self, pads: hal::surface::Pads) {
+        hal::identity::set_app(hal::identity::App {
+            name: "MPE diamond",
+            version: env!("CARGO_PKG_VERSION"),
+            build: option_env!("BUILD_HASH").unwrap_or("dev"),
+        });
+        draw_pads();
+        let mut state = STATE.lock();
+        state.diamond.update_notes();
+        state.mpe.configure(MPEZone::Lower, MAX_VOICES as u8);
+        state.schedule_init();
+        state.pads = Some(pads)
+    }
+
+    fn cable_event(&self, cable_event: hal::midi::CableEvent) {
+        if let hal::midi::CableEvent::Connect(_cable) = cable_event {
+            STATE.lock().schedule_init()
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        let point = match button_event.button {
+            surface::Button::Pad(point) => point,
+            surface::Button::Setup => return,
+        };
+        let mut state = STATE.lock();
+        state.looper.capture(Action::Button(button_event));
+        state.pad_event(point, button_event.event);
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: hal::surface::AftertouchEvent) {
+        let mut state = STATE.lock();
+        state.looper.capture(Action::Aftertouch(aftertouch_event));
+        state.pressure_event(aftertouch_event.point, aftertouch_event.value);
+    }
+
+    fn suspend_event(&self) {
+        STATE.lock().release_pads();
+    }
+
+    fn resume_event(&self) {
+        draw_pads();
+    }
+}
+
+/// Shifts the octave with the up and down buttons.
+struct Octave;
+
+impl LaunchpadApp for Octave {
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        if let (surface::Button::Pad(point), surface::Event::Press(_)) =
+            (button_event.button, button_event.event)
+        {
+            let octaves = if point.to_index() == UP { 1 } else { -1 };
+            STATE.lock().shift_octave(octaves);
+        }
+    }
+
+    fn resume_event(&self) {
+        draw_octave(TRANSPOSE.lock().semitones() / 12);
+    }
+}
+
+/// Saves and recalls presets with the scene buttons and Shift, and backs them up over SysEx.
+struct PresetButtons;
+
+impl LaunchpadApp for PresetButtons {
+    fn init_event(&self, _pads: hal::surface::Pads) {
+        let mut state = STATE.lock();
+        state.presets.load();
+        state.draw_presets();
+    }
+
+    fn sysex_event(&self, port: hal::midi::Port, data: &[u8]) {
+        let mut state = STATE.lock();
+        let reply = |reply: &[u8]| hal::midi::send_sysex(port, reply);
+        if RPC.handle(&mut state, data, reply) {
+            return;
+        }
+        if let Ok(Some(_)) = state.presets.sysex(data, reply) {
+            state.draw_presets();
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        let point = match button_event.button {
+            surface::Button::Pad(point) => point,
+            surface::Button::Setup => return,
+        };
+        let mut state = STATE.lock();
+        match (point.to_index(), button_event.event) {
+            (SHIFT, surface::Event::Press(_)) => state.shift = true,
+            (SHIFT, surface::Event::Release) => state.shift = false,
+            (_, surface::Event::Press(_)) => {
+                state.preset_button(PRESET_SLOTS - point.y() as usize);
+            }
+            (_, surface::Event::Release) => (),
+        }
+    }
+
+    fn suspend_event(&self) {
+        STATE.lock().shift = false;
+    }
+
+    fn resume_event(&self) {
+        STATE.lock().draw_presets();
+    }
+}
+
+/// Records and plays back loops with the buttons above the pads, and exports them over SysEx.
+struct LooperButtons;
+
+impl LaunchpadApp for LooperButtons {
+    fn init_event(&self, _pads: hal::surface::Pads) {
+        STATE.lock().draw_looper();
+    }
+
+    fn sysex_event(&self, port: hal::midi::Port, data: &[u8]) {
+        if data == looper::EXPORT_REQUEST {
+            let state = STATE.lock();
+            state
+                .looper
+                .export(|message| hal::midi::send_sysex(port, message));
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        if let (surface::Button::Pad(point), surface::Event::Press(_)) =
+            (button_event.button, button_event.event)
+        {
+            STATE.lock().looper_button(point.to_index());
+        }
+    }
+
+    fn suspend_event(&self) {
+        STATE.lock().stop_looper();
+    }
+
+    fn resume_event(&self) {
+        STATE.lock().draw_looper();
+    }
+}
+
+/// Starts and stops the arpeggiator with the Setup button and runs its clock.
+struct Transport;
+
+impl LaunchpadApp for Transport {
+    fn timer_event(&self) {
+        STATE.lock().tick();
+    }
+
+    fn midi_event(&self, _port: hal::midi::Port, midi_event: hal::midi::MidiMessage) {
+        let mut state = STATE.lock();
+        if let Some(event) = state.clock.midi_message(&midi_event) {
+            state.clock_event(event);
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        if let surface::Event::Press(_) = button_event.event {
+            STATE.lock().toggle_arp();
+        }
+    }
+
+    fn suspend_event(&self) {
+        let mut state = STATE.lock();
+        if state.is_arpeggiating() {
+            state.toggle_arp();
+        }
+    }
+}
+
+/// Shift and the scene buttons, top to bottom.
+const PRESET_BUTTONS: [u8; PRESET_SLOTS + 1] = [SHIFT, 89, 79, 69, 59, 49, 39, 29, 19];
+
+/// Route events from the hardware to the components of the app.
+pub static APP: Router<5> = Router::new([
+    (Region::PADS, &Keys),
+    (Region::Buttons(&[UP, DOWN]), &Octave),
+    (Region::Buttons(&LOOPER_BUTTONS), &LooperButtons),
+    (Region::Buttons(&PRESET_BUTTONS), &PresetButtons),
+    (Region::Setup, &Transport),
+]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(button: surface::Button) {
+        APP.button_event(ButtonEvent {
+            button,
+            event: Event::Press(127),
+        });
+        APP.button_event(ButtonEvent {
+            button,
+            event: Event::Release,
+        });
+    }
+
+    #[test]
+    fn buttons_reach_their_components() {
+        let adc = [0u16; 64];
+        APP.init_event(Pads::new(adc.as_ptr()));
+
+        // MPE is configured once the synth has had time to start
+        let init = STATE.lock().init.unwrap();
+        for _ in 0..INIT_DELAY {
+            APP.timer_event();
+        }
+        assert!(!STATE.lock().timers.is_scheduled(init));
+        assert!(STATE.lock().init.is_none());
+
+        press(Button::Pad(Point::from_index(UP)));
+        assert_eq!(TRANSPOSE.lock().semitones(), 12);
+        press(Button::Pad(Point::from_index(DOWN)));
+        assert_eq!(TRANSPOSE.lock().semitones(), 0);
+
+        press(Button::Setup);
+        assert!(STATE.lock().is_arpeggiating());
+        press(Button::Setup);
+        assert!(!STATE.lock().is_arpeggiating());
+
+        // a pad takes a voice until it is released
+        APP.button_event(ButtonEvent {
+            button: Button::Pad(Point::new(3, 3)),
+            event: Event::Press(127),
+        });
+        assert!(!STATE.lock().mpe.is_idle());
+        APP.button_event(ButtonEvent {
+            button: Button::Pad(Point::new(3, 3)),
+            event: Event::Release,
+        });
+        assert!(STATE.lock().mpe.is_idle());
+    }
+
+    #[test]
+    fn pads_are_looped() {
+        let mut state = State::new();
+        state.mpe.configure(MPEZone::Lower, MAX_VOICES as u8);
+        let pad = Point::new(2, 5);
+        state.looper_button(RECORD);
+        for event in [Event::Press(100), Event::Release] {
+            state.looper.capture(Action::Button(ButtonEvent {
+                button: Button::Pad(pad),
+                event,
+            }));
+            state.pad_event(pad, event);
+            for _ in 0..200 {
+                state.tick();
+            }
+        }
+        state.looper_button(RECORD);
+        assert_eq!(state.looper.mode(), Mode::Playing);
+
+        // the loop plays the pad, and stopping it releases the voice
+        for _ in 0..100 {
+            state.tick();
+        }
+        assert!(!state.mpe.is_idle());
+        state.looper_button(STOP);
+        assert!(state.mpe.is_idle());
+        state.looper_button(STOP);
+        assert_eq!(state.looper.mode(), Mode::Empty);
+    }
+
+    #[test]
+    fn pressure_is_looped() {
+        use hal::midi::MidiMessage;
+
+        let mut state = State::new();
+        state.mpe.configure(MPEZone::Lower, MAX_VOICES as u8);
+        let pad = Point::new(3, 4);
+        let button = |event| {
+            Action::Button(ButtonEvent {
+                button: Button::Pad(pad),
+                event,
+            })
+        };
+        let touch = |value| Action::Aftertouch(AftertouchEvent { point: pad, value });
+        state.looper_button(RECORD);
+        for (time, action) in [
+            (0, button(Event::Press(90))),
+            (50, touch(40)),
+            (100, touch(80)),
+            (150, button(Event::Release)),
+        ] {
+            while state.looper.position() < time {
+                state.tick();
+            }
+            state.looper.capture(action);
+            state.play_actions(&[action]);
+        }
+        while state.looper.position() < 200 {
+            state.tick();
+        }
+        state.looper_button(RECORD);
+
+        // the next pass plays the note with the pressure it was recorded with
+        hal::capture::start();
+        for _ in 0..200 {
+            state.tick();
+        }
+        let sent: Vec<_> = hal::capture::stop()
+            .iter()
+            .filter(|captured| captured.port == hal::midi::Port::USB)
+            .filter_map(|captured| match captured.message()? {
+                MidiMessage::NoteOn(..) => Some("note on"),
+                MidiMessage::ChannelPressure(_, pressure) => match u8::from(pressure) {
+                    40 => Some("pressure 40"),
+                    80 => Some("pressure 80"),
+                    _ => None,
+                },
+                MidiMessage::NoteOff(..) => Some("note off"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, ["note on", "pressure 40", "pressure 80", "note off"]);
+    }
+
+    #[test]
+    fn quantise_button_toggles_the_grid() {
+        let mut state = State::new();
+        state.looper_button(QUANTISE);
+        // a sixteenth note at 120 BPM
+        assert_eq!(state.looper.quantise(), Some(125));
+        state.looper_button(QUANTISE);
+        assert_eq!(state.looper.quantise(), None);
+    }
+
+    #[test]
+    fn empty_presets_cannot_be_recalled_over_rpc() {
+        let mut client = rpc::Client::new(0);
+        let mut result = None;
+        let request = client.request(RECALL_PRESET, &[3]);
+        let handled = RPC.handle(&mut State::new(), &request, |reply| {
+            result = client.reply(reply)
+        });
+        assert!(handled);
+        assert_eq!(result, Some(Err(rpc::Error::BadArguments)));
+    }
+
+    #[test]
+    fn presets_are_not_recalled_over_rpc_while_notes_sound() {
+        let mut state = State::new();
+        state.mpe.configure(MPEZone::Lower, MAX_VOICES as u8);
+        for slot in [3, 5] {
+            let preset = Preset::new(PRESET_NAMES[slot], state.settings());
+            state.presets.save(slot, preset).unwrap();
+        }
+        let mut client = rpc::Client::new(0);
+        let mut recall = |state: &mut State| {
+            let mut result = None;
+            let request = client.request(RECALL_PRESET, &[3]);
+            RPC.handle(state, &request, |reply| result = client.reply(reply));
+            result
+        };
+
+        let pad = Point::new(2, 5);
+        state.pad_event(pad, Event::Press(100));
+        assert_eq!(recall(&mut state), Some(Err(rpc::Error::Failed)));
+        assert_eq!(state.presets.current(), Some(5));
+
+        state.pad_event(pad, Event::Release);
+        assert_eq!(recall(&mut state), Some(Ok(rpc::Payload::new())));
+        assert_eq!(state.presets.current(), Some(3));
+    }
+
+    #[test]
+    fn imported_presets_are_checked() {
+        let mut exported = Presets::<MpeSettings, PRESET_SLOTS>::new(0, PRESET_SLOT_SIZE);
+        let mut settings = State::new().settings();
+        settings.pitch_bend_range = 0;
+        exported.save(2, Preset::new("bad", settings)).unwrap();
+        let mut buffer = [0; launchpad_pro_rs::presets::MAX_SYSEX_SIZE];
+        let length = exported.export(2, &mut buffer).unwrap();
+
+        let mut state = State::new();
+        let imported = state.presets.sysex(&buffer[..length], |_| {});
+        assert_eq!(imported, Err(Error::Invalid));
+        assert!(!state.presets.is_occupied(2));
+    }
+}
@@ -1,13 +1,32 @@
 //use crate::hal::{Grid, Point};
 //use super::diamond::resources;
+use super::resources::TONES;
 use super::COLOURS;
 use crate::hal::Rgb;
-use crate::resources::TONES;
 use wmidi::Note as MidiNote;
 
 // Number of tones in diamond row/column
 const DIAMOND_SIZE: usize = 8;
 
+/// The widest interval above the base note in the diamond, in semitones
+pub const MAX_SEMITONES: u8 = max_semitones();
+
+const fn max_semitones() -> u8 {
+    let mut max = 0;
+    let mut row = 0;
+    while row < DIAMOND_SIZE {
+        let mut col = 0;
+        while col < DIAMOND_SIZE {
+            if TONES[row][col].semitones > max {
+                max = TONES[row][col].semitones;
+            }
+            col += 1;
+        }
+        row += 1;
+    }
+    max
+}
+
 /// Precomputed JI tone
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Tone {
@@ -87,8 +106,13 @@ impl Diamond {
         }
     }
 
+    /// Get base note (MIDI note number)
+    pub const fn base_note(&self) -> u8 {
+        self.base_note
+    }
+
     /// Set base note (MIDI note number)
-    fn set_base_note(&mut self, note: u8) {
+    pub fn set_base_note(&mut self, note: u8) {
         self.base_note = note;
         self.update_notes()
     }
@@ -99,33 +123,31 @@ impl Diamond {
     }
 
     /// Set pitch bend range in semitones. The bigger the less precise.
-    fn set_pitch_bend_range(&mut self, range: u8) {
+    pub fn set_pitch_bend_range(&mut self, range: u8) {
         self.pitch_bend_range = range;
         self.update_notes()
     }
 
     /// Update note - called when base note or pitch bend range is changed
     pub fn update_notes(&mut self) {
-        for i in 0..8 {
-            for j in 0..8 {
-                let mut note = &mut self.notes[i][j];
-                note.midi_note = MidiNote::from_u8_lossy(self.base_note + TONES[i][j].semitones);
-                note.bend =
-                    (TONES[i][j].cents / ((self.pitch_bend_range as u32) << 19)) as u16 + 8192
+        for (notes, tones) in self.notes.iter_mut().zip(TONES.iter()) {
+            for (note, tone) in notes.iter_mut().zip(tones.iter()) {
+                note.midi_note = MidiNote::from_u8_lossy(self.base_note + tone.semitones);
+                note.bend = (tone.cents / ((self.pitch_bend_range as u32) << 19)) as u16 + 8192
             }
         }
     }
 
     /// Get note by row and column number
     pub const fn get_note(&self, row: usize, col: usize) -> Note {
-        return self.notes[row][col];
+        self.notes[row][col]
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::diamond::*;
-    use crate::resources::TONES;
+    use super::super::resources::TONES;
+    use super::*;
 
     #[test]
     fn test_tone_semitones() {
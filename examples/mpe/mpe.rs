@@ -1,12 +1,16 @@
 use super::diamond::*;
+use super::resources::TONES;
 use crate::hal::midi;
-use crate::hal::Rgb;
-use crate::resources::TONES;
-use wmidi::{Channel, ControlFunction, MidiMessage, U14, U7};
+use crate::hal::{Mutex, Rgb};
+use launchpad_pro_rs::effects::{Processor, Transpose};
+use wmidi::{Channel, ControlFunction, MidiMessage, Note as MidiNote, U14, U7};
 
 pub const MAX_VOICES: usize = 6;
 const MAX_VOICES_PLUS_ONE: usize = MAX_VOICES + 1;
 
+/// Transposition applied to every message the voices send.
+pub static TRANSPOSE: Mutex<Transpose> = Mutex::new(Transpose::new(0));
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Voice {
     note: Note,
@@ -59,7 +63,7 @@ impl Voice {
 
     /// Pitch bend sensitivity [RPN 0] - Note level, only integer number of semitones
     /// is supported.
-    pub fn pitch_bend_range_message(&self, pitch_bend_range: u8) -> Option<MidiMessage> {
+    pub fn pitch_bend_range_message(&self, pitch_bend_range: u8) -> Option<MidiMessage<'static>> {
         match self.channel {
             0 => None,
             _ => Some(MidiMessage::ControlChange(
@@ -70,33 +74,51 @@ impl Voice {
         }
     }
 
+    pub const fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    pub const fn note(&self) -> Note {
+        self.note
+    }
+
     pub fn send_note_on(&self, velocity: u8) {
+        self.send_note_on_at(self.note.midi_note(), velocity)
+    }
+
+    pub fn send_note_off(&self, velocity: u8) {
+        self.send_note_off_at(self.note.midi_note(), velocity)
+    }
+
+    /// Send a note on with the voice's pitch bend but a different MIDI note, e.g. when the
+    /// arpeggiator plays the voice in a higher octave.
+    pub fn send_note_on_at(&self, midi_note: MidiNote, velocity: u8) {
         let channel = Channel::from_index(self.channel).unwrap();
         let messages = [
             MidiMessage::PitchBendChange(channel, U14::try_from(self.note.pitch_bend()).unwrap()),
             //MidiMessage::ChannelPressure(
             //    channel, U7::try_from(velocity).unwrap()),
-            MidiMessage::NoteOn(
-                channel,
-                self.note.midi_note(),
-                U7::try_from(velocity).unwrap(),
-            ),
+            MidiMessage::NoteOn(channel, midi_note, U7::try_from(velocity).unwrap()),
         ];
         VoiceManager::send_messages(&messages)
     }
 
-    pub fn send_note_off(&self, velocity: u8) {
+    pub fn send_note_off_at(&self, midi_note: MidiNote, velocity: u8) {
         let channel = Channel::from_index(self.channel).unwrap();
-        let message = MidiMessage::NoteOff(
-            channel,
-            self.note.midi_note(),
-            U7::try_from(velocity).unwrap(),
-        );
+        let message = MidiMessage::NoteOff(channel, midi_note, U7::try_from(velocity).unwrap());
+        VoiceManager::send_message(&message)
+    }
+
+    /// Send the pressure on the voice's pad as channel pressure.
+    pub fn send_pressure(&self, pressure: u8) {
+        let channel = Channel::from_index(self.channel).unwrap();
+        let message = MidiMessage::ChannelPressure(channel, U7::from_u8_lossy(pressure));
         VoiceManager::send_message(&message)
     }
 }
 
-enum MPEZone {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MPEZone {
     Lower,
     Upper,
 }
@@ -124,12 +146,38 @@ impl VoiceManager {
         for (i, channel) in channels.iter().enumerate() {
             self.voices[i].channel = *channel;
             if *channel > 0 {
-                self.voice_queue.enqueue(i as usize).unwrap();
+                self.voice_queue.enqueue(i).unwrap();
             }
         }
         self
     }
 
+    /// Give the voices the member channels of a zone, counting up from channel 2 in the lower zone
+    /// and down from channel 15 in the upper zone. Only call while no voice is taken.
+    pub fn configure(&mut self, zone: MPEZone, voice_count: u8) -> &mut Self {
+        let voice_count = voice_count.clamp(1, MAX_VOICES as u8);
+        while self.voice_queue.dequeue().is_some() {}
+        let mut channels = [0; MAX_VOICES];
+        for (i, channel) in channels.iter_mut().take(voice_count as usize).enumerate() {
+            *channel = match zone {
+                MPEZone::Lower => 1 + i as u8,
+                MPEZone::Upper => 14 - i as u8,
+            };
+        }
+        self.zone = zone;
+        self.num_channels = voice_count;
+        self.fill_voices(&channels)
+    }
+
+    pub const fn zone(&self) -> MPEZone {
+        self.zone
+    }
+
+    /// Number of voices with a member channel
+    pub const fn voice_count(&self) -> u8 {
+        self.num_channels
+    }
+
     pub fn get_voice_mut(&mut self, index: u8) -> Option<&mut Voice> {
         let index: usize = index.into();
         if index < MAX_VOICES {
@@ -148,6 +196,13 @@ impl VoiceManager {
         }
     }
 
+    /// Find the voice that plays on a channel
+    pub fn get_voice_by_channel(&self, channel: u8) -> Option<&Voice> {
+        self.voices
+            .iter()
+            .find(|voice| voice.channel != 0 && voice.channel == channel)
+    }
+
     /// Take a new voice if available
     pub fn take(&mut self, row: u8, col: u8) -> Option<&mut Voice> {
         match self.voice_queue.dequeue() {
@@ -159,11 +214,18 @@ impl VoiceManager {
         }
     }
 
+    /// The voice playing a pad, if it is held
+    pub fn get_taken(&self, row: u8, col: u8) -> Option<&Voice> {
+        self.voices
+            .iter()
+            .find(|voice| voice.row == row && voice.col == col && voice.is_taken)
+    }
+
     pub fn release(&mut self, row: u8, col: u8) -> Option<&mut Voice> {
         for (i, v) in &mut self.voices.iter_mut().enumerate() {
             if v.row == row && v.col == col && v.is_taken {
                 v.release();
-                self.voice_queue.enqueue(i as usize).unwrap();
+                self.voice_queue.enqueue(i).unwrap();
                 self.num_taken -= 1;
                 return Some(v);
             }
@@ -172,7 +234,7 @@ impl VoiceManager {
     }
 
     /// MPE Configuration Message [RPN 6]
-    pub fn mcm_messages(&self) -> [MidiMessage; 3] {
+    pub fn mcm_messages(&self) -> [MidiMessage<'static>; 3] {
         let channel = match self.zone {
             MPEZone::Lower => Channel::Ch1,
             MPEZone::Upper => Channel::Ch16,
@@ -196,7 +258,7 @@ impl VoiceManager {
         ]
     }
 
-    pub fn mono_mode_message(&self) -> MidiMessage {
+    pub fn mono_mode_message(&self) -> MidiMessage<'static> {
         let channel = match self.zone {
             MPEZone::Lower => Channel::Ch1,
             MPEZone::Upper => Channel::Ch16,
@@ -208,7 +270,7 @@ impl VoiceManager {
         )
     }
 
-    pub fn poly_mode_message(&self) -> MidiMessage {
+    pub fn poly_mode_message(&self) -> MidiMessage<'static> {
         let channel = match self.zone {
             MPEZone::Lower => Channel::Ch1,
             MPEZone::Upper => Channel::Ch16,
@@ -222,7 +284,7 @@ impl VoiceManager {
 
     /// Pitch bend sensitivity [RPN 0] - Zone level, only integer number of semitones
     /// is supported.
-    pub fn pitch_bend_range_message(&self, pitch_bend_range: u8) -> MidiMessage {
+    pub fn pitch_bend_range_message(&self, pitch_bend_range: u8) -> MidiMessage<'static> {
         let channel = match self.zone {
             MPEZone::Lower => Channel::Ch1,
             MPEZone::Upper => Channel::Ch16,
@@ -234,17 +296,22 @@ impl VoiceManager {
         )
     }
 
-    fn send_message(msg: &MidiMessage) {
-        for port in [midi::Port::USB, midi::Port::DIN] {
-            midi::send_message(port, msg)
-        }
+    /// Whether any voice is currently taken.
+    pub const fn is_idle(&self) -> bool {
+        self.num_taken == 0
     }
 
-    fn send_messages(messages: &[MidiMessage]) {
-        for msg in messages {
+    fn send_message(msg: &MidiMessage<'static>) {
+        TRANSPOSE.lock().apply(msg.clone(), |msg| {
             for port in [midi::Port::USB, midi::Port::DIN] {
-                midi::send_message(port, msg)
+                midi::send_message(port, &msg)
             }
+        })
+    }
+
+    fn send_messages(messages: &[MidiMessage<'static>]) {
+        for msg in messages {
+            Self::send_message(msg)
         }
     }
 
@@ -292,6 +359,24 @@ mod tests {
         assert_eq!(v.col, 2);
     }
 
+    #[test]
+    fn manager_configure() {
+        let mut mpe = VoiceManager::new();
+        mpe.configure(MPEZone::Upper, 3);
+        assert_eq!(mpe.voice_count(), 3);
+        assert_eq!(mpe.take(0, 0).map(|v| v.channel()), Some(14));
+        assert_eq!(mpe.take(0, 1).map(|v| v.channel()), Some(13));
+        assert_eq!(mpe.take(0, 2).map(|v| v.channel()), Some(12));
+        assert_eq!(mpe.take(0, 3), None);
+
+        mpe.release(0, 0);
+        mpe.release(0, 1);
+        mpe.release(0, 2);
+        mpe.configure(MPEZone::Lower, 2);
+        assert_eq!(mpe.get_voice(2).map(|v| v.channel()), Some(0));
+        assert_eq!(mpe.take(0, 0).map(|v| v.channel()), Some(1));
+    }
+
     #[test]
     fn manager_take_no_voices() {
         let mut mpe = VoiceManager::new();
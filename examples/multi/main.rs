@@ -0,0 +1,152 @@
+#![cfg_attr(target_arch = "arm", no_std)]
+#![cfg_attr(target_arch = "arm", no_main)]
+
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
+use core::panic::PanicInfo;
+
+#[path = "../life/app.rs"]
+mod game_of_life;
+#[path = "../keys/app.rs"]
+mod keys;
+#[path = "../mpe/app.rs"]
+mod mpe_diamond;
+#[path = "../sequencer/app.rs"]
+mod sequencer;
+
+use launchpad_pro_rs::hal;
+use launchpad_pro_rs::hal::midi::{MidiMessage, Port};
+use launchpad_pro_rs::hal::{LaunchpadApp, Registration};
+use launchpad_pro_rs::launchpad_app;
+use launchpad_pro_rs::switcher::Switcher;
+
+/// Shows what the hardware reports: pads light with their velocity and pressure, and the first
+/// three buttons on the bottom row flash when MIDI arrives on the standalone, USB and DIN ports.
+struct DiagnosticsApp;
+
+impl DiagnosticsApp {
+    fn level(value: u8) -> hal::Rgb {
+        hal::Rgb::new(value * 2, value * 2, value * 2)
+    }
+}
+
+impl LaunchpadApp for DiagnosticsApp {
+    fn midi_event(&self, port: Port, _midi_event: MidiMessage) {
+        hal::surface::set_led(
+            hal::Point::new(port as i8 + 1, 0),
+            hal::Rgb::new(255, 0, 255),
+        );
+    }
+
+    fn timer_event(&self) {
+        for x in 1..=3 {
+            hal::surface::set_led(hal::Point::new(x, 0), hal::Rgb::new(16, 0, 16));
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        if let hal::surface::Button::Pad(point) = button_event.button {
+            let value = match button_event.event {
+                hal::surface::Event::Press(velocity) => velocity,
+                hal::surface::Event::Release => 0,
+            };
+            hal::surface::set_led(point, Self::level(value));
+        }
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: hal::surface::AftertouchEvent) {
+        hal::surface::set_led(aftertouch_event.point, Self::level(aftertouch_event.value));
+    }
+}
+
+/// The keyboard saves its layout after the MPE presets.
+static KEYS: Registration<keys::App> = Registration::new(keys::App::new(mpe_diamond::PRESETS_SIZE));
+static DIAGNOSTICS: DiagnosticsApp = DiagnosticsApp;
+
+/// Hold Setup and press one of the first five pads on the top row to switch app.
+static SWITCHER: Switcher<5> = Switcher::new([
+    &KEYS,
+    &game_of_life::APP,
+    &mpe_diamond::APP,
+    &sequencer::APP,
+    &DIAGNOSTICS,
+]);
+
+// Register the switcher to receive events from the hardware and pass them on.
+launchpad_app!(SWITCHER);
+
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+#[cfg(not(target_arch = "arm"))]
+fn main() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use launchpad_pro_rs::layout::{Layout, Style};
+    use wmidi::{Channel, Note};
+
+    fn send(button: hal::surface::Button, event: hal::surface::Event) {
+        SWITCHER.button_event(hal::surface::ButtonEvent { button, event });
+    }
+
+    /// Hold Setup and choose an app from the menu.
+    fn switch_to(index: i8) {
+        send(hal::surface::Button::Setup, hal::surface::Event::Press(127));
+        send(
+            hal::surface::Button::Pad(hal::Point::new(index + 1, 8)),
+            hal::surface::Event::Press(127),
+        );
+        send(hal::surface::Button::Setup, hal::surface::Event::Release);
+        assert_eq!(SWITCHER.current(), index as usize);
+    }
+
+    // Life and the MPE diamond are left alone, as their own tests, which are built into this
+    // example too, use the same statics.
+    #[test]
+    fn switching_ends_notes_and_redraws() {
+        let adc = [0u16; 64];
+        SWITCHER.init_event(hal::surface::Pads::new(adc.as_ptr()));
+        // a pad the keyboard's own tests don't play, as held notes are tracked for every test
+        let pad = hal::Point::new(4, 4);
+        let note = Note::from_u8_lossy(Layout::new(Style::FOURTHS).note(pad).unwrap());
+        let key = hal::surface::read_led(pad);
+        assert_ne!(key, Some(hal::Rgb::new(0, 0, 0)));
+
+        // hold a note on the keyboard
+        send(
+            hal::surface::Button::Pad(pad),
+            hal::surface::Event::Press(100),
+        );
+        assert!(hal::midi::is_note_held(Port::USB, Channel::Ch1, note));
+
+        // switching away lets go of the note and clears the keyboard
+        switch_to(4);
+        assert!(!hal::midi::is_note_held(Port::USB, Channel::Ch1, note));
+        assert_eq!(hal::surface::read_led(pad), Some(hal::Rgb::new(0, 0, 0)));
+
+        // the pad now shows its velocity
+        send(hal::surface::Button::Pad(pad), hal::surface::Event::Release);
+        send(
+            hal::surface::Button::Pad(pad),
+            hal::surface::Event::Press(64),
+        );
+        assert_eq!(hal::surface::read_led(pad), Some(DiagnosticsApp::level(64)));
+        send(hal::surface::Button::Pad(pad), hal::surface::Event::Release);
+
+        // the sequencer and the keyboard redraw when shown again
+        switch_to(3);
+        switch_to(0);
+        assert_eq!(hal::surface::read_led(pad), key);
+        send(
+            hal::surface::Button::Pad(pad),
+            hal::surface::Event::Press(100),
+        );
+        assert!(hal::midi::is_note_held(Port::USB, Channel::Ch1, note));
+        send(hal::surface::Button::Pad(pad), hal::surface::Event::Release);
+        assert!(!hal::midi::is_note_held(Port::USB, Channel::Ch1, note));
+    }
+}
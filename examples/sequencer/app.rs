@@ -0,0 +1,453 @@
+use launchpad_pro_rs::clock::{Clock, ClockEvent, Source};
+use launchpad_pro_rs::hal;
+use launchpad_pro_rs::hal::midi::{MidiMessage, Port};
+use launchpad_pro_rs::hal::LaunchpadApp;
+use launchpad_pro_rs::sequencer::{Sequencer, Trigger, LANES};
+use wmidi::Channel;
+
+/// The number of steps shown on the grid at once.
+const STEPS_PER_PAGE: u8 = 8;
+/// The notes played by each lane, from the bottom row of pads to the top.
+const LANE_NOTES: [u8; LANES] = [60, 62, 64, 65, 67, 69, 71, 72];
+
+/// Indices of the border buttons used by the sequencer.
+const UP: u8 = 91;
+const DOWN: u8 = 92;
+const LEFT: u8 = 93;
+const RIGHT: u8 = 94;
+const SESSION: u8 = 95;
+const SHIFT: u8 = 80;
+const TRIGGER_MODE: u8 = 1;
+const GATE_MODE: u8 = 2;
+const PROBABILITY_MODE: u8 = 3;
+
+/// What pressing a pad edits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Pads toggle triggers on and off.
+    Trigger,
+    /// The row of a pad sets the gate length of the triggers in its step.
+    Gate,
+    /// The row of a pad sets the probability of the triggers in its step.
+    Probability,
+}
+
+/// The Launchpad Pro app state.
+struct State {
+    /// The clock driving the sequencer.
+    clock: Clock,
+    /// Our step sequencer.
+    sequencer: Sequencer,
+    /// The page of eight steps shown on the grid.
+    page: u8,
+    /// What pressing a pad edits.
+    mode: Mode,
+    /// Whether the Shift button is held.
+    shift: bool,
+}
+
+impl State {
+    /// Create the app.
+    const fn new() -> Self {
+        Self {
+            clock: Clock::new(120),
+            sequencer: Sequencer::new(Channel::Ch1),
+            page: 0,
+            mode: Mode::Trigger,
+            shift: false,
+        }
+    }
+
+    /// Send a MIDI message produced by the sequencer to USB and DIN.
+    fn send(message: MidiMessage<'static>) {
+        for port in [Port::USB, Port::DIN] {
+            hal::midi::send_message(port, &message);
+        }
+    }
+
+    /// Pass a clock event to the sequencer, redrawing the grid if the playhead moved.
+    fn clock_event(&mut self, event: ClockEvent) {
+        let playhead = self.sequencer.playhead();
+        self.sequencer.clock_event(event, &mut Self::send);
+        if playhead != self.sequencer.playhead() {
+            self.draw();
+        }
+    }
+
+    /// Start or stop the internal clock.
+    fn toggle_is_running(&mut self) {
+        if self.clock.is_running() {
+            self.clock.stop();
+            self.clock_event(ClockEvent::Stop);
+        } else {
+            self.clock.start();
+            self.clock_event(ClockEvent::Start);
+        }
+    }
+
+    /// Switch between the internal clock and incoming MIDI clock.
+    fn toggle_source(&mut self) {
+        self.clock.stop();
+        self.clock_event(ClockEvent::Stop);
+        self.clock.set_source(match self.clock.source() {
+            Source::Internal => Source::External,
+            Source::External => Source::Internal,
+        });
+        self.draw();
+    }
+
+    /// Change the tempo of the internal clock, by ten BPM at a time while Shift is held.
+    fn change_bpm(&mut self, delta: i16) {
+        let delta = if self.shift { delta * 10 } else { delta };
+        let bpm = (self.clock.bpm() as i16 + delta).max(1);
+        self.clock.set_bpm(bpm as u16);
+    }
+
+    /// Returns the number of pages needed to show the whole pattern.
+    fn pages(&self) -> u8 {
+        self.sequencer.pattern().length().div_ceil(STEPS_PER_PAGE)
+    }
+
+    /// Move to another page, staying within the pattern.
+    fn change_page(&mut self, delta: i8) {
+        let page = self.page as i8 + delta;
+        if page >= 0 && (page as u8) < self.pages() {
+            self.page = page as u8;
+            self.draw();
+        }
+    }
+
+    /// Edit the step under a pad according to the current mode.
+    fn press_pad(&mut self, point: hal::Point, velocity: u8) {
+        let step = self.page * STEPS_PER_PAGE + point.x() as u8 - 1;
+        let lane = point.y() as u8 - 1;
+
+        if self.shift {
+            self.sequencer.pattern_mut().set_length(step + 1);
+            self.page = self.page.min(self.pages() - 1);
+            self.draw();
+            return;
+        }
+
+        let pulses_per_step = self.sequencer.pulses_per_step();
+        let pattern = self.sequencer.pattern_mut();
+        match self.mode {
+            Mode::Trigger => {
+                let trigger = match pattern.get(step, lane) {
+                    Some(_) => None,
+                    None => Some(Trigger::new(LANE_NOTES[lane as usize], velocity)),
+                };
+                pattern.set(step, lane, trigger);
+            }
+            Mode::Gate => {
+                // each row is a quarter of a step, so the top row holds for two steps
+                let gate = ((lane + 1) * pulses_per_step / 4).max(1);
+                Self::edit_step(pattern, step, |trigger| trigger.gate = gate);
+            }
+            Mode::Probability => {
+                let probability = (lane + 1) * 100 / LANES as u8;
+                Self::edit_step(pattern, step, |trigger| trigger.probability = probability);
+            }
+        }
+        self.draw();
+    }
+
+    /// Apply a change to every trigger in a step.
+    fn edit_step(
+        pattern: &mut launchpad_pro_rs::sequencer::Pattern,
+        step: u8,
+        edit: impl Fn(&mut Trigger),
+    ) {
+        for lane in 0..LANES as u8 {
+            if let Some(mut trigger) = pattern.get(step, lane) {
+                edit(&mut trigger);
+                pattern.set(step, lane, Some(trigger));
+            }
+        }
+    }
+
+    /// Returns the number of rows to light for a step in the gate and probability modes.
+    fn step_level(&self, step: u8) -> u8 {
+        let pulses_per_step = self.sequencer.pulses_per_step() as u16;
+        self.sequencer
+            .pattern()
+            .triggers(step)
+            .map(|trigger| match self.mode {
+                Mode::Gate => (trigger.gate as u16 * 4 / pulses_per_step) as u8,
+                _ => (trigger.probability as u16 * LANES as u16 / 100) as u8,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Draw the visible page of the pattern, the playhead and the controls.
+    fn draw(&self) {
+        let pattern = self.sequencer.pattern();
+        for x in 1..=STEPS_PER_PAGE {
+            let step = self.page * STEPS_PER_PAGE + x - 1;
+            let is_playhead =
+                self.sequencer.is_playing() && self.sequencer.playhead() == Some(step);
+            for y in 1..=LANES as u8 {
+                let colour = if step >= pattern.length() {
+                    hal::Rgb::new(0, 0, 0)
+                } else if self.mode == Mode::Trigger {
+                    match pattern.get(step, y - 1) {
+                        Some(trigger) => hal::Rgb::new(0, 64 + trigger.velocity, 0),
+                        None if is_playhead => hal::Rgb::new(64, 64, 64),
+                        None => hal::Rgb::new(0, 0, 0),
+                    }
+                } else if y <= self.step_level(step) {
+                    hal::Rgb::new(0, 64, 255)
+                } else if is_playhead {
+                    hal::Rgb::new(64, 64, 64)
+                } else {
+                    hal::Rgb::new(0, 0, 0)
+                };
+                hal::surface::set_led(hal::Point::new(x as i8, y as i8), colour);
+            }
+        }
+
+        // the scene buttons show the pages of the pattern, top to bottom
+        for page in 0..8 {
+            let colour = if page == self.page {
+                hal::Rgb::new(255, 255, 255)
+            } else if page < self.pages() {
+                hal::Rgb::new(32, 32, 32)
+            } else {
+                hal::Rgb::new(0, 0, 0)
+            };
+            hal::surface::set_led(hal::Point::new(9, 8 - page as i8), colour);
+        }
+
+        for (index, mode) in [
+            (TRIGGER_MODE, Mode::Trigger),
+            (GATE_MODE, Mode::Gate),
+            (PROBABILITY_MODE, Mode::Probability),
+        ] {
+            let colour = if self.mode == mode {
+                hal::Rgb::new(255, 127, 0)
+            } else {
+                hal::Rgb::new(32, 16, 0)
+            };
+            hal::surface::set_led(hal::Point::from_index(index), colour);
+        }
+
+        // the tempo only applies to the internal clock
+        for index in [UP, DOWN] {
+            let colour = match self.clock.source() {
+                Source::Internal => hal::Rgb::new(0, 64, 64),
+                Source::External => hal::Rgb::new(0, 0, 0),
+            };
+            hal::surface::set_led(hal::Point::from_index(index), colour);
+        }
+
+        hal::surface::set_led(
+            hal::Point::from_index(SESSION),
+            match self.clock.source() {
+                Source::Internal => hal::Rgb::new(0, 0, 64),
+                Source::External => hal::Rgb::new(0, 0, 255),
+            },
+        );
+    }
+}
+
+/// The step sequencer app.
+pub struct App {
+    state: hal::Mutex<State>,
+}
+
+impl App {
+    const fn new() -> Self {
+        Self {
+            state: hal::Mutex::new(State::new()),
+        }
+    }
+}
+
+/// Implement the LaunchpadApp trait for our app in order to be notified of events that occur on
+/// the Launchpad Pro hardware.
+impl LaunchpadApp for App {
+    fn init_event(&self, _pads: hal::surface::Pads) {
+        self.state.lock().draw();
+    }
+
+    fn timer_event(&self) {
+        let mut state = self.state.lock();
+        if let Some(event) = state.clock.tick() {
+            state.clock_event(event);
+        }
+    }
+
+    fn midi_event(&self, _port: Port, midi_event: MidiMessage) {
+        let mut state = self.state.lock();
+        if let Some(event) = state.clock.midi_message(&midi_event) {
+            state.clock_event(event);
+        }
+    }
+
+    fn button_event(&self, button_event: hal::surface::ButtonEvent) {
+        let mut state = self.state.lock();
+
+        match (button_event.button, button_event.event) {
+            (hal::surface::Button::Setup, hal::surface::Event::Press(_)) => {
+                state.toggle_is_running();
+            }
+            (hal::surface::Button::Pad(point), event) => match (point.to_index(), event) {
+                (SHIFT, hal::surface::Event::Press(_)) => state.shift = true,
+                (SHIFT, hal::surface::Event::Release) => state.shift = false,
+                (_, hal::surface::Event::Release) => (),
+                (UP, _) => state.change_bpm(1),
+                (DOWN, _) => state.change_bpm(-1),
+                (LEFT, _) => state.change_page(-1),
+                (RIGHT, _) => state.change_page(1),
+                (SESSION, _) => state.toggle_source(),
+                (TRIGGER_MODE, _) => {
+                    state.mode = Mode::Trigger;
+                    state.draw();
+                }
+                (GATE_MODE, _) => {
+                    state.mode = Mode::Gate;
+                    state.draw();
+                }
+                (PROBABILITY_MODE, _) => {
+                    state.mode = Mode::Probability;
+                    state.draw();
+                }
+                (_, hal::surface::Event::Press(velocity)) => {
+                    if (1..=8).contains(&point.x()) && (1..=8).contains(&point.y()) {
+                        state.press_pad(point, velocity);
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn suspend_event(&self) {
+        // timer events stop while another app is shown, so stop the clock too
+        let mut state = self.state.lock();
+        if state.clock.is_running() {
+            state.toggle_is_running();
+        }
+        state.shift = false;
+    }
+
+    fn resume_event(&self) {
+        self.state.lock().draw();
+    }
+}
+
+/// Create a static instance of our app.
+pub static APP: App = App::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(app: &App, index: u8) {
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Pad(hal::Point::from_index(index)),
+            event: hal::surface::Event::Press(100),
+        });
+    }
+
+    fn release(app: &App, index: u8) {
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Pad(hal::Point::from_index(index)),
+            event: hal::surface::Event::Release,
+        });
+    }
+
+    #[test]
+    fn pads_edit_the_visible_page() {
+        let app = App::new();
+
+        // the bottom left pad is the first lane of the first step
+        press(&app, 11);
+        assert_eq!(
+            app.state.lock().sequencer.pattern().get(0, 0),
+            Some(Trigger::new(60, 100))
+        );
+
+        // move to the second page and add a trigger to the top lane of its last step
+        press(&app, RIGHT);
+        press(&app, 88);
+        assert_eq!(app.state.lock().page, 1);
+        assert_eq!(
+            app.state.lock().sequencer.pattern().get(15, 7),
+            Some(Trigger::new(72, 100))
+        );
+
+        // holding shift and pressing a pad sets the pattern length
+        press(&app, SHIFT);
+        press(&app, 13);
+        release(&app, SHIFT);
+        assert_eq!(app.state.lock().sequencer.pattern().length(), 11);
+
+        // the page can't move past the end of the pattern
+        press(&app, RIGHT);
+        assert_eq!(app.state.lock().page, 1);
+
+        // set the gate of the first step to a whole step
+        press(&app, LEFT);
+        press(&app, GATE_MODE);
+        press(&app, 41);
+        assert_eq!(
+            app.state.lock().sequencer.pattern().get(0, 0).unwrap().gate,
+            6
+        );
+    }
+
+    #[test]
+    fn setup_button_starts_the_sequencer() {
+        let app = App::new();
+        press(&app, 11);
+        assert!(!app.state.lock().sequencer.is_playing());
+
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Setup,
+            event: hal::surface::Event::Press(127),
+        });
+        assert!(app.state.lock().sequencer.is_playing());
+
+        // a sixteenth note at 120 BPM lasts 125 ms
+        app.timer_event();
+        assert_eq!(app.state.lock().sequencer.playhead(), Some(0));
+        for _ in 0..125 {
+            app.timer_event();
+        }
+        assert_eq!(app.state.lock().sequencer.playhead(), Some(1));
+    }
+
+    #[test]
+    fn up_and_down_change_the_tempo() {
+        let app = App::new();
+        press(&app, UP);
+        assert_eq!(app.state.lock().clock.bpm(), 121);
+        press(&app, DOWN);
+        press(&app, DOWN);
+        assert_eq!(app.state.lock().clock.bpm(), 119);
+
+        // shift moves in tens, and the tempo stays within the clock's range
+        press(&app, SHIFT);
+        press(&app, UP);
+        assert_eq!(app.state.lock().clock.bpm(), 129);
+        for _ in 0..20 {
+            press(&app, DOWN);
+        }
+        release(&app, SHIFT);
+        assert_eq!(app.state.lock().clock.bpm(), 1);
+
+        // a sixteenth note at 150 BPM lasts 100 ms
+        app.state.lock().clock.set_bpm(150);
+        app.button_event(hal::surface::ButtonEvent {
+            button: hal::surface::Button::Setup,
+            event: hal::surface::Event::Press(127),
+        });
+        app.timer_event();
+        for _ in 0..100 {
+            app.timer_event();
+        }
+        assert_eq!(app.state.lock().sequencer.playhead(), Some(1));
+    }
+}
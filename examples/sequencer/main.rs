@@ -0,0 +1,21 @@
+#![cfg_attr(target_arch = "arm", no_std)]
+#![cfg_attr(target_arch = "arm", no_main)]
+
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
+use core::panic::PanicInfo;
+
+mod app;
+
+use launchpad_pro_rs::launchpad_app;
+
+// Register our app to receive events from the hardware.
+launchpad_app!(app::APP);
+
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+#[cfg(not(target_arch = "arm"))]
+fn main() {}
@@ -0,0 +1,607 @@
+use crate::clock::ClockEvent;
+use wmidi::{Channel, MidiMessage, Note, U7};
+
+/// The maximum number of notes that can be held at once.
+pub const MAX_NOTES: usize = 16;
+
+/// The order that held notes are played in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// Lowest to highest.
+    Up,
+    /// Highest to lowest.
+    Down,
+    /// Lowest to highest and back again, without repeating the end notes.
+    UpDown,
+    /// A random held note on every step.
+    Random,
+    /// The order the notes were pressed in.
+    AsPlayed,
+}
+
+/// The rate of the arpeggiator as a note division, synced to a 24 PPQN clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rate {
+    Quarter,
+    Eighth,
+    EighthTriplet,
+    Sixteenth,
+    SixteenthTriplet,
+    ThirtySecond,
+}
+
+impl Rate {
+    /// Returns the number of clock pulses per note at this rate.
+    pub const fn pulses(&self) -> u8 {
+        match self {
+            Rate::Quarter => 24,
+            Rate::Eighth => 12,
+            Rate::EighthTriplet => 8,
+            Rate::Sixteenth => 6,
+            Rate::SixteenthTriplet => 4,
+            Rate::ThirtySecond => 3,
+        }
+    }
+}
+
+/// A note held on the arpeggiator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpNote {
+    /// The MIDI note number.
+    pub pitch: u8,
+    /// The MIDI velocity.
+    pub velocity: u8,
+    /// A value chosen by the app to tell notes apart, e.g. a MIDI channel or an MPE voice.
+    pub voice: u8,
+}
+
+impl ArpNote {
+    /// Returns a note on message for this note on the channel given by its voice. Voices that are
+    /// not a valid channel index play on channel 1.
+    pub fn note_on(&self) -> MidiMessage<'static> {
+        MidiMessage::NoteOn(
+            self.channel(),
+            Note::from_u8_lossy(self.pitch),
+            U7::from_u8_lossy(self.velocity.max(1)),
+        )
+    }
+
+    /// Returns a note off message for this note on the channel given by its voice.
+    pub fn note_off(&self) -> MidiMessage<'static> {
+        MidiMessage::NoteOff(self.channel(), Note::from_u8_lossy(self.pitch), U7::MIN)
+    }
+
+    fn channel(&self) -> Channel {
+        Channel::from_index(self.voice).unwrap_or(Channel::Ch1)
+    }
+}
+
+/// The notes started and stopped by the arpeggiator. Pitches include the octave offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpEvent {
+    NoteOn(ArpNote),
+    NoteOff(ArpNote),
+}
+
+impl ArpEvent {
+    /// Returns the MIDI message for this event, using the voice of the note as its channel.
+    pub fn to_midi(&self) -> MidiMessage<'static> {
+        match self {
+            ArpEvent::NoteOn(note) => note.note_on(),
+            ArpEvent::NoteOff(note) => note.note_off(),
+        }
+    }
+}
+
+/// A held note along with whether its key is still physically down.
+#[derive(Clone, Copy, Debug)]
+struct Held {
+    note: ArpNote,
+    is_down: bool,
+}
+
+/// An arpeggiator that plays back held notes in time with a clock.
+///
+/// Like the sequencer, the arpeggiator is advanced with `ClockEvent`s and reports the notes it
+/// plays through a callback. The notes carry a `voice` so that apps can route each one, for
+/// example to the MPE channel of the pad that is holding it.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::arpeggiator::{Arpeggiator, ArpEvent, ArpNote, Order};
+/// use launchpad_pro_rs::clock::ClockEvent;
+/// use launchpad_pro_rs::hal::midi::{send_message, Port};
+///
+/// let mut arp = Arpeggiator::new();
+/// arp.set_order(Order::UpDown);
+/// arp.note_on(ArpNote { pitch: 60, velocity: 100, voice: 0 });
+/// arp.note_on(ArpNote { pitch: 64, velocity: 100, voice: 0 });
+///
+/// let mut send = |event: ArpEvent| send_message(Port::USB, &event.to_midi());
+/// arp.clock_event(ClockEvent::Start, &mut send);
+/// arp.clock_event(ClockEvent::Pulse, &mut send);
+/// ```
+pub struct Arpeggiator {
+    held: heapless::Vec<Held, MAX_NOTES>,
+    order: Order,
+    octaves: u8,
+    rate: Rate,
+    gate: u8,
+    latch: bool,
+    is_playing: bool,
+    pulse: u8,
+    step: u16,
+    sounding: Option<(ArpNote, u8)>,
+    seed: u32,
+}
+
+impl Arpeggiator {
+    /// Construct a stopped arpeggiator that plays upwards in sixteenth notes over one octave.
+    pub const fn new() -> Self {
+        Arpeggiator {
+            held: heapless::Vec::new(),
+            order: Order::Up,
+            octaves: 1,
+            rate: Rate::Sixteenth,
+            gate: 50,
+            latch: false,
+            is_playing: false,
+            pulse: 0,
+            step: 0,
+            sounding: None,
+            seed: 0x1234_5678,
+        }
+    }
+
+    /// Set the order that held notes are played in.
+    pub fn set_order(&mut self, order: Order) {
+        self.order = order;
+    }
+
+    /// Set the number of octaves the held notes are repeated over. The range is clamped to
+    /// `[1, 4]`.
+    pub fn set_octaves(&mut self, octaves: u8) {
+        self.octaves = octaves.clamp(1, 4);
+    }
+
+    /// Set the note division the arpeggiator plays at.
+    pub fn set_rate(&mut self, rate: Rate) {
+        self.rate = rate;
+    }
+
+    /// Set how long each note is held for as a percentage of the rate. The gate is clamped to
+    /// `[1, 100]`.
+    pub fn set_gate(&mut self, gate: u8) {
+        self.gate = gate.clamp(1, 100);
+    }
+
+    /// Seed the random number generator used by `Order::Random`.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed.max(1);
+    }
+
+    /// Returns true if notes are latched after their keys are released.
+    pub fn is_latched(&self) -> bool {
+        self.latch
+    }
+
+    /// Enable or disable latch mode. With latch enabled, released notes keep playing until a new
+    /// note is pressed while no keys are down. Disabling latch drops the notes whose keys are up.
+    pub fn set_latch(&mut self, latch: bool, output: &mut impl FnMut(ArpEvent)) {
+        self.latch = latch;
+        if !latch {
+            self.held.retain(|held| held.is_down);
+            self.stop_sounding_if_released(output);
+        }
+    }
+
+    /// Returns true if the arpeggiator is playing.
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    /// Returns the number of notes being arpeggiated.
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Returns true if no notes are being arpeggiated.
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Returns true if a note is being arpeggiated.
+    pub fn contains(&self, pitch: u8, voice: u8) -> bool {
+        self.find(pitch, voice).is_some()
+    }
+
+    /// Add a note to the arpeggiator. Notes beyond the capacity of the arpeggiator are ignored.
+    pub fn note_on(&mut self, note: ArpNote) {
+        if self.latch && !self.held.iter().any(|held| held.is_down) {
+            // a new chord replaces the latched one
+            self.held.clear();
+        }
+
+        match self.find(note.pitch, note.voice) {
+            Some(index) => {
+                self.held[index] = Held {
+                    note,
+                    is_down: true,
+                }
+            }
+            None => {
+                let _ = self.held.push(Held {
+                    note,
+                    is_down: true,
+                });
+            }
+        }
+    }
+
+    /// Release a note. Unless latch mode is enabled the note is removed, and ended immediately if
+    /// it is the one sounding.
+    pub fn note_off(&mut self, pitch: u8, voice: u8, output: &mut impl FnMut(ArpEvent)) {
+        if let Some(index) = self.find(pitch, voice) {
+            if self.latch {
+                self.held[index].is_down = false;
+            } else {
+                self.held.remove(index);
+                self.stop_sounding_if_released(output);
+            }
+        }
+    }
+
+    /// Hold or release notes from an incoming MIDI message. The channel of the message is used as
+    /// the voice, and a note on with a velocity of zero releases the note.
+    pub fn midi_message(&mut self, message: &MidiMessage, output: &mut impl FnMut(ArpEvent)) {
+        match message {
+            MidiMessage::NoteOn(channel, note, velocity) if u8::from(*velocity) == 0 => {
+                self.note_off(u8::from(*note), channel.index(), output)
+            }
+            MidiMessage::NoteOn(channel, note, velocity) => self.note_on(ArpNote {
+                pitch: u8::from(*note),
+                velocity: u8::from(*velocity),
+                voice: channel.index(),
+            }),
+            MidiMessage::NoteOff(channel, note, _) => {
+                self.note_off(u8::from(*note), channel.index(), output)
+            }
+            _ => (),
+        }
+    }
+
+    /// Release every note, including latched ones, and end the sounding note.
+    pub fn clear(&mut self, output: &mut impl FnMut(ArpEvent)) {
+        self.held.clear();
+        self.stop(output);
+    }
+
+    /// Advance the arpeggiator with an event from a clock.
+    pub fn clock_event(&mut self, event: ClockEvent, output: &mut impl FnMut(ArpEvent)) {
+        match event {
+            ClockEvent::Start => {
+                self.stop(output);
+                self.pulse = 0;
+                self.step = 0;
+                self.is_playing = true;
+            }
+            ClockEvent::Continue => self.is_playing = true,
+            ClockEvent::Stop => {
+                self.stop(output);
+                self.is_playing = false;
+            }
+            ClockEvent::Pulse if self.is_playing => self.pulse(output),
+            ClockEvent::Pulse => (),
+        }
+    }
+
+    fn pulse(&mut self, output: &mut impl FnMut(ArpEvent)) {
+        if let Some((note, remaining)) = self.sounding {
+            if remaining <= 1 {
+                self.stop(output);
+            } else {
+                self.sounding = Some((note, remaining - 1));
+            }
+        }
+
+        if self.pulse == 0 {
+            self.stop(output);
+            if let Some(note) = self.next_note() {
+                let gate = (self.rate.pulses() as u16 * self.gate as u16 / 100).max(1) as u8;
+                self.sounding = Some((note, gate));
+                output(ArpEvent::NoteOn(note));
+            }
+        }
+
+        self.pulse += 1;
+        if self.pulse >= self.rate.pulses() {
+            self.pulse = 0;
+        }
+    }
+
+    /// End the sounding note.
+    fn stop(&mut self, output: &mut impl FnMut(ArpEvent)) {
+        if let Some((note, _)) = self.sounding.take() {
+            output(ArpEvent::NoteOff(note));
+        }
+    }
+
+    /// End the sounding note if the note it was played from is no longer held.
+    fn stop_sounding_if_released(&mut self, output: &mut impl FnMut(ArpEvent)) {
+        if let Some((note, _)) = self.sounding {
+            let is_held = self.held.iter().any(|held| {
+                held.note.voice == note.voice
+                    && note.pitch >= held.note.pitch
+                    && (note.pitch - held.note.pitch) % 12 == 0
+                    && (note.pitch - held.note.pitch) / 12 < self.octaves
+            });
+            if !is_held {
+                self.stop(output);
+            }
+        }
+    }
+
+    fn find(&self, pitch: u8, voice: u8) -> Option<usize> {
+        self.held
+            .iter()
+            .position(|held| held.note.pitch == pitch && held.note.voice == voice)
+    }
+
+    /// Returns the next note to play and advances the arpeggiator.
+    fn next_note(&mut self) -> Option<ArpNote> {
+        let count = self.held.len() as u16;
+        if count == 0 {
+            return None;
+        }
+
+        let length = count * self.octaves as u16;
+        let index = match self.order {
+            Order::Up | Order::AsPlayed => self.step % length,
+            Order::Down => length - 1 - self.step % length,
+            Order::UpDown if length < 2 => 0,
+            Order::UpDown => {
+                let period = 2 * (length - 1);
+                let position = self.step % period;
+                if position < length {
+                    position
+                } else {
+                    period - position
+                }
+            }
+            Order::Random => self.random() % length,
+        };
+        self.step = self.step.wrapping_add(1);
+
+        let octave = (index / count) as u8;
+        let index = (index % count) as usize;
+        let mut note = match self.order {
+            Order::AsPlayed => self.held[index].note,
+            _ => self.sorted_note(index),
+        };
+        note.pitch = note.pitch.saturating_add(octave * 12).min(127);
+        Some(note)
+    }
+
+    /// Returns the held note at a position when the notes are sorted by pitch. Notes with the same
+    /// pitch stay in the order they were played.
+    fn sorted_note(&self, position: usize) -> ArpNote {
+        let mut order: [u8; MAX_NOTES] = [0; MAX_NOTES];
+        for (i, slot) in order.iter_mut().enumerate() {
+            *slot = i as u8;
+        }
+        let order = &mut order[..self.held.len()];
+        order.sort_unstable_by_key(|&i| (self.held[i as usize].note.pitch, i));
+        self.held[order[position] as usize].note
+    }
+
+    fn random(&mut self) -> u16 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as u16
+    }
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(pitch: u8) -> ArpNote {
+        ArpNote {
+            pitch,
+            velocity: 100,
+            voice: 0,
+        }
+    }
+
+    /// Run the arpeggiator and return the pitches of the notes it starts.
+    fn played(arp: &mut Arpeggiator, notes: usize) -> Vec<u8> {
+        let mut events = Vec::new();
+        arp.clock_event(ClockEvent::Start, &mut |_| ());
+        for _ in 0..notes * arp.rate.pulses() as usize {
+            arp.clock_event(ClockEvent::Pulse, &mut |e| events.push(e));
+        }
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                ArpEvent::NoteOn(note) => Some(note.pitch),
+                ArpEvent::NoteOff(_) => None,
+            })
+            .collect()
+    }
+
+    fn chord(pitches: &[u8]) -> Arpeggiator {
+        let mut arp = Arpeggiator::new();
+        for &pitch in pitches {
+            arp.note_on(note(pitch));
+        }
+        arp
+    }
+
+    #[test]
+    fn orders() {
+        let mut arp = chord(&[64, 60, 67]);
+        assert_eq!(played(&mut arp, 6), [60, 64, 67, 60, 64, 67]);
+
+        arp.set_order(Order::Down);
+        assert_eq!(played(&mut arp, 4), [67, 64, 60, 67]);
+
+        arp.set_order(Order::UpDown);
+        assert_eq!(played(&mut arp, 6), [60, 64, 67, 64, 60, 64]);
+
+        arp.set_order(Order::AsPlayed);
+        assert_eq!(played(&mut arp, 4), [64, 60, 67, 64]);
+    }
+
+    #[test]
+    fn random_order_is_deterministic_and_uses_held_notes() {
+        let mut arp = chord(&[60, 64, 67]);
+        arp.set_order(Order::Random);
+        arp.set_seed(7);
+        let first = played(&mut arp, 32);
+        assert!(first.iter().all(|pitch| [60, 64, 67].contains(pitch)));
+
+        arp.set_seed(7);
+        assert_eq!(played(&mut arp, 32), first);
+    }
+
+    #[test]
+    fn octave_range() {
+        let mut arp = chord(&[60, 64]);
+        arp.set_octaves(3);
+        assert_eq!(played(&mut arp, 7), [60, 64, 72, 76, 84, 88, 60]);
+
+        arp.set_order(Order::UpDown);
+        assert_eq!(played(&mut arp, 8), [60, 64, 72, 76, 84, 88, 84, 76]);
+    }
+
+    #[test]
+    fn rate_and_gate() {
+        let mut arp = chord(&[60]);
+        arp.set_rate(Rate::Eighth);
+        arp.set_gate(25);
+
+        let mut events = Vec::new();
+        arp.clock_event(ClockEvent::Start, &mut |_| ());
+        for pulse in 0..24 {
+            arp.clock_event(ClockEvent::Pulse, &mut |e| events.push((pulse, e)));
+        }
+        assert_eq!(
+            events,
+            [
+                (0, ArpEvent::NoteOn(note(60))),
+                (3, ArpEvent::NoteOff(note(60))),
+                (12, ArpEvent::NoteOn(note(60))),
+                (15, ArpEvent::NoteOff(note(60))),
+            ]
+        );
+    }
+
+    #[test]
+    fn releasing_the_sounding_note_ends_it() {
+        let mut arp = chord(&[60, 64]);
+        arp.clock_event(ClockEvent::Start, &mut |_| ());
+        arp.clock_event(ClockEvent::Pulse, &mut |_| ());
+
+        let mut events = Vec::new();
+        arp.note_off(60, 0, &mut |e| events.push(e));
+        assert_eq!(events, [ArpEvent::NoteOff(note(60))]);
+        assert_eq!(arp.len(), 1);
+        assert!(!arp.contains(60, 0));
+        assert!(arp.contains(64, 0));
+    }
+
+    #[test]
+    fn latch_holds_notes_until_a_new_chord() {
+        let mut arp = Arpeggiator::new();
+        arp.set_latch(true, &mut |_| ());
+        arp.note_on(note(60));
+        arp.note_on(note(64));
+        arp.note_off(60, 0, &mut |_| ());
+        arp.note_off(64, 0, &mut |_| ());
+        assert_eq!(played(&mut arp, 2), [60, 64]);
+
+        // a new note while no keys are down starts a new chord
+        arp.note_on(note(67));
+        assert_eq!(played(&mut arp, 2), [67, 67]);
+
+        // notes added while a key is down join the chord
+        arp.note_on(note(72));
+        assert_eq!(played(&mut arp, 2), [67, 72]);
+
+        // turning latch off drops the notes that are no longer held
+        arp.note_off(67, 0, &mut |_| ());
+        arp.set_latch(false, &mut |_| ());
+        assert_eq!(played(&mut arp, 2), [72, 72]);
+    }
+
+    #[test]
+    fn voices_are_kept_apart() {
+        let mut arp = Arpeggiator::new();
+        let mut events = Vec::new();
+        arp.midi_message(
+            &MidiMessage::NoteOn(Channel::Ch2, Note::C4, U7::from_u8_lossy(90)),
+            &mut |_| (),
+        );
+        arp.midi_message(
+            &MidiMessage::NoteOn(Channel::Ch3, Note::C4, U7::from_u8_lossy(80)),
+            &mut |_| (),
+        );
+        assert_eq!(arp.len(), 2);
+
+        arp.clock_event(ClockEvent::Start, &mut |_| ());
+        arp.clock_event(ClockEvent::Pulse, &mut |e| events.push(e.to_midi()));
+        assert_eq!(
+            events,
+            [MidiMessage::NoteOn(
+                Channel::Ch2,
+                Note::C4,
+                U7::from_u8_lossy(90)
+            )]
+        );
+
+        arp.midi_message(
+            &MidiMessage::NoteOff(Channel::Ch2, Note::C4, U7::MIN),
+            &mut |e| events.push(e.to_midi()),
+        );
+        assert_eq!(arp.len(), 1);
+        assert_eq!(
+            events[1],
+            MidiMessage::NoteOff(Channel::Ch2, Note::C4, U7::MIN)
+        );
+    }
+
+    #[test]
+    fn note_on_with_zero_velocity_releases_the_note() {
+        let mut arp = Arpeggiator::new();
+        let mut events = Vec::new();
+        for velocity in [90, 0] {
+            arp.midi_message(
+                &MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::from_u8_lossy(velocity)),
+                &mut |e| events.push(e.to_midi()),
+            );
+        }
+        assert_eq!(arp.len(), 0);
+        assert!(events.is_empty());
+
+        // the sounding note ends when it is released
+        arp.note_on(note(60));
+        arp.clock_event(ClockEvent::Start, &mut |_| ());
+        arp.clock_event(ClockEvent::Pulse, &mut |_| ());
+        arp.midi_message(
+            &MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::MIN),
+            &mut |e| events.push(e.to_midi()),
+        );
+        assert_eq!(
+            events,
+            [MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN)]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 #![cfg_attr(target_arch="arm", no_std)]
 #![cfg_attr(target_arch="arm", no_main)]
 
-#[cfg(target_arch="arm")]
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
 use core::panic::PanicInfo;
 use wmidi::MidiMessage;
 use launchpad_pro_rs::hal;
@@ -25,7 +25,7 @@ impl LaunchpadApp for App {
     fn aftertouch_event(&self, _aftertouch_event: hal::surface::AftertouchEvent) {}
 }
 
-#[cfg(target_arch="arm")]
+#[cfg(all(target_arch = "arm", not(feature = "panic-handler")))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
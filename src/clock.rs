@@ -0,0 +1,222 @@
+use wmidi::MidiMessage;
+
+/// The number of MIDI clock pulses per quarter note.
+pub const PPQN: u8 = 24;
+
+/// The number of milliseconds in a minute. The internal clock is advanced by the 1 kHz timer.
+const MS_PER_MINUTE: u32 = 60_000;
+
+/// Where a clock takes its pulses from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Pulses are generated from the 1 ms timer at the clock's tempo.
+    Internal,
+    /// Pulses are taken from incoming MIDI clock messages.
+    External,
+}
+
+/// The transport and timing events produced by a clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockEvent {
+    /// One clock pulse (1/24 of a quarter note) has elapsed.
+    Pulse,
+    /// Playback should start from the beginning.
+    Start,
+    /// Playback should resume from the current position.
+    Continue,
+    /// Playback should stop.
+    Stop,
+}
+
+/// A 24 PPQN musical clock that is driven either by the 1 kHz timer or by incoming MIDI clock.
+///
+/// The internal clock uses integer arithmetic only, so the same sequence of calls will always
+/// produce the same sequence of pulses.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::clock::{Clock, ClockEvent, PPQN};
+///
+/// let mut clock = Clock::new(120);
+/// clock.start();
+///
+/// // at 120 BPM a quarter note lasts 500 ms
+/// let pulses = (0..500).filter(|_| clock.tick() == Some(ClockEvent::Pulse)).count();
+/// assert_eq!(pulses, PPQN as usize);
+/// ```
+pub struct Clock {
+    source: Source,
+    bpm: u16,
+    running: bool,
+    accumulator: u32,
+}
+
+impl Clock {
+    /// Construct a new, stopped, internal clock running at the given tempo. The tempo is clamped
+    /// to `[1, 999]`.
+    pub const fn new(bpm: u16) -> Self {
+        Clock {
+            source: Source::Internal,
+            bpm: if bpm < 1 {
+                1
+            } else if bpm > 999 {
+                999
+            } else {
+                bpm
+            },
+            running: false,
+            accumulator: 0,
+        }
+    }
+
+    /// Returns where the clock takes its pulses from.
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// Select where the clock takes its pulses from.
+    pub fn set_source(&mut self, source: Source) {
+        self.source = source;
+        self.accumulator = 0;
+    }
+
+    /// Returns the tempo of the internal clock in beats per minute.
+    pub fn bpm(&self) -> u16 {
+        self.bpm
+    }
+
+    /// Set the tempo of the internal clock in beats per minute. The tempo is clamped to
+    /// `[1, 999]`.
+    pub fn set_bpm(&mut self, bpm: u16) {
+        self.bpm = bpm.clamp(1, 999);
+    }
+
+    /// Returns true if the clock is running.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Start the internal clock. The first pulse is produced by the next call to `tick`.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.accumulator = MS_PER_MINUTE - self.pulse_increment();
+    }
+
+    /// Stop the internal clock.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Advance the internal clock by one millisecond. This should be called from the 1 kHz timer.
+    /// Returns a pulse if one is due.
+    pub fn tick(&mut self) -> Option<ClockEvent> {
+        if self.source != Source::Internal || !self.running {
+            return None;
+        }
+
+        self.accumulator += self.pulse_increment();
+        if self.accumulator >= MS_PER_MINUTE {
+            self.accumulator -= MS_PER_MINUTE;
+            Some(ClockEvent::Pulse)
+        } else {
+            None
+        }
+    }
+
+    /// The amount the accumulator is advanced by each millisecond. A pulse is due whenever the
+    /// accumulator passes one minute.
+    fn pulse_increment(&self) -> u32 {
+        self.bpm as u32 * PPQN as u32
+    }
+
+    /// Handle an incoming MIDI message. Realtime messages are translated into clock events when the
+    /// clock is following an external source, anything else is ignored.
+    pub fn midi_message(&mut self, message: &MidiMessage) -> Option<ClockEvent> {
+        if self.source != Source::External {
+            return None;
+        }
+
+        match message {
+            MidiMessage::TimingClock if self.running => Some(ClockEvent::Pulse),
+            MidiMessage::Start => {
+                self.running = true;
+                Some(ClockEvent::Start)
+            }
+            MidiMessage::Continue => {
+                self.running = true;
+                Some(ClockEvent::Continue)
+            }
+            MidiMessage::Stop => {
+                self.running = false;
+                Some(ClockEvent::Stop)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_pulses(clock: &mut Clock, ms: u32) -> u32 {
+        (0..ms)
+            .filter(|_| clock.tick() == Some(ClockEvent::Pulse))
+            .count() as u32
+    }
+
+    #[test]
+    fn stopped_clock_does_not_pulse() {
+        let mut clock = Clock::new(120);
+        assert_eq!(count_pulses(&mut clock, 1000), 0);
+    }
+
+    #[test]
+    fn internal_clock_keeps_tempo() {
+        let mut clock = Clock::new(120);
+        clock.start();
+        assert_eq!(clock.tick(), Some(ClockEvent::Pulse));
+        assert_eq!(count_pulses(&mut clock, 59_999), 120 * PPQN as u32 - 1);
+
+        let mut clock = Clock::new(97);
+        clock.start();
+        assert_eq!(count_pulses(&mut clock, 60_000), 97 * PPQN as u32);
+    }
+
+    #[test]
+    fn external_clock_follows_midi() {
+        let mut clock = Clock::new(120);
+        clock.set_source(Source::External);
+        clock.start();
+        clock.stop();
+
+        assert_eq!(clock.tick(), None);
+        assert_eq!(clock.midi_message(&MidiMessage::TimingClock), None);
+        assert_eq!(
+            clock.midi_message(&MidiMessage::Start),
+            Some(ClockEvent::Start)
+        );
+        assert_eq!(
+            clock.midi_message(&MidiMessage::TimingClock),
+            Some(ClockEvent::Pulse)
+        );
+        assert_eq!(
+            clock.midi_message(&MidiMessage::Stop),
+            Some(ClockEvent::Stop)
+        );
+        assert_eq!(clock.midi_message(&MidiMessage::TimingClock), None);
+        assert_eq!(
+            clock.midi_message(&MidiMessage::Continue),
+            Some(ClockEvent::Continue)
+        );
+        assert!(clock.is_running());
+    }
+
+    #[test]
+    fn internal_clock_ignores_midi() {
+        let mut clock = Clock::new(120);
+        assert_eq!(clock.midi_message(&MidiMessage::Start), None);
+        assert!(!clock.is_running());
+    }
+}
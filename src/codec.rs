@@ -0,0 +1,486 @@
+use core::fmt;
+
+/// A way of packing 8-bit data into the 7-bit bytes that can be sent in SysEx.
+///
+/// Each scheme packs every seven bytes of data into eight bytes, and the last few bytes of data
+/// into one more byte than there are of them, so all three give the same lengths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// The scheme used by the Launchpad Pro bootloader and `hextosyx`: the data is read as a
+    /// stream of bits, most significant first, and split into groups of seven.
+    Bootloader,
+    /// Each group starts with a byte of the top bits of the seven data bytes that follow, the
+    /// first byte's in bit 6.
+    MsbFirst,
+    /// Each group starts with a byte of the top bits of the seven data bytes that follow, the
+    /// first byte's in bit 0. This is the scheme used by many synthesisers' SysEx dumps.
+    LsbFirst,
+}
+
+impl Scheme {
+    /// Pack seven bytes of data into eight 7-bit bytes.
+    pub fn pack(self, data: &[u8; 7]) -> [u8; 8] {
+        let mut packed = [0; 8];
+        match self {
+            Scheme::Bootloader => {
+                packed[0] = data[0] >> 1;
+                for i in 1..7 {
+                    packed[i] = (data[i - 1] << (7 - i)) | (data[i] >> (i + 1));
+                }
+                packed[7] = data[6];
+            }
+            Scheme::MsbFirst | Scheme::LsbFirst => {
+                for (i, &byte) in data.iter().enumerate() {
+                    let bit = match self {
+                        Scheme::MsbFirst => 6 - i,
+                        _ => i,
+                    };
+                    packed[0] |= (byte >> 7) << bit;
+                    packed[i + 1] = byte;
+                }
+            }
+        }
+        packed.map(|byte| byte & 0x7f)
+    }
+
+    /// Unpack eight 7-bit bytes into the seven bytes of data packed by [`pack`]. The top bit of
+    /// each packed byte is ignored.
+    ///
+    /// [`pack`]: Scheme::pack
+    pub fn unpack(self, packed: &[u8; 8]) -> [u8; 7] {
+        let packed = packed.map(|byte| byte & 0x7f);
+        let mut data = [0; 7];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = match self {
+                Scheme::Bootloader => (packed[i] << (i + 1)) | (packed[i + 1] >> (6 - i)),
+                Scheme::MsbFirst => packed[i + 1] | ((packed[0] >> (6 - i)) << 7),
+                Scheme::LsbFirst => packed[i + 1] | ((packed[0] >> i) << 7),
+            };
+        }
+        data
+    }
+}
+
+/// An error encoding or decoding data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer is too small.
+    Overflow,
+    /// A packed byte has its top bit set, so it can't have come from SysEx.
+    NotSevenBit,
+    /// The packed data ended part way through a byte.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Overflow => write!(f, "the output buffer is too small"),
+            Error::NotSevenBit => write!(f, "a packed byte is not 7-bit"),
+            Error::Truncated => write!(f, "the packed data is truncated"),
+        }
+    }
+}
+
+/// Returns the number of 7-bit bytes needed to pack some bytes of data.
+pub const fn encoded_len(len: usize) -> usize {
+    len / 7 * 8 + if len.is_multiple_of(7) { 0 } else { len % 7 + 1 }
+}
+
+/// Returns the number of bytes of data in some packed bytes, or `None` if the last byte of data
+/// would be incomplete.
+pub const fn decoded_len(len: usize) -> Option<usize> {
+    match len % 8 {
+        0 => Some(len / 8 * 7),
+        1 => None,
+        rest => Some(len / 8 * 7 + rest - 1),
+    }
+}
+
+/// Pack some data into 7-bit bytes, returning the number of bytes written to the output.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::codec::{self, Scheme};
+///
+/// let mut packed = [0; 16];
+/// let len = codec::encode(Scheme::LsbFirst, &[0x80, 0x01, 0xff], &mut packed).unwrap();
+/// assert_eq!(&packed[..len], [0x05, 0x00, 0x01, 0x7f]);
+///
+/// let mut data = [0; 16];
+/// let len = codec::decode(Scheme::LsbFirst, &packed[..len], &mut data).unwrap();
+/// assert_eq!(&data[..len], [0x80, 0x01, 0xff]);
+/// ```
+pub fn encode(scheme: Scheme, data: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    if output.len() < encoded_len(data.len()) {
+        return Err(Error::Overflow);
+    }
+    let mut encoder = Encoder::new(scheme);
+    let written = encoder.encode(data, output)?;
+    Ok(written + encoder.finish(&mut output[written..])?)
+}
+
+/// Unpack 7-bit bytes into data, returning the number of bytes written to the output.
+pub fn decode(scheme: Scheme, packed: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    let len = decoded_len(packed.len()).ok_or(Error::Truncated)?;
+    if output.len() < len {
+        return Err(Error::Overflow);
+    }
+    let mut decoder = Decoder::new(scheme);
+    let written = decoder.decode(packed, output)?;
+    Ok(written + decoder.finish(&mut output[written..])?)
+}
+
+/// Packs data into 7-bit bytes as it arrives, e.g. to send a large dump in several messages.
+///
+/// Each call to [`encode`] writes the complete groups of eight bytes that are ready, keeping any
+/// data left over for the next call, and [`finish`] writes the last, partial group.
+///
+/// [`encode`]: Encoder::encode
+/// [`finish`]: Encoder::finish
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::codec::{Encoder, Scheme};
+///
+/// let mut encoder = Encoder::new(Scheme::Bootloader);
+/// let mut packed = [0; 8];
+/// assert_eq!(encoder.encode(&[1, 2, 3, 4], &mut packed), Ok(0));
+/// assert_eq!(encoder.encode(&[5, 6, 7, 8], &mut packed), Ok(8));
+/// assert_eq!(encoder.finish(&mut packed), Ok(2));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoder {
+    scheme: Scheme,
+    pending: [u8; 7],
+    len: usize,
+}
+
+impl Encoder {
+    /// Construct an encoder using the given scheme.
+    pub const fn new(scheme: Scheme) -> Self {
+        Encoder {
+            scheme,
+            pending: [0; 7],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of bytes of data waiting to complete a group.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Pack some data, returning the number of bytes written to the output. If the output can't
+    /// hold the complete groups, nothing is consumed and [`Error::Overflow`] is returned.
+    pub fn encode(&mut self, data: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        if output.len() < (self.len + data.len()) / 7 * 8 {
+            return Err(Error::Overflow);
+        }
+        let mut written = 0;
+        for &byte in data {
+            self.pending[self.len] = byte;
+            self.len += 1;
+            if self.len == 7 {
+                output[written..written + 8].copy_from_slice(&self.scheme.pack(&self.pending));
+                written += 8;
+                self.len = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Pack the data left over, returning the number of bytes written, and start again.
+    pub fn finish(&mut self, output: &mut [u8]) -> Result<usize, Error> {
+        let len = encoded_len(self.len);
+        let output = output.get_mut(..len).ok_or(Error::Overflow)?;
+        self.pending[self.len..].fill(0);
+        output.copy_from_slice(&self.scheme.pack(&self.pending)[..len]);
+        self.len = 0;
+        Ok(len)
+    }
+}
+
+/// Unpacks 7-bit bytes into data as they arrive, e.g. from a dump sent in several messages.
+///
+/// Each call to [`decode`] writes the data from the complete groups of eight bytes, keeping any
+/// bytes left over for the next call, and [`finish`] writes the data from the last, partial group.
+///
+/// [`decode`]: Decoder::decode
+/// [`finish`]: Decoder::finish
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decoder {
+    scheme: Scheme,
+    pending: [u8; 8],
+    len: usize,
+}
+
+impl Decoder {
+    /// Construct a decoder using the given scheme.
+    pub const fn new(scheme: Scheme) -> Self {
+        Decoder {
+            scheme,
+            pending: [0; 8],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of packed bytes waiting to complete a group.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Unpack some bytes, returning the number of bytes of data written to the output. If the
+    /// output can't hold the data from the complete groups, or a byte isn't 7-bit, nothing is
+    /// consumed.
+    pub fn decode(&mut self, packed: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        if packed.iter().any(|&byte| byte >= 0x80) {
+            return Err(Error::NotSevenBit);
+        }
+        if output.len() < (self.len + packed.len()) / 8 * 7 {
+            return Err(Error::Overflow);
+        }
+        let mut written = 0;
+        for &byte in packed {
+            self.pending[self.len] = byte;
+            self.len += 1;
+            if self.len == 8 {
+                output[written..written + 7].copy_from_slice(&self.scheme.unpack(&self.pending));
+                written += 7;
+                self.len = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Unpack the bytes left over, returning the number of bytes of data written, and start again.
+    pub fn finish(&mut self, output: &mut [u8]) -> Result<usize, Error> {
+        let len = decoded_len(self.len).ok_or(Error::Truncated)?;
+        let output = output.get_mut(..len).ok_or(Error::Overflow)?;
+        self.pending[self.len..].fill(0);
+        output.copy_from_slice(&self.scheme.unpack(&self.pending)[..len]);
+        self.len = 0;
+        Ok(len)
+    }
+}
+
+/// Returns the CRC-16/CCITT-FALSE checksum of some data.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Calculates a CRC-16/CCITT-FALSE checksum of data that arrives in pieces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc16(u16);
+
+impl Crc16 {
+    /// Start a new checksum.
+    pub const fn new() -> Self {
+        Crc16(0xffff)
+    }
+
+    /// Add some data to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            })
+        });
+    }
+
+    /// Returns the checksum of the data so far.
+    pub fn finish(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the 7-bit checksum used by Roland and others: the value that makes the sum of the
+/// bytes and the checksum a multiple of 128.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::codec;
+///
+/// assert_eq!(codec::checksum7(&[0x40, 0x00, 0x7f, 0x00]), 0x41);
+/// ```
+pub fn checksum7(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    sum.wrapping_neg() & 0x7f
+}
+
+/// Split a 16-bit value, e.g. a [`crc16`], into three 7-bit bytes, most significant first.
+pub const fn split_u16(value: u16) -> [u8; 3] {
+    [
+        (value >> 14) as u8,
+        (value >> 7) as u8 & 0x7f,
+        value as u8 & 0x7f,
+    ]
+}
+
+/// Join three 7-bit bytes made by [`split_u16`], or return `None` if they don't hold a 16-bit
+/// value.
+pub const fn join_u16(bytes: [u8; 3]) -> Option<u16> {
+    if bytes[0] > 0x03 || bytes[1] >= 0x80 || bytes[2] >= 0x80 {
+        return None;
+    }
+    Some((bytes[0] as u16) << 14 | (bytes[1] as u16) << 7 | bytes[2] as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    const SCHEMES: [Scheme; 3] = [Scheme::Bootloader, Scheme::MsbFirst, Scheme::LsbFirst];
+
+    #[test]
+    fn groups_are_packed() {
+        let data = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x23, 0x45];
+        // the packing hextosyx sends to the bootloader
+        assert_eq!(
+            Scheme::Bootloader.pack(&data),
+            [0x6f, 0x2b, 0x37, 0x6e, 0x78, 0x04, 0x46, 0x45]
+        );
+        assert_eq!(
+            Scheme::MsbFirst.pack(&data),
+            [0x78, 0x5e, 0x2d, 0x3e, 0x6f, 0x01, 0x23, 0x45]
+        );
+        assert_eq!(
+            Scheme::LsbFirst.pack(&data),
+            [0x0f, 0x5e, 0x2d, 0x3e, 0x6f, 0x01, 0x23, 0x45]
+        );
+        for scheme in SCHEMES {
+            assert_eq!(scheme.unpack(&scheme.pack(&data)), data);
+        }
+    }
+
+    #[test]
+    fn lengths_match() {
+        assert_eq!(encoded_len(0), 0);
+        assert_eq!(encoded_len(1), 2);
+        assert_eq!(encoded_len(7), 8);
+        assert_eq!(encoded_len(8), 10);
+        assert_eq!(decoded_len(9), None);
+        for len in 0..100 {
+            assert_eq!(decoded_len(encoded_len(len)), Some(len));
+        }
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        let mut output = [0; 16];
+        assert_eq!(
+            encode(Scheme::MsbFirst, &[0; 8], &mut output[..9]),
+            Err(Error::Overflow)
+        );
+        assert_eq!(
+            decode(Scheme::MsbFirst, &[0; 9], &mut output),
+            Err(Error::Truncated)
+        );
+        assert_eq!(
+            decode(Scheme::MsbFirst, &[0, 0x80], &mut output),
+            Err(Error::NotSevenBit)
+        );
+        assert_eq!(join_u16([0x04, 0, 0]), None);
+        assert_eq!(split_u16(crc16(b"123456789")), [0x00, 0x53, 0x31]);
+        assert_eq!(join_u16(split_u16(0xffff)), Some(0xffff));
+    }
+
+    /// Split data into pieces of the given sizes, wrapped to what is left, with the rest last.
+    fn pieces<'a>(mut data: &'a [u8], sizes: &[Index]) -> Vec<&'a [u8]> {
+        let mut pieces = Vec::new();
+        for size in sizes {
+            let (piece, rest) = data.split_at(size.index(data.len() + 1));
+            pieces.push(piece);
+            data = rest;
+        }
+        pieces.push(data);
+        pieces
+    }
+
+    proptest! {
+        #[test]
+        fn data_survives_a_round_trip(data in vec(any::<u8>(), 0..300)) {
+            let mut packed = [0; encoded_len(300)];
+            let mut unpacked = [0; 300];
+            for scheme in SCHEMES {
+                let encoded = encode(scheme, &data, &mut packed).unwrap();
+                prop_assert_eq!(encoded, encoded_len(data.len()));
+                prop_assert!(packed[..encoded].iter().all(|&byte| byte < 0x80));
+
+                let decoded = decode(scheme, &packed[..encoded], &mut unpacked).unwrap();
+                prop_assert_eq!(&unpacked[..decoded], &data[..]);
+            }
+        }
+
+        #[test]
+        fn streaming_matches_one_shot(
+            data in vec(any::<u8>(), 0..100),
+            sizes in vec(any::<Index>(), 0..12),
+        ) {
+            for scheme in SCHEMES {
+                let mut expected = [0; encoded_len(100)];
+                let len = encode(scheme, &data, &mut expected).unwrap();
+                let expected = &expected[..len];
+
+                let mut packed = Vec::new();
+                let mut encoder = Encoder::new(scheme);
+                for piece in pieces(&data, &sizes) {
+                    let mut output = vec![0; (encoder.pending() + piece.len()) / 7 * 8];
+                    let written = encoder.encode(piece, &mut output);
+                    packed.extend_from_slice(&output[..written.unwrap()]);
+                }
+                let mut output = [0; 8];
+                let written = encoder.finish(&mut output).unwrap();
+                packed.extend_from_slice(&output[..written]);
+                prop_assert_eq!(&packed[..], expected);
+
+                let mut unpacked = Vec::new();
+                let mut decoder = Decoder::new(scheme);
+                for piece in pieces(expected, &sizes) {
+                    let mut output = vec![0; (decoder.pending() + piece.len()) / 8 * 7];
+                    let written = decoder.decode(piece, &mut output);
+                    unpacked.extend_from_slice(&output[..written.unwrap()]);
+                }
+                let mut output = [0; 7];
+                let written = decoder.finish(&mut output).unwrap();
+                unpacked.extend_from_slice(&output[..written]);
+                prop_assert_eq!(&unpacked, &data);
+            }
+        }
+
+        #[test]
+        fn checksums_detect_changes(mut data in vec(any::<u8>(), 1..64), bit in any::<Index>()) {
+            let mut crc = Crc16::new();
+            for piece in data.chunks(5) {
+                crc.update(piece);
+            }
+            prop_assert_eq!(crc.finish(), crc16(&data));
+
+            let sum = checksum7(&data);
+            prop_assert!(sum < 0x80);
+            let total: u32 = data.iter().map(|&byte| byte as u32).sum::<u32>() + sum as u32;
+            prop_assert_eq!(total % 128, 0);
+
+            // any single changed bit changes the CRC
+            let bit = bit.index(data.len() * 8);
+            let before = crc16(&data);
+            data[bit / 8] ^= 1 << (bit % 8);
+            prop_assert_ne!(crc16(&data), before);
+        }
+    }
+}
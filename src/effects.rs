@@ -0,0 +1,549 @@
+use crate::hal::routing::{self, Thru};
+use crate::hal::Mutex;
+use crate::scale::Key;
+use wmidi::{Channel, MidiMessage, Note, U7};
+
+/// The maximum number of messages a processor can produce from a single message.
+pub const MAX_OUTPUT: usize = 8;
+
+/// The messages produced by a processor.
+pub type Output = heapless::Vec<MidiMessage<'static>, MAX_OUTPUT>;
+
+/// A MIDI processor transforms each message it is given into zero or more messages.
+///
+/// Processors are stateless with respect to the notes passing through them, so a note off is
+/// always transformed in the same way as its note on. Settings should be changed while no notes
+/// are held to avoid stuck notes.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::effects::{Harmonizer, Processor, Transpose};
+/// use launchpad_pro_rs::hal::midi::{send_message, MidiMessage, Port};
+/// use wmidi::{Channel, Note, U7};
+///
+/// // play every note an octave up along with a fifth above it
+/// let mut chain = Transpose::new(12).then(Harmonizer::new(&[7]));
+///
+/// let message = MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::from_u8_lossy(100));
+/// chain.apply(message, |message| send_message(Port::USB, &message));
+/// ```
+pub trait Processor {
+    /// Process a message, pushing the results to `output`. Messages that don't fit in the output
+    /// are dropped.
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output);
+
+    /// Process a message and pass every result to a callback.
+    fn apply(&mut self, message: MidiMessage<'static>, mut sink: impl FnMut(MidiMessage<'static>))
+    where
+        Self: Sized,
+    {
+        let mut output = Output::new();
+        self.process(message, &mut output);
+        for message in output {
+            sink(message);
+        }
+    }
+
+    /// Chain another processor after this one.
+    fn then<P: Processor>(self, next: P) -> Chain<Self, P>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+/// Two processors applied one after the other.
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Processor, B: Processor> Processor for Chain<A, B> {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let mut intermediate = Output::new();
+        self.first.process(message, &mut intermediate);
+        for message in intermediate {
+            self.second.process(message, output);
+        }
+    }
+}
+
+/// Apply a function to the note of note on, note off and polyphonic pressure messages. The message
+/// is dropped if the function returns None. Other messages are passed through unchanged.
+fn map_note(
+    message: MidiMessage<'static>,
+    f: impl Fn(u8) -> Option<u8>,
+) -> Option<MidiMessage<'static>> {
+    let note = |note: Note| f(u8::from(note)).and_then(|n| Note::try_from(n).ok());
+    match message {
+        MidiMessage::NoteOn(channel, n, velocity) => {
+            note(n).map(|n| MidiMessage::NoteOn(channel, n, velocity))
+        }
+        MidiMessage::NoteOff(channel, n, velocity) => {
+            note(n).map(|n| MidiMessage::NoteOff(channel, n, velocity))
+        }
+        MidiMessage::PolyphonicKeyPressure(channel, n, pressure) => {
+            note(n).map(|n| MidiMessage::PolyphonicKeyPressure(channel, n, pressure))
+        }
+        message => Some(message),
+    }
+}
+
+/// Push a message if there is one.
+fn push(output: &mut Output, message: Option<MidiMessage<'static>>) {
+    if let Some(message) = message {
+        let _ = output.push(message);
+    }
+}
+
+/// Shift notes by a number of semitones. Notes shifted out of the MIDI range are dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transpose {
+    semitones: i8,
+}
+
+impl Transpose {
+    /// Construct a processor that shifts notes by a number of semitones.
+    pub const fn new(semitones: i8) -> Self {
+        Transpose { semitones }
+    }
+
+    /// Returns the number of semitones notes are shifted by.
+    pub fn semitones(&self) -> i8 {
+        self.semitones
+    }
+
+    /// Set the number of semitones notes are shifted by.
+    pub fn set_semitones(&mut self, semitones: i8) {
+        self.semitones = semitones;
+    }
+}
+
+impl Processor for Transpose {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let semitones = self.semitones as i16;
+        push(
+            output,
+            map_note(message, |note| u8::try_from(note as i16 + semitones).ok()),
+        );
+    }
+}
+
+/// Move notes that are not in a key to the nearest note that is. When two notes are equally near
+/// the lower one is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScaleQuantise {
+    key: Key,
+}
+
+impl ScaleQuantise {
+    /// Construct a processor that quantises notes to a key.
+    pub const fn new(key: Key) -> Self {
+        ScaleQuantise { key }
+    }
+
+    /// Returns the key notes are quantised to.
+    pub fn key(&self) -> Key {
+        self.key
+    }
+
+    /// Set the key notes are quantised to.
+    pub fn set_key(&mut self, key: Key) {
+        self.key = key;
+    }
+
+    /// Returns the nearest note in the key, or None if the scale is empty.
+    pub fn quantise(&self, note: u8) -> Option<u8> {
+        self.key.nearest(note)
+    }
+}
+
+impl Processor for ScaleQuantise {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        push(output, map_note(message, |note| self.quantise(note)));
+    }
+}
+
+/// Move channel messages from one channel to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelRemap {
+    map: [Channel; 16],
+}
+
+impl ChannelRemap {
+    /// Construct a processor that leaves every channel where it is.
+    pub const fn new() -> Self {
+        use Channel::*;
+        ChannelRemap {
+            map: [
+                Ch1, Ch2, Ch3, Ch4, Ch5, Ch6, Ch7, Ch8, Ch9, Ch10, Ch11, Ch12, Ch13, Ch14, Ch15,
+                Ch16,
+            ],
+        }
+    }
+
+    /// Construct a processor that moves every channel message to a single channel.
+    pub const fn all_to(channel: Channel) -> Self {
+        ChannelRemap { map: [channel; 16] }
+    }
+
+    /// Move messages on one channel to another.
+    pub fn set(&mut self, from: Channel, to: Channel) {
+        self.map[from.index() as usize] = to;
+    }
+}
+
+impl Default for ChannelRemap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Processor for ChannelRemap {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let remap = |channel: Channel| self.map[channel.index() as usize];
+        let message = match message {
+            MidiMessage::NoteOff(c, n, v) => MidiMessage::NoteOff(remap(c), n, v),
+            MidiMessage::NoteOn(c, n, v) => MidiMessage::NoteOn(remap(c), n, v),
+            MidiMessage::PolyphonicKeyPressure(c, n, v) => {
+                MidiMessage::PolyphonicKeyPressure(remap(c), n, v)
+            }
+            MidiMessage::ControlChange(c, f, v) => MidiMessage::ControlChange(remap(c), f, v),
+            MidiMessage::ProgramChange(c, p) => MidiMessage::ProgramChange(remap(c), p),
+            MidiMessage::ChannelPressure(c, v) => MidiMessage::ChannelPressure(remap(c), v),
+            MidiMessage::PitchBendChange(c, b) => MidiMessage::PitchBendChange(remap(c), b),
+            message => message,
+        };
+        let _ = output.push(message);
+    }
+}
+
+/// Scale, offset and clamp the velocity of note on messages.
+///
+/// The velocity becomes `velocity * percent / 100 + offset`, clamped to `[min, max]`. Note ons
+/// with a velocity of 0 are note offs, so they are passed through unchanged, and other note ons
+/// never drop below a velocity of 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Velocity {
+    /// The percentage the velocity is scaled by.
+    pub percent: u16,
+    /// The amount added to the velocity after scaling.
+    pub offset: i8,
+    /// The lowest velocity allowed.
+    pub min: u8,
+    /// The highest velocity allowed.
+    pub max: u8,
+}
+
+impl Velocity {
+    /// Construct a processor that leaves velocities unchanged.
+    pub const fn new() -> Self {
+        Velocity {
+            percent: 100,
+            offset: 0,
+            min: 1,
+            max: 127,
+        }
+    }
+
+    /// Construct a processor that plays every note on at the same velocity.
+    pub const fn fixed(velocity: u8) -> Self {
+        Velocity {
+            percent: 0,
+            offset: 0,
+            min: velocity,
+            max: velocity,
+        }
+    }
+
+    /// Returns the transformed velocity.
+    pub fn apply_to(&self, velocity: u8) -> u8 {
+        let scaled = velocity as i32 * self.percent as i32 / 100 + self.offset as i32;
+        let min = self.min.clamp(1, 127) as i32;
+        let max = self.max.clamp(1, 127) as i32;
+        scaled.max(min).min(max) as u8
+    }
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Processor for Velocity {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let message = match message {
+            MidiMessage::NoteOn(channel, note, velocity) if u8::from(velocity) > 0 => {
+                MidiMessage::NoteOn(
+                    channel,
+                    note,
+                    U7::from_u8_lossy(self.apply_to(u8::from(velocity))),
+                )
+            }
+            message => message,
+        };
+        let _ = output.push(message);
+    }
+}
+
+/// Drop notes outside of an inclusive range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteRange {
+    /// The lowest note let through.
+    pub low: u8,
+    /// The highest note let through.
+    pub high: u8,
+}
+
+impl NoteRange {
+    /// Construct a processor that only lets notes in `[low, high]` through.
+    pub const fn new(low: u8, high: u8) -> Self {
+        NoteRange { low, high }
+    }
+}
+
+impl Processor for NoteRange {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let range = self.low..=self.high;
+        push(
+            output,
+            map_note(message, |note| Some(note).filter(|n| range.contains(n))),
+        );
+    }
+}
+
+/// The maximum number of intervals a harmonizer can add.
+pub const MAX_INTERVALS: usize = MAX_OUTPUT - 1;
+
+/// Add notes at fixed intervals to every note. The original note is always kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Harmonizer {
+    intervals: heapless::Vec<i8, MAX_INTERVALS>,
+}
+
+impl Harmonizer {
+    /// Construct a harmonizer that adds notes at the given intervals in semitones. Intervals
+    /// beyond the capacity of the harmonizer are ignored.
+    pub fn new(intervals: &[i8]) -> Self {
+        let mut harmonizer = Harmonizer {
+            intervals: heapless::Vec::new(),
+        };
+        harmonizer.set_intervals(intervals);
+        harmonizer
+    }
+
+    /// Replace the intervals that are added.
+    pub fn set_intervals(&mut self, intervals: &[i8]) {
+        self.intervals.clear();
+        for &interval in intervals.iter().take(MAX_INTERVALS) {
+            let _ = self.intervals.push(interval);
+        }
+    }
+}
+
+impl Processor for Harmonizer {
+    fn process(&mut self, message: MidiMessage<'static>, output: &mut Output) {
+        let _ = output.push(message.clone());
+        if matches!(
+            message,
+            MidiMessage::NoteOn(..)
+                | MidiMessage::NoteOff(..)
+                | MidiMessage::PolyphonicKeyPressure(..)
+        ) {
+            for &interval in self.intervals.iter() {
+                Transpose::new(interval).process(message.clone(), output);
+            }
+        }
+    }
+}
+
+/// A MIDI processor that can be applied to forwarded messages.
+pub type ThruEffect = Mutex<dyn Processor + Send>;
+
+/// The processor applied to forwarded messages.
+static THRU_EFFECT: Mutex<Option<&'static ThruEffect>> = Mutex::new(None);
+
+/// Run the thru effect on a message forwarded by the router.
+fn thru(message: MidiMessage<'static>, send: &mut dyn FnMut(&MidiMessage)) {
+    if let Some(effect) = *THRU_EFFECT.lock() {
+        let mut output = Output::new();
+        effect.lock().process(message, &mut output);
+        for processed in output.iter() {
+            send(processed);
+        }
+    }
+}
+
+/// Set or remove a processor that transforms every message forwarded by the
+/// [router](crate::hal::routing::router). SysEx is always forwarded unchanged.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::effects::{set_thru_effect, Transpose};
+/// use launchpad_pro_rs::hal::Mutex;
+///
+/// static TRANSPOSE: Mutex<Transpose> = Mutex::new(Transpose::new(12));
+///
+/// set_thru_effect(Some(&TRANSPOSE));
+/// ```
+pub fn set_thru_effect(effect: Option<&'static ThruEffect>) {
+    *THRU_EFFECT.lock() = effect;
+    routing::set_thru(effect.map(|_| thru as Thru));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale::Scale;
+
+    fn note_on(note: u8, velocity: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOn(
+            Channel::Ch1,
+            Note::from_u8_lossy(note),
+            U7::from_u8_lossy(velocity),
+        )
+    }
+
+    fn note_off(note: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOff(Channel::Ch1, Note::from_u8_lossy(note), U7::MIN)
+    }
+
+    fn run(
+        processor: &mut impl Processor,
+        message: MidiMessage<'static>,
+    ) -> Vec<MidiMessage<'static>> {
+        let mut messages = Vec::new();
+        processor.apply(message, |m| messages.push(m));
+        messages
+    }
+
+    #[test]
+    fn transpose() {
+        let mut transpose = Transpose::new(12);
+        assert_eq!(run(&mut transpose, note_on(60, 100)), [note_on(72, 100)]);
+        assert_eq!(run(&mut transpose, note_off(60)), [note_off(72)]);
+        assert_eq!(run(&mut transpose, note_on(120, 100)), []);
+        assert_eq!(
+            run(&mut transpose, MidiMessage::TimingClock),
+            [MidiMessage::TimingClock]
+        );
+
+        transpose.set_semitones(-61);
+        assert_eq!(run(&mut transpose, note_on(60, 100)), []);
+    }
+
+    #[test]
+    fn scale_quantise() {
+        // D major: D E F# G A B C#
+        let mut quantise = ScaleQuantise::new(Key::new(2, Scale::MAJOR));
+        assert_eq!(quantise.quantise(62), Some(62));
+        assert_eq!(quantise.quantise(63), Some(62));
+        assert_eq!(quantise.quantise(65), Some(64));
+        assert_eq!(quantise.quantise(72), Some(71));
+        assert_eq!(quantise.quantise(0), Some(1));
+        assert_eq!(run(&mut quantise, note_on(60, 1)), [note_on(59, 1)]);
+
+        assert_eq!(
+            ScaleQuantise::new(Key::new(0, Scale::new(0))).quantise(60),
+            None
+        );
+    }
+
+    #[test]
+    fn channel_remap() {
+        let mut remap = ChannelRemap::new();
+        remap.set(Channel::Ch1, Channel::Ch10);
+        assert_eq!(
+            run(&mut remap, note_on(60, 100)),
+            [MidiMessage::NoteOn(
+                Channel::Ch10,
+                Note::C4,
+                U7::from_u8_lossy(100)
+            )]
+        );
+
+        let mut remap = ChannelRemap::all_to(Channel::Ch3);
+        assert_eq!(
+            run(
+                &mut remap,
+                MidiMessage::ProgramChange(Channel::Ch16, U7::MIN)
+            ),
+            [MidiMessage::ProgramChange(Channel::Ch3, U7::MIN)]
+        );
+    }
+
+    #[test]
+    fn velocity() {
+        let mut velocity = Velocity {
+            percent: 50,
+            offset: 10,
+            min: 20,
+            max: 60,
+        };
+        assert_eq!(run(&mut velocity, note_on(60, 100)), [note_on(60, 60)]);
+        assert_eq!(run(&mut velocity, note_on(60, 80)), [note_on(60, 50)]);
+        assert_eq!(run(&mut velocity, note_on(60, 1)), [note_on(60, 20)]);
+        assert_eq!(run(&mut velocity, note_off(60)), [note_off(60)]);
+        assert_eq!(run(&mut velocity, note_on(60, 0)), [note_on(60, 0)]);
+
+        let mut velocity = Velocity {
+            offset: -127,
+            ..Velocity::new()
+        };
+        assert_eq!(run(&mut velocity, note_on(60, 100)), [note_on(60, 1)]);
+        assert_eq!(
+            run(&mut Velocity::fixed(64), note_on(60, 3)),
+            [note_on(60, 64)]
+        );
+        assert_eq!(
+            run(&mut Velocity::fixed(64), note_on(60, 0)),
+            [note_on(60, 0)]
+        );
+    }
+
+    #[test]
+    fn note_range() {
+        let mut range = NoteRange::new(48, 72);
+        assert_eq!(run(&mut range, note_on(48, 1)), [note_on(48, 1)]);
+        assert_eq!(run(&mut range, note_on(72, 1)), [note_on(72, 1)]);
+        assert_eq!(run(&mut range, note_on(73, 1)), []);
+        assert_eq!(run(&mut range, note_off(47)), []);
+    }
+
+    #[test]
+    fn harmonizer() {
+        let mut harmonizer = Harmonizer::new(&[4, 7, 100]);
+        assert_eq!(
+            run(&mut harmonizer, note_on(60, 90)),
+            [note_on(60, 90), note_on(64, 90), note_on(67, 90)]
+        );
+        assert_eq!(
+            run(&mut harmonizer, note_off(60)),
+            [note_off(60), note_off(64), note_off(67)]
+        );
+        assert_eq!(
+            run(&mut harmonizer, MidiMessage::Start),
+            [MidiMessage::Start]
+        );
+    }
+
+    #[test]
+    fn chains() {
+        let mut chain = Harmonizer::new(&[12])
+            .then(NoteRange::new(0, 80))
+            .then(Transpose::new(-1))
+            .then(Velocity::fixed(10));
+        assert_eq!(
+            run(&mut chain, note_on(60, 100)),
+            [note_on(59, 10), note_on(71, 10)]
+        );
+        assert_eq!(run(&mut chain, note_on(70, 100)), [note_on(69, 10)]);
+    }
+}
@@ -0,0 +1,657 @@
+use core::future::Future;
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use wmidi::MidiMessage;
+
+use crate::clock::PPQN;
+use crate::hal::midi::{CableEvent, Port, MAX_SYSEX_LENGTH};
+use crate::hal::surface::{AftertouchEvent, Button, ButtonEvent, Pads};
+use crate::hal::time::{Duration, Instant};
+use crate::hal::{LaunchpadApp, Mutex};
+
+/// The alignment of the storage for each task. Futures that need more can't be spawned.
+const TASK_ALIGN: usize = 8;
+
+/// An event from the hardware, as seen by the futures waiting on it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<'a> {
+    Init,
+    Timer,
+    Button(ButtonEvent),
+    Aftertouch(AftertouchEvent),
+    Midi(Port, MidiMessage<'a>),
+    SysEx(Port, &'a [u8]),
+    Cable(CableEvent),
+    Suspend,
+    Resume,
+}
+
+/// A copy of an event, kept by an executor while its tasks are polled.
+struct Mail {
+    /// The event, numbered so that futures only see the events that arrive after they start
+    /// waiting.
+    generation: u32,
+    /// The event, without the data of a SysEx message.
+    event: Event<'static>,
+    /// The data of a SysEx message.
+    sysex: heapless::Vec<u8, MAX_SYSEX_LENGTH>,
+}
+
+impl Mail {
+    /// Copy an event, or return `None` for SysEx that is too long to keep, or that arrives as a
+    /// MIDI message.
+    fn new(generation: u32, event: Event) -> Option<Self> {
+        let mut sysex = heapless::Vec::new();
+        let event = match event {
+            Event::Init => Event::Init,
+            Event::Timer => Event::Timer,
+            Event::Button(button_event) => Event::Button(button_event),
+            Event::Aftertouch(aftertouch_event) => Event::Aftertouch(aftertouch_event),
+            Event::Midi(port, message) => Event::Midi(port, message.drop_unowned_sysex()?),
+            Event::SysEx(port, data) => {
+                sysex = heapless::Vec::from_slice(data).ok()?;
+                Event::SysEx(port, &[])
+            }
+            Event::Cable(cable_event) => Event::Cable(cable_event),
+            Event::Suspend => Event::Suspend,
+            Event::Resume => Event::Resume,
+        };
+        Some(Mail {
+            generation,
+            event,
+            sysex,
+        })
+    }
+
+    /// Returns the event, borrowing the data of a SysEx message from the copy.
+    fn event(&self) -> Event<'_> {
+        match self.event {
+            Event::SysEx(port, _) => Event::SysEx(port, &self.sysex),
+            ref event => event.clone(),
+        }
+    }
+}
+
+/// The event an executor is delivering.
+struct Mailbox {
+    mail: Mutex<Option<Mail>>,
+    /// The number of the last event delivered.
+    generation: AtomicU32,
+}
+
+/// An error spawning a task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Every task slot is in use.
+    Full,
+    /// The future is too big or too strictly aligned for a task slot.
+    TooLarge,
+}
+
+#[repr(C, align(8))]
+struct Storage<const SIZE: usize>([MaybeUninit<u8>; SIZE]);
+
+/// The functions that poll and drop the future in a task slot, which only know its type.
+#[derive(Clone, Copy)]
+struct VTable {
+    poll: unsafe fn(*mut u8, &mut Context) -> Poll<()>,
+    drop: unsafe fn(*mut u8),
+}
+
+struct Slot<const SIZE: usize> {
+    storage: Storage<SIZE>,
+    /// Set while the slot holds a future.
+    vtable: Option<VTable>,
+}
+
+/// # Safety
+///
+/// `future` must point to a pinned, initialised `F`.
+unsafe fn poll_future<F: Future<Output = ()>>(future: *mut u8, cx: &mut Context) -> Poll<()> {
+    Pin::new_unchecked(&mut *(future as *mut F)).poll(cx)
+}
+
+/// # Safety
+///
+/// `future` must point to an initialised `F`, which is not used again.
+unsafe fn drop_future<F>(future: *mut u8) {
+    core::ptr::drop_in_place(future as *mut F);
+}
+
+/// The functions of an executor's wakers. Every task is polled on every event, so there is nothing
+/// for a waker to do, but it points to the executor's mailbox.
+static WAKER: RawWakerVTable = RawWakerVTable::new(
+    |mailbox| RawWaker::new(mailbox, &WAKER),
+    |_| {},
+    |_| {},
+    |_| {},
+);
+
+/// Returns the waker an executor polls its tasks with.
+fn waker(mailbox: &Mailbox) -> Waker {
+    let mailbox = mailbox as *const Mailbox as *const ();
+    // SAFETY: the waker functions don't use the pointer, which is only read by `mailbox`
+    unsafe { Waker::from_raw(RawWaker::new(mailbox, &WAKER)) }
+}
+
+/// Returns the mailbox of the executor polling a future, or `None` if it isn't polled by one.
+fn mailbox<'a>(cx: &'a Context) -> Option<&'a Mailbox> {
+    let waker = cx.waker();
+    // SAFETY: wakers with this vtable are only made by `waker`, from the mailbox of an executor
+    // with tasks. Tasks can only be spawned on a static executor, so the mailbox outlives them and
+    // any waker they keep
+    core::ptr::eq(waker.vtable(), &WAKER).then(|| unsafe { &*(waker.data() as *const Mailbox) })
+}
+
+/// Runs `async` tasks, polling them whenever an event arrives from the hardware.
+///
+/// The executor is an app, so it is registered with [`launchpad_app!`](crate::launchpad_app) or run
+/// by a [`Switcher`](crate::switcher::Switcher) or [`Router`](crate::router::Router). Its init
+/// function spawns the first tasks. Each of the `N` tasks is stored in the executor itself, in
+/// `SIZE` bytes, so nothing is allocated.
+///
+/// Tasks wait for events with the futures in this module, e.g. [`press`], [`midi`] and [`sleep`].
+/// A future only sees events that arrive at its own executor after it starts waiting. Each event
+/// is copied into the executor while its tasks are polled, so SysEx longer than
+/// [`MAX_SYSEX_LENGTH`] isn't delivered.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::executor::{self, Executor};
+/// use launchpad_pro_rs::hal::surface::{set_led, Button, Pads};
+/// use launchpad_pro_rs::hal::{Point, Rgb};
+/// use launchpad_pro_rs::launchpad_app;
+///
+/// /// Flash a pad for a quarter of a second each time it is pressed.
+/// async fn flash(point: Point) {
+///     loop {
+///         executor::press(Button::Pad(point)).await;
+///         set_led(point, Rgb::new(255, 255, 255));
+///         executor::sleep(250).await;
+///         set_led(point, Rgb::new(0, 0, 0));
+///     }
+/// }
+///
+/// fn init(_pads: Pads) {
+///     EXECUTOR.spawn(flash(Point::new(1, 1))).unwrap();
+///     EXECUTOR.spawn(flash(Point::new(8, 8))).unwrap();
+/// }
+///
+/// static EXECUTOR: Executor<2, 64> = Executor::new(init);
+///
+/// launchpad_app!(EXECUTOR);
+/// ```
+pub struct Executor<const N: usize, const SIZE: usize> {
+    init: fn(Pads),
+    slots: [Mutex<Slot<SIZE>>; N],
+    mailbox: Mailbox,
+}
+
+impl<const N: usize, const SIZE: usize> Executor<N, SIZE> {
+    /// Construct an executor. `init` is called on startup, and should spawn the app's tasks.
+    pub const fn new(init: fn(Pads)) -> Self {
+        Executor {
+            init,
+            slots: [const {
+                Mutex::new(Slot {
+                    storage: Storage([MaybeUninit::uninit(); SIZE]),
+                    vtable: None,
+                })
+            }; N],
+            mailbox: Mailbox {
+                mail: Mutex::new(None),
+                generation: AtomicU32::new(0),
+            },
+        }
+    }
+
+    /// Start running a task. It is first polled on the next event, or straight away if it is
+    /// spawned by another task.
+    pub fn spawn<F>(&'static self, future: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if size_of::<F>() > SIZE || align_of::<F>() > TASK_ALIGN {
+            return Err(Error::TooLarge);
+        }
+
+        // a slot that is locked is being polled, so it is in use
+        let mut slot = self
+            .slots
+            .iter()
+            .filter_map(|slot| slot.try_lock())
+            .find(|slot| slot.vtable.is_none())
+            .ok_or(Error::Full)?;
+
+        // the executor is static, so the future is never moved once it is in the slot
+        unsafe {
+            (slot.storage.0.as_mut_ptr() as *mut F).write(future);
+        }
+        slot.vtable = Some(VTable {
+            poll: poll_future::<F>,
+            drop: drop_future::<F>,
+        });
+        Ok(())
+    }
+
+    /// Returns the number of tasks that haven't finished.
+    pub fn tasks(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| match slot.try_lock() {
+                Some(slot) => slot.vtable.is_some(),
+                None => true,
+            })
+            .count()
+    }
+
+    /// Poll every task with an event, dropping those that finish.
+    fn deliver(&self, event: Event) {
+        let mailbox = &self.mailbox;
+        let generation = mailbox
+            .generation
+            .fetch_add(1, Ordering::SeqCst)
+            .wrapping_add(1);
+        let Some(mail) = Mail::new(generation, event) else {
+            return;
+        };
+        // an event delivered from a task, or preempting one, is seen instead until it is done
+        let previous = mailbox.mail.lock().replace(mail);
+
+        let waker = waker(mailbox);
+        let mut cx = Context::from_waker(&waker);
+        for slot in &self.slots {
+            // a locked slot is already being polled further up the stack
+            let mut slot = match slot.try_lock() {
+                Some(slot) => slot,
+                None => continue,
+            };
+            if let Some(vtable) = slot.vtable {
+                let future = slot.storage.0.as_mut_ptr() as *mut u8;
+                unsafe {
+                    if (vtable.poll)(future, &mut cx).is_ready() {
+                        (vtable.drop)(future);
+                        slot.vtable = None;
+                    }
+                }
+            }
+        }
+
+        *mailbox.mail.lock() = previous;
+    }
+}
+
+impl<const N: usize, const SIZE: usize> LaunchpadApp for Executor<N, SIZE> {
+    fn init_event(&self, pads: Pads) {
+        (self.init)(pads);
+        self.deliver(Event::Init);
+    }
+
+    fn timer_event(&self) {
+        self.deliver(Event::Timer);
+    }
+
+    fn midi_event(&self, port: Port, midi_event: MidiMessage) {
+        self.deliver(Event::Midi(port, midi_event));
+    }
+
+    fn sysex_event(&self, port: Port, data: &[u8]) {
+        self.deliver(Event::SysEx(port, data));
+    }
+
+    fn cable_event(&self, cable_event: CableEvent) {
+        self.deliver(Event::Cable(cable_event));
+    }
+
+    fn button_event(&self, button_event: ButtonEvent) {
+        self.deliver(Event::Button(button_event));
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: AftertouchEvent) {
+        self.deliver(Event::Aftertouch(aftertouch_event));
+    }
+
+    fn suspend_event(&self) {
+        self.deliver(Event::Suspend);
+    }
+
+    fn resume_event(&self) {
+        self.deliver(Event::Resume);
+    }
+}
+
+/// A future that waits for an event, returned by [`wait_for`].
+pub struct WaitFor<F> {
+    /// The event being delivered when the future was first polled.
+    since: Option<u32>,
+    matches: F,
+}
+
+impl<T, F: FnMut(&Event) -> Option<T> + Unpin> Future for WaitFor<F> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let this = self.get_mut();
+        let Some(mailbox) = mailbox(cx) else {
+            return Poll::Pending;
+        };
+        if let Some(mail) = &*mailbox.mail.lock() {
+            let since = *this.since.get_or_insert(mail.generation);
+            if mail.generation != since {
+                if let Some(output) = (this.matches)(&mail.event()) {
+                    return Poll::Ready(output);
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Wait for an event that `matches` maps to some output.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::executor::{wait_for, Event};
+/// use launchpad_pro_rs::hal::midi::Port;
+///
+/// /// Wait for a DIN cable to be plugged in or pulled out.
+/// async fn cable() {
+///     wait_for(|event| match event {
+///         Event::Cable(_) => Some(()),
+///         _ => None,
+///     })
+///     .await
+/// }
+/// ```
+pub fn wait_for<T, F: FnMut(&Event) -> Option<T> + Unpin>(matches: F) -> WaitFor<F> {
+    WaitFor {
+        since: None,
+        matches,
+    }
+}
+
+/// Wait for the next button or pad to be pressed or released.
+pub async fn button() -> ButtonEvent {
+    wait_for(|event| match event {
+        Event::Button(button_event) => Some(*button_event),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for a button to be pressed, returning the velocity.
+pub async fn press(button: Button) -> u8 {
+    wait_for(|event| match event {
+        Event::Button(ButtonEvent {
+            button: pressed,
+            event: crate::hal::surface::Event::Press(velocity),
+        }) if *pressed == button => Some(*velocity),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for a button to be released.
+pub async fn release(button: Button) {
+    wait_for(|event| match event {
+        Event::Button(ButtonEvent {
+            button: released,
+            event: crate::hal::surface::Event::Release,
+        }) if *released == button => Some(()),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for the next change in pressure on a pad.
+pub async fn aftertouch() -> AftertouchEvent {
+    wait_for(|event| match event {
+        Event::Aftertouch(aftertouch_event) => Some(*aftertouch_event),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for the next MIDI message. SysEx messages are returned by [`sysex`] instead.
+pub async fn midi() -> (Port, MidiMessage<'static>) {
+    wait_for(|event| match event {
+        Event::Midi(port, message) => message
+            .clone()
+            .drop_unowned_sysex()
+            .map(|message| (*port, message)),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for a beat of incoming MIDI clock, i.e. [`PPQN`] timing clock messages.
+pub async fn beat() {
+    let mut pulses = 0;
+    wait_for(move |event| match event {
+        Event::Midi(_, MidiMessage::TimingClock) => {
+            pulses += 1;
+            (pulses == PPQN).then_some(())
+        }
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for the next SysEx message that fits in `N` bytes. Longer messages are ignored.
+pub async fn sysex<const N: usize>() -> (Port, heapless::Vec<u8, N>) {
+    wait_for(|event| match event {
+        Event::SysEx(port, data) => heapless::Vec::from_slice(data)
+            .ok()
+            .map(|data| (*port, data)),
+        _ => None,
+    })
+    .await
+}
+
+/// A future that waits for a time, returned by [`sleep`].
+pub struct Sleep {
+    /// The time to wake.
+    deadline: Instant,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        match Instant::now() >= self.deadline {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    }
+}
+
+/// Wait for `ms` milliseconds.
+pub fn sleep(ms: u32) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + Duration::from_millis(ms as u64),
+    }
+}
+
+/// The error returned when a future passed to [`timeout`] takes too long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// A future that gives up on another after a time, returned by [`timeout`].
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, TimedOut>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // SAFETY: the future is never moved out of the timeout
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|_| Err(TimedOut))
+    }
+}
+
+/// Wait for a future for at most `ms` milliseconds.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::executor::{press, timeout};
+/// use launchpad_pro_rs::hal::surface::Button;
+///
+/// /// Returns whether Setup was pressed twice within half a second.
+/// async fn double_press() -> bool {
+///     press(Button::Setup).await;
+///     timeout(500, press(Button::Setup)).await.is_ok()
+/// }
+/// ```
+pub fn timeout<F: Future>(ms: u32, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(ms),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::surface;
+    use crate::hal::Point;
+    use crate::hal::timer;
+
+    /// The steps of the test app that have been reached.
+    static PROGRESS: AtomicU32 = AtomicU32::new(0);
+
+    fn step(step: u32) {
+        assert_eq!(PROGRESS.fetch_add(1, Ordering::SeqCst), step);
+    }
+
+    async fn app() {
+        step(0);
+        let velocity = press(Button::Pad(Point::new(1, 1))).await;
+        assert_eq!(velocity, 100);
+        step(1);
+
+        // the pad isn't released in time
+        let released = timeout(10, release(Button::Pad(Point::new(1, 1)))).await;
+        assert_eq!(released, Err(TimedOut));
+        step(2);
+
+        // wait for a SysEx message, and a beat of MIDI clock
+        let (port, data) = sysex::<4>().await;
+        assert_eq!((port, &data[..]), (Port::USB, &[0xf0, 0x7d, 0xf7][..]));
+        beat().await;
+        step(3);
+
+        EXECUTOR.spawn(echo()).unwrap();
+        sleep(5).await;
+        step(4);
+    }
+
+    /// Waits for a note and finishes.
+    async fn echo() {
+        let (port, message) = midi().await;
+        assert_eq!(port, Port::DIN);
+        assert!(matches!(message, MidiMessage::NoteOn(..)));
+        step(5);
+    }
+
+    fn init(_pads: Pads) {
+        EXECUTOR.spawn(app()).unwrap();
+    }
+
+    static EXECUTOR: Executor<2, 256> = Executor::new(init);
+
+    fn tick(ms: u32) {
+        for _ in 0..ms {
+            timer::tick();
+            EXECUTOR.timer_event();
+        }
+    }
+
+    fn button(button: Button, event: surface::Event) {
+        EXECUTOR.button_event(ButtonEvent { button, event });
+    }
+
+    #[test]
+    fn async_app_runs_to_completion() {
+        let adc = [0u16; 64];
+        EXECUTOR.init_event(Pads::new(adc.as_ptr()));
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 1);
+        assert_eq!(EXECUTOR.tasks(), 1);
+
+        // other buttons are ignored
+        button(Button::Setup, surface::Event::Press(127));
+        button(Button::Pad(Point::new(1, 1)), surface::Event::Press(100));
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 2);
+
+        tick(10);
+        button(Button::Pad(Point::new(1, 1)), surface::Event::Release);
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 3);
+
+        // a message that is too long is ignored
+        EXECUTOR.sysex_event(Port::DIN, &[0xf0, 0x7d, 0x01, 0x02, 0xf7]);
+        EXECUTOR.sysex_event(Port::USB, &[0xf0, 0x7d, 0xf7]);
+        for _ in 0..PPQN {
+            EXECUTOR.midi_event(Port::USB, MidiMessage::TimingClock);
+        }
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 4);
+        assert_eq!(EXECUTOR.tasks(), 2);
+
+        tick(5);
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 5);
+        assert_eq!(EXECUTOR.tasks(), 1);
+
+        let note = MidiMessage::NoteOn(wmidi::Channel::Ch1, wmidi::Note::C4, wmidi::U7::MAX);
+        EXECUTOR.midi_event(Port::DIN, note);
+        assert_eq!(PROGRESS.load(Ordering::SeqCst), 6);
+        assert_eq!(EXECUTOR.tasks(), 0);
+    }
+
+    #[test]
+    fn spawning_checks_the_task_size() {
+        static SMALL: Executor<1, 8> = Executor::new(|_| ());
+
+        assert_eq!(
+            SMALL.spawn(async {
+                sleep(1).await;
+                sleep(2).await
+            }),
+            Err(Error::TooLarge)
+        );
+        assert_eq!(SMALL.spawn(async {}), Ok(()));
+        assert_eq!(SMALL.spawn(async {}), Err(Error::Full));
+    }
+
+    #[test]
+    fn executors_only_deliver_their_own_events() {
+        static FIRST: Executor<1, 64> = Executor::new(|_| ());
+        static SECOND: Executor<1, 64> = Executor::new(|_| ());
+
+        FIRST
+            .spawn(async {
+                let (_, data) = sysex::<3>().await;
+                assert_eq!(&data[..], &[0xf0, 0x7d, 0xf7]);
+            })
+            .unwrap();
+        FIRST.timer_event();
+
+        SECOND.sysex_event(Port::USB, &[0xf0, 0x7d, 0xf7]);
+        assert_eq!(FIRST.tasks(), 1);
+
+        // the message is copied, so the task sees it after the caller's buffer is gone
+        let data = [0xf0, 0x7d, 0xf7];
+        FIRST.sysex_event(Port::USB, &data);
+        assert_eq!(FIRST.tasks(), 0);
+    }
+}
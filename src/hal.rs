@@ -1,7 +1,8 @@
 use core::ops::Add;
 pub use spin::Mutex as Mutex;
 use wmidi::MidiMessage;
-use core::convert::TryFrom;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(target_arch="arm")]
 extern "C" {
@@ -11,14 +12,27 @@ extern "C" {
     fn hal_send_sysex(port: u8, data: *const u8, length: u16);
 }
 
+#[cfg(not(target_arch="arm"))]
+std::thread_local! {
+    /// The colour of each LED plotted on this thread, read back by `hal_read_led`.
+    static LEDS: core::cell::Cell<[(u8, u8, u8); Grid::size() as usize]> =
+        const { core::cell::Cell::new([(0, 0, 0); Grid::size() as usize]) };
+}
+
 #[cfg(not(target_arch="arm"))]
 unsafe fn hal_plot_led(t: u8, index: u8, red: u8, green: u8, blue: u8) {
     println!("plot_led, type: {}, index: {}, color: ({}, {}, {})", t, index, red, green, blue);
+    LEDS.with(|leds| {
+        let mut colours = leds.get();
+        colours[index as usize] = (red, green, blue);
+        leds.set(colours);
+    });
 }
 
 #[cfg(not(target_arch="arm"))]
-unsafe fn hal_read_led(t: u8, index: u8, _red: *mut u8, _green: *mut u8, _blue: *mut u8) {
+unsafe fn hal_read_led(t: u8, index: u8, red: *mut u8, green: *mut u8, blue: *mut u8) {
     println!("read_led, type: {}, index: {}", t, index);
+    (*red, *green, *blue) = LEDS.with(|leds| leds.get()[index as usize]);
 }
 
 #[cfg(not(target_arch="arm"))]
@@ -94,6 +108,21 @@ impl Rgb {
     pub const fn new(red: u8, green: u8, blue: u8) -> Self {
         Rgb(convert_to_6_bit(red), convert_to_6_bit(green), convert_to_6_bit(blue))
     }
+
+    /// Returns the red component, from 0 to 63.
+    pub const fn red(&self) -> u8 {
+        self.0
+    }
+
+    /// Returns the green component, from 0 to 63.
+    pub const fn green(&self) -> u8 {
+        self.1
+    }
+
+    /// Returns the blue component, from 0 to 63.
+    pub const fn blue(&self) -> u8 {
+        self.2
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -170,6 +199,39 @@ impl Point {
 pub mod surface {
     use crate::hal::Point;
     use crate::hal::Rgb;
+    #[cfg(target_arch="arm")]
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    /// A bit for every LED set since [`take_written`] was last called. The bits are atomic so that
+    /// setting an LED never waits for a lock.
+    #[cfg(target_arch="arm")]
+    static WRITTEN: [AtomicU32; 4] = [const { AtomicU32::new(0) }; 4];
+
+    #[cfg(not(target_arch="arm"))]
+    std::thread_local! {
+        /// A bit for every LED set on this thread since [`take_written`] was last called.
+        static WRITTEN: core::cell::Cell<u128> = const { core::cell::Cell::new(0) };
+    }
+
+    /// Remember that an LED was set.
+    fn mark_written(index: u8) {
+        #[cfg(target_arch="arm")]
+        WRITTEN[index as usize / 32].fetch_or(1 << (index % 32), Ordering::Relaxed);
+        #[cfg(not(target_arch="arm"))]
+        WRITTEN.with(|written| written.set(written.get() | 1 << index));
+    }
+
+    /// Returns a bit for every LED set since the last call, indexed like [`Point::to_index`], and
+    /// forgets them.
+    pub(crate) fn take_written() -> u128 {
+        #[cfg(target_arch="arm")]
+        return WRITTEN
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (word, bits)| mask | (bits.swap(0, Ordering::Relaxed) as u128) << (32 * word));
+        #[cfg(not(target_arch="arm"))]
+        return WRITTEN.with(|written| written.replace(0));
+    }
 
     /// Set the colour of an LED on the grid.
     ///
@@ -187,6 +249,7 @@ pub mod surface {
             unsafe {
                 super::hal_plot_led(0, point.to_index(), rgb.0, rgb.1, rgb.2);
             };
+            mark_written(point.to_index());
         }
     }
 
@@ -213,7 +276,15 @@ pub mod surface {
         None
     }
 
+    /// Turn off every LED on the grid.
+    pub fn clear_leds() {
+        for point in super::Grid::points() {
+            set_led(point, Rgb(0, 0, 0));
+        }
+    }
+
     /// The types of button on the surface of the Launchpad Pro.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum Button {
         /// A pad button.
         Pad(Point),
@@ -222,6 +293,7 @@ pub mod surface {
     }
 
     /// The types of event that can occur on a button.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum Event {
         /// A button has been pressed. Contains the value of the button press.
         Press(u8),
@@ -230,6 +302,7 @@ pub mod surface {
     }
 
     /// Button events occur when a button is pressed or released on the Launchpad Pro.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct ButtonEvent {
         /// The button that was pressed or released.
         pub button: Button,
@@ -238,12 +311,14 @@ pub mod surface {
     }
 
     /// Aftertouch events occur when an aftertouch (pad pressure) event is reported.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct AftertouchEvent {
         pub point: Point,
         pub value: u8,
     }
 
     /// A wrapper around the raw ADC pointer to allow reading values from the pads.
+    #[derive(Clone, Copy)]
     pub struct Pads {
         adc: *const u16
     }
@@ -314,6 +389,8 @@ pub mod surface {
     }
 
     #[test]
+    // the pads read the values through a raw pointer, which the compiler can't see
+    #[allow(clippy::unnecessary_cast, unused_assignments)]
     fn read_adc_value() {
         let mut values = [0 as u16; 64];
         let pads = Pads::new(values.as_ptr());
@@ -331,6 +408,8 @@ pub mod surface {
 /// Send and receive MIDI messages.
 pub mod midi {
     pub use wmidi::MidiMessage;
+    use core::convert::TryFrom;
+    use core::sync::atomic::{AtomicU32, Ordering};
 
     /// The MIDI ports available on the Launchpad Pro.
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -340,13 +419,33 @@ pub mod midi {
         DIN = 2,
     }
 
+    impl Port {
+        /// Every MIDI port on the device.
+        pub const ALL: [Port; 3] = [Port::Standalone, Port::USB, Port::DIN];
+    }
+
+    impl TryFrom<u8> for Port {
+        type Error = ();
+
+        fn try_from(port: u8) -> Result<Self, Self::Error> {
+            match port {
+                0 => Ok(Port::Standalone),
+                1 => Ok(Port::USB),
+                2 => Ok(Port::DIN),
+                _ => Err(()),
+            }
+        }
+    }
+
     /// The MIDI DIN socket types available.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum Cable {
         MidiIn,
         MidiOut,
     }
 
     /// The events that can occur for the MIDI DIN sockets.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum CableEvent {
         Connect(Cable),
         Disconnect(Cable),
@@ -365,6 +464,9 @@ pub mod midi {
     /// send_message(Port::DIN, &msg);
     /// ```
     pub fn send_message(port: Port, message: &MidiMessage) {
+        track_held_note(port, message);
+        #[cfg(not(target_arch="arm"))]
+        super::capture::record(port, message);
         let mut data: [u8; 3] = [0; 3];
         match message.copy_to_slice(&mut data).unwrap() {
             3 => unsafe {
@@ -374,10 +476,78 @@ pub mod midi {
                 // Pad with "Reserved" message to fit in 3 bytes exactly
                 super::hal_send_midi(port as u8, data[0], data[1], 0xFD)
             },
+            1 => unsafe {
+                super::hal_send_midi(port as u8, data[0], 0xFD, 0xFD)
+            },
             _ => ()
         }
     }
 
+    /// A bit for every note on every channel of every port, set while the note is held. The bits
+    /// are atomic so that sending a note never waits for a lock.
+    static HELD_NOTES: [[[AtomicU32; 4]; 16]; 3] =
+        [const { [const { [const { AtomicU32::new(0) }; 4] }; 16] }; 3];
+
+    /// Returns the word holding the bit of a note, along with the bit.
+    fn held_note(port: Port, channel: wmidi::Channel, note: wmidi::Note) -> (&'static AtomicU32, u32) {
+        let note = u8::from(note);
+        (&HELD_NOTES[port as usize][channel.index() as usize][note as usize / 32], 1 << (note % 32))
+    }
+
+    /// Remember which notes have been started but not ended.
+    fn track_held_note(port: Port, message: &MidiMessage) {
+        let (channel, note, is_held) = match message {
+            MidiMessage::NoteOn(channel, note, velocity) => (channel, note, u8::from(*velocity) > 0),
+            MidiMessage::NoteOff(channel, note, _) => (channel, note, false),
+            _ => return,
+        };
+        let (notes, bit) = held_note(port, *channel, *note);
+        if is_held {
+            notes.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            notes.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns true if a note on has been sent on a port without a matching note off.
+    pub fn is_note_held(port: Port, channel: wmidi::Channel, note: wmidi::Note) -> bool {
+        let (notes, bit) = held_note(port, channel, note);
+        notes.load(Ordering::Relaxed) & bit != 0
+    }
+
+    /// Send a note off for every note that has been started with `send_message` but not ended, so
+    /// that nothing is left hanging when an app stops.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use launchpad_pro_rs::hal::midi::{self, MidiMessage, Port};
+    /// use wmidi::{Channel, Note, U7};
+    ///
+    /// midi::send_message(Port::USB, &MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::MAX));
+    /// assert!(midi::is_note_held(Port::USB, Channel::Ch1, Note::C4));
+    ///
+    /// midi::release_held_notes();
+    /// assert!(!midi::is_note_held(Port::USB, Channel::Ch1, Note::C4));
+    /// ```
+    pub fn release_held_notes() {
+        for (port, channels) in Port::ALL.into_iter().zip(&HELD_NOTES) {
+            for (channel, words) in channels.iter().enumerate() {
+                for (word, notes) in words.iter().enumerate() {
+                    let mut notes = notes.swap(0, Ordering::Relaxed);
+                    while notes != 0 {
+                        let note = (word * 32) as u8 + notes.trailing_zeros() as u8;
+                        notes &= notes - 1;
+                        send_message(port, &MidiMessage::NoteOff(
+                            wmidi::Channel::from_index(channel as u8).unwrap(),
+                            wmidi::Note::from_u8_lossy(note),
+                            wmidi::U7::MIN));
+                    }
+                }
+            }
+        }
+    }
+
     /// Send a SysEx message to one of the ports on the device.
     /// The caller is responsible for ensuring that the message is correctly formatted:
     ///     - Starts with 0xF0 and ends with 0xF7.
@@ -392,14 +562,43 @@ pub mod midi {
     /// send_sysex(Port::USB, &sysex_message);
     /// ```
     pub fn send_sysex(port: Port, data: &[u8]) {
-        if data.len() <= 320 {
+        if data.len() <= MAX_SYSEX_LENGTH {
             unsafe {
                 crate::hal::hal_send_sysex(port as u8, data.as_ptr(), data.len() as u16);
             }
         }
     }
+
+    /// The longest SysEx message that can be sent.
+    pub const MAX_SYSEX_LENGTH: usize = 320;
 }
 
+/// Forward incoming MIDI between the ports. SysEx longer than [`midi::MAX_SYSEX_LENGTH`] is
+/// forwarded in parts of at most that length.
+pub mod routing;
+
+/// Read and write the area of flash reserved for apps.
+pub mod flash;
+
+/// Schedule timers that fire after a delay or periodically, advanced from an app's timer event.
+pub mod timer;
+
+/// Measure time since startup, and find out when events arrived.
+pub mod time;
+
+/// Buffer events in lock-free queues, to be handled from the timer event.
+pub mod queue;
+
+/// Lock app state without spinning forever, reporting where locks were contended.
+pub mod lock;
+
+/// Answer the MIDI Universal Identity Request, and tell hosts which app is loaded.
+pub mod identity;
+
+/// Record the MIDI messages an app sends, with the time they were sent. Only on the host.
+#[cfg(not(target_arch="arm"))]
+pub mod capture;
+
 /// The EventListener trait can be implemented to receive events from the Launchpad Pro hardware.
 pub trait LaunchpadApp: Sync {
     /// Called on startup.
@@ -416,47 +615,243 @@ pub trait LaunchpadApp: Sync {
     fn button_event(&self, _button_event: surface::ButtonEvent) {}
     /// Called when an aftertouch (pad pressure) event is reported by the low level firmware.
     fn aftertouch_event(&self, _aftertouch_event: surface::AftertouchEvent) {}
+    /// Called when another app is about to take over the surface.
+    fn suspend_event(&self) {}
+    /// Called when the app takes over the surface again after being suspended. The LEDs have been
+    /// cleared and should be redrawn.
+    fn resume_event(&self) {}
 }
 
-/// Register an instance of some type that implements the `EventListener` trait to receive event
-/// notifications from the Launchpad Pro hardware.
+/// An app that owns its state and handles events through `&mut self`, so it doesn't need a
+/// [`Mutex`]. Register it with `launchpad_app!(mut App = App::new())`, or put it in a static
+/// [`Registration`] to run it in a [`Switcher`](crate::switcher::Switcher) or
+/// [`Router`](crate::router::Router).
 ///
-/// This macro should only be called once.
+/// Apps shared through a static reference implement [`LaunchpadApp`] instead, and are handled
+/// through the blanket implementation for `&'static T`.
+pub trait LaunchpadAppMut {
+    /// Called on startup.
+    fn init_event(&mut self, _pads: surface::Pads) {}
+    /// A 1 kHz (1 millisecond) timer.
+    fn timer_event(&mut self) {}
+    /// Called when a MIDI message is received from USB or DIN.
+    fn midi_event(&mut self, _port: midi::Port, _midi_event: MidiMessage) {}
+    /// Called when a SysEx message is received from USB or DIN.
+    fn sysex_event(&mut self, _port: midi::Port, _data: &[u8]) {}
+    /// Called when a MIDI DIN cable is connected or disconnected.
+    fn cable_event(&mut self, _cable_event: midi::CableEvent) {}
+    /// Called when the user presses or releases a button or pad on the surface.
+    fn button_event(&mut self, _button_event: surface::ButtonEvent) {}
+    /// Called when an aftertouch (pad pressure) event is reported by the low level firmware.
+    fn aftertouch_event(&mut self, _aftertouch_event: surface::AftertouchEvent) {}
+    /// Called when another app is about to take over the surface.
+    fn suspend_event(&mut self) {}
+    /// Called when the app takes over the surface again after being suspended. The LEDs have been
+    /// cleared and should be redrawn.
+    fn resume_event(&mut self) {}
+}
+
+impl<T: LaunchpadApp + ?Sized> LaunchpadAppMut for &'static T {
+    fn init_event(&mut self, pads: surface::Pads) {
+        LaunchpadApp::init_event(*self, pads)
+    }
+
+    fn timer_event(&mut self) {
+        LaunchpadApp::timer_event(*self)
+    }
+
+    fn midi_event(&mut self, port: midi::Port, midi_event: MidiMessage) {
+        LaunchpadApp::midi_event(*self, port, midi_event)
+    }
+
+    fn sysex_event(&mut self, port: midi::Port, data: &[u8]) {
+        LaunchpadApp::sysex_event(*self, port, data)
+    }
+
+    fn cable_event(&mut self, cable_event: midi::CableEvent) {
+        LaunchpadApp::cable_event(*self, cable_event)
+    }
+
+    fn button_event(&mut self, button_event: surface::ButtonEvent) {
+        LaunchpadApp::button_event(*self, button_event)
+    }
+
+    fn aftertouch_event(&mut self, aftertouch_event: surface::AftertouchEvent) {
+        LaunchpadApp::aftertouch_event(*self, aftertouch_event)
+    }
+
+    fn suspend_event(&mut self) {
+        LaunchpadApp::suspend_event(*self)
+    }
+
+    fn resume_event(&mut self) {
+        LaunchpadApp::resume_event(*self)
+    }
+}
+
+/// Holds an app that owns its state, handing `&mut` to one event handler at a time.
+///
+/// `launchpad_app!(mut App = App::new())` registers the app in one of these. A static registration
+/// is also a [`LaunchpadApp`], so an app written with `&mut self` can be run by a
+/// [`Switcher`](crate::switcher::Switcher) or a [`Router`](crate::router::Router), which call it
+/// from their own handlers.
+///
+/// Its handlers must not preempt each other: one called while another is running panics. Where the
+/// firmware may call handlers from interrupts, wrap the app that calls the registration, e.g. the
+/// switcher, in a [`Queued`](queue::Queued), which calls it only from the timer event.
 ///
 /// # Example
 ///
 /// ```
-/// use launchpad_pro_rs::hal::{LaunchpadApp, Point, Rgb};
-/// use launchpad_pro_rs::hal::surface::{Pads, set_led, AftertouchEvent, ButtonEvent};
+/// use launchpad_pro_rs::hal::{LaunchpadAppMut, Registration};
 /// use launchpad_pro_rs::launchpad_app;
-/// use launchpad_pro_rs::hal::midi::{MidiMessage, Port, CableEvent};
-///
-/// struct App; // define our app type
-///
-/// static APP: App = App; // create a static instance of our app
+/// use launchpad_pro_rs::switcher::Switcher;
 ///
-/// impl LaunchpadApp for App { // implement the EventListener trait for our app
-///     fn init_event(&self, _: Pads) {
-///         // when the Launchpad is initialised we will set a white LED at the center of the grid
-///         set_led(Point::new(5, 5), Rgb::new(255, 255, 255));
+/// struct Counter(u32);
+/// impl LaunchpadAppMut for Counter {
+///     fn timer_event(&mut self) {
+///         self.0 += 1;
 ///     }
 /// }
 ///
-/// launchpad_app!(APP); // register it as the global event listener
+/// static FIRST: Registration<Counter> = Registration::new(Counter(0));
+/// static SECOND: Registration<Counter> = Registration::new(Counter(0));
+/// static SWITCHER: Switcher<2> = Switcher::new([&FIRST, &SECOND]);
+///
+/// launchpad_app!(SWITCHER);
 /// ```
+pub struct Registration<A> {
+    app: UnsafeCell<A>,
+    /// Set while an event handler is running, so that a nested or concurrent call panics instead
+    /// of making a second `&mut` to the app.
+    busy: AtomicBool,
+}
+
+// SAFETY: `borrow` hands out a `&mut A` only after swapping the `busy` flag from false to true,
+// and clears it once the handler returns, so at most one `&mut A` exists at a time on any thread.
+// A handler that is nested in another, whether through a switcher calling back into the app or a
+// second thread, panics instead. Apps whose handlers may be preempted are wrapped in a `Queued`,
+// which is registered in a `Shared` and calls them only from the timer event.
+unsafe impl<A: Send> Sync for Registration<A> {}
+
+impl<A: LaunchpadAppMut> Registration<A> {
+    /// Construct a registration holding an app.
+    pub const fn new(app: A) -> Self {
+        Registration {
+            app: UnsafeCell::new(app),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    /// Call one of the app's event handlers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another of the app's handlers is still running.
+    fn borrow(&self, handler: impl FnOnce(&mut A)) {
+        assert!(!self.busy.swap(true, Ordering::Acquire), "app event handler re-entered");
 
-static mut EVENT_LISTENER: Option<&dyn LaunchpadApp> = None;
+        // SAFETY: the busy flag was clear, so no other handler holds a reference to the app
+        handler(unsafe { &mut *self.app.get() });
 
-pub fn set_listener(listener: &'static dyn LaunchpadApp) {
-    unsafe {
-        EVENT_LISTENER.replace(listener);
+        self.busy.store(false, Ordering::Release);
     }
 }
 
-#[no_mangle]
-pub extern "C" fn app_surface_event(event: u8, index: u8, value: u8) {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
-        listener.button_event(surface::ButtonEvent {
+impl<A: LaunchpadAppMut + Send> LaunchpadApp for Registration<A> {
+    fn init_event(&self, pads: surface::Pads) {
+        self.borrow(|app| app.init_event(pads))
+    }
+
+    fn timer_event(&self) {
+        self.borrow(|app| app.timer_event())
+    }
+
+    fn midi_event(&self, port: midi::Port, midi_event: MidiMessage) {
+        self.borrow(|app| app.midi_event(port, midi_event))
+    }
+
+    fn sysex_event(&self, port: midi::Port, data: &[u8]) {
+        self.borrow(|app| app.sysex_event(port, data))
+    }
+
+    fn cable_event(&self, cable_event: midi::CableEvent) {
+        self.borrow(|app| app.cable_event(cable_event))
+    }
+
+    fn button_event(&self, button_event: surface::ButtonEvent) {
+        self.borrow(|app| app.button_event(button_event))
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: surface::AftertouchEvent) {
+        self.borrow(|app| app.aftertouch_event(aftertouch_event))
+    }
+
+    fn suspend_event(&self) {
+        self.borrow(|app| app.suspend_event())
+    }
+
+    fn resume_event(&self) {
+        self.borrow(|app| app.resume_event())
+    }
+}
+
+/// Calls the event handlers of the app registered with [`launchpad_app!`](crate::launchpad_app).
+#[doc(hidden)]
+pub trait Registered {
+    type App: LaunchpadAppMut;
+
+    /// Call one of the app's event handlers, recording the time the event arrived.
+    fn with(&self, handler: impl FnOnce(&mut Self::App));
+}
+
+impl<A: LaunchpadAppMut> Registered for Registration<A> {
+    type App = A;
+
+    fn with(&self, handler: impl FnOnce(&mut A)) {
+        time::handle_at(time::Instant::now(), || self.borrow(handler));
+    }
+}
+
+/// Holds a [`LaunchpadApp`] registered by name with [`launchpad_app!`](crate::launchpad_app).
+///
+/// Its handlers take `&self`, so they are called without an exclusive borrow and may preempt each
+/// other, e.g. when the firmware calls them from interrupts. A [`Queued`](queue::Queued) app pushes
+/// events onto queues when they arrive and handles them all from the timer event, so the apps it
+/// calls, including any [`Registration`], are never preempted.
+#[doc(hidden)]
+pub struct Shared<T: ?Sized + 'static>(&'static T);
+
+impl<T: LaunchpadApp + ?Sized> Shared<T> {
+    pub const fn new(app: &'static T) -> Self {
+        Shared(app)
+    }
+}
+
+impl<T: LaunchpadApp + ?Sized> Registered for Shared<T> {
+    type App = &'static T;
+
+    fn with(&self, handler: impl FnOnce(&mut &'static T)) {
+        // each call borrows its own copy of the reference
+        let mut app = self.0;
+        time::handle_at(time::Instant::now(), || handler(&mut app));
+    }
+}
+
+/// The entry points called by the firmware, which convert the raw events and pass them to the
+/// registered app. [`launchpad_app!`](crate::launchpad_app) exports them under the names the
+/// firmware expects.
+#[doc(hidden)]
+pub mod entry {
+    use super::{identity, midi, routing, surface, timer, LaunchpadAppMut, MidiMessage, Point, Registered};
+    use core::convert::TryFrom;
+
+    pub fn init(app: &impl Registered, adc: *const u16) {
+        app.with(|app| app.init_event(surface::Pads::new(adc)));
+    }
+
+    pub fn surface_event(app: &impl Registered, event: u8, index: u8, value: u8) {
+        let button_event = surface::ButtonEvent {
             button: if event == 1 {
                 surface::Button::Setup
             } else {
@@ -467,59 +862,44 @@ pub extern "C" fn app_surface_event(event: u8, index: u8, value: u8) {
             } else {
                 surface::Event::Press(value)
             },
-        });
-    }
-}
-
-#[no_mangle]
-pub extern "C" fn app_midi_event(port: u8, status: u8, data1: u8, data2: u8) {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
-        let port = match port {
-            0 => Some(midi::Port::Standalone),
-            1 => Some(midi::Port::USB),
-            2 => Some(midi::Port::DIN),
-            _ => None,
         };
+        app.with(|app| app.button_event(button_event));
+    }
 
-        if let Some(port) = port {
+    pub fn midi_event(app: &impl Registered, port: u8, status: u8, data1: u8, data2: u8) {
+        if let Ok(port) = midi::Port::try_from(port) {
             let data = [status, data1, data2];
             let msg = MidiMessage::try_from(data.as_ref()).unwrap();
-            listener.midi_event(port, msg);
+            routing::forward(port, &msg);
+
+            app.with(|app| app.midi_event(port, msg));
         }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn app_sysex_event(port: u8, data: *mut u8, count: u16) {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
-        let port = match port {
-            0 => Some(midi::Port::Standalone),
-            1 => Some(midi::Port::USB),
-            2 => Some(midi::Port::DIN),
-            _ => None,
-        };
-
-        if let Some(port) = port {
-            let slice = unsafe { core::slice::from_raw_parts(data, count as usize) };
-            listener.sysex_event(port, slice);
+    /// # Safety
+    ///
+    /// `data` must point to `count` bytes.
+    pub unsafe fn sysex_event(app: &impl Registered, port: u8, data: *mut u8, count: u16) {
+        if let Ok(port) = midi::Port::try_from(port) {
+            let slice = core::slice::from_raw_parts(data, count as usize);
+            routing::forward_sysex(port, slice);
+
+            if identity::intercept(port, slice) {
+                return;
+            }
+            app.with(|app| app.sysex_event(port, slice));
         }
     }
 
-}
-
-#[no_mangle]
-pub extern "C" fn app_aftertouch_event(index: u8, value: u8) {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
-        listener.aftertouch_event(surface::AftertouchEvent {
+    pub fn aftertouch_event(app: &impl Registered, index: u8, value: u8) {
+        let aftertouch_event = surface::AftertouchEvent {
             point: Point::from_index(index),
             value,
-        });
+        };
+        app.with(|app| app.aftertouch_event(aftertouch_event));
     }
-}
 
-#[no_mangle]
-extern "C" fn app_cable_event(cable_type: u8, value: u8) {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
+    pub fn cable_event(app: &impl Registered, cable_type: u8, value: u8) {
         let cable_type = match cable_type {
             0 => Some(midi::Cable::MidiIn),
             1 => Some(midi::Cable::MidiOut),
@@ -527,33 +907,139 @@ extern "C" fn app_cable_event(cable_type: u8, value: u8) {
         };
 
         if let Some(cable_type) = cable_type {
-            listener.cable_event(match value {
+            let cable_event = match value {
                 0 => midi::CableEvent::Disconnect(cable_type),
                 _ => midi::CableEvent::Connect(cable_type),
-            });
+            };
+            app.with(|app| app.cable_event(cable_event));
         }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn app_timer_event() {
-    if let Some(listener) = unsafe { EVENT_LISTENER } {
-        listener.timer_event();
+    pub fn timer_event(app: &impl Registered) {
+        timer::tick();
+
+        app.with(|app| app.timer_event());
     }
 }
 
+/// Register an app to receive event notifications from the Launchpad Pro hardware.
+///
+/// An app that owns its state is registered with its type and initial value, and its handlers get
+/// `&mut self`:
+///
+/// ```
+/// use launchpad_pro_rs::hal::{LaunchpadAppMut, Point, Rgb};
+/// use launchpad_pro_rs::hal::surface::{set_led, ButtonEvent, Event};
+/// use launchpad_pro_rs::launchpad_app;
+///
+/// struct App {
+///     presses: u32,
+/// }
+///
+/// impl LaunchpadAppMut for App {
+///     fn button_event(&mut self, button_event: ButtonEvent) {
+///         if let Event::Press(_) = button_event.event {
+///             self.presses += 1;
+///         }
+///     }
+/// }
+///
+/// launchpad_app!(mut App = App { presses: 0 });
+/// ```
+///
+/// A static that implements [`LaunchpadApp`], e.g. a [`Switcher`](crate::switcher::Switcher), is
+/// registered by name. Its handlers share it, so they may be called while another is running:
+///
+/// ```
+/// use launchpad_pro_rs::hal::{LaunchpadApp, Point, Rgb};
+/// use launchpad_pro_rs::hal::surface::{Pads, set_led, AftertouchEvent, ButtonEvent};
+/// use launchpad_pro_rs::launchpad_app;
+/// use launchpad_pro_rs::hal::midi::{MidiMessage, Port, CableEvent};
+///
+/// struct App; // define our app type
+///
+/// static APP: App = App; // create a static instance of our app
+///
+/// impl LaunchpadApp for App { // implement the EventListener trait for our app
+///     fn init_event(&self, _: Pads) {
+///         // when the Launchpad is initialised we will set a white LED at the center of the grid
+///         set_led(Point::new(5, 5), Rgb::new(255, 255, 255));
+///     }
+/// }
+///
+/// launchpad_app!(APP); // register it as the global event listener
+/// ```
+///
+/// Only one app can be registered, and registering another doesn't compile:
+///
+/// ```compile_fail
+/// use launchpad_pro_rs::hal::LaunchpadApp;
+/// use launchpad_pro_rs::launchpad_app;
+///
+/// struct App;
+/// impl LaunchpadApp for App {}
+///
+/// static FIRST: App = App;
+/// static SECOND: App = App;
+///
+/// launchpad_app!(FIRST);
+///
+/// mod second {
+///     launchpad_pro_rs::launchpad_app!(super::SECOND);
+/// }
+/// ```
 #[macro_export]
 macro_rules! launchpad_app {
-    ($app:expr) => {
-        #[no_mangle]
-        pub static __LAUNCHPAD_APP: &dyn $crate::hal::LaunchpadApp = &$app;
+    (@register $registration:ty, $init:expr) => {
+        static __LAUNCHPAD_APP: $registration = $init;
 
         #[no_mangle]
         pub extern "C" fn app_init(adc: *const u16) {
-            $crate::hal::set_listener(__LAUNCHPAD_APP);
-            __LAUNCHPAD_APP.init_event($crate::hal::surface::Pads::new(adc));
+            $crate::hal::entry::init(&__LAUNCHPAD_APP, adc);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_surface_event(event: u8, index: u8, value: u8) {
+            $crate::hal::entry::surface_event(&__LAUNCHPAD_APP, event, index, value);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_midi_event(port: u8, status: u8, data1: u8, data2: u8) {
+            $crate::hal::entry::midi_event(&__LAUNCHPAD_APP, port, status, data1, data2);
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn app_sysex_event(port: u8, data: *mut u8, count: u16) {
+            $crate::hal::entry::sysex_event(&__LAUNCHPAD_APP, port, data, count);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_aftertouch_event(index: u8, value: u8) {
+            $crate::hal::entry::aftertouch_event(&__LAUNCHPAD_APP, index, value);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_cable_event(cable_type: u8, value: u8) {
+            $crate::hal::entry::cable_event(&__LAUNCHPAD_APP, cable_type, value);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_timer_event() {
+            $crate::hal::entry::timer_event(&__LAUNCHPAD_APP);
         }
     };
+    (mut $app:ty = $init:expr) => {
+        $crate::launchpad_app!(
+            @register $crate::hal::Registration<$app>,
+            $crate::hal::Registration::new($init)
+        );
+    };
+    ($app:expr) => {
+        $crate::launchpad_app!(
+            @register $crate::hal::Shared<dyn $crate::hal::LaunchpadApp>,
+            $crate::hal::Shared::new(&$app)
+        );
+    };
 }
 
 #[cfg(test)]
@@ -561,6 +1047,34 @@ mod tests {
     use super::*;
     //use wmidi::MidiMessage;
 
+    use crate::testing::Counter;
+
+    #[test]
+    fn registration_owns_the_app() {
+        static COUNTER: Counter = Counter::new();
+        let registration = Registration::new(&COUNTER);
+        entry::surface_event(&registration, 0, 11, 127);
+        entry::surface_event(&registration, 0, 11, 0);
+        assert_eq!(COUNTER.counts(), [0, 1, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "re-entered")]
+    fn registration_catches_reentry() {
+        static COUNTER: Counter = Counter::new();
+        let registration = Registration::new(&COUNTER);
+        registration.with(|_| registration.with(|_| ()));
+    }
+
+    #[test]
+    fn shared_apps_may_be_preempted() {
+        static COUNTER: Counter = Counter::new();
+        let shared = Shared::new(&COUNTER);
+        // a press interrupting the handling of another event
+        shared.with(|_| entry::surface_event(&shared, 0, 11, 127));
+        assert_eq!(COUNTER.counts(), [0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
     #[test]
     fn can_construct_point() {
         let p = Point::new(7, 3);
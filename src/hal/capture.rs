@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use super::midi::{MidiMessage, Port};
+use super::time::Instant;
+
+/// A message sent with [`send_message`](super::midi::send_message) while capturing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captured {
+    /// When the message was sent.
+    pub time: Instant,
+    /// The port it was sent on.
+    pub port: Port,
+    /// The bytes of the message.
+    pub data: Vec<u8>,
+}
+
+impl Captured {
+    /// Returns the message that was sent.
+    pub fn message(&self) -> Option<MidiMessage<'_>> {
+        MidiMessage::try_from(&self.data[..]).ok()
+    }
+}
+
+/// Writes the time in ms, the port number and the bytes in hex, separated by spaces, e.g.
+/// `1500 1 90 3c 64`. The `smf` tool reads captures in this format.
+impl fmt::Display for Captured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.time.as_millis(), self.port as u8)?;
+        for byte in &self.data {
+            write!(f, " {:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+std::thread_local! {
+    /// The messages sent on this thread since capturing started, or `None` when not capturing.
+    static CAPTURE: RefCell<Option<Vec<Captured>>> = const { RefCell::new(None) };
+}
+
+/// Start recording the messages sent on this thread, forgetting any already recorded.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::capture;
+/// use launchpad_pro_rs::hal::midi::{self, MidiMessage, Port};
+/// use launchpad_pro_rs::hal::timer;
+/// use wmidi::{Channel, Note, U7};
+///
+/// capture::start();
+/// midi::send_message(Port::USB, &MidiMessage::NoteOn(Channel::Ch2, Note::C4, U7::MAX));
+/// timer::tick();
+/// midi::send_message(Port::USB, &MidiMessage::NoteOff(Channel::Ch2, Note::C4, U7::MIN));
+///
+/// let captured = capture::stop();
+/// assert_eq!(captured.len(), 2);
+/// assert_eq!(captured[1].time.duration_since(captured[0].time).as_millis(), 1);
+/// assert!(captured[0].to_string().ends_with(" 1 91 3c 7f"));
+/// ```
+pub fn start() {
+    CAPTURE.with(|capture| *capture.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stop recording, returning the messages sent since [`start`].
+pub fn stop() -> Vec<Captured> {
+    CAPTURE.with(|capture| capture.borrow_mut().take().unwrap_or_default())
+}
+
+/// Record a message, if capturing.
+pub(crate) fn record(port: Port, message: &MidiMessage) {
+    CAPTURE.with(|capture| {
+        if let Some(captured) = capture.borrow_mut().as_mut() {
+            let mut data = vec![0; message.bytes_size()];
+            if message.copy_to_slice(&mut data).is_ok() {
+                captured.push(Captured {
+                    time: Instant::now(),
+                    port,
+                    data,
+                });
+            }
+        }
+    });
+}
@@ -0,0 +1,208 @@
+/// The size in bytes of the flash area reserved for apps.
+pub const USER_AREA_SIZE: usize = 1024;
+
+/// The value of every byte in flash that has never been written.
+pub const ERASED: u8 = 0xff;
+
+#[cfg(target_arch = "arm")]
+extern "C" {
+    fn hal_read_flash(offset: u32, data: *mut u8, length: u32);
+    fn hal_write_flash(offset: u32, data: *const u8, length: u32);
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub use host::set_path;
+
+/// On the host the user area is kept in a file. Each thread has a file of its own, so that tests
+/// running in parallel don't share their settings.
+#[cfg(not(target_arch = "arm"))]
+mod host {
+    use super::{ERASED, USER_AREA_SIZE};
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The file that holds the user area.
+    struct File {
+        path: PathBuf,
+        /// Whether the file is removed once it is no longer used.
+        is_temporary: bool,
+    }
+
+    impl File {
+        /// Returns a new, erased file in the temporary directory.
+        fn temporary() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "launchpad-pro-flash-{}-{}.bin",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = std::env::temp_dir().join(name);
+            let _ = std::fs::remove_file(&path);
+            File {
+                path,
+                is_temporary: true,
+            }
+        }
+    }
+
+    impl Drop for File {
+        fn drop(&mut self) {
+            if self.is_temporary {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+    }
+
+    std::thread_local! {
+        static FILE: RefCell<File> = RefCell::new(File::temporary());
+    }
+
+    /// Set the file that holds the user area for the current thread, e.g. so that settings
+    /// survive between runs. By default each thread has a temporary file that starts out erased
+    /// and is removed when the thread exits.
+    pub fn set_path(path: impl AsRef<Path>) {
+        FILE.with(|file| {
+            *file.borrow_mut() = File {
+                path: path.as_ref().to_path_buf(),
+                is_temporary: false,
+            }
+        });
+    }
+
+    /// Give the current thread a new temporary file, returning its path.
+    #[cfg(test)]
+    pub fn set_temporary() -> PathBuf {
+        FILE.with(|file| {
+            *file.borrow_mut() = File::temporary();
+            file.borrow().path.clone()
+        })
+    }
+
+    fn path() -> PathBuf {
+        FILE.with(|file| file.borrow().path.clone())
+    }
+
+    /// Returns the contents of the file, padded as if the rest were erased.
+    fn image() -> Vec<u8> {
+        let mut image = std::fs::read(path()).unwrap_or_default();
+        image.resize(USER_AREA_SIZE, ERASED);
+        image
+    }
+
+    pub unsafe fn hal_read_flash(offset: u32, data: *mut u8, length: u32) {
+        let offset = offset as usize;
+        let data = core::slice::from_raw_parts_mut(data, length as usize);
+        data.copy_from_slice(&image()[offset..offset + data.len()]);
+    }
+
+    pub unsafe fn hal_write_flash(offset: u32, data: *const u8, length: u32) {
+        let offset = offset as usize;
+        let data = core::slice::from_raw_parts(data, length as usize);
+        let mut image = image();
+        image[offset..offset + data.len()].copy_from_slice(data);
+        std::fs::write(path(), image).expect("failed to write the flash file");
+    }
+}
+
+#[cfg(not(target_arch = "arm"))]
+use host::{hal_read_flash, hal_write_flash};
+
+/// An error accessing the user area.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The access would go past the end of the user area.
+    OutOfBounds,
+}
+
+fn check_bounds(offset: usize, length: usize) -> Result<(), Error> {
+    match offset.checked_add(length) {
+        Some(end) if end <= USER_AREA_SIZE => Ok(()),
+        _ => Err(Error::OutOfBounds),
+    }
+}
+
+/// Read bytes from the user area, starting `offset` bytes from its beginning.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::flash;
+///
+/// let mut data = [0; 4];
+/// flash::read(0, &mut data).unwrap();
+///
+/// assert!(flash::read(1022, &mut data).is_err());
+/// ```
+pub fn read(offset: usize, data: &mut [u8]) -> Result<(), Error> {
+    check_bounds(offset, data.len())?;
+    unsafe {
+        hal_read_flash(offset as u32, data.as_mut_ptr(), data.len() as u32);
+    }
+    Ok(())
+}
+
+/// Write bytes to the user area, starting `offset` bytes from its beginning. Flash wears out, so
+/// avoid writing more often than necessary, e.g. on every timer event.
+pub fn write(offset: usize, data: &[u8]) -> Result<(), Error> {
+    check_bounds(offset, data.len())?;
+    unsafe {
+        hal_write_flash(offset as u32, data.as_ptr(), data.len() as u32);
+    }
+    Ok(())
+}
+
+/// Run a test against an empty user area in a temporary file of its own.
+#[cfg(test)]
+pub(crate) fn with_temp_file(test: impl FnOnce(&std::path::Path)) {
+    test(&host::set_temporary());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_backed_user_area() {
+        with_temp_file(|path| {
+            let mut data = [0; 4];
+            read(10, &mut data).unwrap();
+            assert_eq!(data, [ERASED; 4]);
+
+            write(11, &[1, 2]).unwrap();
+            read(10, &mut data).unwrap();
+            assert_eq!(data, [ERASED, 1, 2, ERASED]);
+            assert_eq!(
+                std::fs::metadata(path).unwrap().len(),
+                USER_AREA_SIZE as u64
+            );
+
+            assert_eq!(write(USER_AREA_SIZE - 1, &[1, 2]), Err(Error::OutOfBounds));
+            assert_eq!(read(usize::MAX, &mut data), Err(Error::OutOfBounds));
+        });
+    }
+
+    #[test]
+    fn threads_have_their_own_user_area() {
+        write(0, &[1]).unwrap();
+        let path = std::thread::spawn(|| {
+            let mut data = [0];
+            read(0, &mut data).unwrap();
+            assert_eq!(data, [ERASED]);
+
+            let path = host::set_temporary();
+            write(0, &[2]).unwrap();
+            assert!(path.exists());
+            path
+        })
+        .join()
+        .unwrap();
+
+        // the other thread's file is removed when it exits
+        assert!(!path.exists());
+        let mut data = [0];
+        read(0, &mut data).unwrap();
+        assert_eq!(data, [1]);
+    }
+}
@@ -0,0 +1,290 @@
+use core::fmt::Write;
+
+use super::midi::{self, Port};
+use super::Mutex;
+use crate::panic::SysExWriter;
+
+/// The device ID that addresses every device in a Universal SysEx message.
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// The manufacturer ID and message type that start a request for, or reply with, the app's name,
+/// version and build.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'I'];
+
+/// A request for the app's name, version and build.
+pub const APP_REQUEST: u8 = 0x01;
+
+/// The reply to an [`APP_REQUEST`].
+pub const APP_REPLY: u8 = 0x02;
+
+/// The longest app name, version or build sent in an [`APP_REPLY`]. Longer strings are truncated.
+pub const MAX_STRING_SIZE: usize = 32;
+
+/// The longest reply to either request.
+pub const MAX_REPLY_SIZE: usize = 1 + SYSEX_ID.len() + 1 + 3 * (MAX_STRING_SIZE + 1) + 1;
+
+/// A reply to an identity or app request.
+pub type Reply = heapless::Vec<u8, MAX_REPLY_SIZE>;
+
+/// How the device describes itself in reply to the Universal Identity Request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// The device ID the device answers to, as well as [`ALL_DEVICES`].
+    pub device: u8,
+    /// The manufacturer ID, one byte or three starting with zero.
+    pub manufacturer: &'static [u8],
+    /// The device family code.
+    pub family: u16,
+    /// The model number within the family.
+    pub model: u16,
+}
+
+impl Identity {
+    /// The default identity: the non-commercial manufacturer ID, and the Launchpad Pro's family
+    /// code as used by its bootloader.
+    pub const DEFAULT: Identity = Identity {
+        device: 0,
+        manufacturer: &[0x7d],
+        family: 0x51,
+        model: 0,
+    };
+}
+
+impl Default for Identity {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The app in the firmware, as returned by an [`APP_REQUEST`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct App<'a> {
+    /// A human-readable name.
+    pub name: &'a str,
+    /// The version, e.g. `env!("CARGO_PKG_VERSION")`.
+    pub version: &'a str,
+    /// Identifies the build, e.g. a commit hash passed in by the build.
+    pub build: &'a str,
+}
+
+static IDENTITY: Mutex<Identity> = Mutex::new(Identity::DEFAULT);
+
+static APP: Mutex<App<'static>> = Mutex::new(App {
+    name: "",
+    version: "",
+    build: "",
+});
+
+/// Set how the device describes itself.
+pub fn set_identity(identity: Identity) {
+    *IDENTITY.lock() = identity;
+}
+
+/// Returns how the device describes itself.
+pub fn identity() -> Identity {
+    *IDENTITY.lock()
+}
+
+/// Register the app in the firmware, so hosts can find out what is loaded.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::identity::{self, App};
+///
+/// identity::set_app(App {
+///     name: "Game of Life",
+///     version: env!("CARGO_PKG_VERSION"),
+///     build: option_env!("BUILD_HASH").unwrap_or("dev"),
+/// });
+/// ```
+pub fn set_app(app: App<'static>) {
+    *APP.lock() = app;
+}
+
+/// Returns the app registered with [`set_app`].
+pub fn app() -> App<'static> {
+    *APP.lock()
+}
+
+/// Returns the major and minor numbers of a version such as `1.2.3`, or zero where missing.
+fn major_minor(version: &str) -> [u8; 2] {
+    let mut numbers = version
+        .split('.')
+        .map(|number| number.parse::<u8>().map_or(0, |number| number.min(0x7f)));
+    [numbers.next().unwrap_or(0), numbers.next().unwrap_or(0)]
+}
+
+/// Returns the four software revision bytes sent in the identity reply: the major and minor
+/// versions of this crate, then of the app.
+pub fn version() -> [u8; 4] {
+    let [crate_major, crate_minor] = major_minor(env!("CARGO_PKG_VERSION"));
+    let [app_major, app_minor] = major_minor(app().version);
+    [crate_major, crate_minor, app_major, app_minor]
+}
+
+/// Returns the reply to a Universal Identity Request or an [`APP_REQUEST`], or `None` if the
+/// message is something else.
+///
+/// The identity reply is `F0 7E`, the device ID, `06 02`, the manufacturer ID, the family and
+/// model as two 7-bit bytes each, least significant first, then the [`version`] and `F7`. The app
+/// reply is `F0 7D 49 02`, then the name, version and build, each as ASCII ended by a zero byte,
+/// and finally `F7`.
+pub fn reply(data: &[u8]) -> Option<Reply> {
+    let mut reply = Reply::new();
+    match data {
+        [0xf0, 0x7e, device, 0x06, 0x01, 0xf7] => {
+            let identity = identity();
+            if *device != identity.device && *device != ALL_DEVICES {
+                return None;
+            }
+            let _ = reply.extend_from_slice(&[0xf0, 0x7e, identity.device & 0x7f, 0x06, 0x02]);
+            let _ = reply.extend_from_slice(identity.manufacturer);
+            for value in [identity.family, identity.model] {
+                let _ = reply.extend_from_slice(&[value as u8 & 0x7f, (value >> 7) as u8 & 0x7f]);
+            }
+            let _ = reply.extend_from_slice(&version());
+        }
+        [0xf0, id @ .., APP_REQUEST, 0xf7] if id == SYSEX_ID => {
+            let app = app();
+            let _ = reply.extend_from_slice(&[0xf0, SYSEX_ID[0], SYSEX_ID[1], APP_REPLY]);
+            for string in [app.name, app.version, app.build] {
+                let mut writer = SysExWriter {
+                    buffer: &mut reply,
+                    remaining: MAX_STRING_SIZE,
+                };
+                let _ = writer.write_str(string);
+                let _ = reply.push(0);
+            }
+        }
+        _ => return None,
+    }
+    let _ = reply.push(0xf7);
+    Some(reply)
+}
+
+/// Answer an identity or app request on the port it arrived on, returning true if the message
+/// was one, so it isn't passed to the app.
+pub(crate) fn intercept(port: Port, data: &[u8]) -> bool {
+    match reply(data) {
+        Some(reply) => {
+            midi::send_sysex(port, &reply);
+            true
+        }
+        None => false,
+    }
+}
+
+/// A Universal Identity Reply read on the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inquiry {
+    /// The device ID of the device that replied.
+    pub device: u8,
+    /// The manufacturer ID, one byte or three.
+    pub manufacturer: heapless::Vec<u8, 3>,
+    /// The device family code.
+    pub family: u16,
+    /// The model number within the family.
+    pub model: u16,
+    /// The software revision bytes.
+    pub version: [u8; 4],
+}
+
+/// Read a Universal Identity Reply from any device.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::identity;
+///
+/// let reply = identity::reply(&[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]).unwrap();
+/// let inquiry = identity::parse_identity(&reply).unwrap();
+/// assert_eq!(inquiry.family, 0x51);
+/// ```
+pub fn parse_identity(data: &[u8]) -> Option<Inquiry> {
+    let (device, body) = match data {
+        [0xf0, 0x7e, device, 0x06, 0x02, body @ .., 0xf7] => (*device, body),
+        _ => return None,
+    };
+    let manufacturer_size = if body.first() == Some(&0) { 3 } else { 1 };
+    let (manufacturer, rest) = body.split_at_checked(manufacturer_size)?;
+    let [family_low, family_high, model_low, model_high, version @ ..] = rest else {
+        return None;
+    };
+    Some(Inquiry {
+        device,
+        manufacturer: heapless::Vec::from_slice(manufacturer).ok()?,
+        family: *family_low as u16 | (*family_high as u16) << 7,
+        model: *model_low as u16 | (*model_high as u16) << 7,
+        version: version.try_into().ok()?,
+    })
+}
+
+/// Read an [`APP_REPLY`].
+pub fn parse_app(data: &[u8]) -> Option<App<'_>> {
+    let body = match data {
+        [0xf0, id @ .., 0xf7] => id.strip_prefix(&SYSEX_ID)?.strip_prefix(&[APP_REPLY])?,
+        _ => return None,
+    };
+    let mut strings = body.split(|&byte| byte == 0).map(core::str::from_utf8);
+    Some(App {
+        name: strings.next()?.ok()?,
+        version: strings.next()?.ok()?,
+        build: strings.next()?.ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one test, since the identity and app are global
+    #[test]
+    fn requests_are_answered() {
+        let request = [0xf0, 0x7e, ALL_DEVICES, 0x06, 0x01, 0xf7];
+        let inquiry = parse_identity(&reply(&request).unwrap()).unwrap();
+        assert_eq!(inquiry.manufacturer, [0x7d]);
+        assert_eq!(inquiry.version, [0, 1, 0, 0]);
+
+        set_identity(Identity {
+            device: 5,
+            manufacturer: &[0x00, 0x20, 0x29],
+            family: 0x1234,
+            model: 300,
+        });
+        set_app(App {
+            name: "Life",
+            version: "2.13.0",
+            build: "a1b2c3d",
+        });
+        assert_eq!(reply(&request), reply(&[0xf0, 0x7e, 5, 0x06, 0x01, 0xf7]));
+        assert_eq!(reply(&[0xf0, 0x7e, 4, 0x06, 0x01, 0xf7]), None);
+
+        let reply = reply(&request).unwrap();
+        assert!(reply[1..reply.len() - 1].iter().all(|&byte| byte < 0x80));
+        assert_eq!(
+            parse_identity(&reply),
+            Some(Inquiry {
+                device: 5,
+                manufacturer: heapless::Vec::from_slice(&[0x00, 0x20, 0x29]).unwrap(),
+                family: 0x1234,
+                model: 300,
+                version: [0, 1, 2, 13],
+            })
+        );
+
+        let app = super::reply(&[0xf0, 0x7d, b'I', APP_REQUEST, 0xf7]).unwrap();
+        assert_eq!(
+            parse_app(&app),
+            Some(App {
+                name: "Life",
+                version: "2.13.0",
+                build: "a1b2c3d",
+            })
+        );
+
+        assert_eq!(super::reply(&[0xf0, 0x7d, b'P', APP_REQUEST, 0xf7]), None);
+        set_identity(Identity::DEFAULT);
+        set_app(App::default());
+    }
+}
@@ -0,0 +1,87 @@
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use super::Mutex;
+
+pub use spin::MutexGuard;
+
+/// The number of times [`try_lock`] has found a mutex already locked.
+static CONTENDED: AtomicU32 = AtomicU32::new(0);
+
+/// Where [`try_lock`] last found a mutex already locked.
+static LAST_CONTENDED: AtomicPtr<Location<'static>> = AtomicPtr::new(ptr::null_mut());
+
+/// Lock contention seen by [`try_lock`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Contention {
+    /// The number of times a mutex was already locked.
+    pub count: u32,
+    /// The caller of the most recent contended [`try_lock`].
+    pub location: Option<&'static Location<'static>>,
+}
+
+/// Lock a mutex if it is free, or record the contention and return `None`.
+///
+/// A handler that calls `lock()` while an interrupted handler holds the same spin lock will wait
+/// forever. Using `try_lock` instead lets the handler skip its work, and [`contention`] reports
+/// where that happened, so that the app can be changed to avoid it, e.g. by wrapping it in a
+/// [`Queued`](super::queue::Queued).
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::{lock, Mutex};
+///
+/// static COUNT: Mutex<u32> = Mutex::new(0);
+///
+/// let held = COUNT.lock();
+/// assert!(lock::try_lock(&COUNT).is_none());
+/// drop(held);
+///
+/// *lock::try_lock(&COUNT).unwrap() += 1;
+/// assert!(lock::contention().count > 0);
+/// ```
+#[track_caller]
+pub fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    let guard = mutex.try_lock();
+    if guard.is_none() {
+        let location = Location::caller();
+        CONTENDED.fetch_add(1, Ordering::Relaxed);
+        LAST_CONTENDED.store(location as *const _ as *mut _, Ordering::Relaxed);
+    }
+    guard
+}
+
+/// Returns the contention seen by [`try_lock`] since startup.
+pub fn contention() -> Contention {
+    let location = LAST_CONTENDED.load(Ordering::Relaxed);
+    Contention {
+        count: CONTENDED.load(Ordering::Relaxed),
+        // SAFETY: the only pointers stored are from `Location::caller`, which live forever
+        location: unsafe { location.as_ref() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contention_reports_the_caller() {
+        let mutex = Mutex::new(());
+        let before = contention().count;
+
+        let held = mutex.lock();
+        let line = line!() + 1;
+        assert!(try_lock(&mutex).is_none());
+        drop(held);
+        assert!(try_lock(&mutex).is_some());
+
+        let contention = contention();
+        assert!(contention.count > before);
+        let location = contention.location.unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+    }
+}
@@ -0,0 +1,561 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use super::midi::{self, CableEvent, MidiMessage, Port};
+use super::surface::{AftertouchEvent, ButtonEvent, Pads};
+use super::time::{self, Instant};
+use super::LaunchpadApp;
+
+/// The longest SysEx message that can be queued, the longest that can be sent. Longer messages are
+/// dropped.
+pub const MAX_SYSEX_SIZE: usize = midi::MAX_SYSEX_LENGTH;
+
+/// The number of SysEx messages that can wait in the queue.
+const SYSEX_QUEUE_SIZE: usize = 4;
+
+/// A lock-free queue with one producer and one consumer.
+///
+/// The queue can live in a static. Items are pushed through a [`Producer`] and popped through a
+/// [`Consumer`], and only one of each can exist at a time, so that e.g. the firmware's event
+/// callbacks can push while the timer event pops. Asking for a second producer or consumer while
+/// the first is still alive returns `None`. Like [`heapless::spsc::Queue`], it holds `N - 1`
+/// items.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::queue::Spsc;
+///
+/// static QUEUE: Spsc<u8, 4> = Spsc::new();
+///
+/// let mut producer = QUEUE.producer().unwrap();
+/// producer.push(1).unwrap();
+/// producer.push(2).unwrap();
+///
+/// let mut consumer = QUEUE.consumer().unwrap();
+/// assert!(QUEUE.consumer().is_none());
+/// assert_eq!(consumer.pop(), Some(1));
+/// assert_eq!(consumer.pop(), Some(2));
+/// assert_eq!(consumer.pop(), None);
+/// ```
+pub struct Spsc<T, const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+    /// The index of the next item to pop, written only by the consumer.
+    head: AtomicUsize,
+    /// The index of the next item to push, written only by the producer.
+    tail: AtomicUsize,
+    /// Set while a producer exists.
+    is_producing: AtomicBool,
+    /// Set while a consumer exists.
+    is_consuming: AtomicBool,
+}
+
+// SAFETY: there is at most one producer and one consumer at a time. The producer only writes the
+// slot at the tail and the consumer only reads the slot at the head, and each slot is handed over
+// by the release store of the index that follows it
+unsafe impl<T: Send, const N: usize> Sync for Spsc<T, N> {}
+
+impl<T, const N: usize> Spsc<T, N> {
+    /// Construct an empty queue.
+    pub const fn new() -> Self {
+        Spsc {
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            is_producing: AtomicBool::new(false),
+            is_consuming: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of items the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Returns the number of items in the queue.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + N - head) % N
+    }
+
+    /// Returns true if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the queue's producer, or `None` if it is in use.
+    pub fn producer(&self) -> Option<Producer<'_, T, N>> {
+        match self.is_producing.swap(true, Ordering::Acquire) {
+            false => Some(Producer { queue: self }),
+            true => None,
+        }
+    }
+
+    /// Returns the queue's consumer, or `None` if it is in use.
+    pub fn consumer(&self) -> Option<Consumer<'_, T, N>> {
+        match self.is_consuming.swap(true, Ordering::Acquire) {
+            false => Some(Consumer { queue: self }),
+            true => None,
+        }
+    }
+}
+
+impl<T, const N: usize> Default for Spsc<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Spsc<T, N> {
+    fn drop(&mut self) {
+        if let Some(mut consumer) = self.consumer() {
+            while consumer.pop().is_some() {}
+        }
+    }
+}
+
+/// Pushes items onto an [`Spsc`] queue.
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a Spsc<T, N>,
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Add an item to the back of the queue, or give it back if the queue is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let queue = self.queue;
+        let tail = queue.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == queue.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        // SAFETY: this is the only producer, and the consumer doesn't read the slot at the tail
+        unsafe {
+            (*queue.buffer[tail].get()).write(item);
+        }
+        queue.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Drop for Producer<'_, T, N> {
+    fn drop(&mut self) {
+        self.queue.is_producing.store(false, Ordering::Release);
+    }
+}
+
+/// Takes items from an [`Spsc`] queue.
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a Spsc<T, N>,
+}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Returns the item at the front of the queue without taking it.
+    pub fn peek(&self) -> Option<&T> {
+        let queue = self.queue;
+        let head = queue.head.load(Ordering::Relaxed);
+        if head == queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: this is the only consumer, and the producer doesn't write the slot at the head
+        Some(unsafe { (*queue.buffer[head].get()).assume_init_ref() })
+    }
+
+    /// Take the item from the front of the queue.
+    pub fn pop(&mut self) -> Option<T> {
+        let queue = self.queue;
+        let head = queue.head.load(Ordering::Relaxed);
+        if head == queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: as for `peek`, and the head moves on so the item is only read once
+        let item = unsafe { (*queue.buffer[head].get()).assume_init_read() };
+        queue.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+}
+
+impl<T, const N: usize> Drop for Consumer<'_, T, N> {
+    fn drop(&mut self) {
+        self.queue.is_consuming.store(false, Ordering::Release);
+    }
+}
+
+/// An event waiting in one of a [`Queued`] app's queues, with the order and time it arrived.
+struct Entry<T> {
+    sequence: u32,
+    arrived: Instant,
+    event: T,
+}
+
+/// Buffers the events for an app, so that they are all handled from the timer event.
+///
+/// Button, aftertouch, MIDI, SysEx and cable events are pushed onto lock-free queues as they
+/// arrive, and passed to the app at the start of the next timer event, or whenever [`drain`] is
+/// called. Every handler then runs in the same context, so the app's state is never locked by one
+/// handler while another waits for it, even if the firmware calls them from interrupts. While an
+/// event is handled, [`time::event_time`] returns the time it arrived.
+///
+/// Each kind of event has its own queue, so each queue is only pushed by one of the firmware's
+/// callbacks, and events are numbered as they arrive so they are handled in the same order. Events
+/// that arrive while their queue is full, or while another event of the same kind is being pushed,
+/// are dropped and counted, as is SysEx longer than [`MAX_SYSEX_SIZE`].
+///
+/// The app is suspended as soon as the suspend event arrives. Events that arrived before it and
+/// are still queued are dropped by the next drain, so the app doesn't see them once it resumes.
+///
+/// Register a queued app by name, so that its callbacks don't borrow it exclusively and may
+/// preempt each other.
+///
+/// [`drain`]: Queued::drain
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::queue::Queued;
+/// use launchpad_pro_rs::hal::LaunchpadApp;
+/// use launchpad_pro_rs::launchpad_app;
+///
+/// struct App;
+/// impl LaunchpadApp for App {}
+///
+/// static APP: Queued<App, 32> = Queued::new(App);
+///
+/// launchpad_app!(APP);
+/// ```
+pub struct Queued<A, const N: usize> {
+    app: A,
+    /// The number given to the next event to arrive.
+    sequence: AtomicU32,
+    buttons: Spsc<Entry<ButtonEvent>, N>,
+    aftertouches: Spsc<Entry<AftertouchEvent>, N>,
+    midi: Spsc<Entry<(Port, MidiMessage<'static>)>, N>,
+    cables: Spsc<Entry<CableEvent>, N>,
+    sysex: Spsc<Entry<(Port, Sysex)>, SYSEX_QUEUE_SIZE>,
+    dropped: AtomicU32,
+    /// Set when the app is suspended, until the next drain drops the events that arrived before.
+    is_suspended: AtomicBool,
+    /// The number given to the first event to arrive after the app was suspended.
+    suspended_at: AtomicU32,
+}
+
+/// A queued SysEx message.
+type Sysex = heapless::Vec<u8, MAX_SYSEX_SIZE>;
+
+/// The consumers of a [`Queued`] app's queues, held while they are drained.
+struct Consumers<'a, const N: usize> {
+    buttons: Consumer<'a, Entry<ButtonEvent>, N>,
+    aftertouches: Consumer<'a, Entry<AftertouchEvent>, N>,
+    midi: Consumer<'a, Entry<(Port, MidiMessage<'static>)>, N>,
+    cables: Consumer<'a, Entry<CableEvent>, N>,
+    sysex: Consumer<'a, Entry<(Port, Sysex)>, SYSEX_QUEUE_SIZE>,
+}
+
+impl<A: LaunchpadApp, const N: usize> Queued<A, N> {
+    /// Wrap an app, queueing up to `N - 1` events of each kind for it.
+    pub const fn new(app: A) -> Self {
+        Queued {
+            app,
+            sequence: AtomicU32::new(0),
+            buttons: Spsc::new(),
+            aftertouches: Spsc::new(),
+            midi: Spsc::new(),
+            cables: Spsc::new(),
+            sysex: Spsc::new(),
+            dropped: AtomicU32::new(0),
+            is_suspended: AtomicBool::new(false),
+            suspended_at: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the app.
+    pub fn app(&self) -> &A {
+        &self.app
+    }
+
+    /// Returns the number of events dropped because their queue was full or busy, or because they
+    /// were too long.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn push<T, const M: usize>(&self, queue: &Spsc<Entry<T>, M>, event: T) {
+        let entry = Entry {
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            arrived: time::event_time(),
+            event,
+        };
+        // a callback that preempts another of the same kind finds the producer in use
+        let is_pushed = queue
+            .producer()
+            .is_some_and(|mut producer| producer.push(entry).is_ok());
+        if !is_pushed {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Pass the queued events to the app, oldest first. This is called at the start of each timer
+    /// event. A drain that starts while another is running, e.g. from one of the app's handlers,
+    /// leaves the events to the one already running.
+    pub fn drain(&self) {
+        let (Some(buttons), Some(aftertouches), Some(midi), Some(cables), Some(sysex)) = (
+            self.buttons.consumer(),
+            self.aftertouches.consumer(),
+            self.midi.consumer(),
+            self.cables.consumer(),
+            self.sysex.consumer(),
+        ) else {
+            return;
+        };
+        let mut queues = Consumers {
+            buttons,
+            aftertouches,
+            midi,
+            cables,
+            sysex,
+        };
+        let stale_before = self
+            .is_suspended
+            .swap(false, Ordering::Acquire)
+            .then(|| self.suspended_at.load(Ordering::Relaxed));
+        while self.handle_oldest(&mut queues, stale_before) {}
+    }
+
+    /// Pass the event that arrived first to the app, or drop it if it arrived before the sequence
+    /// number `stale_before`. Returns false if every queue is empty.
+    fn handle_oldest(&self, queues: &mut Consumers<N>, stale_before: Option<u32>) -> bool {
+        let sequences = [
+            queues.buttons.peek().map(|entry| entry.sequence),
+            queues.aftertouches.peek().map(|entry| entry.sequence),
+            queues.midi.peek().map(|entry| entry.sequence),
+            queues.cables.peek().map(|entry| entry.sequence),
+            queues.sysex.peek().map(|entry| entry.sequence),
+        ];
+        // read after the queues, so every event seen is numbered before it, even after wrapping
+        let next = self.sequence.load(Ordering::Relaxed);
+        let oldest = sequences
+            .iter()
+            .enumerate()
+            .filter_map(|(queue, sequence)| Some((queue, next.wrapping_sub((*sequence)?))))
+            .max_by_key(|&(_, age)| age);
+        let Some((queue, age)) = oldest else {
+            return false;
+        };
+        let is_stale = stale_before.is_some_and(|sequence| age > next.wrapping_sub(sequence));
+        match queue {
+            0 => handle(&mut queues.buttons, is_stale, |event| {
+                self.app.button_event(event)
+            }),
+            1 => handle(&mut queues.aftertouches, is_stale, |event| {
+                self.app.aftertouch_event(event)
+            }),
+            2 => handle(&mut queues.midi, is_stale, |(port, message)| {
+                self.app.midi_event(port, message)
+            }),
+            3 => handle(&mut queues.cables, is_stale, |event| {
+                self.app.cable_event(event)
+            }),
+            _ => handle(&mut queues.sysex, is_stale, |(port, data)| {
+                self.app.sysex_event(port, &data)
+            }),
+        }
+        true
+    }
+}
+
+/// Pass the event at the front of a queue to a handler, at the time it arrived, unless it is
+/// stale.
+fn handle<T, const M: usize>(
+    queue: &mut Consumer<Entry<T>, M>,
+    is_stale: bool,
+    handler: impl FnOnce(T),
+) {
+    if let Some(entry) = queue.pop() {
+        if !is_stale {
+            time::handle_at(entry.arrived, || handler(entry.event));
+        }
+    }
+}
+
+impl<A: LaunchpadApp, const N: usize> LaunchpadApp for Queued<A, N> {
+    fn init_event(&self, pads: Pads) {
+        self.app.init_event(pads);
+    }
+
+    fn timer_event(&self) {
+        self.drain();
+        self.app.timer_event();
+    }
+
+    fn midi_event(&self, port: Port, midi_event: MidiMessage) {
+        match midi_event.drop_unowned_sysex() {
+            Some(message) => self.push(&self.midi, (port, message)),
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn sysex_event(&self, port: Port, data: &[u8]) {
+        match heapless::Vec::from_slice(data) {
+            Ok(data) => self.push(&self.sysex, (port, data)),
+            Err(()) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn cable_event(&self, cable_event: CableEvent) {
+        self.push(&self.cables, cable_event);
+    }
+
+    fn button_event(&self, button_event: ButtonEvent) {
+        self.push(&self.buttons, button_event);
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: AftertouchEvent) {
+        self.push(&self.aftertouches, aftertouch_event);
+    }
+
+    fn suspend_event(&self) {
+        // the drain runs in the timer's context, so leave the queued events for it to drop
+        self.suspended_at
+            .store(self.sequence.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.is_suspended.store(true, Ordering::Release);
+        self.app.suspend_event();
+    }
+
+    fn resume_event(&self) {
+        self.app.resume_event();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::surface::{Button, Event};
+    use crate::hal::Mutex;
+
+    #[test]
+    fn queue_wraps_around() {
+        let queue: Spsc<u32, 3> = Spsc::new();
+        assert_eq!(queue.capacity(), 2);
+
+        let mut producer = queue.producer().unwrap();
+        let mut consumer = queue.consumer().unwrap();
+        for item in 0..10 {
+            producer.push(item).unwrap();
+            assert_eq!(queue.len(), 1);
+            assert_eq!(consumer.pop(), Some(item));
+        }
+
+        producer.push(1).unwrap();
+        producer.push(2).unwrap();
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.peek(), Some(&1));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(consumer.pop(), Some(2));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queue_has_one_producer_and_one_consumer() {
+        let queue: Spsc<u32, 3> = Spsc::new();
+        let producer = queue.producer().unwrap();
+        let consumer = queue.consumer().unwrap();
+        assert!(queue.producer().is_none());
+        assert!(queue.consumer().is_none());
+
+        drop(producer);
+        drop(consumer);
+        assert!(queue.producer().is_some());
+        assert!(queue.consumer().is_some());
+    }
+
+    /// Records the order events reach it.
+    struct Recorder {
+        log: Mutex<heapless::Vec<&'static str, 16>>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: &'static str) {
+            self.log.lock().push(event).unwrap();
+        }
+    }
+
+    impl LaunchpadApp for Recorder {
+        fn timer_event(&self) {
+            self.record("timer");
+        }
+
+        fn midi_event(&self, _port: Port, _midi_event: MidiMessage) {
+            self.record("midi");
+        }
+
+        fn sysex_event(&self, _port: Port, _data: &[u8]) {
+            self.record("sysex");
+        }
+
+        fn button_event(&self, _button_event: ButtonEvent) {
+            self.record("button");
+        }
+
+        fn suspend_event(&self) {
+            self.record("suspend");
+        }
+    }
+
+    #[test]
+    fn events_are_handled_on_the_timer() {
+        let app: Queued<Recorder, 3> = Queued::new(Recorder {
+            log: Mutex::new(heapless::Vec::new()),
+        });
+
+        app.sysex_event(Port::USB, &[0xf0, 0x7d, 0xf7]);
+        app.button_event(ButtonEvent {
+            button: Button::Setup,
+            event: Event::Press(127),
+        });
+        app.midi_event(Port::DIN, MidiMessage::TimingClock);
+        app.midi_event(Port::DIN, MidiMessage::Start);
+        assert!(app.app().log.lock().is_empty());
+
+        // the MIDI queue is full, and the message is too long
+        app.midi_event(Port::DIN, MidiMessage::Stop);
+        app.sysex_event(Port::USB, &[0; MAX_SYSEX_SIZE + 1]);
+        assert_eq!(app.dropped(), 2);
+
+        // each kind of event has its own queue, but they are handled in the order they arrived
+        app.timer_event();
+        assert_eq!(
+            app.app().log.lock().as_slice(),
+            ["sysex", "button", "midi", "midi", "timer"]
+        );
+    }
+
+    #[test]
+    fn events_from_before_a_suspension_are_dropped_on_the_timer() {
+        let app: Queued<Recorder, 3> = Queued::new(Recorder {
+            log: Mutex::new(heapless::Vec::new()),
+        });
+
+        app.button_event(ButtonEvent {
+            button: Button::Setup,
+            event: Event::Press(127),
+        });
+        app.suspend_event();
+        assert_eq!(app.app().log.lock().as_slice(), ["suspend"]);
+
+        // SysEx as long as can be sent, e.g. an RPC frame, arriving once the app has resumed
+        app.resume_event();
+        app.sysex_event(Port::USB, &[0; MAX_SYSEX_SIZE]);
+
+        app.timer_event();
+        assert_eq!(
+            app.app().log.lock().as_slice(),
+            ["suspend", "sysex", "timer"]
+        );
+        assert_eq!(app.dropped(), 0);
+    }
+}
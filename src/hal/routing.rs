@@ -0,0 +1,477 @@
+use core::ops::BitOr;
+use wmidi::Channel;
+
+use super::midi::{self, MidiMessage, Port};
+use super::Mutex;
+
+/// The number of MIDI ports on the Launchpad Pro.
+pub const PORTS: usize = 3;
+
+/// A set of MIDI message types that a route lets through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageTypes(u16);
+
+impl MessageTypes {
+    pub const NONE: MessageTypes = MessageTypes(0);
+    /// Note on and note off.
+    pub const NOTES: MessageTypes = MessageTypes(1 << 0);
+    pub const POLY_PRESSURE: MessageTypes = MessageTypes(1 << 1);
+    pub const CONTROL_CHANGE: MessageTypes = MessageTypes(1 << 2);
+    pub const PROGRAM_CHANGE: MessageTypes = MessageTypes(1 << 3);
+    pub const CHANNEL_PRESSURE: MessageTypes = MessageTypes(1 << 4);
+    pub const PITCH_BEND: MessageTypes = MessageTypes(1 << 5);
+    pub const SYSEX: MessageTypes = MessageTypes(1 << 6);
+    /// Timing clock, start, continue, stop and song position.
+    pub const CLOCK: MessageTypes = MessageTypes(1 << 7);
+    /// Every other system common and realtime message.
+    pub const SYSTEM: MessageTypes = MessageTypes(1 << 8);
+    /// Every message that carries a channel.
+    pub const CHANNEL_VOICE: MessageTypes = MessageTypes(0x3f);
+    pub const ALL: MessageTypes = MessageTypes(0x1ff);
+
+    /// Returns the type of a message.
+    pub fn of(message: &MidiMessage) -> Self {
+        match message {
+            MidiMessage::NoteOn(..) | MidiMessage::NoteOff(..) => Self::NOTES,
+            MidiMessage::PolyphonicKeyPressure(..) => Self::POLY_PRESSURE,
+            MidiMessage::ControlChange(..) => Self::CONTROL_CHANGE,
+            MidiMessage::ProgramChange(..) => Self::PROGRAM_CHANGE,
+            MidiMessage::ChannelPressure(..) => Self::CHANNEL_PRESSURE,
+            MidiMessage::PitchBendChange(..) => Self::PITCH_BEND,
+            MidiMessage::SysEx(..) => Self::SYSEX,
+            MidiMessage::TimingClock
+            | MidiMessage::Start
+            | MidiMessage::Continue
+            | MidiMessage::Stop
+            | MidiMessage::SongPositionPointer(..) => Self::CLOCK,
+            _ => Self::SYSTEM,
+        }
+    }
+
+    /// Returns true if every type in `other` is also in this set.
+    pub const fn contains(&self, other: MessageTypes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the set as a raw bitmask.
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Construct a set from a raw bitmask. Unknown bits are ignored.
+    pub const fn from_bits(bits: u16) -> Self {
+        MessageTypes(bits & Self::ALL.0)
+    }
+}
+
+impl BitOr for MessageTypes {
+    type Output = MessageTypes;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        MessageTypes(self.0 | rhs.0)
+    }
+}
+
+/// A set of MIDI channels that a route lets through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channels(u16);
+
+impl Channels {
+    pub const NONE: Channels = Channels(0);
+    pub const ALL: Channels = Channels(0xffff);
+
+    /// A set containing a single channel.
+    pub fn only(channel: Channel) -> Self {
+        Channels(1 << channel.index())
+    }
+
+    /// Returns a copy of this set with a channel added.
+    pub fn with(self, channel: Channel) -> Self {
+        Channels(self.0 | (1 << channel.index()))
+    }
+
+    /// Returns true if the channel is in this set.
+    pub fn contains(&self, channel: Channel) -> bool {
+        self.0 & (1 << channel.index()) != 0
+    }
+
+    /// Returns the set as a raw bitmask, with channel 1 in the lowest bit.
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Construct a set from a raw bitmask, with channel 1 in the lowest bit.
+    pub const fn from_bits(bits: u16) -> Self {
+        Channels(bits)
+    }
+}
+
+/// An error changing the routes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A port can't be routed to itself, as its messages would be sent back where they came from.
+    SelfRoute,
+}
+
+/// A route forwards the messages that pass its filters from one port to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route {
+    /// The types of message to forward.
+    pub types: MessageTypes,
+    /// The channels to forward channel messages on. Messages without a channel are forwarded
+    /// whenever their type passes.
+    pub channels: Channels,
+}
+
+impl Route {
+    /// A route that forwards everything.
+    pub const fn all() -> Self {
+        Route {
+            types: MessageTypes::ALL,
+            channels: Channels::ALL,
+        }
+    }
+
+    /// Returns true if a message passes the filters of this route.
+    pub fn passes(&self, message: &MidiMessage) -> bool {
+        self.types.contains(MessageTypes::of(message))
+            && message
+                .channel()
+                .is_none_or(|channel| self.channels.contains(channel))
+    }
+}
+
+/// A matrix of routes between the MIDI ports.
+///
+/// Incoming messages are forwarded by the HAL before they reach the app, so an app gets MIDI thru
+/// without handling it itself. The app still receives every message.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::midi::Port;
+/// use launchpad_pro_rs::hal::routing::{router, Channels, MessageTypes, Route};
+/// use wmidi::Channel;
+///
+/// // forward notes on channel 1 and clock from USB to DIN
+/// router().set_route(Port::USB, Port::DIN, Some(Route {
+///     types: MessageTypes::NOTES | MessageTypes::CLOCK,
+///     channels: Channels::only(Channel::Ch1),
+/// })).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Router {
+    routes: [[Option<Route>; PORTS]; PORTS],
+}
+
+/// The size of a serialised router in bytes.
+pub const SERIALIZED_SIZE: usize = PORTS * PORTS * 5;
+
+impl Router {
+    /// Construct a router without any routes.
+    pub const fn new() -> Self {
+        Router {
+            routes: [[None; PORTS]; PORTS],
+        }
+    }
+
+    /// Returns the route between two ports, if there is one.
+    pub fn route(&self, from: Port, to: Port) -> Option<Route> {
+        self.routes[from as usize][to as usize]
+    }
+
+    /// Add, replace or remove the route between two ports. Routes from a port to itself are
+    /// rejected.
+    pub fn set_route(&mut self, from: Port, to: Port, route: Option<Route>) -> Result<(), Error> {
+        if from == to && route.is_some() {
+            return Err(Error::SelfRoute);
+        }
+        self.routes[from as usize][to as usize] = route;
+        Ok(())
+    }
+
+    /// Remove every route.
+    pub fn clear(&mut self) {
+        self.routes = [[None; PORTS]; PORTS];
+    }
+
+    /// Returns the ports that a message arriving on a port should be forwarded to.
+    pub fn destinations<'a>(
+        &'a self,
+        from: Port,
+        message: &'a MidiMessage,
+    ) -> impl Iterator<Item = Port> + 'a {
+        Port::ALL.into_iter().filter(move |&to| {
+            self.route(from, to)
+                .is_some_and(|route| route.passes(message))
+        })
+    }
+
+    /// Forward a message that arrived on a port according to the routes. If a thru function is
+    /// set, the messages it produces are forwarded instead.
+    pub fn forward(&self, from: Port, message: &MidiMessage) {
+        if self.destinations(from, message).next().is_none() {
+            return;
+        }
+
+        let thru = *THRU.lock();
+        match (thru, message.clone().drop_unowned_sysex()) {
+            (Some(thru), Some(owned)) => thru(owned, &mut |processed| {
+                for to in self.destinations(from, message) {
+                    midi::send_message(to, processed);
+                }
+            }),
+            _ => {
+                for to in self.destinations(from, message) {
+                    midi::send_message(to, message);
+                }
+            }
+        }
+    }
+
+    /// Forward a SysEx message, or part of one, that arrived on a port according to the routes.
+    /// Data longer than [`MAX_SYSEX_LENGTH`](midi::MAX_SYSEX_LENGTH) is sent in parts of at most
+    /// that length, one after another.
+    pub fn forward_sysex(&self, from: Port, data: &[u8]) {
+        self.forward_sysex_with(from, data, midi::send_sysex);
+    }
+
+    /// Forward SysEx as `forward_sysex` does, sending each part with `send`.
+    fn forward_sysex_with(&self, from: Port, data: &[u8], mut send: impl FnMut(Port, &[u8])) {
+        let destinations = Port::ALL.into_iter().filter(|&to| {
+            self.route(from, to)
+                .is_some_and(|route| route.types.contains(MessageTypes::SYSEX))
+        });
+        for to in destinations {
+            for part in data.chunks(midi::MAX_SYSEX_LENGTH) {
+                send(to, part);
+            }
+        }
+    }
+
+    /// Serialise the routes so that they can be stored.
+    pub fn to_bytes(&self) -> [u8; SERIALIZED_SIZE] {
+        let mut bytes = [0; SERIALIZED_SIZE];
+        let routes = self.routes.iter().flatten();
+        for (chunk, route) in bytes.chunks_exact_mut(5).zip(routes) {
+            if let Some(route) = route {
+                chunk[0] = 1;
+                chunk[1..3].copy_from_slice(&route.types.bits().to_le_bytes());
+                chunk[3..5].copy_from_slice(&route.channels.bits().to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Restore routes serialised with `to_bytes`. Returns None if the data is malformed or routes a
+    /// port to itself.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != SERIALIZED_SIZE {
+            return None;
+        }
+
+        let mut router = Router::new();
+        let ports = Port::ALL
+            .into_iter()
+            .flat_map(|from| Port::ALL.map(|to| (from, to)));
+        for (chunk, (from, to)) in bytes.chunks_exact(5).zip(ports) {
+            let route = match chunk[0] {
+                0 => None,
+                1 => Some(Route {
+                    types: MessageTypes::from_bits(u16::from_le_bytes([chunk[1], chunk[2]])),
+                    channels: Channels::from_bits(u16::from_le_bytes([chunk[3], chunk[4]])),
+                }),
+                _ => return None,
+            };
+            router.set_route(from, to, route).ok()?;
+        }
+        Some(router)
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The routes applied to incoming messages.
+static ROUTER: Mutex<Router> = Mutex::new(Router::new());
+
+/// Returns the router applied to incoming messages, so that routes can be changed at runtime.
+pub fn router() -> spin::MutexGuard<'static, Router> {
+    ROUTER.lock()
+}
+
+/// Forward an incoming message with the router. The routes are copied first, so that the router
+/// isn't locked while the message is sent and the thru function runs.
+pub fn forward(from: Port, message: &MidiMessage) {
+    let router = *ROUTER.lock();
+    router.forward(from, message);
+}
+
+/// Forward an incoming SysEx message with the router, without keeping it locked while sending.
+pub fn forward_sysex(from: Port, data: &[u8]) {
+    let router = *ROUTER.lock();
+    router.forward_sysex(from, data);
+}
+
+/// Transforms a forwarded message, passing each message it produces to `send`.
+pub type Thru = fn(MidiMessage<'static>, &mut dyn FnMut(&MidiMessage));
+
+/// The function applied to forwarded messages.
+static THRU: Mutex<Option<Thru>> = Mutex::new(None);
+
+/// Set or remove a function that transforms every message forwarded by the router. SysEx is
+/// always forwarded unchanged. Use [`set_thru_effect`](crate::effects::set_thru_effect) to apply
+/// one of the processors in the `effects` module.
+pub fn set_thru(thru: Option<Thru>) {
+    *THRU.lock() = thru;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wmidi::{Note, U7};
+
+    fn note_on(channel: Channel) -> MidiMessage<'static> {
+        MidiMessage::NoteOn(channel, Note::C4, U7::from_u8_lossy(100))
+    }
+
+    #[test]
+    fn message_types() {
+        assert_eq!(
+            MessageTypes::of(&note_on(Channel::Ch1)),
+            MessageTypes::NOTES
+        );
+        assert_eq!(
+            MessageTypes::of(&MidiMessage::TimingClock),
+            MessageTypes::CLOCK
+        );
+        assert_eq!(
+            MessageTypes::of(&MidiMessage::ActiveSensing),
+            MessageTypes::SYSTEM
+        );
+        assert!(MessageTypes::ALL.contains(MessageTypes::SYSEX));
+        assert!(MessageTypes::CHANNEL_VOICE.contains(MessageTypes::PITCH_BEND));
+        assert!(!MessageTypes::CHANNEL_VOICE.contains(MessageTypes::CLOCK));
+    }
+
+    #[test]
+    fn routes_filter_by_type_and_channel() {
+        let route = Route {
+            types: MessageTypes::NOTES | MessageTypes::CLOCK,
+            channels: Channels::only(Channel::Ch2).with(Channel::Ch3),
+        };
+        assert!(route.passes(&note_on(Channel::Ch2)));
+        assert!(route.passes(&note_on(Channel::Ch3)));
+        assert!(!route.passes(&note_on(Channel::Ch1)));
+        assert!(route.passes(&MidiMessage::TimingClock));
+        assert!(!route.passes(&MidiMessage::ProgramChange(Channel::Ch2, U7::MIN)));
+    }
+
+    #[test]
+    fn destinations() {
+        let mut router = Router::new();
+        let message = note_on(Channel::Ch1);
+        assert_eq!(router.destinations(Port::USB, &message).count(), 0);
+
+        router
+            .set_route(Port::USB, Port::DIN, Some(Route::all()))
+            .unwrap();
+        router
+            .set_route(
+                Port::USB,
+                Port::Standalone,
+                Some(Route {
+                    types: MessageTypes::CLOCK,
+                    channels: Channels::ALL,
+                }),
+            )
+            .unwrap();
+        router
+            .set_route(Port::DIN, Port::USB, Some(Route::all()))
+            .unwrap();
+
+        assert!(router.destinations(Port::USB, &message).eq([Port::DIN]));
+        assert!(router
+            .destinations(Port::USB, &MidiMessage::Start)
+            .eq([Port::Standalone, Port::DIN]));
+
+        router.set_route(Port::USB, Port::DIN, None).unwrap();
+        assert_eq!(router.destinations(Port::USB, &message).count(), 0);
+    }
+
+    #[test]
+    fn routes_can_be_serialised() {
+        let mut router = Router::new();
+        router
+            .set_route(Port::USB, Port::DIN, Some(Route::all()))
+            .unwrap();
+        router
+            .set_route(
+                Port::DIN,
+                Port::Standalone,
+                Some(Route {
+                    types: MessageTypes::SYSEX,
+                    channels: Channels::only(Channel::Ch16),
+                }),
+            )
+            .unwrap();
+
+        let bytes = router.to_bytes();
+        assert_eq!(Router::from_bytes(&bytes), Some(router));
+        assert_eq!(Router::from_bytes(&bytes[1..]), None);
+
+        let mut corrupted = bytes;
+        corrupted[0] = 7;
+        assert_eq!(Router::from_bytes(&corrupted), None);
+
+        // the fifth route is from USB to itself
+        let mut looped = bytes;
+        looped[20] = 1;
+        assert_eq!(Router::from_bytes(&looped), None);
+    }
+
+    #[test]
+    fn ports_cant_be_routed_to_themselves() {
+        let mut router = Router::new();
+        assert_eq!(
+            router.set_route(Port::USB, Port::USB, Some(Route::all())),
+            Err(Error::SelfRoute)
+        );
+        assert_eq!(router.route(Port::USB, Port::USB), None);
+        assert_eq!(router.set_route(Port::USB, Port::USB, None), Ok(()));
+    }
+
+    #[test]
+    fn long_sysex_is_forwarded_in_parts() {
+        let mut router = Router::new();
+        router
+            .set_route(Port::USB, Port::DIN, Some(Route::all()))
+            .unwrap();
+        let mut long = [0x00; 2 * midi::MAX_SYSEX_LENGTH + 1];
+        long[0] = 0xf0;
+        long[2 * midi::MAX_SYSEX_LENGTH] = 0xf7;
+
+        let mut parts = Vec::new();
+        let mut send = |to, part: &[u8]| parts.push((to, part.to_vec()));
+        router.forward_sysex_with(Port::USB, &long, &mut send);
+        let lengths: Vec<_> = parts.iter().map(|(to, part)| (*to, part.len())).collect();
+        assert_eq!(
+            lengths,
+            [
+                (Port::DIN, midi::MAX_SYSEX_LENGTH),
+                (Port::DIN, midi::MAX_SYSEX_LENGTH),
+                (Port::DIN, 1)
+            ]
+        );
+        let sent: Vec<u8> = parts.iter().flat_map(|(_, part)| part.clone()).collect();
+        assert_eq!(sent, long);
+
+        // messages that aren't routed aren't sent
+        let mut sent = false;
+        router.forward_sysex_with(Port::DIN, &long, |_, _| sent = true);
+        assert!(!sent);
+    }
+}
@@ -0,0 +1,238 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub use core::time::Duration;
+
+/// The low 32 bits of the time in ms since startup.
+static LOW: AtomicU32 = AtomicU32::new(0);
+/// The number of times [`LOW`] has passed a multiple of 2^31, stored after it does.
+static HALVES: AtomicU32 = AtomicU32::new(0);
+/// The low 32 bits of the time the current event arrived.
+static EVENT: AtomicU32 = AtomicU32::new(0);
+
+/// A point in time, measured in ms since startup by the 1 kHz timer.
+///
+/// The Launchpad Pro firmware gives apps no finer clock, so every instant during a timer period
+/// reads the same. On the host, time only moves when [`timer::tick`](super::timer::tick) is called,
+/// so tests are deterministic.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::time::{Duration, Instant};
+/// use launchpad_pro_rs::hal::timer;
+///
+/// let start = Instant::now();
+/// for _ in 0..10 {
+///     timer::tick();
+/// }
+/// assert_eq!(start.elapsed(), Duration::from_millis(10));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The time the Launchpad Pro started.
+    pub const STARTUP: Instant = Instant(0);
+
+    /// Returns the current time.
+    pub fn now() -> Self {
+        // never waits for the timer, so it can be read from an interrupt that preempts a tick
+        let halves = HALVES.load(Ordering::Acquire);
+        combine(halves, LOW.load(Ordering::Acquire))
+    }
+
+    /// Construct an instant from the number of ms since startup.
+    pub const fn from_millis(ms: u64) -> Self {
+        Instant(ms)
+    }
+
+    /// Returns the number of ms since startup.
+    pub const fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time from an earlier instant to this one, or zero if it is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the instant a duration later, or `None` if it can't be represented.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let ms = u64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_add(ms).map(Instant)
+    }
+
+    /// Returns the instant a duration earlier, or `None` if that is before startup.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let ms = u64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_sub(ms).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Returns the time the event being handled arrived.
+///
+/// The hal records the time as each event arrives, so a handler can find out when a button was
+/// pressed or a MIDI message received, e.g. to detect gestures, estimate tempo or measure note
+/// lengths. Apps wrapped in [`Queued`](super::queue::Queued) see the time the event arrived, not
+/// the time it was handled.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::surface::{ButtonEvent, Event};
+/// use launchpad_pro_rs::hal::time::{self, Instant};
+/// use launchpad_pro_rs::hal::{LaunchpadApp, Mutex};
+///
+/// struct App {
+///     pressed: Mutex<Option<Instant>>,
+/// }
+///
+/// impl LaunchpadApp for App {
+///     fn button_event(&self, button_event: ButtonEvent) {
+///         let mut pressed = self.pressed.lock();
+///         match button_event.event {
+///             Event::Press(_) => *pressed = Some(time::event_time()),
+///             Event::Release => {
+///                 if let Some(pressed) = pressed.take() {
+///                     let _held = time::event_time() - pressed;
+///                 }
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub fn event_time() -> Instant {
+    before(Instant::now(), EVENT.load(Ordering::Acquire))
+}
+
+/// Returns the time from a count of [`HALVES`] and the low bits read after it.
+///
+/// The low bits are at most 2^31 ms ahead of the count, so they can only be one value in the 2^32
+/// ms from the time the count was stored.
+fn combine(halves: u32, low: u32) -> Instant {
+    let counted = (halves as u64) << 31;
+    Instant(counted + low.wrapping_sub(counted as u32) as u64)
+}
+
+/// Returns the latest instant at or before `now` whose low 32 bits are `low`.
+fn before(now: Instant, low: u32) -> Instant {
+    let age = (now.0 as u32).wrapping_sub(low);
+    Instant(now.0.saturating_sub(age as u64))
+}
+
+/// Handle an event that arrived at the given time, restoring the time of any event it preempted
+/// when it is done.
+pub(crate) fn handle_at<R>(arrived: Instant, handler: impl FnOnce() -> R) -> R {
+    record(&EVENT, arrived, handler)
+}
+
+fn record<R>(event: &AtomicU32, arrived: Instant, handler: impl FnOnce() -> R) -> R {
+    let preempted = event.swap(arrived.0 as u32, Ordering::AcqRel);
+    let result = handler();
+    event.store(preempted, Ordering::Release);
+    result
+}
+
+/// Move time forward by 1 ms. Called by [`timer::tick`](super::timer::tick).
+pub(crate) fn tick() {
+    let low = LOW.load(Ordering::Relaxed).wrapping_add(1);
+    LOW.store(low, Ordering::Release);
+    if low.is_multiple_of(1 << 31) {
+        HALVES.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant_arithmetic() {
+        let start = Instant::from_millis(1000);
+        let later = start + Duration::from_millis(250);
+
+        assert_eq!(later.as_millis(), 1250);
+        assert_eq!(later - start, Duration::from_millis(250));
+        assert_eq!(start.duration_since(later), Duration::ZERO);
+        assert_eq!(later - Duration::from_millis(250), start);
+        assert!(start < later);
+        assert_eq!(Instant::STARTUP.checked_sub(Duration::from_millis(1)), None);
+    }
+
+    #[test]
+    fn time_is_read_while_the_low_bits_wrap() {
+        assert_eq!(combine(0, 0x7fff_ffff), Instant::from_millis(0x7fff_ffff));
+        assert_eq!(combine(1, 0xffff_ffff), Instant::from_millis(0xffff_ffff));
+        // the low bits have wrapped, but the count hasn't been stored yet
+        assert_eq!(combine(1, 0), Instant::from_millis(0x1_0000_0000));
+        assert_eq!(combine(1, 5), Instant::from_millis(0x1_0000_0005));
+        assert_eq!(combine(2, 5), Instant::from_millis(0x1_0000_0005));
+        assert_eq!(combine(3, 0x8000_0000), Instant::from_millis(0x1_8000_0000));
+    }
+
+    #[test]
+    fn preempting_events_keep_their_own_time() {
+        let event = AtomicU32::new(0);
+        let arrived = || event.load(Ordering::Acquire);
+        record(&event, Instant::from_millis(10), || {
+            record(&event, Instant::from_millis(12), || {
+                assert_eq!(arrived(), 12)
+            });
+            assert_eq!(arrived(), 10);
+        });
+        assert_eq!(arrived(), 0);
+    }
+
+    #[test]
+    fn event_times_are_recovered_from_their_low_bits() {
+        let now = Instant::from_millis(0x1_0000_0010);
+        assert_eq!(before(now, 0x10), now);
+        assert_eq!(before(now, 0x8), Instant::from_millis(0x1_0000_0008));
+        assert_eq!(before(now, 0xffff_fff0), Instant::from_millis(0xffff_fff0));
+        assert_eq!(before(Instant::from_millis(5), 10), Instant::STARTUP);
+    }
+}
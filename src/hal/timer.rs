@@ -0,0 +1,320 @@
+/// The number of timers that can be scheduled at once.
+pub const CAPACITY: usize = 16;
+
+/// The most timers that fire on a single tick. Any others that are due fire on the following ticks
+/// and are reported as late, so that a burst of timers can't hold up the 1 kHz timer.
+pub const MAX_FIRINGS_PER_TICK: usize = 4;
+
+/// Identifies a scheduled timer, so that it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle(u32);
+
+/// An error scheduling a timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// [`CAPACITY`] timers are already scheduled.
+    Full,
+    /// A periodic timer must have a period of at least 1 ms.
+    ZeroPeriod,
+}
+
+/// Counts of the timers that have fired.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of timers that have fired.
+    pub fired: u32,
+    /// The number of timers that fired after the tick they were due on.
+    pub late: u32,
+    /// The furthest behind a timer has fired, in ms.
+    pub max_lateness: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Timer<T> {
+    handle: Handle,
+    /// The time the timer is due, in ms since startup.
+    deadline: u64,
+    /// How often a periodic timer repeats, in ms.
+    period: Option<u32>,
+    value: T,
+}
+
+/// The values of the timers due on a tick, in the order they fired.
+pub type Due<T> = heapless::Vec<T, MAX_FIRINGS_PER_TICK>;
+
+/// A queue of one-shot and periodic timers, counting time in ms.
+///
+/// Each timer carries a value that is handed back when it fires, e.g. an enum saying what the
+/// timer is for, or a function taking the app's state. An app keeps a scheduler in its state and
+/// advances it by 1 ms in its timer event, where it has `&mut` access to everything the timers need.
+/// A suspended app doesn't get timer events, so its timers wait until it resumes. A scheduler can
+/// also be driven from some other source, e.g. MIDI clock.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::timer::Scheduler;
+/// use launchpad_pro_rs::hal::LaunchpadAppMut;
+///
+/// struct App {
+///     timers: Scheduler<fn(&mut App)>,
+///     is_lit: bool,
+/// }
+///
+/// impl App {
+///     fn blink(&mut self) {
+///         self.is_lit = !self.is_lit;
+///     }
+/// }
+///
+/// impl LaunchpadAppMut for App {
+///     fn timer_event(&mut self) {
+///         for callback in self.timers.advance(1) {
+///             callback(self);
+///         }
+///     }
+/// }
+///
+/// let mut app = App { timers: Scheduler::new(), is_lit: false };
+/// let handle = app.timers.every(250, App::blink).unwrap();
+///
+/// for _ in 0..250 {
+///     app.timer_event();
+/// }
+/// assert!(app.is_lit);
+///
+/// app.timers.cancel(handle);
+/// for _ in 0..250 {
+///     app.timer_event();
+/// }
+/// assert!(app.is_lit);
+/// ```
+pub struct Scheduler<T> {
+    /// The time in ms since the scheduler was created.
+    now: u64,
+    next_handle: u32,
+    timers: heapless::Vec<Timer<T>, CAPACITY>,
+    stats: Stats,
+}
+
+impl<T: Copy> Scheduler<T> {
+    /// Construct a scheduler with no timers.
+    pub const fn new() -> Self {
+        Scheduler {
+            now: 0,
+            next_handle: 0,
+            timers: heapless::Vec::new(),
+            stats: Stats {
+                fired: 0,
+                late: 0,
+                max_lateness: 0,
+            },
+        }
+    }
+
+    /// Returns the time in ms since the scheduler was created.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Returns counts of the timers that have fired.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn schedule(&mut self, delay: u32, period: Option<u32>, value: T) -> Result<Handle, Error> {
+        let handle = Handle(self.next_handle);
+        self.timers
+            .push(Timer {
+                handle,
+                deadline: self.now + delay as u64,
+                period,
+                value,
+            })
+            .map_err(|_| Error::Full)?;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        Ok(handle)
+    }
+
+    /// Fire a timer once, `delay` ms from now. A delay of 0 fires it on the next tick.
+    pub fn after(&mut self, delay: u32, value: T) -> Result<Handle, Error> {
+        self.schedule(delay, None, value)
+    }
+
+    /// Fire a timer every `period` ms, starting `period` ms from now.
+    pub fn every(&mut self, period: u32, value: T) -> Result<Handle, Error> {
+        if period == 0 {
+            return Err(Error::ZeroPeriod);
+        }
+        self.schedule(period, Some(period), value)
+    }
+
+    /// Stop a timer. Returns false if it had already fired or been cancelled.
+    pub fn cancel(&mut self, handle: Handle) -> bool {
+        match self.timers.iter().position(|timer| timer.handle == handle) {
+            Some(index) => {
+                self.timers.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether a timer is still waiting to fire.
+    pub fn is_scheduled(&self, handle: Handle) -> bool {
+        self.timers.iter().any(|timer| timer.handle == handle)
+    }
+
+    /// Move time forward and return the values of the timers that are now due, earliest first. The
+    /// scheduler isn't borrowed while they are handled, so they are free to schedule or cancel
+    /// timers.
+    pub fn advance(&mut self, ms: u32) -> Due<T> {
+        self.now += ms as u64;
+
+        let mut due = Due::new();
+        while !due.is_full() {
+            let now = self.now;
+            let next = self
+                .timers
+                .iter_mut()
+                .filter(|timer| timer.deadline <= now)
+                .min_by_key(|timer| (timer.deadline, timer.handle.0));
+            let timer = match next {
+                Some(timer) => timer,
+                None => break,
+            };
+
+            let lateness = (now - timer.deadline) as u32;
+            let _ = due.push(timer.value);
+            let handle = timer.handle;
+            match timer.period {
+                // a periodic timer that has fallen behind skips the periods it missed
+                Some(period) => {
+                    timer.deadline += period as u64;
+                    if timer.deadline <= now {
+                        timer.deadline = now + period as u64;
+                    }
+                }
+                None => {
+                    self.cancel(handle);
+                }
+            }
+
+            self.stats.fired += 1;
+            if lateness > 0 {
+                self.stats.late += 1;
+                self.stats.max_lateness = self.stats.max_lateness.max(lateness);
+            }
+        }
+        due
+    }
+}
+
+impl<T: Copy> Default for Scheduler<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Move time forward by 1 ms. The hal calls this before each timer event; on the host, tests can
+/// call it to move time forward.
+pub fn tick() {
+    super::time::tick();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the timers in the tests are for.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Kind {
+        Once,
+        Repeated,
+    }
+
+    #[test]
+    fn one_shot_and_periodic_timers() {
+        let mut fired = Vec::new();
+        let mut run = |due: Due<Kind>| fired.extend(due);
+
+        let mut scheduler = Scheduler::new();
+        let one_shot = scheduler.after(5, Kind::Once).unwrap();
+        let periodic = scheduler.every(3, Kind::Repeated).unwrap();
+        assert_eq!(scheduler.every(0, Kind::Repeated), Err(Error::ZeroPeriod));
+
+        for _ in 0..10 {
+            run(scheduler.advance(1));
+        }
+        assert!(!scheduler.is_scheduled(one_shot));
+        assert!(!scheduler.cancel(one_shot));
+
+        assert!(scheduler.cancel(periodic));
+        run(scheduler.advance(10));
+        assert_eq!(
+            fired,
+            [Kind::Repeated, Kind::Once, Kind::Repeated, Kind::Repeated]
+        );
+        assert_eq!(
+            scheduler.stats(),
+            Stats {
+                fired: 4,
+                late: 0,
+                max_lateness: 0
+            }
+        );
+    }
+
+    #[test]
+    fn timers_fire_in_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.after(2, 3).unwrap();
+        scheduler.after(1, 1).unwrap();
+        scheduler.after(1, 2).unwrap();
+        let cancelled = scheduler.after(1, 3).unwrap();
+        scheduler.cancel(cancelled);
+
+        let mut fired = Vec::new();
+        for _ in 0..2 {
+            fired.extend(scheduler.advance(1));
+        }
+        assert_eq!(fired, [1, 2, 3]);
+    }
+
+    #[test]
+    fn late_firings_are_reported() {
+        let mut scheduler = Scheduler::new();
+        for _ in 0..MAX_FIRINGS_PER_TICK + 1 {
+            scheduler.after(1, Kind::Once).unwrap();
+        }
+        let periodic = scheduler.every(2, Kind::Repeated).unwrap();
+
+        // one timer too many is due, so it fires on the next tick
+        assert_eq!(scheduler.advance(1).len(), MAX_FIRINGS_PER_TICK);
+        assert_eq!(scheduler.advance(1).len(), 2);
+        assert_eq!(
+            scheduler.stats(),
+            Stats {
+                fired: 6,
+                late: 1,
+                max_lateness: 1
+            }
+        );
+
+        // a periodic timer that misses several periods fires once, then keeps to its period
+        assert_eq!(scheduler.advance(5).len(), 1);
+        assert_eq!(scheduler.stats().max_lateness, 3);
+        assert!(scheduler.advance(1).is_empty());
+        assert_eq!(scheduler.advance(1).len(), 1);
+        assert!(scheduler.is_scheduled(periodic));
+    }
+
+    #[test]
+    fn too_many_timers() {
+        let mut scheduler = Scheduler::new();
+        for _ in 0..CAPACITY {
+            scheduler.after(1, ()).unwrap();
+        }
+        assert_eq!(scheduler.after(1, ()), Err(Error::Full));
+    }
+}
@@ -0,0 +1,441 @@
+use crate::hal::surface::set_led;
+use crate::hal::{Point, Rgb};
+use crate::scale::{Key, Scale};
+use crate::settings::{self, Reader, Settings, Writer};
+
+/// The number of pads along each side of the 8x8 pad area.
+pub const PADS: i8 = 8;
+
+/// The number of semitones in an octave.
+const OCTAVE: i16 = 12;
+
+/// The lowest note a layout can place on the bottom-left pad.
+const DEFAULT_BASE: u8 = 36;
+
+/// A way of arranging notes on the pads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Semitones from left to right, with each row starting `offset` semitones above the row
+    /// below it.
+    Rows { offset: u8 },
+    /// Whole tones from left to right, with rows alternately a fourth and a fifth above the row
+    /// below, approximating the Wicki-Hayden hexagonal layout.
+    WickiHayden,
+    /// Fifths from bottom to top, with columns alternately a major third up and a minor third down,
+    /// approximating the harmonic table hexagonal layout.
+    HarmonicTable,
+    /// Pairs of rows that look like a piano keyboard, the white keys on the lower row and the black
+    /// keys above them. Each pair of rows is an octave above the pair below.
+    Piano,
+}
+
+impl Style {
+    /// Chromatic rows a fourth apart, as on a bass or guitar.
+    pub const FOURTHS: Style = Style::Rows { offset: 5 };
+
+    /// Chromatic rows a fifth apart, as on a violin or cello.
+    pub const FIFTHS: Style = Style::Rows { offset: 7 };
+
+    /// Chromatic rows that continue on from the end of the row below.
+    pub const LINEAR: Style = Style::Rows { offset: PADS as u8 };
+
+    /// Returns the number of semitones above the bottom-left pad of the pad in the given column and
+    /// row, each in the range `[0, 8)`, or None if the pad has no note.
+    fn offset(&self, column: i8, row: i8) -> Option<i16> {
+        let (column, row) = (column as i16, row as i16);
+        match self {
+            Style::Rows { offset } => Some(column + row * *offset as i16),
+            Style::WickiHayden => Some(column * 2 + (row / 2) * OCTAVE + (row % 2) * 5),
+            Style::HarmonicTable => Some(row * 7 + column / 2 + (column % 2) * 4),
+            Style::Piano => {
+                const WHITE: [i16; 8] = [0, 2, 4, 5, 7, 9, 11, 12];
+                const BLACK: [Option<i16>; 8] = [
+                    None,
+                    Some(1),
+                    Some(3),
+                    None,
+                    Some(6),
+                    Some(8),
+                    Some(10),
+                    None,
+                ];
+                let octave = (row / 2) * OCTAVE;
+                if row % 2 == 0 {
+                    Some(octave + WHITE[column as usize])
+                } else {
+                    BLACK[column as usize].map(|note| octave + note)
+                }
+            }
+        }
+    }
+}
+
+/// The colours used to draw a layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// The colour of pads playing the root of the key.
+    pub root: Rgb,
+    /// The colour of pads playing other notes in the key.
+    pub in_key: Rgb,
+    /// The colour of pads playing notes outside the key.
+    pub out_of_key: Rgb,
+    /// The colour of pads with no note.
+    pub none: Rgb,
+}
+
+impl Palette {
+    /// Construct the default palette.
+    pub const fn new() -> Self {
+        Palette {
+            root: Rgb::new(0, 0x7f, 0xff),
+            in_key: Rgb::new(0x7f, 0x7f, 0x7f),
+            out_of_key: Rgb::new(0x0f, 0x0f, 0x0f),
+            none: Rgb::new(0, 0, 0),
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mapping between the 8x8 pads and MIDI notes.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::Point;
+/// use launchpad_pro_rs::layout::{Layout, Style};
+///
+/// let mut layout = Layout::new(Style::FOURTHS);
+/// assert_eq!(layout.note(Point::new(1, 1)), Some(36));
+/// assert_eq!(layout.note(Point::new(1, 2)), Some(41));
+///
+/// layout.shift_octave(1);
+/// assert_eq!(layout.point(48), Some(Point::new(1, 1)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    style: Style,
+    base: u8,
+    octave: i8,
+    transpose: i8,
+    key: Key,
+    scale_lock: bool,
+    palette: Palette,
+}
+
+impl Layout {
+    /// Construct a layout with the given style, with C2 on the bottom-left pad and a key of
+    /// C major.
+    pub const fn new(style: Style) -> Self {
+        Layout {
+            style,
+            base: DEFAULT_BASE,
+            octave: 0,
+            transpose: 0,
+            key: Key::new(0, Scale::MAJOR),
+            scale_lock: false,
+            palette: Palette::new(),
+        }
+    }
+
+    /// Returns the style of the layout.
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    /// Set the style of the layout.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// Returns the note on the bottom-left pad before any octave shift or transposition.
+    pub fn base(&self) -> u8 {
+        self.base
+    }
+
+    /// Set the note on the bottom-left pad before any octave shift or transposition.
+    pub fn set_base(&mut self, base: u8) {
+        self.base = base.min(127);
+    }
+
+    /// Returns the number of octaves the layout is shifted by.
+    pub fn octave(&self) -> i8 {
+        self.octave
+    }
+
+    /// Set the number of octaves the layout is shifted by.
+    pub fn set_octave(&mut self, octave: i8) {
+        self.octave = octave.clamp(-10, 10);
+    }
+
+    /// Shift the layout up or down by a number of octaves.
+    pub fn shift_octave(&mut self, octaves: i8) {
+        self.set_octave(self.octave.saturating_add(octaves));
+    }
+
+    /// Returns the number of semitones the layout is transposed by, not counting octave shifts.
+    pub fn transpose(&self) -> i8 {
+        self.transpose
+    }
+
+    /// Set the number of semitones the layout is transposed by.
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transpose = semitones.clamp(-127, 127);
+    }
+
+    /// Returns the key used to colour the pads.
+    pub fn key(&self) -> Key {
+        self.key
+    }
+
+    /// Set the key used to colour the pads.
+    pub fn set_key(&mut self, key: Key) {
+        self.key = key;
+    }
+
+    /// Returns true if pads playing notes outside the key are hidden.
+    pub fn is_scale_locked(&self) -> bool {
+        self.scale_lock
+    }
+
+    /// Set whether pads playing notes outside the key are hidden. Hidden pads have no note and are
+    /// drawn unlit.
+    pub fn set_scale_lock(&mut self, scale_lock: bool) {
+        self.scale_lock = scale_lock;
+    }
+
+    /// Returns the colours used to draw the layout.
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Set the colours used to draw the layout.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Returns the MIDI note played by a pad, or None if the point is not one of the 8x8 pads or
+    /// the pad has no note.
+    pub fn note(&self, point: Point) -> Option<u8> {
+        let (column, row) = (point.x() - 1, point.y() - 1);
+        if !(0..PADS).contains(&column) || !(0..PADS).contains(&row) {
+            return None;
+        }
+        let note = self.base as i16
+            + self.octave as i16 * OCTAVE
+            + self.transpose as i16
+            + self.style.offset(column, row)?;
+        let note = u8::try_from(note).ok().filter(|&note| note <= 127)?;
+        (!self.scale_lock || self.key.contains(note)).then_some(note)
+    }
+
+    /// Returns an iterator over every pad that plays a note. Isomorphic layouts may have several.
+    pub fn points(&self, note: u8) -> impl Iterator<Item = Point> + '_ {
+        pads().filter(move |&point| self.note(point) == Some(note))
+    }
+
+    /// Returns the lowest, leftmost pad that plays a note.
+    pub fn point(&self, note: u8) -> Option<Point> {
+        self.points(note).next()
+    }
+
+    /// Returns the colour of a pad.
+    pub fn colour(&self, point: Point) -> Rgb {
+        match self.note(point) {
+            None => self.palette.none,
+            Some(note) if self.key.is_root(note) => self.palette.root,
+            Some(note) if self.key.contains(note) => self.palette.in_key,
+            Some(_) => self.palette.out_of_key,
+        }
+    }
+
+    /// Set the LEDs of the 8x8 pads to show the layout.
+    pub fn draw(&self) {
+        for point in pads() {
+            set_led(point, self.colour(point));
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new(Style::FOURTHS)
+    }
+}
+
+/// Everything but the palette is saved.
+impl Settings for Layout {
+    const VERSION: u8 = 1;
+
+    fn serialize(&self, writer: &mut Writer) -> Result<(), settings::Error> {
+        let (style, offset) = match self.style {
+            Style::Rows { offset } => (0, offset),
+            Style::WickiHayden => (1, 0),
+            Style::HarmonicTable => (2, 0),
+            Style::Piano => (3, 0),
+        };
+        writer.put_u8(style)?;
+        writer.put_u8(offset)?;
+        writer.put_u8(self.base)?;
+        writer.put_i8(self.octave)?;
+        writer.put_i8(self.transpose)?;
+        writer.put_u8(self.key.root())?;
+        writer.put_u16(self.key.scale().mask())?;
+        writer.put_bool(self.scale_lock)
+    }
+
+    fn deserialize(reader: &mut Reader) -> Result<Self, settings::Error> {
+        let style = match (reader.get_u8()?, reader.get_u8()?) {
+            (0, offset) => Style::Rows { offset },
+            (1, _) => Style::WickiHayden,
+            (2, _) => Style::HarmonicTable,
+            (3, _) => Style::Piano,
+            _ => return Err(settings::Error::Invalid),
+        };
+        let mut layout = Layout::new(style);
+        layout.set_base(reader.get_u8()?);
+        layout.set_octave(reader.get_i8()?);
+        layout.set_transpose(reader.get_i8()?);
+        let root = reader.get_u8()?;
+        layout.set_key(Key::new(root, Scale::new(reader.get_u16()?)));
+        layout.set_scale_lock(reader.get_bool()?);
+        Ok(layout)
+    }
+}
+
+/// Returns an iterator over the 8x8 pads, row by row from the bottom-left.
+pub fn pads() -> impl Iterator<Item = Point> {
+    (1..=PADS).flat_map(|y| (1..=PADS).map(move |x| Point::new(x, y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows() {
+        let layout = Layout::new(Style::FIFTHS);
+        assert_eq!(layout.note(Point::new(1, 1)), Some(36));
+        assert_eq!(layout.note(Point::new(8, 1)), Some(43));
+        assert_eq!(layout.note(Point::new(1, 2)), Some(43));
+
+        let layout = Layout::new(Style::LINEAR);
+        assert_eq!(layout.note(Point::new(1, 2)), Some(44));
+        assert_eq!(layout.note(Point::new(8, 8)), Some(99));
+
+        let layout = Layout::new(Style::Rows { offset: 4 });
+        assert_eq!(layout.note(Point::new(2, 3)), Some(45));
+    }
+
+    #[test]
+    fn outside_pads() {
+        let layout = Layout::default();
+        assert_eq!(layout.note(Point::new(0, 1)), None);
+        assert_eq!(layout.note(Point::new(9, 1)), None);
+        assert_eq!(layout.note(Point::new(1, 9)), None);
+    }
+
+    #[test]
+    fn hexagonal() {
+        let layout = Layout::new(Style::WickiHayden);
+        let notes: [Option<u8>; 4] =
+            [(1, 1), (2, 1), (1, 2), (1, 3)].map(|(x, y)| layout.note(Point::new(x, y)));
+        assert_eq!(notes, [Some(36), Some(38), Some(41), Some(48)]);
+
+        let layout = Layout::new(Style::HarmonicTable);
+        let notes: [Option<u8>; 4] =
+            [(1, 1), (2, 1), (3, 1), (1, 2)].map(|(x, y)| layout.note(Point::new(x, y)));
+        assert_eq!(notes, [Some(36), Some(40), Some(37), Some(43)]);
+    }
+
+    #[test]
+    fn piano() {
+        let layout = Layout::new(Style::Piano);
+        let white: [Option<u8>; 8] =
+            core::array::from_fn(|x| layout.note(Point::new(x as i8 + 1, 1)));
+        assert_eq!(white, [36, 38, 40, 41, 43, 45, 47, 48].map(Some));
+        assert_eq!(layout.note(Point::new(1, 2)), None);
+        assert_eq!(layout.note(Point::new(2, 2)), Some(37));
+        assert_eq!(layout.note(Point::new(1, 3)), Some(48));
+        assert_eq!(layout.point(37), Some(Point::new(2, 2)));
+    }
+
+    #[test]
+    fn shift_and_reverse() {
+        let mut layout = Layout::new(Style::FOURTHS);
+        layout.shift_octave(-1);
+        layout.set_transpose(2);
+        assert_eq!(layout.note(Point::new(1, 1)), Some(26));
+        assert!(layout.points(31).eq([Point::new(6, 1), Point::new(1, 2)]));
+        assert_eq!(layout.point(20), None);
+
+        layout.set_transpose(0);
+        layout.shift_octave(-3);
+        assert_eq!(layout.note(Point::new(1, 1)), None);
+        assert_eq!(layout.note(Point::new(8, 2)), Some(0));
+    }
+
+    #[test]
+    fn colours() {
+        let mut layout = Layout::new(Style::LINEAR);
+        layout.set_key(Key::new(2, Scale::MAJOR));
+        let palette = layout.palette();
+        assert_eq!(layout.colour(Point::new(3, 1)), palette.root);
+        assert_eq!(layout.colour(Point::new(5, 1)), palette.in_key);
+        assert_eq!(layout.colour(Point::new(4, 1)), palette.out_of_key);
+        assert_eq!(
+            Layout::new(Style::Piano).colour(Point::new(1, 2)),
+            palette.none
+        );
+    }
+
+    #[test]
+    fn scale_lock() {
+        let mut layout = Layout::new(Style::FOURTHS);
+        layout.set_key(Key::new(0, Scale::MINOR_PENTATONIC));
+        layout.set_scale_lock(true);
+        let row: [Option<u8>; 8] =
+            core::array::from_fn(|x| layout.note(Point::new(x as i8 + 1, 1)));
+        assert_eq!(
+            row,
+            [
+                Some(36),
+                None,
+                None,
+                Some(39),
+                None,
+                Some(41),
+                None,
+                Some(43)
+            ]
+        );
+        assert_eq!(layout.colour(Point::new(2, 1)), layout.palette().none);
+        assert_eq!(layout.point(37), None);
+
+        layout.set_scale_lock(false);
+        assert_eq!(layout.point(37), Some(Point::new(2, 1)));
+    }
+
+    #[test]
+    fn settings() {
+        let mut layout = Layout::new(Style::Rows { offset: 3 });
+        layout.shift_octave(-2);
+        layout.set_transpose(5);
+        layout.set_key(Key::new(4, Scale::BLUES));
+        layout.set_scale_lock(true);
+
+        let mut buffer = [0; 32];
+        let length = settings::encode(&layout, &mut buffer).unwrap();
+        assert_eq!(settings::decode(&buffer[..length]), Ok(layout));
+
+        let length = settings::encode(&Layout::new(Style::Piano), &mut buffer).unwrap();
+        assert_eq!(
+            settings::decode::<Layout>(&buffer[..length]).map(|layout| layout.style()),
+            Ok(Style::Piano)
+        );
+    }
+}
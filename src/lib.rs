@@ -1,4 +1,56 @@
 #![cfg_attr(target_arch="arm", no_std)]
 
 /// The interface to the Launchpad Pro hardware.
-pub mod hal;
\ No newline at end of file
+pub mod hal;
+
+/// A musical clock driven by the internal timer or by MIDI clock.
+pub mod clock;
+
+/// A polyphonic step sequencer engine.
+pub mod sequencer;
+
+/// An arpeggiator for held notes.
+pub mod arpeggiator;
+
+/// Composable processors that transform MIDI messages.
+pub mod effects;
+
+/// Isomorphic and piano-style mappings between the pads and notes.
+pub mod layout;
+
+/// Scales, modes and keys.
+pub mod scale;
+
+/// Versioned, checksummed settings stored in flash.
+pub mod settings;
+
+/// Named preset slots saved in flash.
+pub mod presets;
+
+/// Run several apps in one firmware image and switch between them.
+pub mod switcher;
+
+/// Split an app into components that each handle part of the surface.
+pub mod router;
+
+/// Write app logic as `async` tasks that wait for events from the hardware.
+pub mod executor;
+
+/// Show panics on the LEDs and report them over SysEx.
+pub mod panic;
+
+/// Send `log` messages to the host over SysEx.
+pub mod logger;
+
+/// Pack binary data into 7-bit bytes for SysEx, with checksums.
+pub mod codec;
+
+/// Commands sent from the host over SysEx, and their replies.
+pub mod rpc;
+
+/// Record and loop back what is played on the pads.
+pub mod looper;
+
+/// Apps shared by the tests of the modules that pass events on to other apps.
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,332 @@
+use core::fmt::{self, Write};
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+#[cfg(any(target_arch = "arm", test))]
+use crate::hal::time::Instant;
+#[cfg(target_arch = "arm")]
+use crate::hal::{lock, Mutex};
+use crate::panic::SysExWriter;
+
+/// The manufacturer ID and message type that start a log message.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'L'];
+
+/// The longest target sent with a log message. Longer targets are truncated.
+pub const MAX_TARGET_SIZE: usize = 32;
+
+/// The longest log message sent. Longer messages are truncated.
+pub const MAX_MESSAGE_SIZE: usize = 128;
+
+/// The largest packet: the header, level and dropped count, target and message, and terminators.
+pub const MAX_PACKET_SIZE: usize =
+    1 + SYSEX_ID.len() + 3 + MAX_TARGET_SIZE + 1 + MAX_MESSAGE_SIZE + 1 + 1;
+
+/// The most messages that can be sent at once, after a quiet period.
+pub const BURST: u32 = 8;
+
+/// The time it takes to earn another message, in ms, limiting the logger to 20 messages a second.
+pub const MESSAGE_PERIOD: u64 = 50;
+
+/// A log message as sent over SysEx.
+pub type Packet = heapless::Vec<u8, MAX_PACKET_SIZE>;
+
+/// Build the SysEx packet for a log message.
+///
+/// The packet is `F0 7D 4C`, the level from 1 (error) to 5 (trace), the number of messages dropped
+/// since the last one sent in two 7-bit bytes, most significant first, then the target and the
+/// message, each as ASCII ended by a zero byte, and finally `F7`.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::logger;
+/// use log::Level;
+///
+/// let packet = logger::encode(Level::Info, "mpe", 0, format_args!("{} voices", 8));
+/// let line = logger::decode(&packet).next().unwrap();
+/// assert_eq!(line.to_string(), "INFO mpe: 8 voices");
+/// ```
+pub fn encode(level: Level, target: &str, dropped: u32, message: fmt::Arguments) -> Packet {
+    let mut packet = Packet::new();
+    let dropped = dropped.min(0x3fff);
+    let _ = packet.extend_from_slice(&[
+        0xf0,
+        SYSEX_ID[0],
+        SYSEX_ID[1],
+        level as u8,
+        (dropped >> 7) as u8 & 0x7f,
+        dropped as u8 & 0x7f,
+    ]);
+
+    let mut writer = SysExWriter {
+        buffer: &mut packet,
+        remaining: MAX_TARGET_SIZE,
+    };
+    let _ = writer.write_str(target);
+    let _ = packet.push(0);
+
+    let mut writer = SysExWriter {
+        buffer: &mut packet,
+        remaining: MAX_MESSAGE_SIZE,
+    };
+    let _ = writer.write_fmt(message);
+    let _ = packet.push(0);
+    let _ = packet.push(0xf7);
+    packet
+}
+
+/// A log message read from SysEx.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Line<'a> {
+    /// The level the message was logged at.
+    pub level: Level,
+    /// The number of messages the logger dropped just before this one.
+    pub dropped: u32,
+    /// The target of the message, usually the module that logged it.
+    pub target: &'a str,
+    /// The message, possibly truncated.
+    pub message: &'a str,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dropped > 0 {
+            writeln!(f, "({} messages dropped)", self.dropped)?;
+        }
+        write!(f, "{} {}: {}", self.level, self.target, self.message)
+    }
+}
+
+/// Read one log message, without its `F0` and `F7`.
+fn parse(body: &[u8]) -> Option<Line<'_>> {
+    let body = body.strip_prefix(&SYSEX_ID)?;
+    let (header, text) = (body.get(..3)?, &body[3..]);
+    let level = match header[0] {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        5 => Level::Trace,
+        _ => return None,
+    };
+    let dropped = (header[1] as u32) << 7 | header[2] as u32;
+
+    let mut strings = text.split(|&byte| byte == 0);
+    let target = core::str::from_utf8(strings.next()?).ok()?;
+    let message = core::str::from_utf8(strings.next()?).ok()?;
+    Some(Line {
+        level,
+        dropped,
+        target,
+        message,
+    })
+}
+
+/// Extract the log messages from a captured stream of SysEx, skipping any other messages.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::logger;
+/// use log::Level;
+///
+/// let mut stream = vec![0xf0, 0x7d, b'P', 1, 0xf7];
+/// stream.extend(logger::encode(Level::Warn, "clock", 0, format_args!("no MIDI clock")));
+///
+/// let lines: Vec<_> = logger::decode(&stream).map(|line| line.to_string()).collect();
+/// assert_eq!(lines, ["WARN clock: no MIDI clock"]);
+/// ```
+pub fn decode(stream: &[u8]) -> impl Iterator<Item = Line<'_>> {
+    stream
+        .split(|&byte| byte == 0xf7)
+        .filter_map(|message| {
+            let start = message.iter().rposition(|&byte| byte == 0xf0)?;
+            Some(&message[start + 1..])
+        })
+        .filter_map(parse)
+}
+
+/// Limits the rate messages are sent, allowing short bursts.
+#[cfg(any(target_arch = "arm", test))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RateLimiter {
+    /// The number of messages that can be sent now.
+    tokens: u32,
+    /// When the last token was earned.
+    refilled: Instant,
+    /// The number of messages dropped since the last one sent.
+    dropped: u32,
+}
+
+#[cfg(any(target_arch = "arm", test))]
+impl RateLimiter {
+    const fn new() -> Self {
+        RateLimiter {
+            tokens: BURST,
+            refilled: Instant::STARTUP,
+            dropped: 0,
+        }
+    }
+
+    /// Take a token to send a message, returning the number of messages dropped since the last
+    /// one, or `None` if this one should be dropped too.
+    fn take(&mut self, now: Instant) -> Option<u32> {
+        let earned = (now - self.refilled).as_millis() as u64 / MESSAGE_PERIOD;
+        if earned > 0 {
+            self.tokens = (self.tokens as u64 + earned).min(BURST as u64) as u32;
+            self.refilled =
+                Instant::from_millis(self.refilled.as_millis() + earned * MESSAGE_PERIOD);
+        }
+
+        if self.tokens == 0 {
+            self.dropped = self.dropped.saturating_add(1);
+            return None;
+        }
+        self.tokens -= 1;
+        Some(core::mem::take(&mut self.dropped))
+    }
+}
+
+/// Sends log messages over SysEx on the USB port, or prints them to stderr on the host.
+struct SysExLogger;
+
+/// Limits the rate the logger sends messages.
+#[cfg(target_arch = "arm")]
+static LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new());
+
+/// Messages dropped because the limiter was in use, e.g. logging from an interrupt.
+#[cfg(target_arch = "arm")]
+static CONTENDED: AtomicU32 = AtomicU32::new(0);
+
+impl Log for SysExLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        #[cfg(not(target_arch = "arm"))]
+        eprintln!("{} {}: {}", record.level(), record.target(), record.args());
+
+        #[cfg(target_arch = "arm")]
+        {
+            let dropped = match lock::try_lock(&LIMITER) {
+                Some(mut limiter) => limiter.take(Instant::now()),
+                None => {
+                    CONTENDED.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            };
+            if let Some(dropped) = dropped {
+                let dropped = dropped + CONTENDED.swap(0, Ordering::Relaxed);
+                crate::hal::midi::send_sysex(
+                    crate::hal::midi::Port::USB,
+                    &encode(record.level(), record.target(), dropped, *record.args()),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: SysExLogger = SysExLogger;
+
+/// Send messages from the `log` macros to the host, up to the given level.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::logger;
+/// use log::LevelFilter;
+///
+/// logger::init(LevelFilter::Info).unwrap();
+/// log::info!("started");
+/// ```
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_survive_a_stream() {
+        let mut stream = Vec::new();
+        stream.extend(encode(
+            Level::Error,
+            "flash",
+            0,
+            format_args!("erase failed"),
+        ));
+        stream.extend([0xf0, 0x7d, b'L', 9, 0, 0, 0, 0, 0xf7]);
+        stream.extend([0x90, 60, 100]);
+        stream.extend(encode(Level::Debug, "é", 200, format_args!("{:?}", [1, 2])));
+
+        let lines: Vec<_> = decode(&stream).collect();
+        assert_eq!(
+            lines,
+            [
+                Line {
+                    level: Level::Error,
+                    dropped: 0,
+                    target: "flash",
+                    message: "erase failed",
+                },
+                Line {
+                    level: Level::Debug,
+                    dropped: 200,
+                    target: "?",
+                    message: "[1, 2]",
+                },
+            ]
+        );
+        assert_eq!(
+            lines[1].to_string(),
+            "(200 messages dropped)\nDEBUG ?: [1, 2]"
+        );
+    }
+
+    #[test]
+    fn long_messages_are_truncated() {
+        let packet = encode(Level::Info, "test", 0, format_args!("{}", "x".repeat(500)));
+        assert_eq!(packet.len(), MAX_PACKET_SIZE - MAX_TARGET_SIZE + 4);
+        assert_eq!(
+            decode(&packet).next().unwrap().message.len(),
+            MAX_MESSAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn rate_is_limited() {
+        let mut limiter = RateLimiter::new();
+        let start = Instant::from_millis(1000);
+
+        for _ in 0..BURST {
+            assert_eq!(limiter.take(start), Some(0));
+        }
+        assert_eq!(limiter.take(start), None);
+        assert_eq!(limiter.take(start), None);
+
+        // one message is earned each period, and reports those dropped before it
+        let later = Instant::from_millis(1000 + MESSAGE_PERIOD);
+        assert_eq!(limiter.take(later), Some(2));
+        assert_eq!(limiter.take(later), None);
+
+        // a long quiet period only earns a burst
+        let much_later = Instant::from_millis(100_000);
+        assert_eq!(limiter.take(much_later), Some(1));
+        for _ in 1..BURST {
+            assert_eq!(limiter.take(much_later), Some(0));
+        }
+        assert_eq!(limiter.take(much_later), None);
+    }
+}
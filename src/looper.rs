@@ -0,0 +1,1047 @@
+use core::convert::TryFrom;
+
+use crate::clock::{ClockEvent, PPQN};
+use crate::codec::{self, Decoder, Encoder, Scheme};
+use crate::hal::midi::{MidiMessage, Port};
+use crate::hal::surface::{AftertouchEvent, Button, ButtonEvent, Event as Press};
+use crate::hal::Point;
+use crate::settings::{Error, Reader, Writer};
+
+/// The manufacturer ID and message type that start each message of an exported loop.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'O'];
+
+/// A request for the device to [`export`](Looper::export) its loop: `F0 7D 4F F7`.
+pub const EXPORT_REQUEST: [u8; 4] = [0xf0, SYSEX_ID[0], SYSEX_ID[1], 0xf7];
+
+/// The version of the export format.
+pub const EXPORT_VERSION: u8 = 1;
+
+/// The most notes that can be held down while recording one layer. Later notes aren't recorded.
+pub const MAX_HELD: usize = 16;
+
+/// The bytes of data in each exported message, a whole number of 7-byte groups.
+const CHUNK_SIZE: usize = 36 * 7;
+
+/// The bytes in each exported event: the time, layer, kind and three bytes of data.
+const RECORD_SIZE: usize = 4 + 1 + 1 + 3;
+
+/// The bytes in the export header: the version, length, number of layers and number of events.
+const HEADER_SIZE: usize = 1 + 4 + 1 + 2;
+
+/// The longest exported message.
+pub const MAX_SYSEX_SIZE: usize = 1 + SYSEX_ID.len() + 2 + codec::encoded_len(CHUNK_SIZE) + 1;
+
+/// Something a player did, recorded in a loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// A button was pressed or released.
+    Button(ButtonEvent),
+    /// A pad was pressed harder or softer.
+    Aftertouch(AftertouchEvent),
+    /// A channel message was sent on a port, stored as its bytes padded with zeros.
+    Midi(Port, [u8; 3]),
+}
+
+impl Action {
+    /// Construct an action from a channel message. Returns `None` for system messages, which
+    /// aren't recorded.
+    pub fn midi(port: Port, message: &MidiMessage) -> Option<Self> {
+        let mut data = [0; 3];
+        message.copy_to_slice(&mut data).ok()?;
+        (data[0] < 0xf0).then_some(Action::Midi(port, data))
+    }
+
+    /// Returns the MIDI message of a [`Midi`](Action::Midi) action.
+    pub fn message(&self) -> Option<MidiMessage<'_>> {
+        match self {
+            Action::Midi(_, data) => MidiMessage::try_from(&data[..]).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns what the action does to a note, and which note.
+    fn role(&self) -> Option<(Key, Role)> {
+        match *self {
+            Action::Button(ButtonEvent { button, event }) => match event {
+                Press::Press(_) => Some((Key::Button(button), Role::Onset)),
+                Press::Release => Some((Key::Button(button), Role::Release)),
+            },
+            Action::Aftertouch(AftertouchEvent { point, .. }) => {
+                Some((Key::Button(Button::Pad(point)), Role::Follower))
+            }
+            Action::Midi(port, [status, note, velocity]) => {
+                let key = Key::Note(port, status & 0x0f, note);
+                match status & 0xf0 {
+                    0x90 if velocity > 0 => Some((key, Role::Onset)),
+                    0x80 | 0x90 => Some((key, Role::Release)),
+                    0xa0 => Some((key, Role::Follower)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// A button or note that is started and ended by actions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Button(Button),
+    Note(Port, u8, u8),
+}
+
+impl Key {
+    /// Returns the action that ends the note.
+    fn release(self) -> Action {
+        match self {
+            Key::Button(button) => Action::Button(ButtonEvent {
+                button,
+                event: Press::Release,
+            }),
+            Key::Note(port, channel, note) => Action::Midi(port, [0x80 | channel, note, 0]),
+        }
+    }
+}
+
+/// What an action does to its note.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    /// Starts the note.
+    Onset,
+    /// Ends the note.
+    Release,
+    /// Changes the note while it sounds, and moves with its onset when quantised.
+    Follower,
+}
+
+/// An action in a loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// When the action was recorded, in ms from the start of the loop.
+    pub time: u32,
+    /// The layer the action was recorded in, counting from 0 for the first take.
+    pub layer: u8,
+    /// What the player did.
+    pub action: Action,
+    /// When the action is played back, after quantising.
+    play: u32,
+}
+
+impl Event {
+    /// Returns when the action is played back, in ms from the start of the loop.
+    pub fn play_time(&self) -> u32 {
+        self.play
+    }
+}
+
+/// What a looper is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Nothing has been recorded.
+    Empty,
+    /// The first take is being recorded. It sets the length of the loop.
+    Recording,
+    /// The loop is playing.
+    Playing,
+    /// The loop is playing and a new layer is being recorded over it.
+    Overdubbing,
+    /// The loop is stopped.
+    Stopped,
+}
+
+/// Records what a player does and loops it back, with overdubs that can be undone.
+///
+/// Actions are captured with millisecond timestamps into a buffer of `N` events, and played back by
+/// [`tick`], which should be called from the 1 kHz timer. The first take sets the length of the
+/// loop, and each overdub adds a layer that [`undo`] removes. A note is only recorded if there is
+/// room for its release, and releases are added for any notes held at the end of a layer, so
+/// played back notes always end. When a layer is removed or the loop stops, notes that are
+/// sounding are ended.
+///
+/// When synced to a [`Clock`](crate::clock::Clock), the length of the first take is rounded to
+/// whole beats, and the loop waits for the clock to reach the end of each repeat before starting
+/// the next.
+///
+/// [`tick`]: Looper::tick
+/// [`undo`]: Looper::undo
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::surface::{Button, ButtonEvent, Event};
+/// use launchpad_pro_rs::hal::Point;
+/// use launchpad_pro_rs::looper::{Action, Looper, Mode};
+///
+/// let pad = |event| Action::Button(ButtonEvent { button: Button::Pad(Point::new(4, 4)), event });
+///
+/// let mut looper: Looper<64> = Looper::new();
+/// looper.toggle();
+/// looper.capture(pad(Event::Press(100)));
+/// for _ in 0..250 {
+///     looper.tick(|_| ());
+/// }
+/// looper.capture(pad(Event::Release));
+/// for _ in 0..250 {
+///     looper.tick(|_| ());
+/// }
+///
+/// // the take lasts 500 ms and plays back from the start
+/// looper.toggle();
+/// assert_eq!(looper.mode(), Mode::Playing);
+/// assert_eq!(looper.length(), 501);
+///
+/// let mut played = Vec::new();
+/// for _ in 0..looper.length() {
+///     looper.tick(|action| played.push(*action));
+/// }
+/// assert_eq!(played, [pad(Event::Press(100)), pad(Event::Release)]);
+/// ```
+pub struct Looper<const N: usize> {
+    events: heapless::Vec<Event, N>,
+    /// Notes started in the layer being recorded that haven't ended.
+    held: heapless::Vec<Key, MAX_HELD>,
+    mode: Mode,
+    /// The time in ms since the start of the loop, or of the first take while recording it.
+    position: u32,
+    length: u32,
+    layers: u8,
+    /// The number of layers that are played back. An overdub is heard once the loop repeats.
+    audible: u8,
+    quantise: Option<u32>,
+    sync: bool,
+    /// Clock pulses since the start of the loop.
+    pulses: u32,
+    /// The length of the loop in clock pulses, when synced.
+    loop_pulses: u32,
+    dropped: u32,
+}
+
+impl<const N: usize> Looper<N> {
+    /// Construct an empty looper.
+    pub const fn new() -> Self {
+        Looper {
+            events: heapless::Vec::new(),
+            held: heapless::Vec::new(),
+            mode: Mode::Empty,
+            position: 0,
+            length: 0,
+            layers: 0,
+            audible: 0,
+            quantise: None,
+            sync: false,
+            pulses: 0,
+            loop_pulses: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns what the looper is doing.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the length of the loop in ms, or 0 until the first take has been recorded.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the time in ms since the start of the loop.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Returns the number of layers, including the first take.
+    pub fn layers(&self) -> u8 {
+        self.layers
+    }
+
+    /// Returns the recorded events, in the order they were recorded.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns the number of actions that weren't recorded because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the quantise grid in ms.
+    pub fn quantise(&self) -> Option<u32> {
+        self.quantise
+    }
+
+    /// Play notes back on a grid of some ms, or as recorded. Notes keep their lengths, and the
+    /// actions that change a note move with it.
+    pub fn set_quantise(&mut self, grid: Option<u32>) {
+        self.quantise = grid.filter(|&grid| grid > 0);
+        for index in 0..self.events.len() {
+            self.events[index].play = self.play_time(index);
+        }
+    }
+
+    /// Returns true if the loop follows clock events.
+    pub fn is_synced(&self) -> bool {
+        self.sync
+    }
+
+    /// Follow, or stop following, the clock events passed to [`clock_event`](Looper::clock_event).
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Start recording the first take, or an overdub if there is already a loop.
+    pub fn record(&mut self) {
+        match self.mode {
+            Mode::Empty => {
+                self.mode = Mode::Recording;
+                self.position = 0;
+                self.pulses = 0;
+                self.layers = 1;
+                self.audible = 0;
+            }
+            Mode::Playing | Mode::Stopped if self.layers < u8::MAX => {
+                if self.mode == Mode::Stopped {
+                    self.restart();
+                }
+                self.mode = Mode::Overdubbing;
+                self.layers += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Finish recording and play the loop, or start it again if it was stopped.
+    pub fn play(&mut self) {
+        match self.mode {
+            Mode::Recording => {
+                self.end_layer();
+                self.length = self.position + 1;
+                self.loop_pulses =
+                    ((self.pulses + PPQN as u32 / 2) / PPQN as u32).max(1) * PPQN as u32;
+                self.audible = self.layers;
+                self.set_quantise(self.quantise);
+                self.restart();
+                self.mode = Mode::Playing;
+            }
+            Mode::Overdubbing => {
+                self.end_layer();
+                self.mode = Mode::Playing;
+            }
+            Mode::Stopped => {
+                self.restart();
+                self.mode = Mode::Playing;
+            }
+            _ => {}
+        }
+    }
+
+    /// Move to the next mode for a single looper button: record, play, overdub, play, and so on.
+    pub fn toggle(&mut self) {
+        match self.mode {
+            Mode::Empty | Mode::Playing => self.record(),
+            Mode::Recording | Mode::Overdubbing | Mode::Stopped => self.play(),
+        }
+    }
+
+    /// Stop the loop, ending the notes it is playing.
+    pub fn stop(&mut self, mut output: impl FnMut(&Action)) {
+        match self.mode {
+            Mode::Recording => {
+                self.play();
+                self.mode = Mode::Stopped;
+            }
+            Mode::Playing | Mode::Overdubbing => {
+                self.end_layer();
+                self.release_sounding(None, &mut output);
+                self.mode = Mode::Stopped;
+            }
+            _ => {}
+        }
+    }
+
+    /// Remove the last layer, ending the notes it is playing. Removing the first take clears the
+    /// loop.
+    pub fn undo(&mut self, mut output: impl FnMut(&Action)) {
+        let Some(layer) = self.layers.checked_sub(1) else {
+            return;
+        };
+        if matches!(self.mode, Mode::Playing | Mode::Overdubbing) {
+            self.release_sounding(Some(layer), &mut output);
+        }
+        let start = self
+            .events
+            .iter()
+            .position(|event| event.layer == layer)
+            .unwrap_or(self.events.len());
+        self.events.truncate(start);
+        self.held.clear();
+        self.layers = layer;
+        self.audible = self.audible.min(layer);
+        if layer == 0 {
+            self.clear();
+        } else if self.mode == Mode::Overdubbing {
+            self.mode = Mode::Playing;
+        }
+    }
+
+    /// Forget the loop. Notes it is playing are left sounding, so stop it first.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.held.clear();
+        self.mode = Mode::Empty;
+        self.position = 0;
+        self.length = 0;
+        self.layers = 0;
+        self.audible = 0;
+    }
+
+    /// Record an action, if recording. The action is dropped if the buffer is full.
+    pub fn capture(&mut self, action: Action) {
+        if !matches!(self.mode, Mode::Recording | Mode::Overdubbing) {
+            return;
+        }
+        // keep room for the release of every held note
+        match action.role() {
+            Some((key, Role::Onset)) => {
+                if self.events.len() + self.held.len() + 2 > N || self.held.push(key).is_err() {
+                    self.dropped += 1;
+                    return;
+                }
+            }
+            Some((key, Role::Release)) => {
+                // ignore notes that were started before recording
+                match self.held.iter().position(|&held| held == key) {
+                    Some(index) => {
+                        self.held.swap_remove(index);
+                    }
+                    None => return,
+                }
+            }
+            _ => {
+                if self.events.len() + self.held.len() + 1 > N {
+                    self.dropped += 1;
+                    return;
+                }
+            }
+        }
+        self.push(self.position, action);
+    }
+
+    /// Advance the loop by one ms, passing the actions due to `output`.
+    pub fn tick(&mut self, mut output: impl FnMut(&Action)) {
+        match self.mode {
+            Mode::Recording => self.position = self.position.saturating_add(1),
+            Mode::Playing | Mode::Overdubbing => {
+                if self.position + 1 < self.length {
+                    self.position += 1;
+                    self.emit(&mut output);
+                } else if !self.sync {
+                    self.wrap(&mut output);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Follow a clock, if synced. A start restarts the loop, a stop stops it and a continue plays
+    /// it from where it stopped.
+    pub fn clock_event(&mut self, event: ClockEvent, mut output: impl FnMut(&Action)) {
+        if !self.sync {
+            return;
+        }
+        match event {
+            ClockEvent::Pulse => {
+                self.pulses += 1;
+                let playing = matches!(self.mode, Mode::Playing | Mode::Overdubbing);
+                if playing && self.pulses >= self.loop_pulses {
+                    self.flush(&mut output);
+                    self.wrap(&mut output);
+                }
+            }
+            ClockEvent::Start => match self.mode {
+                Mode::Playing | Mode::Overdubbing => {
+                    self.flush(&mut output);
+                    self.wrap(&mut output);
+                }
+                Mode::Stopped => {
+                    self.mode = Mode::Playing;
+                    self.wrap(&mut output);
+                }
+                _ => self.pulses = 0,
+            },
+            ClockEvent::Continue => {
+                if self.mode == Mode::Stopped {
+                    self.mode = Mode::Playing;
+                }
+            }
+            ClockEvent::Stop => self.stop(output),
+        }
+    }
+
+    /// Play from the start of the loop on the next tick.
+    fn restart(&mut self) {
+        self.position = self.length - 1;
+        self.pulses = 0;
+        if !self.sync {
+            return;
+        }
+        // wait for the clock to start the loop
+        self.pulses = self.loop_pulses;
+    }
+
+    /// Start the next repeat of the loop, making any overdub audible.
+    fn wrap(&mut self, output: &mut impl FnMut(&Action)) {
+        self.position = 0;
+        self.pulses = 0;
+        self.audible = self.layers;
+        self.emit(output);
+    }
+
+    /// Play the actions left before the end of the loop, when the clock reaches the end early.
+    fn flush(&mut self, output: &mut impl FnMut(&Action)) {
+        while self.position + 1 < self.length {
+            self.position += 1;
+            self.emit(output);
+        }
+    }
+
+    /// Pass the actions due at the current position to `output`.
+    fn emit(&self, output: &mut impl FnMut(&Action)) {
+        self.events
+            .iter()
+            .filter(|event| event.layer < self.audible && event.play == self.position)
+            .for_each(|event| output(&event.action));
+    }
+
+    fn push(&mut self, time: u32, action: Action) {
+        let event = Event {
+            time,
+            layer: self.layers - 1,
+            action,
+            play: time,
+        };
+        if self.events.push(event).is_ok() {
+            let index = self.events.len() - 1;
+            self.events[index].play = self.play_time(index);
+        }
+    }
+
+    /// Release the notes still held at the end of the layer being recorded.
+    fn end_layer(&mut self) {
+        while let Some(key) = self.held.pop() {
+            self.push(self.position, key.release());
+        }
+    }
+
+    /// Returns the onset of the note an event belongs to.
+    fn onset(&self, index: usize) -> Option<&Event> {
+        let event = &self.events[index];
+        let (key, _) = event.action.role()?;
+        self.events[..index].iter().rev().find(|onset| {
+            onset.layer == event.layer && onset.action.role() == Some((key, Role::Onset))
+        })
+    }
+
+    /// Returns when an event is played back, moving notes onto the quantise grid.
+    fn play_time(&self, index: usize) -> u32 {
+        let event = &self.events[index];
+        let grid = match self.quantise {
+            Some(grid) if self.length > 0 => grid,
+            _ => return event.time,
+        };
+        let onset = match event.action.role() {
+            Some((_, Role::Onset)) => Some(event),
+            Some(_) => self.onset(index),
+            None => None,
+        };
+        let offset = onset.map_or(0, |onset| {
+            let snapped = (onset.time + grid / 2) / grid * grid;
+            snapped as i64 - onset.time as i64
+        });
+        (event.time as i64 + offset).rem_euclid(self.length as i64) as u32
+    }
+
+    /// End the notes that have started but not ended in this repeat of the loop, in one layer or
+    /// all of them.
+    fn release_sounding(&self, layer: Option<u8>, output: &mut impl FnMut(&Action)) {
+        for (index, event) in self.events.iter().enumerate() {
+            let audible =
+                event.layer < self.audible && layer.is_none_or(|layer| layer == event.layer);
+            if !audible || !matches!(event.action.role(), Some((_, Role::Release))) {
+                continue;
+            }
+            let Some(onset) = self.onset(index) else {
+                continue;
+            };
+            let (start, end) = (onset.play, event.play);
+            let sounding = if start <= end {
+                start <= self.position && self.position < end
+            } else {
+                self.position >= start || self.position < end
+            };
+            if sounding {
+                output(&event.action);
+            }
+        }
+    }
+
+    /// Send the loop as SysEx messages, passing each to `send`.
+    ///
+    /// The data is a header of the format version, the length as a `u32`, the number of layers
+    /// and the number of events as a `u16`, then each event, and finally a [`codec::crc16`] of
+    /// everything before it. Multi-byte values are little-endian. Each event is its time as a
+    /// `u32`, its layer, and a kind and three bytes: `0` for a pad with its index and velocity,
+    /// zero for a release, `1` for the Setup button with its velocity, `2` for aftertouch with the
+    /// pad's index and the value, and `3` plus the port for MIDI with its bytes.
+    ///
+    /// The data is packed with [`Scheme::LsbFirst`] and split into messages of `F0 7D 4F`, a
+    /// sequence number, `1` for the last message or `0` for the others, the packed data and `F7`.
+    pub fn export(&self, mut send: impl FnMut(&[u8])) {
+        let mut exporter = Exporter::new(&mut send);
+        let mut header = [0; HEADER_SIZE];
+        let mut writer = Writer::new(&mut header);
+        let _ = writer.put_u8(EXPORT_VERSION);
+        let _ = writer.put_u32(self.length);
+        let _ = writer.put_u8(self.layers);
+        let _ = writer.put_u16(self.events.len() as u16);
+        exporter.write(&header);
+
+        for event in &self.events {
+            let mut record = [0; RECORD_SIZE];
+            record[..4].copy_from_slice(&event.time.to_le_bytes());
+            record[4] = event.layer;
+            record[5..].copy_from_slice(&match event.action {
+                Action::Button(ButtonEvent { button, event }) => {
+                    let velocity = match event {
+                        Press::Press(velocity) => velocity,
+                        Press::Release => 0,
+                    };
+                    match button {
+                        Button::Pad(point) => [0, point.to_index(), velocity, 0],
+                        Button::Setup => [1, velocity, 0, 0],
+                    }
+                }
+                Action::Aftertouch(AftertouchEvent { point, value }) => {
+                    [2, point.to_index(), value, 0]
+                }
+                Action::Midi(port, [status, data1, data2]) => {
+                    [3 + port as u8, status, data1, data2]
+                }
+            });
+            exporter.write(&record);
+        }
+        let crc = exporter.crc.finish();
+        exporter.write(&crc.to_le_bytes());
+        exporter.finish();
+    }
+
+    /// Replace the loop with one read from the messages made by [`export`](Looper::export). The
+    /// loop is left stopped.
+    pub fn import(&mut self, stream: &[u8]) -> Result<(), Error> {
+        let mut decoder = Decoder::new(Scheme::LsbFirst);
+        let mut data = heapless::Vec::<u8, { 2 * CHUNK_SIZE }>::new();
+        let mut crc = codec::Crc16::new();
+        let mut header = None;
+        let mut events = heapless::Vec::<Event, N>::new();
+        let mut sequence = 0u8;
+
+        let messages = stream.split(|&byte| byte == 0xf7).filter_map(|message| {
+            let start = message.iter().rposition(|&byte| byte == 0xf0)?;
+            message[start + 1..].strip_prefix(&SYSEX_ID)
+        });
+        for message in messages {
+            let [number, last, packed @ ..] = message else {
+                return Err(Error::Truncated);
+            };
+            if *number != sequence & 0x7f {
+                return Err(Error::Missing);
+            }
+            sequence = sequence.wrapping_add(1);
+
+            let mut chunk = [0; CHUNK_SIZE + 7];
+            let mut length = decoder
+                .decode(packed, &mut chunk)
+                .map_err(|_| Error::Invalid)?;
+            if *last == 1 {
+                length += decoder
+                    .finish(&mut chunk[length..])
+                    .map_err(|_| Error::Truncated)?;
+            }
+            data.extend_from_slice(&chunk[..length])
+                .map_err(|_| Error::Overflow)?;
+
+            // read what has arrived, keeping the CRC and any partial event for later
+            let mut reader = Reader::new(&data);
+            if header.is_none() && reader.remaining() >= HEADER_SIZE {
+                if reader.get_u8()? != EXPORT_VERSION {
+                    return Err(Error::Version(data[0]));
+                }
+                header = Some((reader.get_u32()?, reader.get_u8()?, reader.get_u16()?));
+            }
+            if let Some((_, _, count)) = header {
+                while events.len() < count as usize && reader.remaining() >= RECORD_SIZE {
+                    let event = read_event(&mut reader)?;
+                    events.push(event).map_err(|_| Error::Overflow)?;
+                }
+            }
+            let used = data.len() - reader.remaining();
+            crc.update(&data[..used]);
+            let rest = heapless::Vec::<u8, { 2 * CHUNK_SIZE }>::from_slice(&data[used..])
+                .map_err(|_| Error::Overflow)?;
+            data = rest;
+
+            if *last == 1 {
+                let (length, layers, count) = header.ok_or(Error::Truncated)?;
+                if events.len() < count as usize || data.len() != 2 {
+                    return Err(Error::Truncated);
+                }
+                if u16::from_le_bytes([data[0], data[1]]) != crc.finish() {
+                    return Err(Error::Checksum);
+                }
+                if length == 0
+                    || events
+                        .iter()
+                        .any(|event| event.time >= length || event.layer >= layers)
+                {
+                    return Err(Error::Invalid);
+                }
+                self.clear();
+                self.events = events;
+                self.length = length;
+                self.layers = layers;
+                self.audible = layers;
+                self.mode = Mode::Stopped;
+                self.set_quantise(self.quantise);
+                return Ok(());
+            }
+        }
+        Err(Error::Missing)
+    }
+}
+
+impl<const N: usize> Default for Looper<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read an event written by [`Looper::export`].
+fn read_event(reader: &mut Reader) -> Result<Event, Error> {
+    let time = reader.get_u32()?;
+    let layer = reader.get_u8()?;
+    let [kind, a, b, c] = [
+        reader.get_u8()?,
+        reader.get_u8()?,
+        reader.get_u8()?,
+        reader.get_u8()?,
+    ];
+    let press = |velocity| match velocity {
+        0 => Press::Release,
+        velocity => Press::Press(velocity),
+    };
+    let pad = |index: u8| match index < crate::hal::Grid::size() {
+        true => Ok(Point::from_index(index)),
+        false => Err(Error::Invalid),
+    };
+    let action = match kind {
+        0 => Action::Button(ButtonEvent {
+            button: Button::Pad(pad(a)?),
+            event: press(b),
+        }),
+        1 => Action::Button(ButtonEvent {
+            button: Button::Setup,
+            event: press(a),
+        }),
+        2 => Action::Aftertouch(AftertouchEvent {
+            point: pad(a)?,
+            value: b,
+        }),
+        _ => {
+            let port = Port::try_from(kind - 3).map_err(|_| Error::Invalid)?;
+            Action::Midi(port, [a, b, c])
+        }
+    };
+    Ok(Event {
+        time,
+        layer,
+        action,
+        play: time,
+    })
+}
+
+/// Packs exported data into SysEx messages.
+struct Exporter<'a, F> {
+    send: &'a mut F,
+    encoder: Encoder,
+    crc: codec::Crc16,
+    message: heapless::Vec<u8, MAX_SYSEX_SIZE>,
+    /// The bytes of data in the message being built.
+    size: usize,
+    sequence: u8,
+}
+
+impl<'a, F: FnMut(&[u8])> Exporter<'a, F> {
+    fn new(send: &'a mut F) -> Self {
+        let mut exporter = Exporter {
+            send,
+            encoder: Encoder::new(Scheme::LsbFirst),
+            crc: codec::Crc16::new(),
+            message: heapless::Vec::new(),
+            size: 0,
+            sequence: 0,
+        };
+        exporter.start();
+        exporter
+    }
+
+    fn start(&mut self) {
+        self.message.clear();
+        let _ = self.message.extend_from_slice(&[
+            0xf0,
+            SYSEX_ID[0],
+            SYSEX_ID[1],
+            self.sequence & 0x7f,
+            0,
+        ]);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.size = 0;
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        for &byte in data {
+            let mut packed = [0; 8];
+            if let Ok(length) = self.encoder.encode(&[byte], &mut packed) {
+                let _ = self.message.extend_from_slice(&packed[..length]);
+            }
+            self.size += 1;
+            if self.size == CHUNK_SIZE {
+                let _ = self.message.push(0xf7);
+                (self.send)(&self.message);
+                self.start();
+            }
+        }
+    }
+
+    fn finish(mut self) {
+        let mut packed = [0; 8];
+        if let Ok(length) = self.encoder.finish(&mut packed) {
+            let _ = self.message.extend_from_slice(&packed[..length]);
+        }
+        self.message[4] = 1;
+        let _ = self.message.push(0xf7);
+        (self.send)(&self.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(x: i8, event: Press) -> Action {
+        Action::Button(ButtonEvent {
+            button: Button::Pad(Point::new(x, 1)),
+            event,
+        })
+    }
+
+    /// Run the looper for some ms, returning the actions it played and when.
+    fn run<const N: usize>(looper: &mut Looper<N>, ms: u32) -> Vec<(u32, Action)> {
+        let mut played = Vec::new();
+        for _ in 0..ms {
+            let mut due = Vec::new();
+            looper.tick(|action| due.push(*action));
+            played.extend(due.into_iter().map(|action| (looper.position(), action)));
+        }
+        played
+    }
+
+    /// Record a take of a note held from `start` to `end` ms in a loop of `length` ms.
+    fn take<const N: usize>(looper: &mut Looper<N>, start: u32, end: u32, length: u32) {
+        looper.record();
+        run(looper, start);
+        looper.capture(pad(1, Press::Press(100)));
+        run(looper, end - start);
+        looper.capture(pad(1, Press::Release));
+        run(looper, length - 1 - end);
+        looper.play();
+    }
+
+    #[test]
+    fn takes_loop() {
+        let mut looper: Looper<16> = Looper::new();
+        take(&mut looper, 100, 300, 1000);
+        assert_eq!(looper.length(), 1000);
+
+        let played = run(&mut looper, 2000);
+        let expected = [
+            (100, pad(1, Press::Press(100))),
+            (300, pad(1, Press::Release)),
+        ];
+        assert_eq!(played[..2], expected);
+        assert_eq!(played.len(), 4);
+        assert_eq!(played[2].1, expected[0].1);
+    }
+
+    #[test]
+    fn overdubs_are_heard_on_the_next_repeat_and_can_be_undone() {
+        let mut looper: Looper<16> = Looper::new();
+        take(&mut looper, 0, 100, 1000);
+        run(&mut looper, 1);
+
+        looper.record();
+        assert_eq!(looper.mode(), Mode::Overdubbing);
+        run(&mut looper, 199);
+        looper.capture(pad(2, Press::Press(50)));
+        let played = run(&mut looper, 800);
+        assert!(played.is_empty());
+
+        // the note held at the end of the overdub is released, and heard from the next repeat
+        looper.play();
+        assert_eq!(looper.layers(), 2);
+        let played = run(&mut looper, 1000);
+        let actions: Vec<_> = played.iter().map(|(_, action)| *action).collect();
+        assert_eq!(
+            actions,
+            [
+                pad(1, Press::Press(100)),
+                pad(1, Press::Release),
+                pad(2, Press::Press(50)),
+                pad(2, Press::Release),
+            ]
+        );
+
+        // undoing while the overdub's note sounds ends it
+        run(&mut looper, 500);
+        let mut released = Vec::new();
+        looper.undo(|action| released.push(*action));
+        assert_eq!(released, [pad(2, Press::Release)]);
+        assert_eq!(looper.layers(), 1);
+        assert_eq!(looper.events().len(), 2);
+
+        looper.undo(|_| ());
+        assert_eq!(looper.mode(), Mode::Empty);
+    }
+
+    #[test]
+    fn quantised_notes_keep_their_length() {
+        let mut looper: Looper<16> = Looper::new();
+        take(&mut looper, 190, 290, 1000);
+        looper.set_quantise(Some(250));
+        let times: Vec<_> = looper.events().iter().map(Event::play_time).collect();
+        assert_eq!(times, [250, 350]);
+
+        // notes near the end move to the start
+        looper.set_quantise(Some(400));
+        let times: Vec<_> = looper.events().iter().map(Event::play_time).collect();
+        assert_eq!(times, [0, 100]);
+
+        looper.set_quantise(None);
+        let times: Vec<_> = looper.events().iter().map(Event::play_time).collect();
+        assert_eq!(times, [190, 290]);
+    }
+
+    #[test]
+    fn notes_are_only_recorded_with_room_for_their_release() {
+        let mut looper: Looper<3> = Looper::new();
+        looper.record();
+        looper.capture(pad(2, Press::Release));
+        looper.capture(pad(1, Press::Press(1)));
+        looper.capture(pad(2, Press::Press(1)));
+        assert_eq!(looper.dropped(), 1);
+        looper.capture(Action::Aftertouch(AftertouchEvent {
+            point: Point::new(1, 1),
+            value: 20,
+        }));
+        looper.capture(Action::Aftertouch(AftertouchEvent {
+            point: Point::new(1, 1),
+            value: 30,
+        }));
+        assert_eq!(looper.dropped(), 2);
+        looper.play();
+        assert_eq!(looper.events().len(), 3);
+        assert_eq!(looper.events()[2].action, pad(1, Press::Release));
+    }
+
+    #[test]
+    fn loops_follow_the_clock() {
+        let mut looper: Looper<16> = Looper::new();
+        looper.set_sync(true);
+        looper.record();
+        looper.capture(pad(1, Press::Press(100)));
+        for _ in 0..490 {
+            looper.tick(|_| ());
+        }
+        for _ in 0..25 {
+            looper.clock_event(ClockEvent::Pulse, |_| ());
+        }
+        looper.capture(pad(1, Press::Release));
+        looper.play();
+        assert_eq!(looper.loop_pulses, PPQN as u32);
+
+        // the loop waits for the clock at its end, and ends its notes when the clock stops
+        let played = run(&mut looper, 1000);
+        assert!(played.is_empty());
+        let mut played = Vec::new();
+        for _ in 0..PPQN {
+            looper.clock_event(ClockEvent::Pulse, |action| played.push(*action));
+        }
+        assert_eq!(played, [pad(1, Press::Press(100))]);
+        run(&mut looper, 100);
+
+        let mut released = Vec::new();
+        looper.clock_event(ClockEvent::Stop, |action| released.push(*action));
+        assert_eq!(released, [pad(1, Press::Release)]);
+        assert_eq!(looper.mode(), Mode::Stopped);
+    }
+
+    #[test]
+    fn loops_survive_export() {
+        let mut looper: Looper<64> = Looper::new();
+        looper.record();
+        for index in 0..30u8 {
+            let note = MidiMessage::NoteOn(
+                wmidi::Channel::from_index(index % 16).unwrap(),
+                wmidi::Note::from_u8_lossy(index + 40),
+                wmidi::U7::from_u8_lossy(100),
+            );
+            looper.capture(Action::midi(Port::DIN, &note).unwrap());
+            looper.tick(|_| ());
+            let note = MidiMessage::NoteOff(
+                wmidi::Channel::from_index(index % 16).unwrap(),
+                wmidi::Note::from_u8_lossy(index + 40),
+                wmidi::U7::from_u8_lossy(0),
+            );
+            looper.capture(Action::midi(Port::DIN, &note).unwrap());
+        }
+        looper.capture(Action::Button(ButtonEvent {
+            button: Button::Setup,
+            event: Press::Press(3),
+        }));
+        assert_eq!(Action::midi(Port::USB, &MidiMessage::TimingClock), None);
+        looper.play();
+
+        let mut stream = Vec::new();
+        let mut messages = 0;
+        looper.export(|message| {
+            assert!(message.len() <= MAX_SYSEX_SIZE);
+            assert!(message[1..message.len() - 1]
+                .iter()
+                .all(|&byte| byte < 0x80));
+            stream.extend_from_slice(message);
+            messages += 1;
+        });
+        assert_eq!(messages, 3);
+
+        let mut imported: Looper<64> = Looper::new();
+        imported.import(&stream).unwrap();
+        assert_eq!(imported.events(), looper.events());
+        assert_eq!(imported.length(), looper.length());
+        assert_eq!(imported.mode(), Mode::Stopped);
+
+        let mut corrupted = stream.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(imported.import(&corrupted), Err(Error::Checksum));
+        let first = stream.iter().position(|&byte| byte == 0xf7).unwrap();
+        assert_eq!(imported.import(&stream[first + 1..]), Err(Error::Missing));
+    }
+}
@@ -0,0 +1,256 @@
+use core::fmt::{self, Write};
+
+/// The manufacturer ID and message type that start a panic report.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'!'];
+
+/// The longest file name sent in a report. Longer names keep their end.
+pub const MAX_FILE_SIZE: usize = 64;
+
+/// The longest panic message sent in a report. Longer messages are truncated.
+pub const MAX_MESSAGE_SIZE: usize = 128;
+
+/// The largest report: the header, line and column, file name and message, and their terminators.
+pub const MAX_REPORT_SIZE: usize =
+    1 + SYSEX_ID.len() + 5 + MAX_FILE_SIZE + 1 + MAX_MESSAGE_SIZE + 1 + 1;
+
+/// A panic report as sent over SysEx.
+pub type Report = heapless::Vec<u8, MAX_REPORT_SIZE>;
+
+/// Replaces bytes that can't be sent in SysEx, and stops writing once the buffer is full.
+pub(crate) struct SysExWriter<'a, const N: usize> {
+    pub(crate) buffer: &'a mut heapless::Vec<u8, N>,
+    pub(crate) remaining: usize,
+}
+
+impl<const N: usize> Write for SysExWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.remaining == 0 {
+                break;
+            }
+            let byte = if c.is_ascii() && c != '\0' {
+                c as u8
+            } else {
+                b'?'
+            };
+            let _ = self.buffer.push(byte);
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// Build the SysEx report of a panic.
+///
+/// The report is `F0 7D 21`, the line in three 7-bit bytes and the column in two, most significant
+/// first, then the file name and the message, each as ASCII ended by a zero byte, and finally `F7`.
+/// Use [`decode`] to read it on the host.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::panic;
+///
+/// let report = panic::encode("src/main.rs", 12, 5, format_args!("oops"));
+/// let decoded = panic::decode(&report).unwrap();
+/// assert_eq!(decoded.to_string(), "panicked at src/main.rs:12:5:\noops");
+/// ```
+pub fn encode(file: &str, line: u32, column: u32, message: fmt::Arguments) -> Report {
+    let mut report = Report::new();
+    let _ = report.extend_from_slice(&[0xf0]);
+    let _ = report.extend_from_slice(&SYSEX_ID);
+
+    let line = line.min(0x1f_ffff);
+    let column = column.min(0x3fff);
+    let _ = report.extend_from_slice(&[
+        (line >> 14) as u8 & 0x7f,
+        (line >> 7) as u8 & 0x7f,
+        line as u8 & 0x7f,
+        (column >> 7) as u8 & 0x7f,
+        column as u8 & 0x7f,
+    ]);
+
+    // keep the end of a long path, which names the file
+    let start = file
+        .char_indices()
+        .map(|(index, _)| index)
+        .find(|&index| file.len() - index <= MAX_FILE_SIZE)
+        .unwrap_or(file.len());
+    let mut writer = SysExWriter {
+        buffer: &mut report,
+        remaining: MAX_FILE_SIZE,
+    };
+    let _ = writer.write_str(&file[start..]);
+    let _ = report.push(0);
+
+    let mut writer = SysExWriter {
+        buffer: &mut report,
+        remaining: MAX_MESSAGE_SIZE,
+    };
+    let _ = writer.write_fmt(message);
+    let _ = report.push(0);
+    let _ = report.push(0xf7);
+    report
+}
+
+/// A panic report read from SysEx.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decoded<'a> {
+    /// The source file that panicked.
+    pub file: &'a str,
+    /// The line in the file.
+    pub line: u32,
+    /// The column in the line.
+    pub column: u32,
+    /// The panic message, possibly truncated.
+    pub message: &'a str,
+}
+
+impl fmt::Display for Decoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "panicked at {}:{}:{}:\n{}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Find and read the first panic report in a dump of SysEx, e.g. a `.syx` file recorded from the
+/// Launchpad Pro's USB port. Returns `None` if there is no complete report.
+pub fn decode(dump: &[u8]) -> Option<Decoded<'_>> {
+    let header = [0xf0, SYSEX_ID[0], SYSEX_ID[1]];
+    let start = dump
+        .windows(header.len())
+        .position(|window| window == header)?;
+    let body = &dump[start + header.len()..];
+    let end = body.iter().position(|&byte| byte == 0xf7)?;
+    let body = &body[..end];
+
+    let (position, text) = (body.get(..5)?, &body[5..]);
+    let line = (position[0] as u32) << 14 | (position[1] as u32) << 7 | position[2] as u32;
+    let column = (position[3] as u32) << 7 | position[4] as u32;
+
+    let mut strings = text.split(|&byte| byte == 0);
+    let file = core::str::from_utf8(strings.next()?).ok()?;
+    let message = core::str::from_utf8(strings.next()?).ok()?;
+    Some(Decoded {
+        file,
+        line,
+        column,
+        message,
+    })
+}
+
+/// Paint a red cross on the pads inside a dim red border.
+pub fn show_error() {
+    use crate::hal::{surface, Grid, Rgb};
+
+    for point in Grid::points() {
+        let (x, y) = (point.x(), point.y());
+        let on_cross = (1..=8).contains(&x) && (1..=8).contains(&y) && (x == y || x + y == 9);
+        let on_border = x == 0 || y == 0 || x == 9 || y == 9;
+        let rgb = if on_cross {
+            Rgb::new(255, 0, 0)
+        } else if on_border {
+            Rgb::new(63, 0, 0)
+        } else {
+            Rgb::new(0, 0, 0)
+        };
+        surface::set_led(point, rgb);
+    }
+}
+
+/// Reset the microcontroller by requesting a system reset from the Cortex-M3.
+#[cfg(all(target_arch = "arm", feature = "panic-reset"))]
+fn reset() -> ! {
+    /// The Application Interrupt and Reset Control Register.
+    const AIRCR: *mut u32 = 0xe000_ed0c as *mut u32;
+    /// The register key and the SYSRESETREQ bit.
+    const SYSRESETREQ: u32 = 0x05fa_0004;
+
+    // give the report time to leave the USB port
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+    unsafe {
+        core::ptr::write_volatile(AIRCR, SYSRESETREQ);
+    }
+    loop {}
+}
+
+/// Show the error, report the panic on the USB port, then halt, or reset with `panic-reset`.
+#[cfg(all(target_arch = "arm", feature = "panic-handler"))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use crate::hal::midi::{send_sysex, Port};
+
+    show_error();
+
+    let (file, line, column) = match info.location() {
+        Some(location) => (location.file(), location.line(), location.column()),
+        None => ("", 0, 0),
+    };
+    send_sysex(
+        Port::USB,
+        &encode(file, line, column, format_args!("{}", info.message())),
+    );
+
+    #[cfg(feature = "panic-reset")]
+    reset();
+
+    #[cfg(not(feature = "panic-reset"))]
+    loop {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_survive_a_dump() {
+        let report = encode(
+            "src/hal.rs",
+            70_000,
+            130,
+            format_args!("{} failed", "unwrap"),
+        );
+        assert_eq!(&report[..3], &[0xf0, 0x7d, b'!']);
+        assert!(report[1..report.len() - 1].iter().all(|&byte| byte < 0x80));
+
+        let mut dump = heapless::Vec::<u8, 512>::new();
+        dump.extend_from_slice(&[0xf0, 0x7d, b'P', 1, 0xf7])
+            .unwrap();
+        dump.extend_from_slice(&report).unwrap();
+        assert_eq!(
+            decode(&dump),
+            Some(Decoded {
+                file: "src/hal.rs",
+                line: 70_000,
+                column: 130,
+                message: "unwrap failed",
+            })
+        );
+    }
+
+    #[test]
+    fn long_and_unusual_text_is_cleaned_up() {
+        let file = "a/".repeat(40) + "main.rs";
+        let message = "é\0".to_string() + &"x".repeat(200);
+        let report = encode(&file, 1, 1, format_args!("{}", message));
+        assert!(report.len() <= MAX_REPORT_SIZE);
+
+        let decoded = decode(&report).unwrap();
+        assert_eq!(decoded.file.len(), MAX_FILE_SIZE);
+        assert!(decoded.file.ends_with("/main.rs"));
+        assert_eq!(decoded.message.len(), MAX_MESSAGE_SIZE);
+        assert!(decoded.message.starts_with("??xxx"));
+    }
+
+    #[test]
+    fn incomplete_reports_are_ignored() {
+        let report = encode("main.rs", 1, 1, format_args!("oops"));
+        assert_eq!(decode(&report[..report.len() - 1]), None);
+        assert_eq!(decode(&report[..6]), None);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::hal::surface::set_led;
+use crate::hal::{flash, Point, Rgb};
+use crate::settings::{self, Error, Reader, Settings, Writer};
+
+/// The maximum length of a preset name in bytes.
+pub const NAME_LENGTH: usize = 8;
+
+/// The bytes after the SysEx start byte that identify preset messages: the non-commercial
+/// manufacturer ID followed by `P`.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'P'];
+
+/// A SysEx message containing a preset, sent when exporting and received when importing.
+pub const SYSEX_PRESET: u8 = 0x01;
+
+/// A SysEx message asking for a preset to be exported.
+pub const SYSEX_REQUEST: u8 = 0x02;
+
+/// The longest SysEx message containing a preset. Each byte of a preset is sent as two nibbles.
+pub const MAX_SYSEX_SIZE: usize = 1 + SYSEX_ID.len() + 2 + 2 * settings::MAX_SIZE + 1;
+
+/// A named value saved in a preset slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preset<T> {
+    name: [u8; NAME_LENGTH],
+    /// The value saved in the preset.
+    pub value: T,
+}
+
+impl<T> Preset<T> {
+    /// Construct a preset. Names longer than [`NAME_LENGTH`] are truncated and characters outside
+    /// ASCII are replaced by `?`.
+    pub fn new(name: &str, value: T) -> Self {
+        let mut bytes = [0; NAME_LENGTH];
+        for (byte, c) in bytes.iter_mut().zip(name.chars()) {
+            *byte = if c.is_ascii() { c as u8 } else { b'?' };
+        }
+        Preset { name: bytes, value }
+    }
+
+    /// Returns the name of the preset.
+    pub fn name(&self) -> &str {
+        let length = self.name.iter().position(|&byte| byte == 0);
+        core::str::from_utf8(&self.name[..length.unwrap_or(NAME_LENGTH)]).unwrap_or("")
+    }
+}
+
+impl<T: Settings> Settings for Preset<T> {
+    const VERSION: u8 = T::VERSION;
+
+    fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+        writer.put_bytes(&self.name)?;
+        self.value.serialize(writer)
+    }
+
+    fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+        let name = read_name(reader)?;
+        let value = T::deserialize(reader)?;
+        Ok(Preset { name, value })
+    }
+
+    fn migrate(version: u8, reader: &mut Reader) -> Result<Self, Error> {
+        let name = read_name(reader)?;
+        let value = T::migrate(version, reader)?;
+        Ok(Preset { name, value })
+    }
+}
+
+fn read_name(reader: &mut Reader) -> Result<[u8; NAME_LENGTH], Error> {
+    let mut name = [0; NAME_LENGTH];
+    name.copy_from_slice(reader.get_bytes(NAME_LENGTH)?);
+    if !name.is_ascii() {
+        return Err(Error::Invalid);
+    }
+    Ok(name)
+}
+
+/// A fixed number of preset slots stored one after another in flash.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::presets::{Preset, Presets};
+/// use launchpad_pro_rs::settings::{Error, Reader, Settings, Writer};
+///
+/// #[derive(Clone, Copy)]
+/// struct Tempo(u16);
+///
+/// impl Settings for Tempo {
+///     const VERSION: u8 = 1;
+///
+///     fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+///         writer.put_u16(self.0)
+///     }
+///
+///     fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+///         Ok(Tempo(reader.get_u16()?))
+///     }
+/// }
+///
+/// // four slots of 32 bytes at the start of the user area
+/// let mut presets: Presets<Tempo, 4> = Presets::new(0, 32);
+/// presets.save(2, Preset::new("fast", Tempo(180))).unwrap();
+/// assert!(presets.is_occupied(2));
+/// assert_eq!(presets.get(2).map(|preset| preset.value.0), Some(180));
+/// ```
+pub struct Presets<T, const N: usize> {
+    slots: [Option<Preset<T>>; N],
+    offset: usize,
+    slot_size: usize,
+    current: Option<usize>,
+}
+
+impl<T: Settings + Clone, const N: usize> Presets<T, N> {
+    /// Construct empty slots. Slot `n` is stored `offset + n * slot_size` bytes from the beginning of
+    /// the user area of flash.
+    pub const fn new(offset: usize, slot_size: usize) -> Self {
+        Presets {
+            slots: [const { None }; N],
+            offset,
+            slot_size,
+            current: None,
+        }
+    }
+
+    /// Returns the number of slots.
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    /// Returns true if there are no slots.
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    fn slot_offset(&self, slot: usize) -> usize {
+        self.offset + slot * self.slot_size
+    }
+
+    /// Read every slot from flash. Slots that were never saved, or can't be read, are left empty.
+    pub fn load(&mut self) {
+        for slot in 0..N {
+            self.slots[slot] = settings::load(self.slot_offset(slot)).ok();
+        }
+        self.current = None;
+    }
+
+    /// Returns true if a preset has been saved in a slot.
+    pub fn is_occupied(&self, slot: usize) -> bool {
+        matches!(self.slots.get(slot), Some(Some(_)))
+    }
+
+    /// Returns the slot that was last saved or recalled.
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Save a preset to a slot and to flash.
+    pub fn save(&mut self, slot: usize, preset: Preset<T>) -> Result<(), Error> {
+        if slot >= N {
+            return Err(Error::Flash(flash::Error::OutOfBounds));
+        }
+        let mut buffer = [0; settings::MAX_SIZE];
+        let length = settings::encode(&preset, &mut buffer)?;
+        if length > self.slot_size {
+            return Err(Error::Overflow);
+        }
+        flash::write(self.slot_offset(slot), &buffer[..length])?;
+        self.slots[slot] = Some(preset);
+        self.current = Some(slot);
+        Ok(())
+    }
+
+    /// Returns the preset in a slot, if there is one.
+    pub fn get(&self, slot: usize) -> Option<&Preset<T>> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    /// Make a slot the current slot once its preset has been recalled. Empty slots are ignored.
+    pub fn select(&mut self, slot: usize) {
+        if self.is_occupied(slot) {
+            self.current = Some(slot);
+        }
+    }
+
+    /// Remove the preset in a slot.
+    pub fn clear(&mut self, slot: usize) -> Result<(), Error> {
+        if self.is_occupied(slot) {
+            flash::write(self.slot_offset(slot), &[flash::ERASED; 2])?;
+            self.slots[slot] = None;
+        }
+        if self.current == Some(slot) {
+            self.current = None;
+        }
+        Ok(())
+    }
+
+    /// Light a button for each slot, brightest for the current slot and unlit for empty slots.
+    pub fn draw(&self, point: impl Fn(usize) -> Point) {
+        for slot in 0..N {
+            let colour = match (self.is_occupied(slot), self.current == Some(slot)) {
+                (true, true) => Rgb::new(0, 255, 0),
+                (true, false) => Rgb::new(0, 48, 0),
+                (false, _) => Rgb::new(0, 0, 0),
+            };
+            set_led(point(slot), colour);
+        }
+    }
+
+    /// Write a SysEx message containing the preset in a slot to a buffer, returning its length.
+    /// Slots above `0x7f` can't be sent in SysEx.
+    pub fn export(&self, slot: usize, buffer: &mut [u8]) -> Result<usize, Error> {
+        if slot > 0x7f {
+            return Err(Error::Invalid);
+        }
+        let preset = self
+            .slots
+            .get(slot)
+            .and_then(Option::as_ref)
+            .ok_or(Error::Missing)?;
+        let mut block = [0; settings::MAX_SIZE];
+        let length = settings::encode(preset, &mut block)?;
+
+        let mut writer = Writer::new(buffer);
+        writer.put_u8(0xf0)?;
+        writer.put_bytes(&SYSEX_ID)?;
+        writer.put_u8(SYSEX_PRESET)?;
+        writer.put_u8(slot as u8)?;
+        for byte in &block[..length] {
+            writer.put_bytes(&[byte >> 4, byte & 0x0f])?;
+        }
+        writer.put_u8(0xf7)?;
+        Ok(writer.len())
+    }
+
+    /// Handle a SysEx message. A preset message is saved to its slot, returning the slot, and a
+    /// request is answered by passing the exported preset to `reply`. Other messages are ignored.
+    pub fn sysex(
+        &mut self,
+        data: &[u8],
+        mut reply: impl FnMut(&[u8]),
+    ) -> Result<Option<usize>, Error> {
+        let body = match data {
+            [0xf0, id @ .., 0xf7] if id.starts_with(&SYSEX_ID) => &id[SYSEX_ID.len()..],
+            _ => return Ok(None),
+        };
+        match body {
+            [SYSEX_REQUEST, slot] => {
+                let mut buffer = [0; MAX_SYSEX_SIZE];
+                let length = self.export(*slot as usize, &mut buffer)?;
+                reply(&buffer[..length]);
+                Ok(None)
+            }
+            [SYSEX_PRESET, slot, nibbles @ ..] => {
+                let mut block = [0; settings::MAX_SIZE];
+                if nibbles.len() % 2 != 0 || nibbles.len() / 2 > block.len() {
+                    return Err(Error::Invalid);
+                }
+                for (byte, pair) in block.iter_mut().zip(nibbles.chunks(2)) {
+                    *byte = (pair[0] << 4) | (pair[1] & 0x0f);
+                }
+                let preset = settings::decode(&block[..nibbles.len() / 2])?;
+                self.save(*slot as usize, preset)?;
+                Ok(Some(*slot as usize))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Note(u8);
+
+    impl Settings for Note {
+        const VERSION: u8 = 1;
+
+        fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+            writer.put_u8(self.0)
+        }
+
+        fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+            Ok(Note(reader.get_u8()?))
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Preset::new("bass", ()).name(), "bass");
+        assert_eq!(Preset::new("long names", ()).name(), "long nam");
+        assert_eq!(Preset::new("café", ()).name(), "caf?");
+        assert_eq!(Preset::new("", ()).name(), "");
+    }
+
+    #[test]
+    fn save_and_load() {
+        flash::with_temp_file(|_| {
+            let mut presets: Presets<Note, 4> = Presets::new(100, 20);
+            presets.save(1, Preset::new("one", Note(60))).unwrap();
+            presets.save(3, Preset::new("three", Note(64))).unwrap();
+            assert_eq!(presets.current(), Some(3));
+            assert_eq!(
+                presets.save(4, Preset::new("four", Note(0))),
+                Err(Error::Flash(flash::Error::OutOfBounds))
+            );
+
+            let mut loaded: Presets<Note, 4> = Presets::new(100, 20);
+            loaded.load();
+            let occupied: [bool; 4] = core::array::from_fn(|slot| loaded.is_occupied(slot));
+            assert_eq!(occupied, [false, true, false, true]);
+            assert_eq!(loaded.get(1), Some(&Preset::new("one", Note(60))));
+            assert_eq!(loaded.current(), None);
+            loaded.select(1);
+            assert_eq!(loaded.current(), Some(1));
+            assert_eq!(loaded.get(0), None);
+            loaded.select(0);
+            assert_eq!(loaded.current(), Some(1));
+
+            loaded.clear(1).unwrap();
+            assert_eq!(loaded.current(), None);
+            presets.load();
+            assert!(!presets.is_occupied(1));
+            assert!(presets.is_occupied(3));
+
+            // slots too small for the preset are rejected
+            let mut small: Presets<Note, 2> = Presets::new(0, 10);
+            assert_eq!(
+                small.save(0, Preset::new("x", Note(1))),
+                Err(Error::Overflow)
+            );
+        });
+    }
+
+    #[test]
+    fn sysex() {
+        flash::with_temp_file(|_| {
+            let mut presets: Presets<Note, 2> = Presets::new(0, 20);
+            presets.save(0, Preset::new("lead", Note(72))).unwrap();
+
+            let mut exported = [0; MAX_SYSEX_SIZE];
+            let mut length = 0;
+            let request = [0xf0, 0x7d, b'P', SYSEX_REQUEST, 0, 0xf7];
+            let result = presets.sysex(&request, |reply| {
+                exported[..reply.len()].copy_from_slice(reply);
+                length = reply.len();
+            });
+            assert_eq!(result, Ok(None));
+            assert_eq!(length, 6 + 2 * (settings::OVERHEAD + NAME_LENGTH + 1));
+            assert!(exported[..length]
+                .iter()
+                .skip(1)
+                .take(length - 2)
+                .all(|&byte| byte < 0x80));
+
+            // import the preset into the other slot
+            exported[4] = 1;
+            assert_eq!(presets.sysex(&exported[..length], |_| ()), Ok(Some(1)));
+            assert_eq!(presets.get(1), Some(&Preset::new("lead", Note(72))));
+
+            exported[10] ^= 1;
+            assert_eq!(
+                presets.sysex(&exported[..length], |_| ()),
+                Err(Error::Checksum)
+            );
+            assert_eq!(presets.sysex(&[0xf0, 0x7e, 0xf7], |_| ()), Ok(None));
+            assert_eq!(
+                presets.sysex(&[0xf0, 0x7d, b'P', SYSEX_REQUEST, 1, 0xf7], |_| ()),
+                Ok(None)
+            );
+
+            // slots above 0x7f don't fit in a data byte
+            let mut buffer = [0; MAX_SYSEX_SIZE];
+            assert_eq!(presets.export(0x80, &mut buffer), Err(Error::Invalid));
+        });
+    }
+}
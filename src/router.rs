@@ -0,0 +1,423 @@
+use crate::hal::midi::{CableEvent, MidiMessage, Port};
+use crate::hal::surface::{clear_leds, AftertouchEvent, Button, ButtonEvent, Event, Pads};
+use crate::hal::{Grid, LaunchpadApp, Mutex};
+
+/// The number of overlays that can be open at once.
+pub const MAX_OVERLAYS: usize = 4;
+
+/// The number of buttons on the surface: every point on the grid and the Setup button.
+const BUTTONS: usize = Grid::size() as usize + 1;
+
+/// The part of the surface claimed by a component.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Every button and pad, including the Setup button.
+    All,
+    /// A rectangle of points, given by its bottom left corner and size. The border buttons are in
+    /// rows and columns 0 and 9.
+    Rect { x: i8, y: i8, width: i8, height: i8 },
+    /// The points with the given indices.
+    Buttons(&'static [u8]),
+    /// The Setup button.
+    Setup,
+}
+
+impl Region {
+    /// The 8x8 grid of pads.
+    pub const PADS: Region = Region::rect(1, 1, 8, 8);
+    /// The row of buttons above the pads.
+    pub const TOP: Region = Region::rect(1, 9, 8, 1);
+    /// The row of buttons below the pads.
+    pub const BOTTOM: Region = Region::rect(1, 0, 8, 1);
+    /// The column of buttons to the left of the pads.
+    pub const LEFT: Region = Region::rect(0, 1, 1, 8);
+    /// The column of scene buttons to the right of the pads.
+    pub const RIGHT: Region = Region::rect(9, 1, 1, 8);
+
+    /// Construct a rectangular region.
+    pub const fn rect(x: i8, y: i8, width: i8, height: i8) -> Self {
+        Region::Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns whether a button is in the region.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use launchpad_pro_rs::hal::surface::Button;
+    /// use launchpad_pro_rs::hal::Point;
+    /// use launchpad_pro_rs::router::Region;
+    ///
+    /// assert!(Region::PADS.contains(&Button::Pad(Point::new(8, 8))));
+    /// assert!(!Region::PADS.contains(&Button::Pad(Point::new(9, 8))));
+    /// assert!(Region::Buttons(&[80, 91]).contains(&Button::Pad(Point::from_index(91))));
+    /// assert!(!Region::PADS.contains(&Button::Setup));
+    /// ```
+    pub fn contains(&self, button: &Button) -> bool {
+        match (self, button) {
+            (Region::All, _) => true,
+            (Region::Setup, Button::Setup) => true,
+            (Region::Setup, Button::Pad(_)) => false,
+            (_, Button::Setup) => false,
+            (
+                Region::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                Button::Pad(point),
+            ) => (*x..x + width).contains(&point.x()) && (*y..y + height).contains(&point.y()),
+            (Region::Buttons(indices), Button::Pad(point)) => indices.contains(&point.to_index()),
+        }
+    }
+}
+
+/// An error opening an overlay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// [`MAX_OVERLAYS`] overlays are already open.
+    TooManyOverlays,
+}
+
+struct State {
+    /// The open overlays, with the one receiving input last.
+    overlays: heapless::Vec<&'static dyn LaunchpadApp, MAX_OVERLAYS>,
+    /// Where each held button's press went, so that its release and aftertouch go there too.
+    pressed: [Option<&'static dyn LaunchpadApp>; BUTTONS],
+}
+
+/// Splits an app into components that each look after part of the surface.
+///
+/// Components are apps in their own right, implementing [`LaunchpadApp`], and each claims a
+/// [`Region`] of the surface. Button presses and aftertouch go to the first component whose region
+/// contains the button, and the release goes wherever the press went. Every component gets the
+/// init, timer, MIDI, SysEx and cable events.
+///
+/// A component can open a modal overlay, e.g. a settings page, which captures all input from the
+/// surface until it is closed. Opening an overlay clears the LEDs and gives the components a suspend
+/// event and the overlay a resume event, in which it should draw itself. Closing it clears the LEDs
+/// again and resumes whatever is underneath. Overlays can be stacked, and only the top one receives
+/// input and timer events, so the timers of the suspended components wait until it closes. The top
+/// overlay gets the MIDI, SysEx and cable events alongside the components.
+///
+/// Components that handle events through `&mut self` are put in a static
+/// [`Registration`](crate::hal::Registration), which is a [`LaunchpadApp`] whose handlers must not
+/// preempt each other.
+///
+/// A router is itself an app, so it can be registered with
+/// [`launchpad_app!`](crate::launchpad_app), nested in another router or run by a
+/// [`Switcher`](crate::switcher::Switcher).
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::surface::{ButtonEvent, Event};
+/// use launchpad_pro_rs::hal::LaunchpadApp;
+/// use launchpad_pro_rs::launchpad_app;
+/// use launchpad_pro_rs::router::{Region, Router};
+///
+/// struct Keys;
+/// impl LaunchpadApp for Keys {}
+///
+/// /// Opens the settings with the Setup button.
+/// struct Transport;
+/// impl LaunchpadApp for Transport {
+///     fn button_event(&self, button_event: ButtonEvent) {
+///         if let Event::Press(_) = button_event.event {
+///             ROUTER.open(&SETTINGS).unwrap();
+///         }
+///     }
+/// }
+///
+/// /// Closes itself with the Setup button.
+/// struct Settings;
+/// impl LaunchpadApp for Settings {
+///     fn button_event(&self, button_event: ButtonEvent) {
+///         if let Event::Press(_) = button_event.event {
+///             ROUTER.close();
+///         }
+///     }
+/// }
+///
+/// static SETTINGS: Settings = Settings;
+/// static ROUTER: Router<2> = Router::new([(Region::PADS, &Keys), (Region::Setup, &Transport)]);
+///
+/// launchpad_app!(ROUTER);
+/// ```
+pub struct Router<const N: usize> {
+    components: [(Region, &'static dyn LaunchpadApp); N],
+    state: Mutex<State>,
+}
+
+impl<const N: usize> Router<N> {
+    /// Construct a router. Where regions overlap, the earlier component takes the input.
+    pub const fn new(components: [(Region, &'static dyn LaunchpadApp); N]) -> Self {
+        Router {
+            components,
+            state: Mutex::new(State {
+                overlays: heapless::Vec::new(),
+                pressed: [None; BUTTONS],
+            }),
+        }
+    }
+
+    /// Returns the overlay receiving input, if any are open.
+    fn overlay(&self) -> Option<&'static dyn LaunchpadApp> {
+        self.state.lock().overlays.last().copied()
+    }
+
+    /// Returns whether an overlay is open.
+    pub fn is_overlay_open(&self) -> bool {
+        self.overlay().is_some()
+    }
+
+    /// Returns what should receive input from a button: the top overlay, or the component that
+    /// claims the button.
+    fn target(&self, button: &Button) -> Option<&'static dyn LaunchpadApp> {
+        self.overlay().or_else(|| {
+            self.components
+                .iter()
+                .find(|(region, _)| region.contains(button))
+                .map(|&(_, component)| component)
+        })
+    }
+
+    fn button_index(button: &Button) -> usize {
+        match button {
+            Button::Pad(point) => point.to_index() as usize,
+            Button::Setup => Grid::size() as usize,
+        }
+    }
+
+    /// Open an overlay on top of the components and any other overlays.
+    pub fn open(&self, overlay: &'static dyn LaunchpadApp) -> Result<(), Error> {
+        let below = {
+            let mut state = self.state.lock();
+            let below = state.overlays.last().copied();
+            state
+                .overlays
+                .push(overlay)
+                .map_err(|_| Error::TooManyOverlays)?;
+            below
+        };
+
+        match below {
+            Some(below) => below.suspend_event(),
+            None => self.each(|component| component.suspend_event()),
+        }
+        clear_leds();
+        overlay.resume_event();
+        Ok(())
+    }
+
+    /// Close the top overlay, returning input to the one below or to the components. Nothing
+    /// happens if no overlays are open.
+    pub fn close(&self) {
+        let (overlay, below) = {
+            let mut state = self.state.lock();
+            (state.overlays.pop(), state.overlays.last().copied())
+        };
+        let overlay = match overlay {
+            Some(overlay) => overlay,
+            None => return,
+        };
+
+        overlay.suspend_event();
+        clear_leds();
+        match below {
+            Some(below) => below.resume_event(),
+            None => self.each(|component| component.resume_event()),
+        }
+    }
+
+    /// Call a function on each component and then on the top overlay.
+    fn each_and_overlay(&self, mut event: impl FnMut(&dyn LaunchpadApp)) {
+        self.each(&mut event);
+        if let Some(overlay) = self.overlay() {
+            event(overlay);
+        }
+    }
+
+    fn each(&self, event: impl FnMut(&dyn LaunchpadApp)) {
+        self.components
+            .iter()
+            .map(|&(_, component)| component)
+            .for_each(event);
+    }
+}
+
+impl<const N: usize> LaunchpadApp for Router<N> {
+    fn init_event(&self, pads: Pads) {
+        self.each(|component| component.init_event(pads));
+    }
+
+    fn timer_event(&self) {
+        match self.overlay() {
+            Some(overlay) => overlay.timer_event(),
+            None => self.each(|component| component.timer_event()),
+        }
+    }
+
+    fn midi_event(&self, port: Port, midi_event: MidiMessage) {
+        self.each_and_overlay(|app| app.midi_event(port, midi_event.clone()));
+    }
+
+    fn sysex_event(&self, port: Port, data: &[u8]) {
+        self.each_and_overlay(|app| app.sysex_event(port, data));
+    }
+
+    fn cable_event(&self, cable_event: CableEvent) {
+        self.each_and_overlay(|app| app.cable_event(cable_event));
+    }
+
+    fn button_event(&self, button_event: ButtonEvent) {
+        let index = Self::button_index(&button_event.button);
+        // the state is not locked while the target handles the event, so that it can open or
+        // close overlays
+        let target = match button_event.event {
+            Event::Press(_) => {
+                let target = self.target(&button_event.button);
+                self.state.lock().pressed[index] = target;
+                target
+            }
+            Event::Release => {
+                let pressed = self.state.lock().pressed[index].take();
+                pressed.or_else(|| self.target(&button_event.button))
+            }
+        };
+        if let Some(target) = target {
+            target.button_event(button_event);
+        }
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: AftertouchEvent) {
+        let pressed = self.state.lock().pressed[aftertouch_event.point.to_index() as usize];
+        let target = pressed.or_else(|| self.target(&Button::Pad(aftertouch_event.point)));
+        if let Some(target) = target {
+            target.aftertouch_event(aftertouch_event);
+        }
+    }
+
+    fn suspend_event(&self) {
+        // the components are already suspended while an overlay is open
+        match self.overlay() {
+            Some(overlay) => overlay.suspend_event(),
+            None => self.each(|component| component.suspend_event()),
+        }
+    }
+
+    fn resume_event(&self) {
+        match self.overlay() {
+            Some(overlay) => overlay.resume_event(),
+            None => self.each(|component| component.resume_event()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::Point;
+    use crate::testing::{send, Counter};
+
+    fn pad(x: i8, y: i8) -> Button {
+        Button::Pad(Point::new(x, y))
+    }
+
+    #[test]
+    fn routing_by_region() {
+        static PADS: Counter = Counter::new();
+        static SCENES: Counter = Counter::new();
+        static SETUP: Counter = Counter::new();
+        static ROUTER: Router<3> = Router::new([
+            (Region::PADS, &PADS),
+            (Region::rect(1, 1, 9, 8), &SCENES),
+            (Region::Setup, &SETUP),
+        ]);
+
+        send(&ROUTER, pad(4, 4), Event::Press(100));
+        ROUTER.aftertouch_event(AftertouchEvent {
+            point: Point::new(4, 4),
+            value: 10,
+        });
+        send(&ROUTER, pad(4, 4), Event::Release);
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 1, 0, 0, 0]);
+
+        // the overlapping region only gets what the pads don't claim
+        send(&ROUTER, pad(9, 4), Event::Press(100));
+        assert_eq!(SCENES.counts(), [0, 1, 0, 0, 0, 0, 0, 0]);
+
+        send(&ROUTER, Button::Setup, Event::Press(100));
+        assert_eq!(SETUP.counts(), [0, 1, 0, 1, 0, 0, 0, 0]);
+
+        // unclaimed buttons are ignored
+        send(&ROUTER, pad(0, 0), Event::Press(100));
+
+        ROUTER.timer_event();
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 1, 1, 0, 0]);
+        assert_eq!(SCENES.counts(), [0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(SETUP.counts(), [0, 1, 0, 1, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn overlays_capture_input() {
+        static PADS: Counter = Counter::new();
+        static FIRST: Counter = Counter::new();
+        static SECOND: Counter = Counter::new();
+        static ROUTER: Router<1> = Router::new([(Region::PADS, &PADS)]);
+
+        // a pad held when the overlay opens is still released by its component
+        send(&ROUTER, pad(1, 1), Event::Press(100));
+        ROUTER.open(&FIRST).unwrap();
+        assert!(ROUTER.is_overlay_open());
+        assert_eq!(PADS.counts(), [0, 1, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(FIRST.counts(), [0, 0, 0, 0, 0, 0, 0, 1]);
+
+        send(&ROUTER, pad(1, 1), Event::Release);
+        send(&ROUTER, pad(2, 2), Event::Press(100));
+        send(&ROUTER, Button::Setup, Event::Press(100));
+        ROUTER.timer_event();
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(FIRST.counts(), [0, 2, 0, 1, 0, 1, 0, 1]);
+
+        // only the top overlay receives events
+        ROUTER.open(&SECOND).unwrap();
+        ROUTER.timer_event();
+        assert_eq!(FIRST.counts(), [0, 2, 0, 1, 0, 1, 1, 1]);
+        assert_eq!(SECOND.counts(), [0, 0, 0, 0, 0, 1, 0, 1]);
+
+        ROUTER.close();
+        assert_eq!(FIRST.counts(), [0, 2, 0, 1, 0, 1, 1, 2]);
+        assert_eq!(SECOND.counts(), [0, 0, 0, 0, 0, 1, 1, 1]);
+
+        ROUTER.close();
+        assert!(!ROUTER.is_overlay_open());
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 0, 0, 1, 1]);
+        ROUTER.timer_event();
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 0, 1, 1, 1]);
+
+        // the pad pressed on the overlay is released there
+        send(&ROUTER, pad(2, 2), Event::Release);
+        assert_eq!(FIRST.counts(), [0, 2, 1, 1, 0, 1, 2, 2]);
+
+        ROUTER.close();
+        assert_eq!(PADS.counts(), [0, 1, 1, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn too_many_overlays() {
+        static OVERLAY: Counter = Counter::new();
+        static ROUTER: Router<0> = Router::new([]);
+
+        for _ in 0..MAX_OVERLAYS {
+            ROUTER.open(&OVERLAY).unwrap();
+        }
+        assert_eq!(ROUTER.open(&OVERLAY), Err(Error::TooManyOverlays));
+    }
+}
@@ -0,0 +1,643 @@
+use core::fmt;
+
+use crate::codec::{self, Scheme};
+use crate::hal::midi::MAX_SYSEX_LENGTH;
+use crate::hal::surface::read_led;
+use crate::hal::{flash, Grid, Rgb};
+use crate::settings::{self, Reader, Writer};
+
+/// The manufacturer ID and message type that start an RPC frame.
+pub const SYSEX_ID: [u8; 2] = [0x7d, b'R'];
+
+/// The device ID that addresses every device.
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// The version of the frame layout and the built-in commands, returned by [`GET_VERSION`].
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// The scheme used to pack payloads into 7-bit bytes.
+pub const SCHEME: Scheme = Scheme::LsbFirst;
+
+/// The largest payload a frame can carry, the most that packs into a single SysEx message.
+pub const MAX_PAYLOAD_SIZE: usize = 272;
+
+/// The largest frame: the header, the address, the packed payload, the checksum and `F7`.
+pub const MAX_FRAME_SIZE: usize =
+    1 + SYSEX_ID.len() + 4 + codec::encoded_len(MAX_PAYLOAD_SIZE) + 1 + 1;
+
+// every frame can be sent with `send_sysex`
+const _: () = assert!(MAX_FRAME_SIZE <= MAX_SYSEX_LENGTH);
+
+/// A request or reply as sent over SysEx.
+pub type Frame = heapless::Vec<u8, MAX_FRAME_SIZE>;
+
+/// The unpacked arguments of a request or result of a reply.
+pub type Payload = heapless::Vec<u8, MAX_PAYLOAD_SIZE>;
+
+/// The colours of the LEDs on the grid, indexed like [`Point::to_index`], each component from 0
+/// to 63.
+///
+/// [`Point::to_index`]: crate::hal::Point::to_index
+pub type Screenshot = [[u8; 3]; Grid::size() as usize];
+
+/// Returns the protocol version, then the firmware version as ASCII. Takes no arguments.
+pub const GET_VERSION: u8 = 0x00;
+
+/// Returns bytes from the user area of flash. Takes the offset and the length as `u16`s.
+pub const READ_SETTINGS: u8 = 0x01;
+
+/// Writes bytes to the user area of flash. Takes the offset as a `u16`, then the bytes.
+pub const WRITE_SETTINGS: u8 = 0x02;
+
+/// Returns the red, green and blue components of the LEDs in [`SCREENSHOT_ROWS`] rows of the
+/// grid. Takes the first row as a `u8`.
+pub const SCREENSHOT: u8 = 0x03;
+
+/// The number of rows of the grid in each [`SCREENSHOT`] reply, so that the whole grid takes two
+/// requests.
+pub const SCREENSHOT_ROWS: u8 = Grid::height() / 2;
+
+/// The first command ID apps can use. Lower IDs are reserved for built-in commands.
+pub const FIRST_APP_COMMAND: u8 = 0x10;
+
+/// The kind of frame, sent after the device ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A request for a device to run a command.
+    Request = 0,
+    /// The result of a command.
+    Reply = 1,
+    /// A command failed. The payload is the [`Error`] code.
+    Error = 2,
+}
+
+/// An error running a command, sent back in an error reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The device has no command with the requested ID.
+    UnknownCommand = 1,
+    /// The arguments are missing or out of range.
+    BadArguments = 2,
+    /// The arguments or the result don't fit in a frame.
+    TooLarge = 3,
+    /// The command couldn't be completed.
+    Failed = 4,
+}
+
+impl Error {
+    /// Returns the error for a code sent in an error reply. Unknown codes are treated as failures.
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => Error::UnknownCommand,
+            2 => Error::BadArguments,
+            3 => Error::TooLarge,
+            _ => Error::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownCommand => write!(f, "unknown command"),
+            Error::BadArguments => write!(f, "bad arguments"),
+            Error::TooLarge => write!(f, "too large for a frame"),
+            Error::Failed => write!(f, "command failed"),
+        }
+    }
+}
+
+impl From<settings::Error> for Error {
+    fn from(error: settings::Error) -> Self {
+        match error {
+            settings::Error::Overflow => Error::TooLarge,
+            settings::Error::Truncated | settings::Error::Invalid => Error::BadArguments,
+            settings::Error::Flash(error) => error.into(),
+            _ => Error::Failed,
+        }
+    }
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        match error {
+            flash::Error::OutOfBounds => Error::BadArguments,
+        }
+    }
+}
+
+/// Build a frame. Payloads longer than [`MAX_PAYLOAD_SIZE`] are truncated.
+///
+/// The frame is `F0 7D 52`, then the device ID, the [`Kind`], the command ID and the request ID,
+/// then the payload packed with [`SCHEME`], a [`codec::checksum7`] of everything after the header,
+/// and finally `F7`.
+pub fn frame(device: u8, kind: Kind, command: u8, request: u8, payload: &[u8]) -> Frame {
+    let payload = &payload[..payload.len().min(MAX_PAYLOAD_SIZE)];
+    let mut frame = Frame::new();
+    let _ = frame.extend_from_slice(&[
+        0xf0,
+        SYSEX_ID[0],
+        SYSEX_ID[1],
+        device & 0x7f,
+        kind as u8,
+        command & 0x7f,
+        request & 0x7f,
+    ]);
+    let start = frame.len();
+    let _ = frame.resize(start + codec::encoded_len(payload.len()), 0);
+    let _ = codec::encode(SCHEME, payload, &mut frame[start..]);
+    let checksum = codec::checksum7(&frame[1 + SYSEX_ID.len()..]);
+    let _ = frame.push(checksum);
+    let _ = frame.push(0xf7);
+    frame
+}
+
+/// A frame read from SysEx.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The device the request was sent to, or the reply came from.
+    pub device: u8,
+    /// Whether this is a request or a reply.
+    pub kind: Kind,
+    /// The command requested.
+    pub command: u8,
+    /// The ID the client gave the request, repeated in its reply.
+    pub request: u8,
+    /// The unpacked arguments or result.
+    pub payload: Payload,
+}
+
+impl Message {
+    /// Read a frame, returning `None` if the data is another SysEx message or is corrupted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use launchpad_pro_rs::rpc::{self, Kind, Message};
+    ///
+    /// let frame = rpc::frame(1, Kind::Reply, 0x10, 7, &[0xff, 0x00]);
+    /// let message = Message::parse(&frame).unwrap();
+    /// assert_eq!(message.kind, Kind::Reply);
+    /// assert_eq!(message.payload, [0xff, 0x00]);
+    /// ```
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let body = match data {
+            [0xf0, body @ .., 0xf7] => body.strip_prefix(&SYSEX_ID)?,
+            _ => return None,
+        };
+        let (&checksum, body) = body.split_last()?;
+        if codec::checksum7(body) != checksum {
+            return None;
+        }
+        let (&[device, kind, command, request], packed) = body.split_first_chunk()?;
+        let kind = match kind {
+            0 => Kind::Request,
+            1 => Kind::Reply,
+            2 => Kind::Error,
+            _ => return None,
+        };
+        let mut payload = Payload::new();
+        payload.resize(codec::decoded_len(packed.len())?, 0).ok()?;
+        codec::decode(SCHEME, packed, &mut payload).ok()?;
+        Some(Message {
+            device,
+            kind,
+            command,
+            request,
+            payload,
+        })
+    }
+}
+
+/// Runs a command. It reads its arguments from the request and writes its result to the reply.
+pub type Handler<C> = fn(&mut C, &mut Reader, &mut Writer) -> Result<(), Error>;
+
+/// Answers requests for the built-in commands and the commands an app adds.
+///
+/// Each app command is a [`Handler`] that is passed a context, usually the app's state, when it
+/// runs. Every reply fits in a single SysEx message, so send them with [`send_sysex`]. Handling a
+/// request stages the request, the reply and its frame on the stack, under 1 KB in all.
+///
+/// [`send_sysex`]: crate::hal::midi::send_sysex
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::rpc::{self, Client, Server, FIRST_APP_COMMAND};
+///
+/// const ADD: u8 = FIRST_APP_COMMAND;
+///
+/// static SERVER: Server<u32, 1> = Server::new(
+///     0,
+///     "1.0.0",
+///     [(ADD, |total, request, reply| {
+///         *total += request.get_u16()? as u32;
+///         Ok(reply.put_u32(*total)?)
+///     })],
+/// );
+///
+/// let mut client = Client::new(0);
+/// let request = client.call(ADD, |arguments| arguments.put_u16(300)).unwrap();
+///
+/// let mut total = 1;
+/// let mut result = None;
+/// SERVER.handle(&mut total, &request, |reply| result = client.reply(reply));
+/// assert_eq!(result, Some(Ok(rpc::Payload::from_slice(&301u32.to_le_bytes()).unwrap())));
+/// ```
+pub struct Server<C, const N: usize> {
+    device: u8,
+    version: &'static str,
+    commands: [(u8, Handler<C>); N],
+}
+
+impl<C, const N: usize> Server<C, N> {
+    /// Construct a server for a device, given the firmware version and the app's commands.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a command ID is reserved, isn't 7-bit, or is used twice. In a static, this
+    /// happens at compile time.
+    pub const fn new(device: u8, version: &'static str, commands: [(u8, Handler<C>); N]) -> Self {
+        let mut i = 0;
+        while i < N {
+            let command = commands[i].0;
+            assert!(command >= FIRST_APP_COMMAND && command < 0x80);
+            let mut j = 0;
+            while j < i {
+                assert!(commands[j].0 != command, "command IDs must be unique");
+                j += 1;
+            }
+            i += 1;
+        }
+        Server {
+            device: device & 0x7f,
+            version,
+            commands,
+        }
+    }
+
+    /// Returns the device ID the server answers to, as well as [`ALL_DEVICES`].
+    pub fn device(&self) -> u8 {
+        self.device
+    }
+
+    /// Handle a SysEx message. A request for this device is run and answered by passing the reply
+    /// or an error reply to `reply`, returning true. Other messages are ignored.
+    pub fn handle(&self, context: &mut C, data: &[u8], mut reply: impl FnMut(&[u8])) -> bool {
+        let request = match Message::parse(data) {
+            Some(message)
+                if message.kind == Kind::Request
+                    && (message.device == self.device || message.device == ALL_DEVICES) =>
+            {
+                message
+            }
+            _ => return false,
+        };
+
+        let mut buffer = [0; MAX_PAYLOAD_SIZE];
+        let mut writer = Writer::new(&mut buffer);
+        let result = self.run(
+            context,
+            request.command,
+            &mut Reader::new(&request.payload),
+            &mut writer,
+        );
+        let length = writer.len();
+        let (kind, payload) = match result {
+            Ok(()) => (Kind::Reply, &buffer[..length]),
+            Err(error) => {
+                buffer[0] = error as u8;
+                (Kind::Error, &buffer[..1])
+            }
+        };
+        reply(&frame(
+            self.device,
+            kind,
+            request.command,
+            request.request,
+            payload,
+        ));
+        true
+    }
+
+    fn run(
+        &self,
+        context: &mut C,
+        command: u8,
+        request: &mut Reader,
+        reply: &mut Writer,
+    ) -> Result<(), Error> {
+        match command {
+            GET_VERSION => {
+                reply.put_u8(PROTOCOL_VERSION)?;
+                reply.put_bytes(self.version.as_bytes())?;
+            }
+            READ_SETTINGS => {
+                let offset = request.get_u16()? as usize;
+                let length = request.get_u16()? as usize;
+                if length > MAX_PAYLOAD_SIZE {
+                    return Err(Error::TooLarge);
+                }
+                if offset + length > flash::USER_AREA_SIZE {
+                    return Err(Error::BadArguments);
+                }
+                // copy through a small buffer to save stack
+                let mut buffer = [0; 32];
+                for start in (offset..offset + length).step_by(buffer.len()) {
+                    let chunk = &mut buffer[..(offset + length - start).min(32)];
+                    flash::read(start, chunk)?;
+                    reply.put_bytes(chunk)?;
+                }
+            }
+            WRITE_SETTINGS => {
+                let offset = request.get_u16()? as usize;
+                flash::write(offset, request.get_bytes(request.remaining())?)?;
+            }
+            SCREENSHOT => {
+                let first = request.get_u8()?;
+                if first >= Grid::height() {
+                    return Err(Error::BadArguments);
+                }
+                let rows = Grid::points()
+                    .skip(first as usize * Grid::width() as usize)
+                    .take(SCREENSHOT_ROWS as usize * Grid::width() as usize);
+                for point in rows {
+                    let rgb = read_led(point).unwrap_or(Rgb::new(0, 0, 0));
+                    reply.put_bytes(&[rgb.red(), rgb.green(), rgb.blue()])?;
+                }
+            }
+            _ => {
+                let (_, handler) = self
+                    .commands
+                    .iter()
+                    .find(|(id, _)| *id == command)
+                    .ok_or(Error::UnknownCommand)?;
+                handler(context, request, reply)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds requests and reads their replies on the host.
+///
+/// Each request is given the next request ID, and only the reply to the latest request is read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Client {
+    device: u8,
+    request: u8,
+}
+
+impl Client {
+    /// Construct a client that sends requests to a device, or to [`ALL_DEVICES`].
+    pub const fn new(device: u8) -> Self {
+        Client {
+            device: device & 0x7f,
+            request: 0,
+        }
+    }
+
+    /// Build a request with some arguments. Arguments longer than [`MAX_PAYLOAD_SIZE`] are
+    /// truncated.
+    pub fn request(&mut self, command: u8, arguments: &[u8]) -> Frame {
+        self.request = (self.request + 1) & 0x7f;
+        frame(self.device, Kind::Request, command, self.request, arguments)
+    }
+
+    /// Build a request, writing its arguments with a [`Writer`].
+    pub fn call(
+        &mut self,
+        command: u8,
+        arguments: impl FnOnce(&mut Writer) -> Result<(), settings::Error>,
+    ) -> Result<Frame, Error> {
+        let mut buffer = [0; MAX_PAYLOAD_SIZE];
+        let mut writer = Writer::new(&mut buffer);
+        arguments(&mut writer)?;
+        let length = writer.len();
+        Ok(self.request(command, &buffer[..length]))
+    }
+
+    /// Build a [`GET_VERSION`] request. Read the reply with [`Version::parse`].
+    pub fn get_version(&mut self) -> Frame {
+        self.request(GET_VERSION, &[])
+    }
+
+    /// Build a [`READ_SETTINGS`] request.
+    pub fn read_settings(&mut self, offset: u16, length: u16) -> Frame {
+        let [offset_low, offset_high] = offset.to_le_bytes();
+        let [length_low, length_high] = length.to_le_bytes();
+        self.request(
+            READ_SETTINGS,
+            &[offset_low, offset_high, length_low, length_high],
+        )
+    }
+
+    /// Build a [`WRITE_SETTINGS`] request.
+    pub fn write_settings(&mut self, offset: u16, data: &[u8]) -> Result<Frame, Error> {
+        self.call(WRITE_SETTINGS, |arguments| {
+            arguments.put_u16(offset)?;
+            arguments.put_bytes(data)
+        })
+    }
+
+    /// Build a [`SCREENSHOT`] request for the rows from `first_row`. Read the replies for the
+    /// lower and upper rows with [`parse_screenshot`].
+    pub fn screenshot(&mut self, first_row: u8) -> Frame {
+        self.request(SCREENSHOT, &[first_row])
+    }
+
+    /// Read the reply to the latest request, returning its payload or the error the device
+    /// reported. Returns `None` if the data is anything else.
+    pub fn reply(&self, data: &[u8]) -> Option<Result<Payload, Error>> {
+        let message = Message::parse(data)?;
+        let from_device = self.device == ALL_DEVICES || message.device == self.device;
+        if !from_device || message.request != self.request {
+            return None;
+        }
+        match message.kind {
+            Kind::Request => None,
+            Kind::Reply => Some(Ok(message.payload)),
+            Kind::Error => Some(Err(Error::from_code(
+                message.payload.first().copied().unwrap_or(0),
+            ))),
+        }
+    }
+}
+
+/// The reply to [`GET_VERSION`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version<'a> {
+    /// The [`PROTOCOL_VERSION`] of the device.
+    pub protocol: u8,
+    /// The version of the firmware.
+    pub firmware: &'a str,
+}
+
+impl<'a> Version<'a> {
+    /// Read the payload of a [`GET_VERSION`] reply.
+    pub fn parse(payload: &'a [u8]) -> Option<Self> {
+        let (&protocol, firmware) = payload.split_first()?;
+        Some(Version {
+            protocol,
+            firmware: core::str::from_utf8(firmware).ok()?,
+        })
+    }
+}
+
+/// Read the payloads of the [`SCREENSHOT`] replies for the rows from 0 and the rows from
+/// [`SCREENSHOT_ROWS`].
+pub fn parse_screenshot(lower: &[u8], upper: &[u8]) -> Option<Screenshot> {
+    let size = 3 * SCREENSHOT_ROWS as usize * Grid::width() as usize;
+    if lower.len() != size || upper.len() != size {
+        return None;
+    }
+    Some(core::array::from_fn(|index| {
+        let payload = if index < size / 3 { lower } else { upper };
+        let index = index % (size / 3);
+        let mut rgb = [0; 3];
+        rgb.copy_from_slice(&payload[3 * index..3 * index + 3]);
+        rgb
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_survive_a_round_trip() {
+        let payload: Vec<u8> = (0..=255).collect();
+        let frame = frame(5, Kind::Request, 0x20, 9, &payload);
+        assert!(frame.len() <= MAX_FRAME_SIZE);
+        assert!(frame[1..frame.len() - 1].iter().all(|&byte| byte < 0x80));
+
+        let message = Message::parse(&frame).unwrap();
+        assert_eq!(
+            (
+                message.device,
+                message.kind,
+                message.command,
+                message.request
+            ),
+            (5, Kind::Request, 0x20, 9)
+        );
+        assert_eq!(message.payload, payload[..]);
+
+        // corrupted and foreign messages are ignored
+        let mut corrupted = frame.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(Message::parse(&corrupted), None);
+        assert_eq!(Message::parse(&frame[..frame.len() - 1]), None);
+        assert_eq!(Message::parse(&[0xf0, 0x7d, b'P', 1, 0xf7]), None);
+    }
+
+    /// The state of a test app.
+    struct Total(u8);
+
+    const INCREMENT: u8 = FIRST_APP_COMMAND;
+
+    static SERVER: Server<Total, 1> = Server::new(
+        3,
+        "0.1.0",
+        [(INCREMENT, |total, request, reply| {
+            let amount = request.get_u8()?;
+            total.0 = total.0.checked_add(amount).ok_or(Error::Failed)?;
+            Ok(reply.put_u8(total.0)?)
+        })],
+    );
+
+    /// Send a request to the test server, returning what the client makes of the reply.
+    fn call(total: &mut Total, client: &Client, request: &[u8]) -> Option<Result<Payload, Error>> {
+        let mut result = None;
+        SERVER.handle(total, request, |reply| result = client.reply(reply));
+        result
+    }
+
+    #[test]
+    fn commands_are_answered() {
+        let mut total = Total(0);
+        let mut client = Client::new(3);
+
+        let request = client.call(INCREMENT, |arguments| arguments.put_u8(200));
+        let reply = call(&mut total, &client, &request.unwrap());
+        assert_eq!(reply, Some(Ok(Payload::from_slice(&[200]).unwrap())));
+
+        let request = client.call(INCREMENT, |arguments| arguments.put_u8(100));
+        let reply = call(&mut total, &client, &request.unwrap());
+        assert_eq!(reply, Some(Err(Error::Failed)));
+
+        let request = client.request(INCREMENT, &[]);
+        assert_eq!(
+            call(&mut total, &client, &request),
+            Some(Err(Error::BadArguments))
+        );
+        let request = client.request(0x7e, &[]);
+        assert_eq!(
+            call(&mut total, &client, &request),
+            Some(Err(Error::UnknownCommand))
+        );
+
+        let request = client.get_version();
+        let reply = call(&mut total, &client, &request).unwrap().unwrap();
+        assert_eq!(
+            Version::parse(&reply),
+            Some(Version {
+                protocol: PROTOCOL_VERSION,
+                firmware: "0.1.0",
+            })
+        );
+
+        let request = client.screenshot(0);
+        let lower = call(&mut total, &client, &request).unwrap().unwrap();
+        let request = client.screenshot(SCREENSHOT_ROWS);
+        let upper = call(&mut total, &client, &request).unwrap().unwrap();
+        assert!(parse_screenshot(&lower, &upper).is_some());
+        assert_eq!(parse_screenshot(&lower, &lower[1..]), None);
+        let request = client.screenshot(Grid::height());
+        assert_eq!(
+            call(&mut total, &client, &request),
+            Some(Err(Error::BadArguments))
+        );
+
+        // requests for other devices are ignored, and so are replies to old requests
+        let mut other = Client::new(4);
+        let request = other.get_version();
+        assert_eq!(call(&mut total, &other, &request), None);
+        let mut everyone = Client::new(ALL_DEVICES);
+        let request = everyone.get_version();
+        assert!(call(&mut total, &everyone, &request).is_some());
+        let old = client.get_version();
+        client.get_version();
+        assert_eq!(call(&mut total, &client, &old), None);
+    }
+
+    #[test]
+    fn settings_are_read_and_written() {
+        flash::with_temp_file(|_| {
+            let mut total = Total(0);
+            let mut client = Client::new(3);
+            let data: Vec<u8> = (0..100).collect();
+
+            let request = client.write_settings(900, &data).unwrap();
+            assert_eq!(
+                call(&mut total, &client, &request),
+                Some(Ok(Payload::new()))
+            );
+
+            let request = client.read_settings(910, 50);
+            let reply = call(&mut total, &client, &request).unwrap().unwrap();
+            assert_eq!(reply, data[10..60]);
+
+            let request = client.read_settings(1000, 100);
+            assert_eq!(
+                call(&mut total, &client, &request),
+                Some(Err(Error::BadArguments))
+            );
+            let request = client.read_settings(0, 1000);
+            assert_eq!(
+                call(&mut total, &client, &request),
+                Some(Err(Error::TooLarge))
+            );
+        });
+    }
+}
@@ -0,0 +1,263 @@
+/// The number of semitones in an octave.
+const OCTAVE: u8 = 12;
+
+/// A set of pitch classes relative to a root, stored as a 12-bit mask where bit `n` is set if the
+/// note `n` semitones above the root is in the scale.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::scale::Scale;
+///
+/// assert!(Scale::MAJOR.contains(4));
+/// assert!(!Scale::MAJOR.contains(3));
+/// assert_eq!(Scale::MINOR_PENTATONIC.len(), 5);
+///
+/// // scales can also be built from any set of semitones
+/// let scale = Scale::from_degrees(&[0, 1, 5, 7, 8]);
+/// assert_eq!(scale, Scale::new(0b0001_1010_0011));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Scale(u16);
+
+impl Scale {
+    /// The major scale, or Ionian mode.
+    pub const MAJOR: Scale = Scale::from_degrees(&[0, 2, 4, 5, 7, 9, 11]);
+    /// The Dorian mode, the second mode of the major scale.
+    pub const DORIAN: Scale = Scale::from_degrees(&[0, 2, 3, 5, 7, 9, 10]);
+    /// The Phrygian mode, the third mode of the major scale.
+    pub const PHRYGIAN: Scale = Scale::from_degrees(&[0, 1, 3, 5, 7, 8, 10]);
+    /// The Lydian mode, the fourth mode of the major scale.
+    pub const LYDIAN: Scale = Scale::from_degrees(&[0, 2, 4, 6, 7, 9, 11]);
+    /// The Mixolydian mode, the fifth mode of the major scale.
+    pub const MIXOLYDIAN: Scale = Scale::from_degrees(&[0, 2, 4, 5, 7, 9, 10]);
+    /// The natural minor scale, or Aeolian mode.
+    pub const MINOR: Scale = Scale::from_degrees(&[0, 2, 3, 5, 7, 8, 10]);
+    /// The Locrian mode, the seventh mode of the major scale.
+    pub const LOCRIAN: Scale = Scale::from_degrees(&[0, 1, 3, 5, 6, 8, 10]);
+    /// The natural minor scale with a raised seventh.
+    pub const HARMONIC_MINOR: Scale = Scale::from_degrees(&[0, 2, 3, 5, 7, 8, 11]);
+    /// The ascending melodic minor scale: the major scale with a flat third.
+    pub const MELODIC_MINOR: Scale = Scale::from_degrees(&[0, 2, 3, 5, 7, 9, 11]);
+    /// The major scale without its fourth and seventh.
+    pub const MAJOR_PENTATONIC: Scale = Scale::from_degrees(&[0, 2, 4, 7, 9]);
+    /// The natural minor scale without its second and sixth.
+    pub const MINOR_PENTATONIC: Scale = Scale::from_degrees(&[0, 3, 5, 7, 10]);
+    /// The minor pentatonic scale with an added flat fifth.
+    pub const BLUES: Scale = Scale::from_degrees(&[0, 3, 5, 6, 7, 10]);
+    /// Six notes a whole tone apart.
+    pub const WHOLE_TONE: Scale = Scale::from_degrees(&[0, 2, 4, 6, 8, 10]);
+    /// The whole-half diminished scale.
+    pub const DIMINISHED: Scale = Scale::from_degrees(&[0, 2, 3, 5, 6, 8, 9, 11]);
+    /// All twelve notes.
+    pub const CHROMATIC: Scale = Scale::new(0xfff);
+
+    /// Every scale in the catalogue, starting with the major modes.
+    pub const ALL: [Scale; 15] = [
+        Scale::MAJOR,
+        Scale::DORIAN,
+        Scale::PHRYGIAN,
+        Scale::LYDIAN,
+        Scale::MIXOLYDIAN,
+        Scale::MINOR,
+        Scale::LOCRIAN,
+        Scale::HARMONIC_MINOR,
+        Scale::MELODIC_MINOR,
+        Scale::MAJOR_PENTATONIC,
+        Scale::MINOR_PENTATONIC,
+        Scale::BLUES,
+        Scale::WHOLE_TONE,
+        Scale::DIMINISHED,
+        Scale::CHROMATIC,
+    ];
+
+    /// Construct a scale from a 12-bit mask. Any higher bits are ignored.
+    pub const fn new(mask: u16) -> Self {
+        Scale(mask & 0xfff)
+    }
+
+    /// Construct a scale from the semitones above the root that are in it. Semitones of an octave
+    /// or more wrap around.
+    pub const fn from_degrees(degrees: &[u8]) -> Self {
+        let mut mask = 0;
+        let mut i = 0;
+        while i < degrees.len() {
+            mask |= 1 << (degrees[i] % OCTAVE);
+            i += 1;
+        }
+        Scale(mask)
+    }
+
+    /// Returns the scale as a 12-bit mask.
+    pub const fn mask(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns the number of notes in the scale.
+    pub const fn len(&self) -> u8 {
+        self.0.count_ones() as u8
+    }
+
+    /// Returns true if the scale has no notes.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if the note a number of semitones above the root is in the scale.
+    pub const fn contains(&self, semitones: u8) -> bool {
+        self.0 & (1 << (semitones % OCTAVE)) != 0
+    }
+
+    /// Returns an iterator over the semitones above the root that are in the scale, in ascending
+    /// order.
+    pub fn degrees(&self) -> impl Iterator<Item = u8> {
+        let scale = *self;
+        (0..OCTAVE).filter(move |&semitones| scale.contains(semitones))
+    }
+
+    /// Returns the position of this scale in [`Scale::ALL`], or None for a user-defined scale.
+    pub fn index(&self) -> Option<usize> {
+        Scale::ALL.iter().position(|scale| scale == self)
+    }
+}
+
+/// A scale played from a root pitch class.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::scale::{Key, Scale};
+///
+/// // D dorian
+/// let key = Key::new(2, Scale::DORIAN);
+/// assert!(key.is_root(62));
+/// assert!(key.contains(72));
+/// assert_eq!(key.nearest(73), Some(72));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    root: u8,
+    scale: Scale,
+}
+
+impl Key {
+    /// Construct a key from a root pitch class, 0 for C, and a scale.
+    pub const fn new(root: u8, scale: Scale) -> Self {
+        Key {
+            root: root % OCTAVE,
+            scale,
+        }
+    }
+
+    /// Returns the root pitch class, 0 for C.
+    pub const fn root(&self) -> u8 {
+        self.root
+    }
+
+    /// Set the root pitch class, 0 for C.
+    pub fn set_root(&mut self, root: u8) {
+        self.root = root % OCTAVE;
+    }
+
+    /// Returns the scale.
+    pub const fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Set the scale.
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// Returns true if a MIDI note is the root of the key in any octave.
+    pub const fn is_root(&self, note: u8) -> bool {
+        note % OCTAVE == self.root
+    }
+
+    /// Returns true if a MIDI note is in the key.
+    pub const fn contains(&self, note: u8) -> bool {
+        self.scale.contains(note % OCTAVE + OCTAVE - self.root)
+    }
+
+    /// Returns the nearest MIDI note that is in the key, choosing the lower note when two are
+    /// equally near, or None if the scale is empty.
+    pub fn nearest(&self, note: u8) -> Option<u8> {
+        let note = note.min(127) as i16;
+        (0..OCTAVE as i16)
+            .flat_map(|offset| [note - offset, note + offset])
+            .filter(|n| (0..=127).contains(n))
+            .map(|n| n as u8)
+            .find(|&n| self.contains(n))
+    }
+}
+
+impl Default for Key {
+    fn default() -> Self {
+        Key::new(0, Scale::MAJOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the mode of a scale that starts on one of its degrees.
+    fn mode(scale: Scale, degree: usize) -> Scale {
+        let start = scale.degrees().nth(degree).unwrap();
+        let mask = scale.mask() as u32;
+        Scale::new(((mask >> start) | (mask << (OCTAVE - start))) as u16)
+    }
+
+    #[test]
+    fn major_modes() {
+        let modes = [
+            Scale::MAJOR,
+            Scale::DORIAN,
+            Scale::PHRYGIAN,
+            Scale::LYDIAN,
+            Scale::MIXOLYDIAN,
+            Scale::MINOR,
+            Scale::LOCRIAN,
+        ];
+        for (degree, scale) in modes.into_iter().enumerate() {
+            assert_eq!(mode(Scale::MAJOR, degree), scale);
+        }
+    }
+
+    #[test]
+    fn catalogue() {
+        assert_eq!(Scale::MAJOR.mask(), 0b1010_1011_0101);
+        assert_eq!(mode(Scale::MAJOR_PENTATONIC, 4), Scale::MINOR_PENTATONIC);
+        assert_eq!(mode(Scale::DIMINISHED, 1), mode(Scale::DIMINISHED, 3));
+        assert!(Scale::BLUES.degrees().eq([0, 3, 5, 6, 7, 10]));
+        assert_eq!(Scale::WHOLE_TONE.len(), 6);
+        assert_eq!(Scale::CHROMATIC.len(), 12);
+
+        assert_eq!(Scale::HARMONIC_MINOR.index(), Some(7));
+        assert_eq!(Scale::new(0b1001).index(), None);
+        assert!(Scale::new(0).is_empty());
+    }
+
+    #[test]
+    fn key_contains() {
+        let key = Key::new(9, Scale::MINOR);
+        let notes: heapless::Vec<u8, 12> = (57..69).filter(|&note| key.contains(note)).collect();
+        assert_eq!(notes, [57, 59, 60, 62, 64, 65, 67]);
+        assert!(key.is_root(21));
+        assert!(!key.is_root(60));
+    }
+
+    #[test]
+    fn nearest() {
+        let key = Key::new(0, Scale::MAJOR_PENTATONIC);
+        assert_eq!(key.nearest(60), Some(60));
+        assert_eq!(key.nearest(61), Some(60));
+        assert_eq!(key.nearest(63), Some(62));
+        assert_eq!(key.nearest(65), Some(64));
+        assert_eq!(key.nearest(66), Some(67));
+        assert_eq!(key.nearest(127), Some(127));
+
+        assert_eq!(Key::new(11, Scale::new(1)).nearest(0), Some(11));
+        assert_eq!(Key::new(0, Scale::new(0)).nearest(60), None);
+    }
+}
@@ -0,0 +1,507 @@
+use crate::clock::ClockEvent;
+use wmidi::{Channel, MidiMessage, Note, U7};
+
+/// The maximum number of steps in a pattern.
+pub const MAX_STEPS: usize = 64;
+/// The number of lanes (simultaneous notes) in each step.
+pub const LANES: usize = 8;
+/// The maximum number of notes that can be sounding at once.
+const MAX_ACTIVE_NOTES: usize = 32;
+/// The default number of clock pulses per step, a sixteenth note at 24 PPQN.
+pub const DEFAULT_PULSES_PER_STEP: u8 = 6;
+/// The default gate length of a trigger in clock pulses.
+pub const DEFAULT_GATE: u8 = 3;
+
+/// A note played by a single lane of a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trigger {
+    /// The MIDI note number.
+    pub note: u8,
+    /// The MIDI velocity in the range `[1, 127]`.
+    pub velocity: u8,
+    /// How long the note is held for, in clock pulses.
+    pub gate: u8,
+    /// The chance that the trigger plays, as a percentage in the range `[0, 100]`.
+    pub probability: u8,
+}
+
+impl Trigger {
+    /// Construct a trigger that always plays with the default gate length.
+    pub const fn new(note: u8, velocity: u8) -> Self {
+        Trigger {
+            note,
+            velocity,
+            gate: DEFAULT_GATE,
+            probability: 100,
+        }
+    }
+}
+
+/// A pattern of up to 64 steps, each of which can trigger a note on every lane.
+pub struct Pattern {
+    steps: [[Option<Trigger>; LANES]; MAX_STEPS],
+    length: u8,
+}
+
+impl Pattern {
+    /// Construct an empty pattern of 16 steps.
+    pub const fn new() -> Self {
+        Pattern {
+            steps: [[None; LANES]; MAX_STEPS],
+            length: 16,
+        }
+    }
+
+    /// Returns the number of steps in the pattern.
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// Set the number of steps in the pattern. The length is clamped to `[1, 64]`. Steps beyond the
+    /// length keep their contents.
+    pub fn set_length(&mut self, length: u8) {
+        self.length = length.clamp(1, MAX_STEPS as u8);
+    }
+
+    /// Returns the trigger on a lane of a step, if there is one.
+    pub fn get(&self, step: u8, lane: u8) -> Option<Trigger> {
+        self.steps
+            .get(step as usize)
+            .and_then(|lanes| lanes.get(lane as usize))
+            .copied()
+            .flatten()
+    }
+
+    /// Set or clear the trigger on a lane of a step. Out of range steps and lanes are ignored.
+    pub fn set(&mut self, step: u8, lane: u8, trigger: Option<Trigger>) {
+        if let Some(slot) = self
+            .steps
+            .get_mut(step as usize)
+            .and_then(|lanes| lanes.get_mut(lane as usize))
+        {
+            *slot = trigger;
+        }
+    }
+
+    /// Returns an iterator over the triggers of a step.
+    pub fn triggers(&self, step: u8) -> impl Iterator<Item = Trigger> + '_ {
+        self.steps[step as usize % MAX_STEPS]
+            .iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Clear every step in the pattern.
+    pub fn clear(&mut self) {
+        self.steps = [[None; LANES]; MAX_STEPS];
+    }
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A note that has been sent and is waiting for its note off.
+#[derive(Clone, Copy, Debug)]
+struct ActiveNote {
+    note: u8,
+    remaining: u8,
+}
+
+/// A polyphonic step sequencer engine.
+///
+/// The sequencer does not talk to the hardware directly. It is advanced with `ClockEvent`s and
+/// hands the MIDI messages it produces to a callback, so it behaves identically on the host and on
+/// the Launchpad Pro.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::clock::ClockEvent;
+/// use launchpad_pro_rs::sequencer::{Sequencer, Trigger};
+/// use launchpad_pro_rs::hal::midi::{send_message, Port};
+/// use wmidi::Channel;
+///
+/// let mut sequencer = Sequencer::new(Channel::Ch1);
+/// sequencer.pattern_mut().set(0, 0, Some(Trigger::new(60, 100)));
+///
+/// sequencer.clock_event(ClockEvent::Start, &mut |message| send_message(Port::USB, &message));
+/// sequencer.clock_event(ClockEvent::Pulse, &mut |message| send_message(Port::USB, &message));
+/// ```
+pub struct Sequencer {
+    pattern: Pattern,
+    channel: Channel,
+    pulses_per_step: u8,
+    pulse: u8,
+    position: u8,
+    playhead: Option<u8>,
+    is_playing: bool,
+    seed: u32,
+    active: heapless::Vec<ActiveNote, MAX_ACTIVE_NOTES>,
+}
+
+impl Sequencer {
+    /// Construct a stopped sequencer with an empty pattern that plays on the given channel.
+    pub const fn new(channel: Channel) -> Self {
+        Sequencer {
+            pattern: Pattern::new(),
+            channel,
+            pulses_per_step: DEFAULT_PULSES_PER_STEP,
+            pulse: 0,
+            position: 0,
+            playhead: None,
+            is_playing: false,
+            seed: 0x1234_5678,
+            active: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns the pattern being played.
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    /// Returns the pattern being played for editing.
+    pub fn pattern_mut(&mut self) -> &mut Pattern {
+        &mut self.pattern
+    }
+
+    /// Set the MIDI channel that notes are sent on. Notes still sounding are ended on the old
+    /// channel first, so none are left hanging.
+    pub fn set_channel(&mut self, channel: Channel, output: &mut impl FnMut(MidiMessage<'static>)) {
+        if channel != self.channel {
+            self.release_all(output);
+            self.channel = channel;
+        }
+    }
+
+    /// Returns the number of clock pulses per step.
+    pub fn pulses_per_step(&self) -> u8 {
+        self.pulses_per_step
+    }
+
+    /// Set the number of clock pulses per step, e.g. 6 for sixteenth notes or 12 for eighth notes.
+    pub fn set_pulses_per_step(&mut self, pulses: u8) {
+        self.pulses_per_step = pulses.max(1);
+        self.pulse %= self.pulses_per_step;
+    }
+
+    /// Seed the random number generator used for trigger probabilities.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed.max(1);
+    }
+
+    /// Returns true if the sequencer is playing.
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    /// Returns the step that was most recently played, or None if nothing has played since the
+    /// sequencer was started.
+    pub fn playhead(&self) -> Option<u8> {
+        self.playhead
+    }
+
+    /// Advance the sequencer with an event from a clock. Any MIDI messages produced are passed to
+    /// `output`.
+    pub fn clock_event(
+        &mut self,
+        event: ClockEvent,
+        output: &mut impl FnMut(MidiMessage<'static>),
+    ) {
+        match event {
+            ClockEvent::Start => {
+                self.release_all(output);
+                self.pulse = 0;
+                self.position = 0;
+                self.playhead = None;
+                self.is_playing = true;
+            }
+            ClockEvent::Continue => self.is_playing = true,
+            ClockEvent::Stop => {
+                self.release_all(output);
+                self.is_playing = false;
+            }
+            ClockEvent::Pulse if self.is_playing => self.pulse(output),
+            ClockEvent::Pulse => (),
+        }
+    }
+
+    /// Send a note off for every note that is currently sounding.
+    pub fn release_all(&mut self, output: &mut impl FnMut(MidiMessage<'static>)) {
+        for active in self.active.iter() {
+            output(self.note_off(active.note));
+        }
+        self.active.clear();
+    }
+
+    fn pulse(&mut self, output: &mut impl FnMut(MidiMessage<'static>)) {
+        // end any gates that expire on this pulse before new notes are started
+        let mut i = 0;
+        while i < self.active.len() {
+            self.active[i].remaining -= 1;
+            if self.active[i].remaining == 0 {
+                let note = self.active.swap_remove(i).note;
+                output(self.note_off(note));
+            } else {
+                i += 1;
+            }
+        }
+
+        if self.pulse == 0 {
+            if self.position >= self.pattern.length() {
+                self.position = 0;
+            }
+            self.play_step(self.position, output);
+            self.playhead = Some(self.position);
+        }
+
+        self.pulse += 1;
+        if self.pulse >= self.pulses_per_step {
+            self.pulse = 0;
+            self.position = (self.position + 1) % self.pattern.length();
+        }
+    }
+
+    fn play_step(&mut self, step: u8, output: &mut impl FnMut(MidiMessage<'static>)) {
+        for lane in 0..LANES as u8 {
+            let trigger = match self.pattern.get(step, lane) {
+                Some(trigger) => trigger,
+                None => continue,
+            };
+
+            if !self.roll(trigger.probability) {
+                continue;
+            }
+
+            // retriggering a sounding note ends the previous one first
+            if let Some(index) = self.active.iter().position(|a| a.note == trigger.note) {
+                self.active.swap_remove(index);
+                output(self.note_off(trigger.note));
+            }
+
+            let active = ActiveNote {
+                note: trigger.note,
+                remaining: trigger.gate.max(1),
+            };
+            if self.active.push(active).is_ok() {
+                output(MidiMessage::NoteOn(
+                    self.channel,
+                    Note::from_u8_lossy(trigger.note),
+                    U7::from_u8_lossy(trigger.velocity.max(1)),
+                ));
+            }
+        }
+    }
+
+    fn note_off(&self, note: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOff(self.channel, Note::from_u8_lossy(note), U7::MIN)
+    }
+
+    /// Returns true with the given percentage chance. A xorshift generator keeps the outcome
+    /// deterministic for a given seed.
+    fn roll(&mut self, probability: u8) -> bool {
+        if probability >= 100 {
+            return true;
+        }
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed % 100) < probability as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the sequencer for a number of pulses and collect everything it sends.
+    fn run(sequencer: &mut Sequencer, pulses: usize) -> Vec<(usize, MidiMessage<'static>)> {
+        let mut messages = Vec::new();
+        for pulse in 0..pulses {
+            sequencer.clock_event(ClockEvent::Pulse, &mut |m| messages.push((pulse, m)));
+        }
+        messages
+    }
+
+    fn note_on(note: u8, velocity: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOn(
+            Channel::Ch1,
+            Note::from_u8_lossy(note),
+            U7::from_u8_lossy(velocity),
+        )
+    }
+
+    fn note_off(note: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOff(Channel::Ch1, Note::from_u8_lossy(note), U7::MIN)
+    }
+
+    #[test]
+    fn pattern_length_is_clamped() {
+        let mut pattern = Pattern::new();
+        assert_eq!(pattern.length(), 16);
+        pattern.set_length(0);
+        assert_eq!(pattern.length(), 1);
+        pattern.set_length(100);
+        assert_eq!(pattern.length(), 64);
+    }
+
+    #[test]
+    fn stopped_sequencer_is_silent() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer
+            .pattern_mut()
+            .set(0, 0, Some(Trigger::new(60, 100)));
+        assert!(run(&mut sequencer, 24).is_empty());
+    }
+
+    #[test]
+    fn plays_polyphonic_steps_with_gates() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer.pattern_mut().set_length(2);
+        sequencer
+            .pattern_mut()
+            .set(0, 0, Some(Trigger::new(60, 100)));
+        sequencer.pattern_mut().set(
+            0,
+            3,
+            Some(Trigger {
+                gate: 1,
+                ..Trigger::new(64, 90)
+            }),
+        );
+        sequencer
+            .pattern_mut()
+            .set(1, 0, Some(Trigger::new(62, 80)));
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+
+        let messages = run(&mut sequencer, 13);
+        assert_eq!(
+            messages,
+            vec![
+                (0, note_on(60, 100)),
+                (0, note_on(64, 90)),
+                (1, note_off(64)),
+                (3, note_off(60)),
+                (6, note_on(62, 80)),
+                (9, note_off(62)),
+                (12, note_on(60, 100)),
+                (12, note_on(64, 90)),
+            ]
+        );
+        assert_eq!(sequencer.playhead(), Some(0));
+    }
+
+    #[test]
+    fn stop_releases_sounding_notes() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer
+            .pattern_mut()
+            .set(0, 0, Some(Trigger::new(60, 100)));
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+        run(&mut sequencer, 1);
+
+        let mut messages = Vec::new();
+        sequencer.clock_event(ClockEvent::Stop, &mut |m| messages.push(m));
+        assert_eq!(messages, vec![note_off(60)]);
+        assert!(run(&mut sequencer, 24).is_empty());
+    }
+
+    #[test]
+    fn changing_channel_ends_notes_on_the_old_one() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer
+            .pattern_mut()
+            .set(0, 0, Some(Trigger::new(60, 100)));
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+        run(&mut sequencer, 1);
+
+        let mut messages = Vec::new();
+        sequencer.set_channel(Channel::Ch2, &mut |m| messages.push(m));
+        assert_eq!(messages, vec![note_off(60)]);
+
+        // the note isn't ended again on the new channel when its gate closes
+        assert!(run(&mut sequencer, 24).is_empty());
+    }
+
+    #[test]
+    fn retriggered_notes_are_ended_first() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer.pattern_mut().set_length(1);
+        sequencer.pattern_mut().set(
+            0,
+            0,
+            Some(Trigger {
+                gate: 12,
+                ..Trigger::new(60, 100)
+            }),
+        );
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+
+        let messages = run(&mut sequencer, 7);
+        assert_eq!(
+            messages,
+            vec![
+                (0, note_on(60, 100)),
+                (6, note_off(60)),
+                (6, note_on(60, 100))
+            ]
+        );
+    }
+
+    #[test]
+    fn probability_is_deterministic() {
+        let play = |seed| {
+            let mut sequencer = Sequencer::new(Channel::Ch1);
+            sequencer.set_seed(seed);
+            sequencer.pattern_mut().set_length(1);
+            sequencer.pattern_mut().set(
+                0,
+                0,
+                Some(Trigger {
+                    probability: 50,
+                    ..Trigger::new(60, 100)
+                }),
+            );
+            sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+            run(&mut sequencer, 6 * 100)
+                .into_iter()
+                .filter(|(_, m)| matches!(m, MidiMessage::NoteOn(..)))
+                .count()
+        };
+
+        let played = play(42);
+        assert_eq!(played, play(42));
+        assert!(played > 20 && played < 80);
+
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer.pattern_mut().set(
+            0,
+            0,
+            Some(Trigger {
+                probability: 0,
+                ..Trigger::new(60, 100)
+            }),
+        );
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+        assert!(run(&mut sequencer, 96).is_empty());
+    }
+
+    #[test]
+    fn shortening_the_pattern_wraps_the_position() {
+        let mut sequencer = Sequencer::new(Channel::Ch1);
+        sequencer
+            .pattern_mut()
+            .set(0, 0, Some(Trigger::new(60, 100)));
+        sequencer.clock_event(ClockEvent::Start, &mut |_| ());
+        run(&mut sequencer, 6 * 10);
+        assert_eq!(sequencer.playhead(), Some(9));
+
+        sequencer.pattern_mut().set_length(4);
+        let messages = run(&mut sequencer, 1);
+        assert_eq!(messages, vec![(0, note_on(60, 100))]);
+        assert_eq!(sequencer.playhead(), Some(0));
+    }
+}
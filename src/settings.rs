@@ -0,0 +1,409 @@
+use crate::hal::flash;
+
+/// The bytes that begin every block of settings.
+const MAGIC: [u8; 2] = *b"LP";
+
+/// The number of bytes added to the settings by the header and checksum.
+pub const OVERHEAD: usize = MAGIC.len() + 1 + 2 + 2;
+
+/// The largest block of settings, including the overhead, that can be saved to flash. Blocks are
+/// staged on the stack, which is small on the Launchpad Pro.
+pub const MAX_SIZE: usize = 128;
+
+/// An error saving or loading settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Accessing the flash failed.
+    Flash(flash::Error),
+    /// The settings don't fit in the buffer or in [`MAX_SIZE`].
+    Overflow,
+    /// The data ended before the settings did.
+    Truncated,
+    /// No settings have been saved, or something else was saved in their place.
+    Missing,
+    /// The data was corrupted.
+    Checksum,
+    /// The settings were saved by a version that can't be migrated.
+    Version(u8),
+    /// A value read from the data is out of range.
+    Invalid,
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        Error::Flash(error)
+    }
+}
+
+/// Writes values to a buffer in little-endian order.
+pub struct Writer<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Construct a writer that writes to the start of a buffer.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Writer {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Returns the number of bytes written.
+    pub fn len(&self) -> usize {
+        self.position
+    }
+
+    /// Returns true if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.position == 0
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.position + bytes.len();
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(Error::Overflow)?
+            .copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> Result<(), Error> {
+        self.put_bytes(&[value])
+    }
+
+    pub fn put_i8(&mut self, value: i8) -> Result<(), Error> {
+        self.put_bytes(&value.to_le_bytes())
+    }
+
+    pub fn put_bool(&mut self, value: bool) -> Result<(), Error> {
+        self.put_u8(value as u8)
+    }
+
+    pub fn put_u16(&mut self, value: u16) -> Result<(), Error> {
+        self.put_bytes(&value.to_le_bytes())
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> Result<(), Error> {
+        self.put_bytes(&value.to_le_bytes())
+    }
+}
+
+/// Reads values written by a [`Writer`].
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Construct a reader that reads from the start of some data.
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    /// Returns the number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn get_bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if length > self.data.len() {
+            return Err(Error::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn get_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.get_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.get_array::<1>()?[0])
+    }
+
+    pub fn get_i8(&mut self) -> Result<i8, Error> {
+        Ok(i8::from_le_bytes(self.get_array()?))
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, Error> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.get_array()?))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.get_array()?))
+    }
+}
+
+/// A value that can be saved to flash and loaded again after a power cycle or firmware update.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::settings::{self, Error, Reader, Settings, Writer};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Tuning {
+///     base_note: u8,
+///     pitch_bend_range: u8,
+/// }
+///
+/// impl Settings for Tuning {
+///     const VERSION: u8 = 2;
+///
+///     fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+///         writer.put_u8(self.base_note)?;
+///         writer.put_u8(self.pitch_bend_range)
+///     }
+///
+///     fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+///         Ok(Tuning {
+///             base_note: reader.get_u8()?,
+///             pitch_bend_range: reader.get_u8()?,
+///         })
+///     }
+///
+///     // version 1 only stored the base note
+///     fn migrate(version: u8, reader: &mut Reader) -> Result<Self, Error> {
+///         match version {
+///             1 => Ok(Tuning {
+///                 base_note: reader.get_u8()?,
+///                 pitch_bend_range: 48,
+///             }),
+///             _ => Err(Error::Version(version)),
+///         }
+///     }
+/// }
+///
+/// let tuning = Tuning { base_note: 60, pitch_bend_range: 2 };
+/// let mut buffer = [0; 16];
+/// let length = settings::encode(&tuning, &mut buffer).unwrap();
+/// assert_eq!(settings::decode(&buffer[..length]), Ok(tuning));
+/// ```
+pub trait Settings: Sized {
+    /// The version of the serialised layout. Increase it whenever the layout changes.
+    const VERSION: u8;
+
+    /// Write the settings.
+    fn serialize(&self, writer: &mut Writer) -> Result<(), Error>;
+
+    /// Read settings written by this version.
+    fn deserialize(reader: &mut Reader) -> Result<Self, Error>;
+
+    /// Read settings written by another version. By default they are rejected.
+    fn migrate(version: u8, reader: &mut Reader) -> Result<Self, Error> {
+        let _ = reader;
+        Err(Error::Version(version))
+    }
+}
+
+pub use crate::codec::crc16;
+
+/// Serialise settings with a header and checksum, returning the number of bytes used.
+pub fn encode<S: Settings>(settings: &S, buffer: &mut [u8]) -> Result<usize, Error> {
+    if buffer.len() < OVERHEAD {
+        return Err(Error::Overflow);
+    }
+    let (header, rest) = buffer.split_at_mut(MAGIC.len() + 3);
+    let length = {
+        let capacity = rest.len() - 2;
+        let mut writer = Writer::new(&mut rest[..capacity]);
+        settings.serialize(&mut writer)?;
+        writer.len()
+    };
+    header[..MAGIC.len()].copy_from_slice(&MAGIC);
+    header[MAGIC.len()] = S::VERSION;
+    header[MAGIC.len() + 1..].copy_from_slice(&(length as u16).to_le_bytes());
+
+    let end = MAGIC.len() + 3 + length;
+    let crc = crc16(&buffer[MAGIC.len()..end]);
+    buffer[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+    Ok(end + 2)
+}
+
+/// Deserialise settings written by [`encode`], migrating them if they came from another version.
+pub fn decode<S: Settings>(data: &[u8]) -> Result<S, Error> {
+    let mut reader = Reader::new(data);
+    if reader.get_bytes(MAGIC.len()).map_err(|_| Error::Missing)? != MAGIC {
+        return Err(Error::Missing);
+    }
+    let version = reader.get_u8()?;
+    let length = reader.get_u16()? as usize;
+    let payload = reader.get_bytes(length)?;
+    if reader.get_u16()? != crc16(&data[MAGIC.len()..MAGIC.len() + 3 + length]) {
+        return Err(Error::Checksum);
+    }
+
+    let mut reader = Reader::new(payload);
+    if version == S::VERSION {
+        S::deserialize(&mut reader)
+    } else {
+        S::migrate(version, &mut reader)
+    }
+}
+
+/// Save settings to the user area of flash, `offset` bytes from its beginning.
+pub fn save<S: Settings>(settings: &S, offset: usize) -> Result<(), Error> {
+    let mut buffer = [0; MAX_SIZE];
+    let length = encode(settings, &mut buffer)?;
+    Ok(flash::write(offset, &buffer[..length])?)
+}
+
+/// Load settings from the user area of flash, `offset` bytes from its beginning.
+pub fn load<S: Settings>(offset: usize) -> Result<S, Error> {
+    let mut header = [0; MAGIC.len() + 3];
+    flash::read(offset, &mut header)?;
+    let length = u16::from_le_bytes([header[MAGIC.len() + 1], header[MAGIC.len() + 2]]) as usize;
+
+    if header[..MAGIC.len()] != MAGIC {
+        return Err(Error::Missing);
+    }
+
+    let mut buffer = [0; MAX_SIZE];
+    let data = buffer
+        .get_mut(..OVERHEAD + length)
+        .ok_or(Error::Truncated)?;
+    flash::read(offset, data)?;
+    decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Version2 {
+        note: u8,
+        range: u16,
+        enabled: bool,
+    }
+
+    impl Settings for Version2 {
+        const VERSION: u8 = 2;
+
+        fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+            writer.put_u8(self.note)?;
+            writer.put_u16(self.range)?;
+            writer.put_bool(self.enabled)
+        }
+
+        fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+            Ok(Version2 {
+                note: reader.get_u8()?,
+                range: reader.get_u16()?,
+                enabled: reader.get_bool()?,
+            })
+        }
+
+        fn migrate(version: u8, reader: &mut Reader) -> Result<Self, Error> {
+            match version {
+                1 => Ok(Version2 {
+                    note: reader.get_u8()?,
+                    range: 48,
+                    enabled: true,
+                }),
+                _ => Err(Error::Version(version)),
+            }
+        }
+    }
+
+    struct Version1(u8);
+
+    impl Settings for Version1 {
+        const VERSION: u8 = 1;
+
+        fn serialize(&self, writer: &mut Writer) -> Result<(), Error> {
+            writer.put_u8(self.0)
+        }
+
+        fn deserialize(reader: &mut Reader) -> Result<Self, Error> {
+            Ok(Version1(reader.get_u8()?))
+        }
+    }
+
+    const SETTINGS: Version2 = Version2 {
+        note: 60,
+        range: 0x1234,
+        enabled: false,
+    };
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = [0; 32];
+        let length = encode(&SETTINGS, &mut buffer).unwrap();
+        assert_eq!(length, OVERHEAD + 4);
+        assert_eq!(buffer[..7], [b'L', b'P', 2, 4, 0, 60, 0x34]);
+        assert_eq!(decode(&buffer[..length]), Ok(SETTINGS));
+
+        assert_eq!(encode(&SETTINGS, &mut buffer[..10]), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut buffer = [0; 32];
+        let length = encode(&SETTINGS, &mut buffer).unwrap();
+
+        assert_eq!(
+            decode::<Version2>(&buffer[..length - 1]),
+            Err(Error::Truncated)
+        );
+        assert_eq!(
+            decode::<Version2>(&[flash::ERASED; 16]),
+            Err(Error::Missing)
+        );
+
+        buffer[6] ^= 1;
+        assert_eq!(decode::<Version2>(&buffer[..length]), Err(Error::Checksum));
+    }
+
+    #[test]
+    fn migration() {
+        let mut buffer = [0; 32];
+        let length = encode(&Version1(64), &mut buffer).unwrap();
+        assert_eq!(
+            decode(&buffer[..length]),
+            Ok(Version2 {
+                note: 64,
+                range: 48,
+                enabled: true
+            })
+        );
+
+        // newer settings can't be read by an older version
+        let length = encode(&SETTINGS, &mut buffer).unwrap();
+        assert!(matches!(
+            decode::<Version1>(&buffer[..length]),
+            Err(Error::Version(2))
+        ));
+    }
+
+    #[test]
+    fn flash() {
+        flash::with_temp_file(|_| {
+            assert_eq!(load::<Version2>(100), Err(Error::Missing));
+            save(&SETTINGS, 100).unwrap();
+            assert_eq!(load(100), Ok(SETTINGS));
+            assert_eq!(
+                save(&SETTINGS, 1020),
+                Err(Error::Flash(flash::Error::OutOfBounds))
+            );
+        });
+    }
+}
@@ -0,0 +1,540 @@
+use crate::hal::midi::{self, CableEvent, MidiMessage, Port};
+use crate::hal::surface::{
+    clear_leds, read_led, set_led, take_written, AftertouchEvent, Button, ButtonEvent, Event, Pads,
+};
+use crate::hal::{Grid, LaunchpadApp, Mutex, Point, Rgb};
+
+/// The colours of the apps in the menu, repeated if there are more apps than colours.
+const MENU_COLOURS: [Rgb; 4] = [
+    Rgb::new(255, 0, 0),
+    Rgb::new(0, 255, 0),
+    Rgb::new(0, 0, 255),
+    Rgb::new(255, 127, 0),
+];
+
+/// The colour of the running app in the menu.
+const CURRENT_COLOUR: Rgb = Rgb::new(255, 255, 255);
+
+struct State<const N: usize> {
+    /// The app that receives events.
+    current: usize,
+    /// Which apps have had their init event.
+    initialised: [bool; N],
+    /// The pads passed to the switcher on startup, handed to each app when it is first shown.
+    pads: Option<Pads>,
+    /// The LEDs under the menu, restored when it closes, or `None` while the menu isn't shown.
+    leds: Option<[Rgb; Grid::size() as usize]>,
+    /// Whether the Setup button is held, showing the menu.
+    is_menu_open: bool,
+    /// Whether an app was chosen while the menu was open.
+    chosen: bool,
+    /// The press that opened the menu, passed on to the running app if no app is chosen.
+    setup_press: Event,
+}
+
+/// What the switcher should do with a button event, decided while its state is locked.
+enum Action {
+    Forward,
+    Ignore,
+    OpenMenu,
+    /// Close the menu, passing on the Setup press if no app was chosen.
+    CloseMenu {
+        setup_press: Option<Event>,
+    },
+    Switch(usize),
+}
+
+/// Holds several apps in one firmware image and switches between them from a menu.
+///
+/// Holding the Setup button shows a menu with a pad for each app, starting at the top left; pressing
+/// one switches to that app. Releasing Setup without choosing an app passes the Setup press and
+/// release on to the running app, so apps can still use it.
+///
+/// On a switch the running app gets a suspend event, any notes it left held are released and the
+/// LEDs are cleared. The chosen app then gets an init event the first time it is shown, or a resume
+/// event after that, in which it should redraw.
+///
+/// The running app keeps getting events while the menu is open, so its clocks keep time. Whatever
+/// it draws under the menu is drawn over again after each event, and shown when the menu closes.
+///
+/// Only the running app gets timer events, so the timers of a suspended app, e.g. those in a
+/// [`Scheduler`](crate::hal::timer::Scheduler) it advances from its timer event, wait until it is
+/// shown again.
+///
+/// An app that handles events through `&mut self` is put in a static
+/// [`Registration`](crate::hal::Registration), which is a [`LaunchpadApp`] whose handlers must not
+/// preempt each other.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::LaunchpadApp;
+/// use launchpad_pro_rs::launchpad_app;
+/// use launchpad_pro_rs::switcher::Switcher;
+///
+/// struct Drums;
+/// impl LaunchpadApp for Drums {}
+///
+/// struct Keys;
+/// impl LaunchpadApp for Keys {}
+///
+/// static SWITCHER: Switcher<2> = Switcher::new([&Drums, &Keys]);
+///
+/// launchpad_app!(SWITCHER);
+/// ```
+pub struct Switcher<const N: usize> {
+    apps: [&'static dyn LaunchpadApp; N],
+    state: Mutex<State<N>>,
+}
+
+impl<const N: usize> Switcher<N> {
+    /// Construct a switcher that starts with the first app.
+    pub const fn new(apps: [&'static dyn LaunchpadApp; N]) -> Self {
+        Switcher {
+            apps,
+            state: Mutex::new(State {
+                current: 0,
+                initialised: [false; N],
+                pads: None,
+                leds: None,
+                is_menu_open: false,
+                chosen: false,
+                setup_press: Event::Release,
+            }),
+        }
+    }
+
+    /// Returns the index of the running app.
+    pub fn current(&self) -> usize {
+        self.state.lock().current
+    }
+
+    fn app(&self) -> &'static dyn LaunchpadApp {
+        self.apps[self.current()]
+    }
+
+    /// Returns the pad that chooses an app in the menu.
+    fn menu_point(index: usize) -> Point {
+        Point::new(index as i8 % 8 + 1, 8 - index as i8 / 8)
+    }
+
+    /// Returns the app chosen by a pad in the menu.
+    fn menu_index(point: Point) -> Option<usize> {
+        (0..N).find(|&index| Self::menu_point(index) == point)
+    }
+
+    /// Switch to another app. Nothing happens if it is already running.
+    pub fn switch(&self, index: usize) {
+        let (previous, pads) = {
+            let mut state = self.state.lock();
+            if index >= N || index == state.current {
+                return;
+            }
+            let previous = state.current;
+            state.current = index;
+            (previous, state.pads)
+        };
+
+        self.apps[previous].suspend_event();
+        midi::release_held_notes();
+        clear_leds();
+
+        let initialised = core::mem::replace(&mut self.state.lock().initialised[index], true);
+        match (initialised, pads) {
+            (true, _) => self.apps[index].resume_event(),
+            (false, Some(pads)) => self.apps[index].init_event(pads),
+            (false, None) => self.state.lock().initialised[index] = false,
+        }
+    }
+
+    /// Returns the colour of a point in the menu.
+    fn menu_colour(point: Point, current: usize) -> Rgb {
+        match Self::menu_index(point) {
+            Some(index) if index == current => CURRENT_COLOUR,
+            Some(index) => MENU_COLOURS[index % MENU_COLOURS.len()],
+            None => Rgb::new(0, 0, 0),
+        }
+    }
+
+    fn open_menu(&self) {
+        let mut state = self.state.lock();
+        let mut leds = [Rgb::new(0, 0, 0); Grid::size() as usize];
+        for point in Grid::points() {
+            leds[point.to_index() as usize] = read_led(point).unwrap_or(Rgb::new(0, 0, 0));
+            set_led(point, Self::menu_colour(point, state.current));
+        }
+        state.leds = Some(leds);
+        take_written();
+    }
+
+    /// Draw the menu over any LEDs the running app set while it is shown, saving them so that
+    /// closing the menu shows what the app drew.
+    fn redraw_menu(&self) {
+        let written = take_written();
+        let mut state = self.state.lock();
+        let current = state.current;
+        let leds = match &mut state.leds {
+            Some(leds) => leds,
+            None => return,
+        };
+        for point in Grid::points() {
+            let index = point.to_index() as usize;
+            if written & 1 << index == 0 {
+                continue;
+            }
+            if let Some(drawn) = read_led(point) {
+                leds[index] = drawn;
+            }
+            set_led(point, Self::menu_colour(point, current));
+        }
+        take_written();
+    }
+
+    /// Pass an event on to the running app, keeping the menu on top if it is shown.
+    fn forward(&self, event: impl FnOnce(&dyn LaunchpadApp)) {
+        event(self.app());
+        self.redraw_menu();
+    }
+
+    fn close_menu(&self) {
+        let leds = self.state.lock().leds.take();
+        if let Some(leds) = leds {
+            for point in Grid::points() {
+                set_led(point, leds[point.to_index() as usize]);
+            }
+        }
+    }
+
+    /// Decide what to do with a button event.
+    fn action(&self, button_event: &ButtonEvent) -> Action {
+        let mut state = self.state.lock();
+        let is_menu_open = state.is_menu_open;
+        match (&button_event.button, &button_event.event) {
+            (Button::Setup, press @ Event::Press(_)) => {
+                state.is_menu_open = true;
+                state.chosen = false;
+                state.setup_press = *press;
+                Action::OpenMenu
+            }
+            (Button::Setup, Event::Release) => {
+                state.is_menu_open = false;
+                let chosen = core::mem::take(&mut state.chosen);
+                Action::CloseMenu {
+                    setup_press: (!chosen).then_some(state.setup_press),
+                }
+            }
+            (Button::Pad(point), Event::Press(_)) if is_menu_open => {
+                match Self::menu_index(*point) {
+                    Some(index) if !state.chosen => {
+                        state.chosen = true;
+                        Action::Switch(index)
+                    }
+                    _ => Action::Ignore,
+                }
+            }
+            // releases still reach the app, so that pads held before the menu opened are let go
+            (Button::Pad(_), Event::Release) if is_menu_open && state.chosen => Action::Ignore,
+            _ => Action::Forward,
+        }
+    }
+}
+
+impl<const N: usize> LaunchpadApp for Switcher<N> {
+    fn init_event(&self, pads: Pads) {
+        {
+            let mut state = self.state.lock();
+            state.pads = Some(pads);
+            let current = state.current;
+            state.initialised[current] = true;
+        }
+        self.app().init_event(pads);
+    }
+
+    fn timer_event(&self) {
+        self.forward(|app| app.timer_event());
+    }
+
+    fn midi_event(&self, port: Port, midi_event: MidiMessage) {
+        self.forward(|app| app.midi_event(port, midi_event));
+    }
+
+    fn sysex_event(&self, port: Port, data: &[u8]) {
+        self.forward(|app| app.sysex_event(port, data));
+    }
+
+    fn cable_event(&self, cable_event: CableEvent) {
+        self.forward(|app| app.cable_event(cable_event));
+    }
+
+    fn button_event(&self, button_event: ButtonEvent) {
+        match self.action(&button_event) {
+            Action::Forward => self.forward(|app| app.button_event(button_event)),
+            Action::Ignore => (),
+            Action::OpenMenu => self.open_menu(),
+            Action::CloseMenu { setup_press: None } => (),
+            Action::CloseMenu {
+                setup_press: Some(event),
+            } => {
+                self.close_menu();
+                let app = self.app();
+                app.button_event(ButtonEvent {
+                    button: Button::Setup,
+                    event,
+                });
+                app.button_event(button_event);
+            }
+            Action::Switch(index) if index == self.current() => self.close_menu(),
+            Action::Switch(index) => {
+                self.state.lock().leds = None;
+                self.switch(index);
+            }
+        }
+    }
+
+    fn aftertouch_event(&self, aftertouch_event: AftertouchEvent) {
+        if !self.state.lock().is_menu_open {
+            self.app().aftertouch_event(aftertouch_event);
+        }
+    }
+
+    fn suspend_event(&self) {
+        self.app().suspend_event();
+    }
+
+    fn resume_event(&self) {
+        self.app().resume_event();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{send, Counter};
+
+    #[test]
+    fn switching_apps() {
+        static FIRST: Counter = Counter::new();
+        static SECOND: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&FIRST, &SECOND]);
+
+        let adc = [0u16; 64];
+        SWITCHER.init_event(Pads::new(adc.as_ptr()));
+        assert_eq!(FIRST.counts(), [1, 0, 0, 0, 0, 0, 0, 0]);
+
+        // a pad press goes to the running app
+        send(&SWITCHER, Button::Pad(Point::new(1, 8)), Event::Press(100));
+        send(&SWITCHER, Button::Pad(Point::new(1, 8)), Event::Release);
+        assert_eq!(FIRST.counts(), [1, 1, 1, 0, 0, 0, 0, 0]);
+
+        // tapping setup passes a press and a release on to the running app
+        send(&SWITCHER, Button::Setup, Event::Press(100));
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(FIRST.counts(), [1, 2, 2, 2, 0, 0, 0, 0]);
+
+        // choose the second app from the menu
+        send(&SWITCHER, Button::Setup, Event::Press(100));
+        send(&SWITCHER, Button::Pad(Point::new(2, 8)), Event::Press(100));
+        send(&SWITCHER, Button::Pad(Point::new(2, 8)), Event::Release);
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(SWITCHER.current(), 1);
+        assert_eq!(FIRST.counts(), [1, 2, 2, 2, 0, 0, 1, 0]);
+        assert_eq!(SECOND.counts(), [1, 0, 0, 0, 0, 0, 0, 0]);
+
+        // and back again
+        send(&SWITCHER, Button::Setup, Event::Press(100));
+        send(&SWITCHER, Button::Pad(Point::new(1, 8)), Event::Press(100));
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(SWITCHER.current(), 0);
+        assert_eq!(FIRST.counts(), [1, 2, 2, 2, 0, 0, 1, 1]);
+        assert_eq!(SECOND.counts(), [1, 0, 0, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn switching_releases_held_notes() {
+        static FIRST: Counter = Counter::new();
+        static SECOND: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&FIRST, &SECOND]);
+
+        let note = MidiMessage::NoteOn(wmidi::Channel::Ch3, wmidi::Note::A0, wmidi::U7::MAX);
+        midi::send_message(Port::DIN, &note);
+        SWITCHER.switch(1);
+        assert!(!midi::is_note_held(
+            Port::DIN,
+            wmidi::Channel::Ch3,
+            wmidi::Note::A0
+        ));
+
+        // the app wasn't initialised because the switcher never was
+        assert_eq!(SECOND.counts(), [0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn setup_taps_keep_their_velocity() {
+        /// Remembers the Setup events it receives.
+        struct Setup(Mutex<Vec<Event>>);
+
+        impl LaunchpadApp for Setup {
+            fn button_event(&self, button_event: ButtonEvent) {
+                if button_event.button == Button::Setup {
+                    self.0.lock().push(button_event.event);
+                }
+            }
+        }
+
+        static APP: Setup = Setup(Mutex::new(Vec::new()));
+        static SWITCHER: Switcher<1> = Switcher::new([&APP]);
+
+        send(&SWITCHER, Button::Setup, Event::Press(42));
+        assert!(APP.0.lock().is_empty());
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(*APP.0.lock(), [Event::Press(42), Event::Release]);
+    }
+
+    #[test]
+    fn apps_can_own_their_state() {
+        use crate::hal::{LaunchpadAppMut, Registered, Registration};
+
+        /// Counts its timer events while it is shown.
+        struct Ticks {
+            count: u32,
+            is_shown: bool,
+        }
+
+        impl LaunchpadAppMut for Ticks {
+            fn init_event(&mut self, _pads: Pads) {
+                self.is_shown = true;
+            }
+
+            fn timer_event(&mut self) {
+                self.count += 1;
+            }
+
+            fn suspend_event(&mut self) {
+                self.is_shown = false;
+            }
+
+            fn resume_event(&mut self) {
+                self.is_shown = true;
+            }
+        }
+
+        static TICKS: Registration<Ticks> = Registration::new(Ticks {
+            count: 0,
+            is_shown: false,
+        });
+        static OTHER: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&TICKS, &OTHER]);
+
+        let adc = [0u16; 64];
+        SWITCHER.init_event(Pads::new(adc.as_ptr()));
+        SWITCHER.timer_event();
+        SWITCHER.switch(1);
+        SWITCHER.timer_event();
+        SWITCHER.switch(0);
+
+        let mut state = None;
+        TICKS.with(|ticks| state = Some((ticks.count, ticks.is_shown)));
+        assert_eq!(state, Some((1, true)));
+        assert_eq!(OTHER.counts(), [1, 0, 0, 0, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn menu_stays_on_top_of_animations() {
+        /// Lights a pad in a new colour on every timer event.
+        struct Animation(Mutex<u8>);
+
+        impl LaunchpadApp for Animation {
+            fn timer_event(&self) {
+                let mut frame = self.0.lock();
+                *frame = frame.wrapping_add(1);
+                set_led(Point::new(5, 5), Rgb::new(*frame * 16, 0, 0));
+            }
+        }
+
+        static APP: Animation = Animation(Mutex::new(0));
+        static OTHER: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&APP, &OTHER]);
+
+        let adc = [0u16; 64];
+        SWITCHER.init_event(Pads::new(adc.as_ptr()));
+        SWITCHER.timer_event();
+
+        send(&SWITCHER, Button::Setup, Event::Press(100));
+        let menu = read_led(Switcher::<2>::menu_point(1));
+        for _ in 0..3 {
+            SWITCHER.timer_event();
+            assert_eq!(read_led(Point::new(5, 5)), Some(Rgb::new(0, 0, 0)));
+            assert_eq!(read_led(Switcher::<2>::menu_point(1)), menu);
+        }
+
+        // the app keeps time under the menu, and its latest frame is shown when it closes
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(read_led(Point::new(5, 5)), Some(Rgb::new(64, 0, 0)));
+    }
+
+    #[test]
+    fn menu_keeps_leds_drawn_in_its_colours() {
+        /// Lights the second app's menu pad in its menu colour on every timer event.
+        struct Mimic;
+
+        impl LaunchpadApp for Mimic {
+            fn timer_event(&self) {
+                set_led(Switcher::<2>::menu_point(1), MENU_COLOURS[1]);
+            }
+        }
+
+        static OTHER: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&Mimic, &OTHER]);
+
+        send(&SWITCHER, Button::Setup, Event::Press(100));
+        SWITCHER.timer_event();
+        send(&SWITCHER, Button::Setup, Event::Release);
+        assert_eq!(
+            read_led(Switcher::<2>::menu_point(1)),
+            Some(MENU_COLOURS[1])
+        );
+    }
+
+    #[test]
+    fn timers_wait_while_their_app_is_hidden() {
+        use crate::hal::timer::Scheduler;
+        use crate::hal::{LaunchpadAppMut, Registered, Registration};
+
+        /// Counts the firings of a periodic timer.
+        struct Ticker {
+            timers: Scheduler<()>,
+            fired: u32,
+        }
+
+        impl LaunchpadAppMut for Ticker {
+            fn init_event(&mut self, _pads: Pads) {
+                self.timers.every(2, ()).unwrap();
+            }
+
+            fn timer_event(&mut self) {
+                self.fired += self.timers.advance(1).len() as u32;
+            }
+        }
+
+        static TICKER: Registration<Ticker> = Registration::new(Ticker {
+            timers: Scheduler::new(),
+            fired: 0,
+        });
+        static OTHER: Counter = Counter::new();
+        static SWITCHER: Switcher<2> = Switcher::new([&TICKER, &OTHER]);
+
+        let adc = [0u16; 64];
+        SWITCHER.init_event(Pads::new(adc.as_ptr()));
+        SWITCHER.timer_event();
+        SWITCHER.switch(1);
+        for _ in 0..10 {
+            SWITCHER.timer_event();
+        }
+        SWITCHER.switch(0);
+        SWITCHER.timer_event();
+
+        let mut fired = None;
+        TICKER.with(|ticker| fired = Some(ticker.fired));
+        assert_eq!(fired, Some(1));
+    }
+}
@@ -0,0 +1,85 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::hal::surface::{AftertouchEvent, Button, ButtonEvent, Event, Pads};
+use crate::hal::LaunchpadApp;
+
+/// Counts the events an app receives.
+pub(crate) struct Counter {
+    inits: AtomicU32,
+    presses: AtomicU32,
+    releases: AtomicU32,
+    setups: AtomicU32,
+    aftertouches: AtomicU32,
+    timers: AtomicU32,
+    suspends: AtomicU32,
+    resumes: AtomicU32,
+}
+
+impl Counter {
+    pub(crate) const fn new() -> Self {
+        Counter {
+            inits: AtomicU32::new(0),
+            presses: AtomicU32::new(0),
+            releases: AtomicU32::new(0),
+            setups: AtomicU32::new(0),
+            aftertouches: AtomicU32::new(0),
+            timers: AtomicU32::new(0),
+            suspends: AtomicU32::new(0),
+            resumes: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the number of init events, button presses, button releases, Setup presses and
+    /// releases, aftertouch events, timer events, suspend events and resume events, in that order.
+    pub(crate) fn counts(&self) -> [u32; 8] {
+        [
+            &self.inits,
+            &self.presses,
+            &self.releases,
+            &self.setups,
+            &self.aftertouches,
+            &self.timers,
+            &self.suspends,
+            &self.resumes,
+        ]
+        .map(|count| count.load(Ordering::SeqCst))
+    }
+}
+
+impl LaunchpadApp for Counter {
+    fn init_event(&self, _pads: Pads) {
+        self.inits.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn timer_event(&self) {
+        self.timers.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn button_event(&self, button_event: ButtonEvent) {
+        match button_event.event {
+            Event::Press(_) => &self.presses,
+            Event::Release => &self.releases,
+        }
+        .fetch_add(1, Ordering::SeqCst);
+        if button_event.button == Button::Setup {
+            self.setups.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn aftertouch_event(&self, _aftertouch_event: AftertouchEvent) {
+        self.aftertouches.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn suspend_event(&self) {
+        self.suspends.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn resume_event(&self) {
+        self.resumes.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Send a button event to an app.
+pub(crate) fn send(app: &dyn LaunchpadApp, button: Button, event: Event) {
+    app.button_event(ButtonEvent { button, event });
+}
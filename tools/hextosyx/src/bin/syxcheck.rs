@@ -0,0 +1,134 @@
+use std::process::ExitCode;
+
+use hextosyx::inspect::{diff, Firmware, Layout};
+
+const USAGE: &str = "\
+usage: syxcheck [options] <file.syx>
+       syxcheck [options] --diff <a.syx> <b.syx>
+
+Check a Launchpad Pro firmware file before uploading it, or compare two firmware files.
+
+options:
+    --ld <script>             read the memory layout from a linker script (default a built-in
+                              copy of the layout in stm32_flash.ld)
+    --base-address <address>  where the image is written (default the start of flash)";
+
+/// Parse an address in decimal, or in hex with a `0x` prefix.
+fn parse_address(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn read(path: &str) -> Result<Firmware, String> {
+    let syx = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    Ok(Firmware::parse(&syx))
+}
+
+/// Describe a firmware file and list its problems. Returns whether it has none.
+fn check(path: &str, layout: &Layout, base_address: Option<u32>) -> Result<bool, String> {
+    let firmware = read(path)?;
+    let base = base_address.unwrap_or(layout.flash.origin) as u64;
+
+    if let Some(id) = firmware.id {
+        println!("id: {:#06x}", id);
+    }
+    if let Some([major, minor, patch]) = firmware.version {
+        println!("version: {:02x}.{:02x}.{:02x}", major, minor, patch);
+    }
+    println!("byte width: {}", firmware.byte_width);
+    println!(
+        "image: {:#010x}..{:#010x} ({} bytes, {}% of flash)",
+        base,
+        base + firmware.data.len() as u64,
+        firmware.data.len(),
+        firmware.data.len() as u64 * 100 / layout.flash.length.max(1) as u64
+    );
+    for range in firmware.ranges() {
+        println!(
+            "  data: {:#010x}..{:#010x}",
+            base + range.start,
+            base + range.end
+        );
+    }
+
+    let problems = firmware.check(layout, base_address);
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    Ok(problems.is_empty())
+}
+
+/// List the ranges where two firmware files differ. Returns whether they are the same.
+fn compare(a: &str, b: &str, layout: &Layout, base_address: Option<u32>) -> Result<bool, String> {
+    let (a, b) = (read(a)?, read(b)?);
+    let base = base_address.unwrap_or(layout.flash.origin) as u64;
+
+    if a.id != b.id {
+        println!("id: {:04x?} -> {:04x?}", a.id, b.id);
+    }
+    if a.version != b.version {
+        println!("version: {:02x?} -> {:02x?}", a.version, b.version);
+    }
+    if a.data.len() != b.data.len() {
+        println!("size: {} -> {} bytes", a.data.len(), b.data.len());
+    }
+    let ranges = diff(&a, &b);
+    for range in &ranges {
+        println!(
+            "differ: {:#010x}..{:#010x} ({} bytes)",
+            base + range.start,
+            base + range.end,
+            range.end - range.start
+        );
+    }
+    Ok(a.id == b.id && a.version == b.version && ranges.is_empty())
+}
+
+fn run(args: &[String]) -> Result<bool, String> {
+    let mut layout = Layout::default();
+    let mut base_address = None;
+    let mut diff_with = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--ld" => {
+                let path = value()?;
+                let script = std::fs::read_to_string(path)
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                layout = Layout::parse(&script).map_err(|error| format!("{}: {}", path, error))?;
+            }
+            "--base-address" => {
+                let value = value()?;
+                base_address = Some(
+                    parse_address(value).ok_or_else(|| format!("invalid address: {}", value))?,
+                );
+            }
+            "--diff" => diff_with = Some(value()?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match (diff_with, paths.as_slice()) {
+        (None, [path]) => check(path, &layout, base_address),
+        (Some(a), [b]) => compare(&a, b, &layout, base_address),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::from(2)
+        }
+    }
+}
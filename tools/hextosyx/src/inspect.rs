@@ -0,0 +1,611 @@
+//! Read `.syx` firmware files back into memory images, and check them against the linker layout.
+//!
+//! The bootloader messages don't carry addresses: the blocks are written in order from the second
+//! block of the image, and the last block sent holds the start of the image. The image is placed at
+//! the start of flash unless another base address is given.
+//!
+//! # Example
+//!
+//! ```
+//! use hextosyx::inspect::{Firmware, Layout};
+//! use hextosyx::{Image, Options};
+//!
+//! let mut image = Image::new();
+//! image.write(0x0800_6400, &[0x00, 0x50, 0x00, 0x20, 0x09, 0x64, 0x00, 0x08]);
+//! let sysex = image.to_sysex(&Options::default()).unwrap();
+//!
+//! let firmware = Firmware::parse(&sysex);
+//! assert_eq!(&firmware.data[..4], &[0x00, 0x50, 0x00, 0x20]);
+//! assert!(firmware.check(&Layout::default(), None).is_empty());
+//! ```
+
+use std::fmt;
+use std::ops::Range;
+
+use launchpad_pro_rs::codec::Scheme;
+
+use crate::{BLOCK, CHECKSUM, LAST_BLOCK, RESET};
+
+/// The message type of the header.
+const HEADER: u8 = RESET[5];
+
+/// A region of memory from the linker script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The first address of the region.
+    pub origin: u32,
+    /// The size of the region in bytes.
+    pub length: u32,
+}
+
+impl Region {
+    /// Returns the address after the end of the region.
+    pub fn end(&self) -> u64 {
+        self.origin as u64 + self.length as u64
+    }
+
+    /// Returns whether the region contains an address.
+    pub fn contains(&self, address: u64) -> bool {
+        (self.origin as u64..self.end()).contains(&address)
+    }
+}
+
+/// The memory layout the firmware is linked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// The flash available to the app, after the bootloader.
+    pub flash: Region,
+    /// The RAM, which holds the stack.
+    pub ram: Region,
+}
+
+impl Default for Layout {
+    /// The layout in `stm32_flash.ld`.
+    fn default() -> Self {
+        Layout {
+            flash: Region {
+                origin: 0x0800_6400,
+                length: 128 * 1024,
+            },
+            ram: Region {
+                origin: 0x2000_0000,
+                length: 20 * 1024,
+            },
+        }
+    }
+}
+
+/// Parse a number in a linker script, e.g. `0x08006400`, `128K` or `1M`.
+fn parse_ld_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let (digits, multiplier) = match text.as_bytes().last()? {
+        b'K' | b'k' => (&text[..text.len() - 1], 1024),
+        b'M' | b'm' => (&text[..text.len() - 1], 1024 * 1024),
+        _ => (text, 1),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    value.checked_mul(multiplier)
+}
+
+impl Layout {
+    /// Read the `FLASH` and `RAM` regions from the `MEMORY` command of a linker script.
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let memory = script
+            .find("MEMORY")
+            .map(|start| &script[start..])
+            .and_then(|memory| Some(&memory[memory.find('{')? + 1..memory.find('}')?]))
+            .ok_or("no MEMORY command")?;
+
+        let region = |name: &str| -> Result<Region, String> {
+            let line = memory
+                .lines()
+                .find(|line| line.split_whitespace().next() == Some(name))
+                .ok_or(format!("no {} region", name))?;
+            let field = |key: &str| {
+                let start = line.find(key)? + key.len();
+                let value = line[start..].trim_start().strip_prefix('=')?;
+                parse_ld_number(value.split(',').next()?)
+            };
+            Ok(Region {
+                origin: field("ORIGIN").ok_or(format!("bad ORIGIN for {}", name))?,
+                length: field("LENGTH").ok_or(format!("bad LENGTH for {}", name))?,
+            })
+        };
+
+        Ok(Layout {
+            flash: region("FLASH")?,
+            ram: region("RAM")?,
+        })
+    }
+}
+
+/// Something wrong with a firmware file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// A message is malformed or out of place, or writes a block that was already written.
+    Corrupt {
+        /// The offset of the message in the file.
+        offset: usize,
+        /// What is wrong with it.
+        reason: &'static str,
+    },
+    /// Bytes outside any message, with the range of their offsets in the file.
+    Stray(Range<usize>),
+    /// A message the bootloader needs is missing.
+    Missing(&'static str),
+    /// Blocks have different sizes.
+    ByteWidth {
+        /// The offset of the block in the file.
+        offset: usize,
+        /// The size of its data.
+        width: usize,
+        /// The size of the first block's data.
+        expected: usize,
+    },
+    /// The image overwrites the bootloader.
+    Overlap(Range<u64>),
+    /// A range of unset memory at least a block long, between parts of the image.
+    Gap(Range<u64>),
+    /// The image doesn't fit in flash.
+    Oversized {
+        /// The end of the image.
+        end: u64,
+        /// The end of flash.
+        limit: u64,
+    },
+    /// The version number in the header doesn't match the one in the image.
+    Version {
+        /// The version in the header.
+        header: [u8; 3],
+        /// The version at offset 0x100 of the image.
+        image: [u8; 3],
+    },
+    /// The initial stack pointer in the vector table is outside RAM.
+    StackPointer(u32),
+    /// The reset handler in the vector table is outside the image, or isn't Thumb code.
+    ResetHandler(u32),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Corrupt { offset, reason } => {
+                write!(f, "corrupt message at offset {}: {}", offset, reason)
+            }
+            Problem::Stray(range) => write!(
+                f,
+                "data outside a message at offsets {}..{}",
+                range.start, range.end
+            ),
+            Problem::Missing(message) => write!(f, "missing {}", message),
+            Problem::ByteWidth {
+                offset,
+                width,
+                expected,
+            } => write!(
+                f,
+                "block at offset {} holds {} bytes, not {}",
+                offset, width, expected
+            ),
+            Problem::Overlap(range) => {
+                write!(f, "overlap at {:#010x}..{:#010x}", range.start, range.end)
+            }
+            Problem::Gap(range) => write!(f, "gap at {:#010x}..{:#010x}", range.start, range.end),
+            Problem::Oversized { end, limit } => write!(
+                f,
+                "image ends at {:#010x}, after the end of flash at {:#010x}",
+                end, limit
+            ),
+            Problem::Version { header, image } => write!(
+                f,
+                "header version {:02x?} doesn't match image version {:02x?}",
+                header, image
+            ),
+            Problem::StackPointer(address) => {
+                write!(f, "initial stack pointer {:#010x} is outside RAM", address)
+            }
+            Problem::ResetHandler(address) => write!(
+                f,
+                "reset handler {:#010x} is outside the image or isn't Thumb code",
+                address
+            ),
+        }
+    }
+}
+
+/// Returns the smallest block size whose packed size is `length`.
+fn width_of(length: usize) -> Option<usize> {
+    (1..=length).find(|&width| 1 + width * 8 / 7 == length)
+}
+
+/// Undo the packing of a block of `width` bytes.
+fn unpack(payload: &[u8], width: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width + 7);
+    for group in payload.chunks(8) {
+        let mut input = [0; 8];
+        input[..group.len()].copy_from_slice(group);
+        data.extend_from_slice(&Scheme::Bootloader.unpack(&input));
+    }
+    data.truncate(width);
+    data
+}
+
+/// A firmware image read from a `.syx` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Firmware {
+    /// The bootloader ID from the header.
+    pub id: Option<u16>,
+    /// The version number from the header, most significant byte first.
+    pub version: Option<[u8; 3]>,
+    /// The number of bytes in each block.
+    pub byte_width: usize,
+    /// The image, from its base address.
+    pub data: Vec<u8>,
+    /// Problems found reading the file.
+    pub problems: Vec<Problem>,
+}
+
+impl Firmware {
+    /// Read a `.syx` file, collecting any problems found.
+    pub fn parse(syx: &[u8]) -> Self {
+        let mut firmware = Firmware::default();
+        let mut first_block = None;
+        let mut blocks = Vec::new();
+        let mut checksum = false;
+
+        for (offset, message) in messages(syx, &mut firmware.problems) {
+            let corrupt = |reason| Problem::Corrupt { offset, reason };
+            if message.len() < 7 || message[..5] != RESET[..5] {
+                firmware.problems.push(corrupt("not a bootloader message"));
+                continue;
+            }
+            if checksum {
+                firmware.problems.push(corrupt("after the checksum"));
+            }
+
+            let body = &message[6..message.len() - 1];
+            match message[5] {
+                HEADER => {
+                    if firmware.id.is_some() {
+                        firmware.problems.push(corrupt("a second header"));
+                    } else if !blocks.is_empty() || first_block.is_some() {
+                        firmware.problems.push(corrupt("header after the blocks"));
+                    }
+                    if body.len() != 8 || body[2..].iter().any(|&nibble| nibble > 0x0f) {
+                        firmware.problems.push(corrupt("bad header"));
+                        continue;
+                    }
+                    firmware.id = Some((body[0] as u16) << 8 | body[1] as u16);
+                    firmware.version = Some([
+                        body[2] << 4 | body[3],
+                        body[4] << 4 | body[5],
+                        body[6] << 4 | body[7],
+                    ]);
+                }
+                kind @ (BLOCK | LAST_BLOCK) => {
+                    let width = match width_of(body.len()) {
+                        Some(width) => width,
+                        None => {
+                            firmware.problems.push(corrupt("bad block length"));
+                            continue;
+                        }
+                    };
+                    if firmware.byte_width == 0 {
+                        firmware.byte_width = width;
+                    } else if width != firmware.byte_width {
+                        firmware.problems.push(Problem::ByteWidth {
+                            offset,
+                            width,
+                            expected: firmware.byte_width,
+                        });
+                        continue;
+                    }
+
+                    let data = unpack(body, width);
+                    if kind == BLOCK {
+                        if first_block.is_some() {
+                            firmware
+                                .problems
+                                .push(corrupt("block after the first block"));
+                        }
+                        blocks.push(data);
+                    } else if first_block.is_some() {
+                        firmware.problems.push(corrupt("overlaps the first block"));
+                    } else {
+                        first_block = Some(data);
+                    }
+                }
+                CHECKSUM => checksum = true,
+                _ => firmware.problems.push(corrupt("unknown message type")),
+            }
+        }
+
+        if firmware.id.is_none() {
+            firmware.problems.push(Problem::Missing("header"));
+        }
+        if !checksum {
+            firmware.problems.push(Problem::Missing("checksum"));
+        }
+        match first_block {
+            Some(first_block) => firmware.data = first_block,
+            None => {
+                firmware.problems.push(Problem::Missing("first block"));
+                firmware.data = vec![0xff; firmware.byte_width];
+            }
+        }
+        for block in blocks {
+            firmware.data.extend(block);
+        }
+        firmware
+    }
+
+    /// Returns the ranges of the image holding data rather than padding, relative to its start.
+    /// Runs of 0xFF shorter than a block are counted as data.
+    pub fn ranges(&self) -> Vec<Range<u64>> {
+        let width = self.byte_width.max(1);
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for (offset, &byte) in self.data.iter().enumerate() {
+            if byte == 0xff {
+                continue;
+            }
+            let offset = offset as u64;
+            match ranges.last_mut() {
+                Some(range) if offset - range.end < width as u64 => range.end = offset + 1,
+                _ => ranges.push(offset..offset + 1),
+            }
+        }
+        ranges
+    }
+
+    /// Check the image against the memory layout, placed at `base_address`, or the start of flash
+    /// if `None`. Returns the problems found reading the file as well.
+    pub fn check(&self, layout: &Layout, base_address: Option<u32>) -> Vec<Problem> {
+        let base = base_address.unwrap_or(layout.flash.origin) as u64;
+        let end = base + self.data.len() as u64;
+        let mut problems = self.problems.clone();
+
+        if base < layout.flash.origin as u64 {
+            problems.push(Problem::Overlap(base..end.min(layout.flash.origin as u64)));
+        }
+        if end > layout.flash.end() {
+            problems.push(Problem::Oversized {
+                end,
+                limit: layout.flash.end(),
+            });
+        }
+
+        let ranges = self.ranges();
+        for pair in ranges.windows(2) {
+            problems.push(Problem::Gap(base + pair[0].end..base + pair[1].start));
+        }
+
+        if let (Some(version), Some(image)) = (self.version, self.data.get(0x100..0x103)) {
+            let image = [image[2], image[1], image[0]];
+            if version != image {
+                problems.push(Problem::Version {
+                    header: version,
+                    image,
+                });
+            }
+        }
+
+        let word = |offset: usize| {
+            let bytes = self.data.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        if let Some(stack_pointer) = word(0) {
+            // the stack grows down from the end of RAM, so the top is the address after it
+            let ram = layout.ram.origin as u64..=layout.ram.end();
+            if !ram.contains(&(stack_pointer as u64)) || stack_pointer % 4 != 0 {
+                problems.push(Problem::StackPointer(stack_pointer));
+            }
+        }
+        if let Some(reset_handler) = word(4) {
+            let address = (reset_handler & !1) as u64;
+            if reset_handler & 1 == 0 || !(base..end).contains(&address) {
+                problems.push(Problem::ResetHandler(reset_handler));
+            }
+        }
+        problems
+    }
+}
+
+/// Split a file into its SysEx messages, with their offsets, reporting stray and truncated data.
+/// A run of stray bytes is reported once.
+fn messages<'a>(syx: &'a [u8], problems: &mut Vec<Problem>) -> Vec<(usize, &'a [u8])> {
+    let mut messages = Vec::new();
+    let mut start = None;
+    for (offset, &byte) in syx.iter().enumerate() {
+        match (byte, start) {
+            (0xf0, None) => start = Some(offset),
+            (0xf0, Some(previous)) => {
+                problems.push(Problem::Corrupt {
+                    offset: previous,
+                    reason: "truncated",
+                });
+                start = Some(offset);
+            }
+            (0xf7, Some(previous)) => {
+                messages.push((previous, &syx[previous..=offset]));
+                start = None;
+            }
+            (0x80..=0xff, Some(previous)) => {
+                problems.push(Problem::Corrupt {
+                    offset: previous,
+                    reason: "status byte inside the message",
+                });
+                start = None;
+            }
+            (_, None) => match problems.last_mut() {
+                Some(Problem::Stray(range)) if range.end == offset => range.end += 1,
+                _ => problems.push(Problem::Stray(offset..offset + 1)),
+            },
+            _ => {}
+        }
+    }
+    if let Some(start) = start {
+        problems.push(Problem::Corrupt {
+            offset: start,
+            reason: "truncated",
+        });
+    }
+    messages
+}
+
+/// Returns the ranges, relative to the start of the images, where two images differ. Where one
+/// image is longer, the rest of it is counted as different unless it is padding.
+pub fn diff(a: &Firmware, b: &Firmware) -> Vec<Range<u64>> {
+    let length = a.data.len().max(b.data.len());
+    let byte = |firmware: &Firmware, offset| firmware.data.get(offset).copied().unwrap_or(0xff);
+
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for offset in 0..length {
+        if byte(a, offset) == byte(b, offset) {
+            continue;
+        }
+        let offset = offset as u64;
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Image, Options};
+
+    const FLASH: u32 = 0x0800_6400;
+
+    /// A small image with a valid vector table and version number.
+    fn image() -> Image {
+        let mut image = Image::new();
+        image.write(FLASH, &0x2000_5000u32.to_le_bytes());
+        image.write(FLASH + 4, &(FLASH + 0x201).to_le_bytes());
+        image.write(FLASH + 8, &[0x5a; 0xf8]);
+        image.write(FLASH + 0x100, &[0x03, 0x02, 0x01]);
+        image.write(FLASH + 0x103, &[0xa5; 0x100]);
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image();
+        let sysex = image.to_sysex(&Options::default()).unwrap();
+        let firmware = Firmware::parse(&sysex);
+
+        assert_eq!(firmware.id, Some(0x51));
+        assert_eq!(firmware.version, Some([0x01, 0x02, 0x03]));
+        assert_eq!(firmware.byte_width, 32);
+        assert_eq!(firmware.data.len(), 0x220);
+        for (offset, &byte) in firmware.data.iter().enumerate() {
+            assert_eq!(Some(byte), image.get(FLASH + offset as u32).or(Some(0xff)));
+        }
+        assert_eq!(firmware.ranges().len(), 1);
+        assert_eq!(firmware.ranges()[0], 0..0x203);
+        assert_eq!(firmware.check(&Layout::default(), None), []);
+    }
+
+    #[test]
+    fn linker_script_layout() {
+        let script = include_str!("../../../stm32_flash.ld");
+        assert_eq!(Layout::parse(script), Ok(Layout::default()));
+        assert!(Layout::parse("SECTIONS {}").is_err());
+    }
+
+    #[test]
+    fn corrupt_messages_are_reported() {
+        let sysex = image().to_sysex(&Options::default()).unwrap();
+        let header_end = sysex.iter().position(|&byte| byte == 0xf7).unwrap() + 1;
+
+        let mut corrupted = sysex[..header_end].to_vec();
+        // stray data, a truncated block, then a block with a status byte in it
+        corrupted.extend_from_slice(&[0x12; 300]);
+        corrupted.extend_from_slice(&sysex[header_end..header_end + 10]);
+        corrupted.extend_from_slice(&sysex[header_end..header_end + 10]);
+        corrupted.push(0x90);
+        corrupted.extend_from_slice(&sysex[header_end..]);
+
+        let problems = Firmware::parse(&corrupted).problems;
+        assert_eq!(
+            problems,
+            [
+                Problem::Stray(header_end..header_end + 300),
+                Problem::Corrupt {
+                    offset: header_end + 300,
+                    reason: "truncated"
+                },
+                Problem::Corrupt {
+                    offset: header_end + 310,
+                    reason: "status byte inside the message"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_problems_are_reported() {
+        let mut image = image();
+        image.write(FLASH, &0x1000_0000u32.to_le_bytes());
+        image.write(FLASH + 4, &(FLASH + 0x200).to_le_bytes());
+        image.write(FLASH + 0xfff, &[0]);
+        let firmware = Firmware::parse(&image.to_sysex(&Options::default()).unwrap());
+        let base = FLASH as u64 - 0x20;
+
+        let small = Layout {
+            flash: Region {
+                origin: FLASH,
+                length: 0x800,
+            },
+            ..Layout::default()
+        };
+        assert_eq!(
+            firmware.check(&small, Some(FLASH - 0x20)),
+            [
+                Problem::Overlap(base..FLASH as u64),
+                Problem::Oversized {
+                    end: base + 0x1000,
+                    limit: FLASH as u64 + 0x800
+                },
+                Problem::Gap(base + 0x203..base + 0xfff),
+                Problem::StackPointer(0x1000_0000),
+                Problem::ResetHandler(FLASH + 0x200),
+            ]
+        );
+    }
+
+    #[test]
+    fn header_must_match_the_image() {
+        let mut sysex = image().to_sysex(&Options::default()).unwrap();
+        // the low digit of the version's last byte
+        sysex[13] = 0x04;
+        let firmware = Firmware::parse(&sysex);
+        assert_eq!(
+            firmware.check(&Layout::default(), None),
+            [Problem::Version {
+                header: [0x01, 0x02, 0x04],
+                image: [0x01, 0x02, 0x03],
+            }]
+        );
+    }
+
+    #[test]
+    fn images_are_diffed() {
+        let a = Firmware::parse(&image().to_sysex(&Options::default()).unwrap());
+        let mut image = image();
+        image.write(FLASH + 0x10, &[0, 0]);
+        image.write(FLASH + 0x3ff, &[0]);
+        let b = Firmware::parse(&image.to_sysex(&Options::default()).unwrap());
+
+        assert_eq!(diff(&a, &b), [0x10..0x12, 0x3ff..0x400]);
+        assert_eq!(diff(&a, &a), []);
+    }
+}
@@ -0,0 +1,432 @@
+//! Convert firmware images into the SysEx format accepted by the Launchpad Pro bootloader.
+//!
+//! This is a port of `tools/hextosyx.cpp`, and produces the same `.syx` files, with the bootloader
+//! ID, block size and base address made configurable. It reads Intel HEX files and ELF files.
+//!
+//! The C++ tool reads one byte of uninitialised memory at the address after the end of the image
+//! and of each gap in it, so its output can differ from run to run. This tool pads those addresses
+//! with 0xFF like every other unset address. The C++ tool also leaves out the last byte of the
+//! image when that byte would start a new block. This tool does the same by default, so its output
+//! is byte for byte the same, and sends that byte when [`Options::include_last_byte`] is set.
+//!
+//! The [`inspect`] module reads `.syx` files back, to check them before they are uploaded.
+//!
+//! # Example
+//!
+//! ```
+//! use hextosyx::{Image, Options};
+//!
+//! let image = Image::from_hex(":0400000001020304F2\n:00000001FF\n").unwrap();
+//! let sysex = image.to_sysex(&Options::default()).unwrap();
+//! assert_eq!(&sysex[..8], &[0xf0, 0x00, 0x20, 0x29, 0x00, 0x71, 0x00, 0x51]);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use launchpad_pro_rs::codec::Scheme;
+
+pub mod inspect;
+
+/// The header of every message sent to the bootloader.
+pub(crate) const RESET: [u8; 6] = [0xf0, 0x00, 0x20, 0x29, 0x00, 0x71];
+
+/// The message type of a block of the image.
+pub(crate) const BLOCK: u8 = 0x72;
+
+/// The message type of the last block, which holds the start of the image.
+pub(crate) const LAST_BLOCK: u8 = 0x73;
+
+/// The message type of the checksum, which the bootloader ignores.
+pub(crate) const CHECKSUM: u8 = 0x76;
+
+/// An error reading an image or converting it to SysEx.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A line of an Intel HEX file is malformed.
+    Hex {
+        /// The line number, counting from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: &'static str,
+    },
+    /// An ELF file is malformed or not a 32-bit little-endian image.
+    Elf(&'static str),
+    /// The image contains no data.
+    Empty,
+    /// The block size is zero or a multiple of seven, which the packing can't represent.
+    ByteWidth(usize),
+    /// The ID doesn't fit in the two 7-bit bytes of the header, which hold its high and low bytes.
+    Id(u16),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Hex { line, reason } => write!(f, "line {}: {}", line, reason),
+            Error::Elf(reason) => write!(f, "bad ELF file: {}", reason),
+            Error::Empty => write!(f, "the image contains no data"),
+            Error::ByteWidth(width) => {
+                write!(
+                    f,
+                    "byte width {} must be non-zero and not a multiple of 7",
+                    width
+                )
+            }
+            Error::Id(id) => write!(f, "ID {:#06x} has a byte larger than 0x7f", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How to pack an image into SysEx.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// The ID sent in the header, which the bootloader checks.
+    pub id: u16,
+    /// The number of bytes of the image sent in each block.
+    pub byte_width: usize,
+    /// The address of the start of the image, or `None` for its lowest address.
+    pub base_address: Option<u32>,
+    /// Whether to send the last byte of the image when it starts a new block, which the C++ tool
+    /// leaves out.
+    pub include_last_byte: bool,
+}
+
+impl Default for Options {
+    /// The settings used by the C++ tool.
+    fn default() -> Self {
+        Options {
+            id: 0x0051,
+            byte_width: 32,
+            base_address: None,
+            include_last_byte: false,
+        }
+    }
+}
+
+/// The contents of memory described by a firmware image.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    bytes: BTreeMap<u32, u8>,
+}
+
+impl Image {
+    /// Construct an empty image.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the bytes starting at an address.
+    pub fn write(&mut self, address: u32, data: &[u8]) {
+        for (offset, &byte) in data.iter().enumerate() {
+            self.bytes.insert(address.wrapping_add(offset as u32), byte);
+        }
+    }
+
+    /// Returns the byte at an address, if it is set.
+    pub fn get(&self, address: u32) -> Option<u8> {
+        self.bytes.get(&address).copied()
+    }
+
+    /// Returns the lowest address that is set.
+    pub fn min_address(&self) -> Option<u32> {
+        self.bytes.keys().next().copied()
+    }
+
+    /// Returns the highest address that is set.
+    pub fn max_address(&self) -> Option<u32> {
+        self.bytes.keys().next_back().copied()
+    }
+
+    /// Read an ELF file if the data starts with the ELF magic number, or an Intel HEX file if not.
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        if data.starts_with(b"\x7fELF") {
+            Self::from_elf(data)
+        } else {
+            let text = std::str::from_utf8(data).map_err(|_| Error::Hex {
+                line: 1,
+                reason: "not a text file",
+            })?;
+            Self::from_hex(text)
+        }
+    }
+
+    /// Read an Intel HEX file.
+    pub fn from_hex(text: &str) -> Result<Self, Error> {
+        let mut image = Image::new();
+        let mut base = 0u32;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |reason| Error::Hex {
+                line: index + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let digits = line.strip_prefix(':').ok_or(error("missing ':'"))?;
+            if digits.len() % 2 != 0 || !digits.is_ascii() {
+                return Err(error("bad hex digits"));
+            }
+            let record = (0..digits.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| error("bad hex digits"))?;
+            if record.len() < 5 || record.len() != 5 + record[0] as usize {
+                return Err(error("wrong length"));
+            }
+            if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+                return Err(error("bad checksum"));
+            }
+
+            let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+            let data = &record[4..record.len() - 1];
+            match record[3] {
+                0 => image.write(base.wrapping_add(address), data),
+                1 => break,
+                2 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+                4 if data.len() == 2 => {
+                    base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16
+                }
+                2 | 4 => return Err(error("wrong length")),
+                // start addresses
+                3 | 5 => {}
+                _ => return Err(error("unknown record type")),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Read the loadable segments of a 32-bit little-endian ELF file, at their load addresses, as
+    /// `objcopy -O ihex` does.
+    pub fn from_elf(data: &[u8]) -> Result<Self, Error> {
+        let u16_at = |offset: usize| -> Result<u16, Error> {
+            data.get(offset..offset + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or(Error::Elf("truncated"))
+        };
+        let u32_at = |offset: usize| -> Result<u32, Error> {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .ok_or(Error::Elf("truncated"))
+        };
+
+        if !data.starts_with(b"\x7fELF") {
+            return Err(Error::Elf("missing magic number"));
+        }
+        // the class and data encoding
+        if data.get(4..6) != Some(&[1, 1]) {
+            return Err(Error::Elf("not a 32-bit little-endian file"));
+        }
+
+        let program_headers = u32_at(28)? as usize;
+        let header_size = u16_at(42)? as usize;
+        let header_count = u16_at(44)? as usize;
+
+        /// A loadable segment.
+        const PT_LOAD: u32 = 1;
+
+        let mut image = Image::new();
+        for index in 0..header_count {
+            let header = program_headers + index * header_size;
+            if u32_at(header)? != PT_LOAD {
+                continue;
+            }
+            let offset = u32_at(header + 4)? as usize;
+            let physical_address = u32_at(header + 12)?;
+            let file_size = u32_at(header + 16)? as usize;
+            let segment = data
+                .get(offset..offset + file_size)
+                .ok_or(Error::Elf("segment outside the file"))?;
+            image.write(physical_address, segment);
+        }
+        Ok(image)
+    }
+
+    /// Pack the image into the messages the bootloader expects: a header holding the ID and the
+    /// version number from offset 0x100 of the image, the blocks of the image from its second
+    /// block onwards, then its first block, and finally a checksum message.
+    pub fn to_sysex(&self, options: &Options) -> Result<Vec<u8>, Error> {
+        let width = options.byte_width;
+        if width == 0 || width.is_multiple_of(7) {
+            return Err(Error::ByteWidth(width));
+        }
+        if options.id & 0x8080 != 0 {
+            return Err(Error::Id(options.id));
+        }
+        let base = match options.base_address {
+            Some(base) => base,
+            None => self.min_address().ok_or(Error::Empty)?,
+        };
+        let max = self.max_address().ok_or(Error::Empty)?;
+
+        let mut sysex = Vec::new();
+        self.write_header(&mut sysex, options.id, base);
+
+        // the C++ tool stops before the block starting at the last address
+        let end = max as u64 + options.include_last_byte as u64;
+        let mut address = base as u64 + width as u64;
+        while address < end {
+            self.write_block(&mut sysex, address as u32, width, BLOCK);
+            address += width as u64;
+        }
+        self.write_block(&mut sysex, base, width, LAST_BLOCK);
+
+        write_checksum(&mut sysex);
+        Ok(sysex)
+    }
+
+    /// Returns the byte at an address, with unset addresses padded with 0xFF.
+    fn padded(&self, address: u32) -> u8 {
+        self.get(address).unwrap_or(0xff)
+    }
+
+    fn write_header(&self, sysex: &mut Vec<u8>, id: u16, base: u32) {
+        sysex.extend_from_slice(&RESET);
+        sysex.extend_from_slice(&[(id >> 8) as u8, id as u8]);
+        // the version number, as hex digits
+        for offset in [0x102, 0x101, 0x100] {
+            let byte = self.padded(base.wrapping_add(offset));
+            sysex.extend_from_slice(&[byte >> 4, byte & 0x0f]);
+        }
+        sysex.push(0xf7);
+    }
+
+    fn write_block(&self, sysex: &mut Vec<u8>, address: u32, width: usize, kind: u8) {
+        sysex.extend_from_slice(&RESET[..5]);
+        sysex.push(kind);
+
+        let mut payload = Vec::new();
+        for group in (0..width).step_by(7) {
+            let mut input = [0; 7];
+            for (i, byte) in input.iter_mut().enumerate() {
+                *byte = self.padded(address.wrapping_add((group + i) as u32));
+            }
+            payload.extend_from_slice(&Scheme::Bootloader.pack(&input));
+        }
+        sysex.extend_from_slice(&payload[..1 + width * 8 / 7]);
+        sysex.push(0xf7);
+    }
+}
+
+/// The checksum message. The bootloader doesn't check it, but it does expect it.
+fn write_checksum(sysex: &mut Vec<u8>) {
+    sysex.extend_from_slice(&RESET[..5]);
+    sysex.extend_from_slice(&[CHECKSUM, 0x00]);
+    sysex.extend_from_slice(b"Firmware");
+    sysex.extend_from_slice(&[0; 8]);
+    sysex.push(0xf7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest::proptest! {
+        #[test]
+        fn packing_matches_the_cpp_tool(input: [u8; 7]) {
+            // the packing as written in the C++ tool
+            let reference = {
+                let input = input.map(|byte| byte as u32);
+                [
+                    input[0] >> 1,
+                    (input[0] << 6) + (input[1] >> 2),
+                    (input[1] << 5) + (input[2] >> 3),
+                    (input[2] << 4) + (input[3] >> 4),
+                    (input[3] << 3) + (input[4] >> 5),
+                    (input[4] << 2) + (input[5] >> 6),
+                    (input[5] << 1) + (input[6] >> 7),
+                    input[6],
+                ]
+                .map(|byte| byte as u8 & 0x7f)
+            };
+            proptest::prop_assert_eq!(Scheme::Bootloader.pack(&input), reference);
+        }
+    }
+
+    #[test]
+    fn hex_records() {
+        let image = Image::from_hex(
+            ":020000040800F2\n:020010001122BB\n:020000021000EC\n:01000000AA55\n:00000001FF\n",
+        )
+        .unwrap();
+        assert_eq!(image.get(0x0800_0010), Some(0x11));
+        assert_eq!(image.get(0x0800_0011), Some(0x22));
+        assert_eq!(image.get(0x0001_0000), Some(0xaa));
+        assert_eq!(image.min_address(), Some(0x0001_0000));
+
+        assert_eq!(
+            Image::from_hex(":020010001122BC\n"),
+            Err(Error::Hex {
+                line: 1,
+                reason: "bad checksum"
+            })
+        );
+        assert_eq!(
+            Image::from_hex("\n020010001122BB\n"),
+            Err(Error::Hex {
+                line: 2,
+                reason: "missing ':'"
+            })
+        );
+    }
+
+    #[test]
+    fn options_are_checked() {
+        let mut image = Image::new();
+        assert_eq!(image.to_sysex(&Options::default()), Err(Error::Empty));
+
+        image.write(0, &[1]);
+        let options = |id, byte_width| Options {
+            id,
+            byte_width,
+            ..Options::default()
+        };
+        assert_eq!(
+            image.to_sysex(&options(0x51, 14)),
+            Err(Error::ByteWidth(14))
+        );
+        assert_eq!(image.to_sysex(&options(0x8000, 32)), Err(Error::Id(0x8000)));
+        assert_eq!(image.to_sysex(&options(0x0080, 32)), Err(Error::Id(0x0080)));
+        assert!(image.to_sysex(&options(0x7f7f, 32)).is_ok());
+    }
+
+    #[test]
+    fn unset_addresses_are_padded() {
+        let mut image = Image::new();
+        image.write(0x100, &[0x12]);
+        let options = Options {
+            base_address: Some(0),
+            include_last_byte: true,
+            ..Options::default()
+        };
+
+        let sysex = image.to_sysex(&options).unwrap();
+        // the version is read from 0x102 down to 0x100, with the missing bytes read as 0xff
+        assert_eq!(&sysex[8..14], &[0xf, 0xf, 0xf, 0xf, 0x1, 0x2]);
+
+        // the header, eight blocks from 0x20 to 0x100, the first block and the checksum
+        let messages: Vec<_> = sysex.split_inclusive(|&byte| byte == 0xf7).collect();
+        assert_eq!(messages.len(), 11);
+        let last_byte = messages[8];
+        assert_eq!(last_byte[5], BLOCK);
+        assert_eq!(last_byte[6..8], [0x09, 0x3f]);
+        let first_block = messages[9];
+        assert_eq!(first_block[5], LAST_BLOCK);
+        assert!(first_block[6..43].iter().all(|&byte| byte == 0x7f));
+        assert_eq!(first_block.len(), 44);
+
+        // the C++ tool leaves out the block holding 0x100
+        let options = Options {
+            include_last_byte: false,
+            ..options
+        };
+        let sysex = image.to_sysex(&options).unwrap();
+        assert_eq!(sysex.iter().filter(|&&byte| byte == 0xf7).count(), 10);
+    }
+}
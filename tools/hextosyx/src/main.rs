@@ -0,0 +1,89 @@
+use std::process::ExitCode;
+
+use hextosyx::{Image, Options};
+
+const USAGE: &str = "\
+usage: hextosyx [options] <input> <output>
+
+Convert an Intel HEX or ELF firmware image into SysEx for the Launchpad Pro bootloader.
+
+options:
+    --id <id>                 the ID sent in the header (default 0x0051)
+    --byte-width <bytes>      the number of bytes in each block (default 32)
+    --base-address <address>  the start of the image (default its lowest address)
+    --include-last-byte       send the last byte of the image when it starts a new block, which
+                              the C++ tool leaves out";
+
+/// Parse a number in decimal, or in hex with a `0x` prefix.
+fn parse_number<T: TryFrom<u64>>(text: &str) -> Option<T> {
+    let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    T::try_from(number).ok()
+}
+
+/// Parse the command line into the options and the input and output paths.
+fn parse_args(args: &[String]) -> Result<(Options, String, String), String> {
+    let mut options = Options::default();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", arg))
+                .map(String::as_str)
+        };
+        let invalid = |value: &str| format!("invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--id" => {
+                let value = value()?;
+                options.id = parse_number(value).ok_or_else(|| invalid(value))?;
+            }
+            "--byte-width" => {
+                let value = value()?;
+                options.byte_width = parse_number(value).ok_or_else(|| invalid(value))?;
+            }
+            "--base-address" => {
+                let value = value()?;
+                options.base_address = Some(parse_number(value).ok_or_else(|| invalid(value))?);
+            }
+            "--include-last-byte" => options.include_last_byte = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok((options, input, output)),
+        Err(_) => Err(USAGE.to_string()),
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (options, input, output) = parse_args(args)?;
+    println!("converting {} to sysex file: {}", input, output);
+
+    let data = std::fs::read(&input).map_err(|error| format!("{}: {}", input, error))?;
+    let image = Image::load(&data).map_err(|error| format!("{}: {}", input, error))?;
+    if let (Some(min), Some(max)) = (image.min_address(), image.max_address()) {
+        println!("max addr: {:x} min_addr: {:x}", max, min);
+    }
+
+    let sysex = image
+        .to_sysex(&options)
+        .map_err(|error| error.to_string())?;
+    std::fs::write(&output, sysex).map_err(|error| format!("{}: {}", output, error))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
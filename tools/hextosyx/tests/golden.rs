@@ -0,0 +1,88 @@
+//! Compare the output with files made by `tools/hextosyx.cpp`.
+//!
+//! `narrow.syx` was made by the C++ tool with `ByteWidth` changed to 16 and `ID` to 0x0123. The
+//! images end one byte into a block, so the C++ tool's output doesn't depend on the uninitialised
+//! byte it reads after the end of the image.
+
+use hextosyx::{Image, Options};
+
+const IMAGE_HEX: &str = include_str!("data/image.hex");
+const IMAGE_ELF: &[u8] = include_bytes!("data/image.elf");
+const IMAGE_SYX: &[u8] = include_bytes!("data/image.syx");
+const NARROW_HEX: &str = include_str!("data/narrow.hex");
+const NARROW_SYX: &[u8] = include_bytes!("data/narrow.syx");
+
+#[test]
+fn hex_matches_the_cpp_tool() {
+    let image = Image::from_hex(IMAGE_HEX).unwrap();
+    assert_eq!(image.to_sysex(&Options::default()).unwrap(), IMAGE_SYX);
+}
+
+#[test]
+fn elf_matches_the_cpp_tool() {
+    // the image's .data is loaded into flash after .text, and its .bss is left out
+    let image = Image::load(IMAGE_ELF).unwrap();
+    assert_eq!(image, Image::from_hex(IMAGE_HEX).unwrap());
+    assert_eq!(image.to_sysex(&Options::default()).unwrap(), IMAGE_SYX);
+}
+
+#[test]
+fn options_match_the_cpp_tool() {
+    let image = Image::load(NARROW_HEX.as_bytes()).unwrap();
+    let options = Options {
+        id: 0x0123,
+        byte_width: 16,
+        base_address: image.min_address(),
+        ..Options::default()
+    };
+    assert_eq!(image.to_sysex(&options).unwrap(), NARROW_SYX);
+}
+
+#[test]
+fn last_byte_is_added_to_the_cpp_output() {
+    let image = Image::load(IMAGE_HEX.as_bytes()).unwrap();
+    let sysex = image
+        .to_sysex(&Options {
+            include_last_byte: true,
+            ..Options::default()
+        })
+        .unwrap();
+
+    // the C++ tool's output, with a block holding the last byte, 0xA8, before the first block
+    let messages: Vec<_> = IMAGE_SYX.split_inclusive(|&byte| byte == 0xf7).collect();
+    let (blocks, end) = messages.split_at(messages.len() - 2);
+    let mut last_byte = vec![0xf0, 0x00, 0x20, 0x29, 0x00, 0x72, 0xa8 >> 1, 0x3f];
+    last_byte.resize(6 + 1 + 32 * 8 / 7, 0x7f);
+    last_byte.push(0xf7);
+    assert_eq!(sysex, [blocks, &[&last_byte[..]], end].concat().concat());
+}
+
+#[test]
+fn last_byte_on_a_block_boundary_is_sent_when_asked() {
+    let mut image = Image::new();
+    image.write(0, &[0x11; 17]);
+    let options = Options {
+        byte_width: 16,
+        include_last_byte: true,
+        ..Options::default()
+    };
+    #[rustfmt::skip]
+    let expected = [
+        // the header, with the version read from unset addresses
+        0xf0, 0x00, 0x20, 0x29, 0x00, 0x71, 0x00, 0x51, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0xf7,
+        // the last byte, at 0x10
+        0xf0, 0x00, 0x20, 0x29, 0x00, 0x72,
+        0x08, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f,
+        0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f,
+        0x7f, 0x7f, 0x7f, 0xf7,
+        // the first block
+        0xf0, 0x00, 0x20, 0x29, 0x00, 0x73,
+        0x08, 0x44, 0x22, 0x11, 0x08, 0x44, 0x22, 0x11,
+        0x08, 0x44, 0x22, 0x11, 0x08, 0x44, 0x22, 0x11,
+        0x08, 0x44, 0x22, 0xf7,
+        // the checksum
+        0xf0, 0x00, 0x20, 0x29, 0x00, 0x76, 0x00, b'F', b'i', b'r', b'm', b'w', b'a', b'r', b'e',
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf7,
+    ];
+    assert_eq!(image.to_sysex(&options).unwrap(), expected);
+}
@@ -0,0 +1,29 @@
+use std::process::ExitCode;
+
+use launchpad_pro_rs::panic;
+
+const USAGE: &str = "\
+usage: panicdecode <input>
+
+Print the panic report in SysEx recorded from the Launchpad Pro's USB port, e.g. a .syx file.";
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [input] = args else {
+        return Err(USAGE.to_string());
+    };
+    let dump = std::fs::read(input).map_err(|error| format!("{}: {}", input, error))?;
+    let report = panic::decode(&dump).ok_or_else(|| format!("{}: no panic report found", input))?;
+    println!("{}", report);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
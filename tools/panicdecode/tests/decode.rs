@@ -0,0 +1,34 @@
+//! Run the tool on a hand-built SysEx dump, which has a device inquiry reply and a log message before
+//! a panic report for an out-of-bounds preset index in the MPE example.
+
+use std::process::Command;
+
+fn panicdecode(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_panicdecode"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reports_are_printed() {
+    let output = panicdecode(&[concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/report.syx"
+    )]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "panicked at examples/mpe/app.rs:161:38:\n\
+         index out of bounds: the len is 8 but the index is 8\n"
+    );
+}
+
+#[test]
+fn dumps_without_a_report_are_an_error() {
+    let output = panicdecode(&[concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .ends_with("no panic report found\n"));
+}
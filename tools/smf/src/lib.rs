@@ -0,0 +1,599 @@
+//! Convert performances recorded on the Launchpad Pro into Standard MIDI Files.
+//!
+//! A performance is read from a loop exported over SysEx by
+//! [`Looper::export`](launchpad_pro_rs::looper::Looper::export), with presses on the surface read
+//! as the MIDI the Launchpad Pro sends for them, or from the messages an app sent on the host,
+//! captured with [`hal::capture`](launchpad_pro_rs::hal::capture). Only channel
+//! messages are written to the file. Every message keeps its channel, so an MPE performance keeps
+//! the pitch bend of each voice and can be rendered again in a DAW.
+//!
+//! A type 1 file has a track holding the tempo map, then a track for each channel used on each
+//! port. A type 0 file has everything in one track. The tempo map follows the MIDI clock on one
+//! port, [`Options::clock_port`], a beat at a time, and otherwise holds [`Options::tempo`].
+//!
+//! # Example
+//!
+//! ```
+//! use smf::{Format, Message, Options};
+//! use launchpad_pro_rs::hal::midi::Port;
+//!
+//! let messages = [
+//!     Message { time: 0, port: Port::USB, data: vec![0x91, 60, 100] },
+//!     Message { time: 500, port: Port::USB, data: vec![0x81, 60, 0] },
+//! ];
+//! let file = smf::write(&messages, &Options { format: Format::Single, ..Options::default() })
+//!     .unwrap();
+//! assert_eq!(&file[..4], b"MThd");
+//! ```
+
+use std::fmt;
+
+use launchpad_pro_rs::hal::capture::Captured;
+use launchpad_pro_rs::hal::midi::Port;
+use launchpad_pro_rs::hal::surface::{AftertouchEvent, Button, ButtonEvent, Event};
+use launchpad_pro_rs::hal::Point;
+use launchpad_pro_rs::looper::{Action, Looper};
+use launchpad_pro_rs::settings;
+
+/// The most events read from an exported loop.
+pub const MAX_LOOP_EVENTS: usize = 4096;
+
+/// The clock pulses in a beat.
+const PPQN: usize = launchpad_pro_rs::clock::PPQN as usize;
+
+/// An error reading a performance or writing a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A line of a capture is malformed.
+    Capture {
+        /// The line number, counting from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: &'static str,
+    },
+    /// An exported loop couldn't be read.
+    Loop(settings::Error),
+    /// The division is zero or doesn't fit in 15 bits.
+    Division(u16),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Capture { line, reason } => write!(f, "line {}: {}", line, reason),
+            Error::Loop(error) => write!(f, "bad loop: {:?}", error),
+            Error::Division(division) => {
+                write!(f, "division {} must be between 1 and 32767", division)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A MIDI message in a performance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// When the message was sent, in ms from the start of the performance.
+    pub time: u64,
+    /// The port it was sent on.
+    pub port: Port,
+    /// The bytes of the message.
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    /// Returns the channel of a channel message.
+    fn channel(&self) -> Option<u8> {
+        match self.data.first() {
+            Some(&status) if (0x80..0xf0).contains(&status) => Some(status & 0x0f),
+            _ => None,
+        }
+    }
+}
+
+/// Read the actions recorded in a loop exported over SysEx. MIDI is read as it was sent, and
+/// presses and pressure on the surface as the MIDI the Launchpad Pro sends for them on its USB
+/// port in programmer mode; see [`surface_message`].
+pub fn from_loop(stream: &[u8]) -> Result<Vec<Message>, Error> {
+    let mut looper = Box::new(Looper::<MAX_LOOP_EVENTS>::new());
+    looper.import(stream).map_err(Error::Loop)?;
+    let mut messages: Vec<_> = looper
+        .events()
+        .iter()
+        .filter_map(|event| {
+            let (port, data) = match event.action {
+                Action::Midi(port, data) => {
+                    let size = event.action.message()?.bytes_size();
+                    (port, data[..size].to_vec())
+                }
+                action => (Port::USB, surface_message(&action)?.to_vec()),
+            };
+            Some(Message {
+                time: event.time as u64,
+                port,
+                data,
+            })
+        })
+        .collect();
+    // overdubs are recorded after the layers they play over
+    messages.sort_by_key(|message| message.time);
+    Ok(messages)
+}
+
+/// Returns the message the Launchpad Pro sends on channel 1 in programmer mode for a press or
+/// pressure on its surface: notes numbered by the pads' indices, with polyphonic pressure for
+/// aftertouch, and control changes for the buttons around the pads. The Setup button sends
+/// nothing.
+///
+/// # Example
+///
+/// ```
+/// use launchpad_pro_rs::hal::surface::{Button, ButtonEvent, Event};
+/// use launchpad_pro_rs::hal::Point;
+/// use launchpad_pro_rs::looper::Action;
+///
+/// let press = Action::Button(ButtonEvent {
+///     button: Button::Pad(Point::new(3, 4)),
+///     event: Event::Press(100),
+/// });
+/// assert_eq!(smf::surface_message(&press), Some([0x90, 43, 100]));
+/// ```
+pub fn surface_message(action: &Action) -> Option<[u8; 3]> {
+    let is_pad = |point: Point| (1..=8).contains(&point.x()) && (1..=8).contains(&point.y());
+    match *action {
+        Action::Button(ButtonEvent {
+            button: Button::Pad(point),
+            event,
+        }) => {
+            let value = match event {
+                Event::Press(velocity) => velocity.clamp(1, 0x7f),
+                Event::Release => 0,
+            };
+            let status = match (is_pad(point), value) {
+                (true, 0) => 0x80,
+                (true, _) => 0x90,
+                (false, _) => 0xb0,
+            };
+            Some([status, point.to_index(), value])
+        }
+        Action::Aftertouch(AftertouchEvent { point, value }) if is_pad(point) => {
+            Some([0xa0, point.to_index(), value.min(0x7f)])
+        }
+        _ => None,
+    }
+}
+
+/// Read the messages captured on the host, timed from the first.
+pub fn from_captured(captured: &[Captured]) -> Vec<Message> {
+    let start = captured.first().map_or(0, |first| first.time.as_millis());
+    captured
+        .iter()
+        .map(|captured| Message {
+            time: captured.time.as_millis() - start,
+            port: captured.port,
+            data: captured.data.clone(),
+        })
+        .collect()
+}
+
+/// Read captured messages written as text by [`Captured`]'s `Display`, one to a line. Blank
+/// lines and lines starting with `#` are ignored.
+pub fn parse_capture(text: &str) -> Result<Vec<Message>, Error> {
+    let mut messages = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason| Error::Capture {
+            line: index + 1,
+            reason,
+        };
+        let mut fields = line.split_whitespace();
+        let time = fields
+            .next()
+            .and_then(|time| time.parse().ok())
+            .ok_or_else(|| error("bad time"))?;
+        let port = fields
+            .next()
+            .and_then(|port| port.parse::<u8>().ok())
+            .and_then(|port| Port::try_from(port).ok())
+            .ok_or_else(|| error("bad port"))?;
+        let data = fields
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error("bad byte"))?;
+        check_message(&data).map_err(error)?;
+        messages.push(Message { time, port, data });
+    }
+    Ok(messages)
+}
+
+/// Check that a message starts with a status byte and has as many data bytes as it needs, each
+/// below 0x80. SysEx must end with its end byte.
+fn check_message(data: &[u8]) -> Result<(), &'static str> {
+    let (&status, rest) = data.split_first().ok_or("missing message")?;
+    let rest = match status {
+        0x00..=0x7f => return Err("missing status byte"),
+        0xf0 => rest.strip_suffix(&[0xf7]).ok_or("unterminated SysEx")?,
+        _ => rest,
+    };
+    if rest.iter().any(|&byte| byte >= 0x80) {
+        return Err("bad data byte");
+    }
+    let length = match status {
+        0xc0..=0xdf | 0xf1 | 0xf3 => 1,
+        0x80..=0xbf | 0xe0..=0xef | 0xf2 => 2,
+        0xf0 => rest.len(),
+        _ => 0,
+    };
+    if rest.len() != length {
+        return Err("wrong number of data bytes");
+    }
+    Ok(())
+}
+
+/// The kind of Standard MIDI File to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Type 0: a single track.
+    Single = 0,
+    /// Type 1: a tempo track, then a track for each channel.
+    Multiple = 1,
+}
+
+/// How to write a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// The type of file.
+    pub format: Format,
+    /// The ticks in a quarter note.
+    pub division: u16,
+    /// The tempo in µs per quarter note, used when the performance has no MIDI clock.
+    pub tempo: u32,
+    /// The port whose MIDI clock sets the tempo, or `None` for the port of the first clock pulse.
+    pub clock_port: Option<Port>,
+}
+
+impl Default for Options {
+    /// A type 1 file at 120 BPM with 480 ticks per quarter note, following the first port to send
+    /// MIDI clock.
+    fn default() -> Self {
+        Options {
+            format: Format::Multiple,
+            division: 480,
+            tempo: 500_000,
+            clock_port: None,
+        }
+    }
+}
+
+/// A change of tempo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tempo {
+    /// When the tempo changes, in ms from the start of the performance.
+    pub time: u64,
+    /// The tempo in µs per quarter note.
+    pub tempo: u32,
+}
+
+/// Returns the tempo of each beat of MIDI clock on a port in a performance, leaving out beats that
+/// don't change it, or of the clock on the port of the first pulse if `port` is `None`. Clock on
+/// other ports is ignored. The first beat's tempo holds from the start, and the last from the last
+/// whole beat. A performance without a whole beat of clock is at `tempo` throughout.
+pub fn tempo_map(messages: &[Message], port: Option<Port>, tempo: u32) -> Vec<Tempo> {
+    let is_pulse = |message: &&Message| message.data == [0xf8];
+    let port = port.or_else(|| messages.iter().find(is_pulse).map(|pulse| pulse.port));
+    let pulses: Vec<_> = messages
+        .iter()
+        .filter(is_pulse)
+        .filter(|message| Some(message.port) == port)
+        .map(|message| message.time)
+        .collect();
+    let mut map = vec![Tempo { time: 0, tempo }];
+    for (beat, start) in pulses.iter().step_by(PPQN).enumerate() {
+        let Some(end) = pulses.get((beat + 1) * PPQN) else {
+            break;
+        };
+        let tempo = ((end - start) * 1000).clamp(1, 0xff_ffff) as u32;
+        match map.last_mut() {
+            Some(last) if beat == 0 => last.tempo = tempo,
+            Some(last) if last.tempo == tempo => {}
+            _ => map.push(Tempo {
+                time: *start,
+                tempo,
+            }),
+        }
+    }
+    map
+}
+
+/// Returns the tick of a time in ms, following a tempo map.
+fn ticks(map: &[Tempo], division: u16, time: u64) -> u64 {
+    let mut ticks = 0;
+    for (index, tempo) in map.iter().enumerate() {
+        if tempo.time >= time {
+            break;
+        }
+        let end = map.get(index + 1).map_or(time, |next| next.time.min(time));
+        let micros = (end - tempo.time) as u128 * 1000 * division as u128;
+        ticks += ((micros + tempo.tempo as u128 / 2) / tempo.tempo as u128) as u64;
+    }
+    ticks
+}
+
+/// A track being written: its events with their ticks.
+#[derive(Default)]
+struct Track(Vec<(u64, Vec<u8>)>);
+
+impl Track {
+    fn meta(&mut self, tick: u64, kind: u8, data: &[u8]) {
+        let mut event = vec![0xff, kind];
+        write_varlen(&mut event, data.len() as u32);
+        event.extend_from_slice(data);
+        self.0.push((tick, event));
+    }
+
+    fn name(&mut self, name: &str) {
+        self.meta(0, 0x03, name.as_bytes());
+    }
+
+    fn tempo_map(&mut self, map: &[Tempo], division: u16) {
+        for tempo in map {
+            let tick = ticks(map, division, tempo.time);
+            self.meta(tick, 0x51, &tempo.tempo.to_be_bytes()[1..]);
+        }
+    }
+
+    /// Write the track's chunk, ending the track after its last event.
+    fn write(mut self, file: &mut Vec<u8>) {
+        // keep events at the same tick in the order they were added
+        self.0.sort_by_key(|(tick, _)| *tick);
+        let mut chunk = Vec::new();
+        let mut last = 0;
+        for (tick, event) in &self.0 {
+            write_varlen(&mut chunk, (tick - last) as u32);
+            chunk.extend_from_slice(event);
+            last = *tick;
+        }
+        chunk.extend_from_slice(&[0x00, 0xff, 0x2f, 0x00]);
+
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+        file.extend_from_slice(&chunk);
+    }
+}
+
+/// Append a variable-length quantity: seven bits to a byte, most significant first, with the top
+/// bit set on every byte but the last.
+fn write_varlen(output: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![value as u8 & 0x7f];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push(value as u8 & 0x7f | 0x80);
+        value >>= 7;
+    }
+    output.extend(bytes.iter().rev());
+}
+
+/// Write a performance as a Standard MIDI File. Messages other than channel messages, such as
+/// MIDI clock, are left out.
+pub fn write(messages: &[Message], options: &Options) -> Result<Vec<u8>, Error> {
+    if options.division == 0 || options.division > 0x7fff {
+        return Err(Error::Division(options.division));
+    }
+    let map = tempo_map(messages, options.clock_port, options.tempo);
+    let tick = |message: &Message| ticks(&map, options.division, message.time);
+
+    let mut tracks = Vec::new();
+    match options.format {
+        Format::Single => {
+            let mut track = Track::default();
+            track.tempo_map(&map, options.division);
+            for message in messages
+                .iter()
+                .filter(|message| message.channel().is_some())
+            {
+                track.0.push((tick(message), message.data.clone()));
+            }
+            tracks.push(track);
+        }
+        Format::Multiple => {
+            let mut tempo = Track::default();
+            tempo.name("Tempo");
+            tempo.tempo_map(&map, options.division);
+            tracks.push(tempo);
+
+            for port in Port::ALL {
+                for channel in 0..16 {
+                    let mut track = Track::default();
+                    track.name(&format!("{:?} channel {}", port, channel + 1));
+                    let on_channel = messages.iter().filter(|message| {
+                        message.port == port && message.channel() == Some(channel)
+                    });
+                    for message in on_channel {
+                        track.0.push((tick(message), message.data.clone()));
+                    }
+                    if track.0.len() > 1 {
+                        tracks.push(track);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&(options.format as u16).to_be_bytes());
+    file.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    file.extend_from_slice(&options.division.to_be_bytes());
+    for track in tracks {
+        track.write(&mut file);
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(times: impl Iterator<Item = u64>) -> Vec<Message> {
+        clock_on(Port::USB, times)
+    }
+
+    fn clock_on(port: Port, times: impl Iterator<Item = u64>) -> Vec<Message> {
+        times
+            .map(|time| Message {
+                time,
+                port,
+                data: vec![0xf8],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn varlen_matches_the_specification() {
+        let examples: [(u32, &[u8]); 6] = [
+            (0, &[0x00]),
+            (0x40, &[0x40]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x81, 0x00]),
+            (0x2000, &[0xc0, 0x00]),
+            (0x0fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for (value, expected) in examples {
+            let mut output = Vec::new();
+            write_varlen(&mut output, value);
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn tempo_follows_the_clock() {
+        // two beats at 120 BPM, then two at 100 BPM
+        let mut messages = clock((0..48).map(|pulse| pulse * 500 / 24));
+        messages.extend(clock((0..=48).map(|pulse| 1000 + pulse * 600 / 24)));
+        assert_eq!(
+            tempo_map(&messages, None, 1),
+            [
+                Tempo {
+                    time: 0,
+                    tempo: 500_000
+                },
+                Tempo {
+                    time: 1000,
+                    tempo: 600_000
+                },
+            ]
+        );
+        let map = tempo_map(&messages, None, 1);
+        assert_eq!(ticks(&map, 480, 1000), 960);
+        assert_eq!(ticks(&map, 480, 1300), 1200);
+
+        assert_eq!(
+            tempo_map(&clock(0..24), None, 400_000)[..],
+            [Tempo {
+                time: 0,
+                tempo: 400_000
+            }]
+        );
+    }
+
+    #[test]
+    fn tempo_follows_one_port() {
+        // both ports clock at 120 BPM, DIN a little behind USB, then DIN slows to 100 BPM
+        let mut messages = clock_on(Port::USB, (0..=96).map(|pulse| pulse * 500 / 24));
+        messages.extend(clock_on(
+            Port::DIN,
+            (0..48).map(|pulse| 5 + pulse * 500 / 24),
+        ));
+        messages.extend(clock_on(
+            Port::DIN,
+            (0..=48).map(|pulse| 1005 + pulse * 600 / 24),
+        ));
+        messages.sort_by_key(|message| message.time);
+
+        let steady = [Tempo {
+            time: 0,
+            tempo: 500_000,
+        }];
+        assert_eq!(tempo_map(&messages, None, 1), steady);
+        assert_eq!(tempo_map(&messages, Some(Port::USB), 1), steady);
+        assert_eq!(
+            tempo_map(&messages, Some(Port::DIN), 1),
+            [
+                Tempo {
+                    time: 0,
+                    tempo: 500_000
+                },
+                Tempo {
+                    time: 1005,
+                    tempo: 600_000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn captures_are_read() {
+        let text = "# a capture\n0 1 b0 65 00\n\n1500 2 91 3c 64\n";
+        assert_eq!(
+            parse_capture(text).unwrap(),
+            [
+                Message {
+                    time: 0,
+                    port: Port::USB,
+                    data: vec![0xb0, 0x65, 0x00]
+                },
+                Message {
+                    time: 1500,
+                    port: Port::DIN,
+                    data: vec![0x91, 0x3c, 0x64]
+                },
+            ]
+        );
+        assert_eq!(
+            parse_capture("0 1 90 3c 64\n10 3 80 3c 00"),
+            Err(Error::Capture {
+                line: 2,
+                reason: "bad port"
+            })
+        );
+        assert_eq!(
+            write(
+                &[],
+                &Options {
+                    division: 0,
+                    ..Options::default()
+                }
+            ),
+            Err(Error::Division(0))
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let error = |line, reason| Err(Error::Capture { line, reason });
+        assert_eq!(
+            parse_capture("0 1 90"),
+            error(1, "wrong number of data bytes")
+        );
+        assert_eq!(
+            parse_capture("0 1 b0 65 00\n5 1 90 3c"),
+            error(2, "wrong number of data bytes")
+        );
+        assert_eq!(
+            parse_capture("0 1 c0 01 02"),
+            error(1, "wrong number of data bytes")
+        );
+        assert_eq!(parse_capture("0 1 90 3c 80"), error(1, "bad data byte"));
+        assert_eq!(parse_capture("0 1 3c 64"), error(1, "missing status byte"));
+        assert_eq!(
+            parse_capture("0 1 f0 7d 01"),
+            error(1, "unterminated SysEx")
+        );
+        assert!(parse_capture("0 1 f0 7d 01 f7\n1 1 f8\n2 1 d0 40").is_ok());
+    }
+}
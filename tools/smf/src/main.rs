@@ -0,0 +1,98 @@
+use std::process::ExitCode;
+
+use launchpad_pro_rs::hal::midi::Port;
+use smf::{Format, Options};
+
+const USAGE: &str = "\
+usage: smf [options] <input> <output>
+
+Convert a loop exported from the Launchpad Pro as SysEx, or messages captured on the host, into
+a Standard MIDI File.
+
+options:
+    --type <0|1>           a single track, or a track for each channel (default 1)
+    --division <ticks>     the ticks in a quarter note (default 480)
+    --bpm <bpm>            the tempo when the performance has no MIDI clock (default 120)
+    --clock-port <port>    the port whose MIDI clock sets the tempo: 0 standalone, 1 USB or 2 DIN
+                           (default the first port to send clock)";
+
+/// Parse the command line into the options and the input and output paths.
+fn parse_args(args: &[String]) -> Result<(Options, String, String), String> {
+    let mut options = Options::default();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", arg))
+                .map(String::as_str)
+        };
+        let invalid = |value: &str| format!("invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--type" => {
+                options.format = match value()? {
+                    "0" => Format::Single,
+                    "1" => Format::Multiple,
+                    value => return Err(invalid(value)),
+                }
+            }
+            "--division" => {
+                let value = value()?;
+                options.division = value.parse().map_err(|_| invalid(value))?;
+            }
+            "--bpm" => {
+                let value = value()?;
+                options.tempo = match value.parse::<f64>() {
+                    Ok(bpm) if bpm > 3.6 => (60_000_000.0 / bpm).round() as u32,
+                    _ => return Err(invalid(value)),
+                };
+            }
+            "--clock-port" => {
+                let value = value()?;
+                let port = value
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|port| Port::try_from(port).ok());
+                options.clock_port = Some(port.ok_or_else(|| invalid(value))?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok((options, input, output)),
+        Err(_) => Err(USAGE.to_string()),
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (options, input, output) = parse_args(args)?;
+    println!("converting {} to midi file: {}", input, output);
+
+    let data = std::fs::read(&input).map_err(|error| format!("{}: {}", input, error))?;
+    // exported loops are SysEx, and captures are text
+    let messages = if data.first() == Some(&0xf0) {
+        smf::from_loop(&data)
+    } else {
+        let text = String::from_utf8_lossy(&data);
+        smf::parse_capture(&text)
+    }
+    .map_err(|error| format!("{}: {}", input, error))?;
+    println!("{} messages", messages.len());
+
+    let file = smf::write(&messages, &options).map_err(|error| error.to_string())?;
+    std::fs::write(&output, file).map_err(|error| format!("{}: {}", output, error))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
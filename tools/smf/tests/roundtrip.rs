@@ -0,0 +1,296 @@
+//! Read written files back with `midly`, a parser that shares no code with the writer.
+
+use launchpad_pro_rs::hal::capture;
+use launchpad_pro_rs::hal::midi::{self, MidiMessage, Port};
+use launchpad_pro_rs::hal::surface::{AftertouchEvent, Button, ButtonEvent, Event};
+use launchpad_pro_rs::hal::{timer, Point};
+use launchpad_pro_rs::looper::{Action, Looper};
+use midly::{MetaMessage, Timing, TrackEventKind};
+use smf::{Format, Options};
+use wmidi::{Channel, Note, U14, U7};
+
+/// Returns the events of each track with their ticks from the start, leaving out track names and
+/// the end of the track.
+fn read(file: &[u8]) -> (midly::Header, Vec<Vec<(u64, TrackEventKind<'_>)>>) {
+    let smf = midly::Smf::parse(file).unwrap();
+    let tracks = smf
+        .tracks
+        .iter()
+        .map(|track| {
+            assert_eq!(
+                track.last().unwrap().kind,
+                TrackEventKind::Meta(MetaMessage::EndOfTrack)
+            );
+            let mut tick = 0;
+            track
+                .iter()
+                .filter_map(|event| {
+                    tick += event.delta.as_int() as u64;
+                    match event.kind {
+                        TrackEventKind::Meta(MetaMessage::TrackName(_))
+                        | TrackEventKind::Meta(MetaMessage::EndOfTrack) => None,
+                        kind => Some((tick, kind)),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    (smf.header, tracks)
+}
+
+fn bend(channel: u8, value: u16) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: channel.into(),
+        message: midly::MidiMessage::PitchBend {
+            bend: midly::PitchBend(value.into()),
+        },
+    }
+}
+
+fn note_on(channel: u8, key: u8, vel: u8) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: channel.into(),
+        message: midly::MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        },
+    }
+}
+
+fn tempo(tempo: u32) -> TrackEventKind<'static> {
+    TrackEventKind::Meta(MetaMessage::Tempo(tempo.into()))
+}
+
+/// Play two MPE voices with their own pitch bend, against MIDI clock that slows from 120 to 100
+/// BPM after a beat, capturing what is sent.
+fn capture_mpe() -> Vec<smf::Message> {
+    let send = |message: MidiMessage| midi::send_message(Port::USB, &message);
+    let voices = [
+        (Channel::Ch2, Note::C4, 0x2400),
+        (Channel::Ch3, Note::E4, 0x1c80),
+    ];
+    capture::start();
+    for ms in 0..1600 {
+        // a pulse every 1/24 beat, on the ms it falls in
+        let pulse_due = |start: u32, beat: u32| ms >= start && ((ms - start) * 24) % beat < 24;
+        if (ms < 500 && pulse_due(0, 500)) || (ms >= 500 && pulse_due(500, 600)) {
+            send(MidiMessage::TimingClock);
+        }
+        for (index, &(channel, note, bend)) in voices.iter().enumerate() {
+            let start = 100 + 200 * index as u32;
+            if ms == start {
+                send(MidiMessage::PitchBendChange(
+                    channel,
+                    U14::try_from(bend).unwrap(),
+                ));
+                send(MidiMessage::NoteOn(channel, note, U7::from_u8_lossy(90)));
+            } else if ms == start + 300 {
+                send(MidiMessage::PitchBendChange(channel, U14::MAX));
+            } else if ms == start + 600 {
+                send(MidiMessage::NoteOff(channel, note, U7::MIN));
+            }
+        }
+        timer::tick();
+    }
+    smf::from_captured(&capture::stop())
+}
+
+#[test]
+fn mpe_captures_keep_each_voice_on_its_own_track() {
+    let messages = capture_mpe();
+    let pulses = messages.iter().filter(|message| message.data == [0xf8]);
+    assert_eq!(pulses.count(), 24 + 44);
+
+    let file = smf::write(&messages, &Options::default()).unwrap();
+    let (header, tracks) = read(&file);
+    assert_eq!(header.format, midly::Format::Parallel);
+    assert_eq!(header.timing, Timing::Metrical(480.into()));
+    assert_eq!(tracks.len(), 3);
+
+    // the tempo map slows after the first beat
+    assert_eq!(tracks[0], [(0, tempo(500_000)), (480, tempo(600_000))]);
+
+    // 100 ms at 120 BPM, then 300 ms at 100 BPM from the second beat
+    let at = |ms: u64| match ms {
+        0..=500 => ms * 480 / 500,
+        _ => 480 + (ms - 500) * 480 / 600,
+    };
+    assert_eq!(
+        tracks[1][..3],
+        [
+            (at(100), bend(1, 0x2400)),
+            (at(100), note_on(1, 60, 90)),
+            (at(400), bend(1, 0x3fff)),
+        ]
+    );
+    assert_eq!(
+        tracks[2][..3],
+        [
+            (at(300), bend(2, 0x1c80)),
+            (at(300), note_on(2, 64, 90)),
+            (at(600), bend(2, 0x3fff)),
+        ]
+    );
+    assert_eq!(tracks[1].len(), 4);
+    assert_eq!(tracks[2].len(), 4);
+
+    // a type 0 file interleaves the voices in one track
+    let options = Options {
+        format: Format::Single,
+        ..Options::default()
+    };
+    let file = smf::write(&messages, &options).unwrap();
+    let (header, tracks) = read(&file);
+    assert_eq!(header.format, midly::Format::SingleTrack);
+    assert_eq!(tracks.len(), 1);
+    let channels: Vec<_> = tracks[0]
+        .iter()
+        .filter_map(|(_, kind)| match kind {
+            TrackEventKind::Midi { channel, .. } => Some(channel.as_int()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(channels, [1, 1, 2, 2, 1, 2, 1, 2]);
+}
+
+#[test]
+fn exported_loops_keep_their_timing() {
+    let mut looper: Looper<32> = Looper::new();
+    looper.record();
+    for (time, data) in [
+        (0, [0xe0, 0x00, 0x50]),
+        (10, [0x90, 62, 80]),
+        (250, [0x80, 62, 0]),
+    ] {
+        while looper.position() < time {
+            looper.tick(|_| ());
+        }
+        let message = MidiMessage::try_from(&data[..]).unwrap();
+        looper.capture(Action::midi(Port::DIN, &message).unwrap());
+    }
+    looper.play();
+    let mut stream = Vec::new();
+    looper.export(|message| stream.extend_from_slice(message));
+
+    let messages = smf::from_loop(&stream).unwrap();
+    assert_eq!(messages.len(), 3);
+    let options = Options {
+        format: Format::Single,
+        division: 96,
+        tempo: 1_000_000,
+        ..Options::default()
+    };
+    let file = smf::write(&messages, &options).unwrap();
+    let (_, tracks) = read(&file);
+    assert_eq!(
+        tracks[0],
+        [
+            (0, tempo(1_000_000)),
+            (0, bend(0, 0x2800)),
+            (1, note_on(0, 62, 80)),
+            (
+                24,
+                TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::NoteOff {
+                        key: 62.into(),
+                        vel: 0.into(),
+                    },
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn exported_pad_presses_become_notes() {
+    let pad = Point::new(3, 4);
+    let button = |event| {
+        Action::Button(ButtonEvent {
+            button: Button::Pad(pad),
+            event,
+        })
+    };
+    let mut looper: Looper<32> = Looper::new();
+    looper.record();
+    for (time, action) in [
+        (0, button(Event::Press(100))),
+        (
+            50,
+            Action::Aftertouch(AftertouchEvent {
+                point: pad,
+                value: 60,
+            }),
+        ),
+        (100, button(Event::Release)),
+        // the record button above the pads
+        (
+            150,
+            Action::Button(ButtonEvent {
+                button: Button::Pad(Point::from_index(93)),
+                event: Event::Press(127),
+            }),
+        ),
+    ] {
+        while looper.position() < time {
+            looper.tick(|_| ());
+        }
+        looper.capture(action);
+    }
+    while looper.position() < 250 {
+        looper.tick(|_| ());
+    }
+    looper.play();
+    let mut stream = Vec::new();
+    looper.export(|message| stream.extend_from_slice(message));
+
+    let messages = smf::from_loop(&stream).unwrap();
+    assert!(messages.iter().all(|message| message.port == Port::USB));
+    let options = Options {
+        format: Format::Single,
+        division: 96,
+        tempo: 1_000_000,
+        ..Options::default()
+    };
+    let file = smf::write(&messages, &options).unwrap();
+    let (_, tracks) = read(&file);
+    let midi = |message| TrackEventKind::Midi {
+        channel: 0.into(),
+        message,
+    };
+    assert_eq!(
+        tracks[0][1..],
+        [
+            (0, note_on(0, 43, 100)),
+            (
+                5,
+                midi(midly::MidiMessage::Aftertouch {
+                    key: 43.into(),
+                    vel: 60.into(),
+                })
+            ),
+            (
+                10,
+                midi(midly::MidiMessage::NoteOff {
+                    key: 43.into(),
+                    vel: 0.into(),
+                })
+            ),
+            (
+                14,
+                midi(midly::MidiMessage::Controller {
+                    controller: 93.into(),
+                    value: 127.into(),
+                })
+            ),
+            // the looper releases the button held at the end of the take
+            (
+                24,
+                midi(midly::MidiMessage::Controller {
+                    controller: 93.into(),
+                    value: 0.into(),
+                })
+            ),
+        ]
+    );
+}